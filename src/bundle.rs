@@ -0,0 +1,191 @@
+//! Bundles of architecture specific cubins with a fallback PTX
+//!
+//! A bundle lets host runtimes load native SASS for the GPUs it was built for
+//! while still being able to JIT compile the PTX on any newer GPU. It is emitted
+//! with `--emit bundle` and can be read with [`Bundle::parse`].
+//!
+//! # Format
+//!
+//! All integers are little endian.
+//!
+//! | Field       | Size          | Description                              |
+//! |-------------|---------------|------------------------------------------|
+//! | magic       | 8             | `PTXLBNDL`                               |
+//! | version     | 4             | format version, currently `1`            |
+//! | entry count | 4             | number of entries that follow            |
+//! | entries     | variable      | entries as described below               |
+//!
+//! Each entry is laid out as
+//!
+//! | Field       | Size          | Description                              |
+//! |-------------|---------------|------------------------------------------|
+//! | kind        | 1             | `0` for PTX, `1` for a cubin             |
+//! | arch length | 2             | length of the architecture name          |
+//! | arch        | arch length   | UTF-8 architecture name, e.g. `sm_80`    |
+//! | data length | 8             | length of the entry data                 |
+//! | data        | data length   | the PTX text or cubin ELF object         |
+
+use std::io::Write;
+
+/// The magic bytes at the start of every bundle
+pub const MAGIC: [u8; 8] = *b"PTXLBNDL";
+
+/// The bundle format version written by this linker
+pub const VERSION: u32 = 1;
+
+/// The kind of code stored in a bundle entry
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum EntryKind {
+    /// PTX text that can be JIT compiled for its or any newer architecture
+    Ptx,
+    /// A cubin with SASS that only runs on its exact architecture
+    Cubin,
+}
+
+/// A single piece of code in a bundle
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Entry {
+    pub kind: EntryKind,
+    /// The architecture the code was compiled for, e.g. `sm_80`
+    pub arch: String,
+    pub data: Vec<u8>,
+}
+
+/// A bundle of cubins and PTX
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Bundle {
+    pub entries: Vec<Entry>,
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, thiserror::Error)]
+/// A bundle could not be parsed
+pub enum BundleError {
+    #[error("not a bundle")]
+    InvalidMagic,
+    #[error("unsupported bundle version {0}")]
+    UnsupportedVersion(u32),
+    #[error("unknown bundle entry kind {0}")]
+    UnknownEntryKind(u8),
+    #[error("bundle entry architecture is not valid UTF-8")]
+    InvalidArch,
+    #[error("truncated bundle")]
+    Truncated,
+}
+
+impl Bundle {
+    /// Parses a bundle from its serialized bytes
+    pub fn parse(bytes: &[u8]) -> Result<Self, BundleError> {
+        let mut reader = Reader(bytes);
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(BundleError::InvalidMagic);
+        }
+
+        let version = u32::from_le_bytes(reader.array()?);
+        if version != VERSION {
+            return Err(BundleError::UnsupportedVersion(version));
+        }
+
+        let count = u32::from_le_bytes(reader.array()?);
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let kind = match reader.array::<1>()?[0] {
+                0 => EntryKind::Ptx,
+                1 => EntryKind::Cubin,
+                kind => return Err(BundleError::UnknownEntryKind(kind)),
+            };
+
+            let arch_len = u16::from_le_bytes(reader.array()?);
+            let arch = std::str::from_utf8(reader.take(usize::from(arch_len))?)
+                .map_err(|_| BundleError::InvalidArch)?
+                .to_owned();
+
+            let data_len = u64::from_le_bytes(reader.array()?);
+            let data_len = usize::try_from(data_len).map_err(|_| BundleError::Truncated)?;
+            let data = reader.take(data_len)?.to_vec();
+
+            entries.push(Entry { kind, arch, data });
+        }
+
+        Ok(Bundle { entries })
+    }
+
+    /// Serializes the bundle
+    pub fn write(&self, mut writer: impl Write) -> std::io::Result<()> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+
+        let count = u32::try_from(self.entries.len())
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+        writer.write_all(&count.to_le_bytes())?;
+
+        for entry in &self.entries {
+            let kind: u8 = match entry.kind {
+                EntryKind::Ptx => 0,
+                EntryKind::Cubin => 1,
+            };
+            writer.write_all(&[kind])?;
+
+            let arch_len = u16::try_from(entry.arch.len())
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+            writer.write_all(&arch_len.to_le_bytes())?;
+            writer.write_all(entry.arch.as_bytes())?;
+
+            writer.write_all(&(entry.data.len() as u64).to_le_bytes())?;
+            writer.write_all(&entry.data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Selects the code to load on a GPU of architecture `arch`
+    ///
+    /// A cubin for exactly `arch` is preferred. Otherwise the PTX for the newest
+    /// architecture not newer than `arch` is returned, which the driver can JIT
+    /// compile.
+    pub fn select(&self, arch: &str) -> Option<&Entry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.kind == EntryKind::Cubin && entry.arch == arch)
+            .or_else(|| {
+                let version = arch_version(arch)?;
+                self.entries
+                    .iter()
+                    .filter(|entry| entry.kind == EntryKind::Ptx)
+                    .filter_map(|entry| Some((arch_version(&entry.arch)?, entry)))
+                    .filter(|&(ptx_version, _)| ptx_version <= version)
+                    .max_by_key(|&(ptx_version, _)| ptx_version)
+                    .map(|(_, entry)| entry)
+            })
+    }
+}
+
+/// The numeric compute capability of an architecture name like `sm_80` or
+/// `sm_90a`
+pub fn arch_version(arch: &str) -> Option<u32> {
+    let digits = arch
+        .strip_prefix("sm_")
+        .or_else(|| arch.strip_prefix("compute_"))?
+        .trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    digits.parse().ok()
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], BundleError> {
+        if len > self.0.len() {
+            return Err(BundleError::Truncated);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], BundleError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+}
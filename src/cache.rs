@@ -0,0 +1,100 @@
+//! `rust-ptx-linker cache` manages the intermediates of earlier links
+//!
+//! Links keep their intermediates in a directory per output when they fail,
+//! for `--resume`, or with `--keep-intermediates`. The bitcode of archives and
+//! the inputs optimized with `--thinlto` are cached in the `cache` directory
+//! next to them, where later links of any output reuse them.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::Parser;
+use ptx_linker::Intermediates;
+
+/// The subcommand managing the intermediates
+pub const SUBCOMMAND: &str = "cache";
+
+#[derive(Debug, Parser)]
+#[command(bin_name = "rust-ptx-linker cache")]
+/// List or remove the intermediates and the cache kept by earlier links
+pub struct Options {
+    #[command(subcommand)]
+    action: Action,
+}
+
+#[derive(Debug, Clone, Copy, clap::Subcommand)]
+enum Action {
+    /// List the intermediates directory of every output and the cache with
+    /// their size
+    List,
+    /// Remove the intermediates of all outputs and the cache, which must not
+    /// be linked concurrently
+    Clean,
+}
+
+/// Lists or removes the intermediates directories
+pub fn run(options: &Options) -> anyhow::Result<()> {
+    let root = Intermediates::root();
+    let dirs = directories(&root)?;
+
+    match options.action {
+        Action::List => {
+            let mut total = 0;
+            for dir in &dirs {
+                let size = size(dir)?;
+                total += size;
+                println!(
+                    "{size:>12} {}",
+                    dir.file_name().unwrap_or_default().to_string_lossy()
+                );
+            }
+            println!(
+                "{total:>12} bytes in {} directories in {}",
+                dirs.len(),
+                root.display()
+            );
+        }
+        Action::Clean => {
+            for dir in &dirs {
+                std::fs::remove_dir_all(dir)
+                    .context(format!("Failed to remove {}", dir.display()))?;
+            }
+            println!("removed {} directories in {}", dirs.len(), root.display());
+        }
+    }
+    Ok(())
+}
+
+/// The intermediates directories in `root`, sorted by name
+fn directories(root: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).context(format!("Failed to read {}", root.display())),
+    };
+
+    let mut dirs = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            dirs.push(path);
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// The total size of the files in `dir` and its subdirectories
+fn size(dir: &Path) -> anyhow::Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(dir).context(format!("Failed to read {}", dir.display()))? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            self::size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use ptx_linker::Target;
+
+/// The configuration file that is used if it exists in the current directory
+pub const DEFAULT_CONFIG_FILE: &str = "ptx-linker.toml";
+
+/// Settings from a `ptx-linker.toml` configuration file
+///
+/// Options given on the command line take precedence over the configuration.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// The major LLVM version of the tools to use, skipping the `rustc` probe
+    pub llvm_major: Option<u32>,
+    /// The target cpus used if none are given on the command line
+    #[serde(default)]
+    pub target_cpus: Vec<String>,
+    /// A bitcode library linked into every module, e.g. CUDA's libdevice,
+    /// relative to the configuration file
+    pub libdevice: Option<PathBuf>,
+    /// Passes run after the optimization pipeline, e.g. `loop-unroll,licm`
+    pub passes: Option<String>,
+    /// Settings for a single target triple in `[target.<triple>]` sections,
+    /// which take precedence over the ones above
+    #[serde(default)]
+    pub target: BTreeMap<String, Profile>,
+}
+
+/// The settings of a `[target.<triple>]` section
+///
+/// Sections for targets this linker does not support are accepted, so that
+/// multi-backend projects can share one configuration file.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Profile {
+    pub llvm_major: Option<u32>,
+    pub target_cpus: Option<Vec<String>>,
+    pub libdevice: Option<PathBuf>,
+    pub passes: Option<String>,
+}
+
+impl Config {
+    /// Loads the configuration from `path`, or from [`DEFAULT_CONFIG_FILE`] if
+    /// it exists
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let path = if let Some(path) = path {
+            path.to_owned()
+        } else {
+            let path = PathBuf::from(DEFAULT_CONFIG_FILE);
+            if !path.is_file() {
+                return Ok(Config::default());
+            }
+            path
+        };
+
+        let content = std::fs::read_to_string(&path)
+            .context(format!("Failed to read config file: {}", path.display()))?;
+        let mut config: Config = toml::from_str(&content)
+            .context(format!("Failed to parse config file: {}", path.display()))?;
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let profiles = config
+            .target
+            .values_mut()
+            .map(|profile| &mut profile.libdevice);
+        for libdevice in std::iter::once(&mut config.libdevice)
+            .chain(profiles)
+            .flatten()
+        {
+            *libdevice = dir.join(&*libdevice);
+        }
+
+        tracing::info!("using config file: {}", path.display());
+        Ok(config)
+    }
+
+    /// The settings for `target`, merging its `[target.<triple>]` section
+    /// into the global ones
+    pub fn for_target(mut self, target: Target) -> Self {
+        if let Some(profile) = self.target.remove(target.triple()) {
+            tracing::debug!("using the config of target {}", target.triple());
+            self.llvm_major = profile.llvm_major.or(self.llvm_major);
+            self.target_cpus = profile.target_cpus.unwrap_or(self.target_cpus);
+            self.libdevice = profile.libdevice.or(self.libdevice);
+            self.passes = profile.passes.or(self.passes);
+        }
+        self.target.clear();
+        self
+    }
+}
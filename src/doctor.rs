@@ -0,0 +1,50 @@
+//! `rust-ptx-linker doctor` checks the environment before a first link
+//!
+//! It finds the LLVM tools of the `rustc` LLVM version and the tools of the
+//! CUDA toolkit, and prints their versions and the intermediates directory.
+
+use std::path::PathBuf;
+
+use clap::Parser;
+use ptx_linker::{capabilities, Intermediates};
+
+/// The subcommand checking the environment
+pub const SUBCOMMAND: &str = "doctor";
+
+#[derive(Debug, Parser)]
+#[command(bin_name = "rust-ptx-linker doctor")]
+/// Check that the external tools the linker runs are installed
+pub struct Options {
+    /// The major LLVM version of the tools to check instead of the one of
+    /// rustc
+    #[arg(long)]
+    llvm_major: Option<u32>,
+    /// The directory of the LLVM tools to check instead of looking them up
+    #[arg(long, value_name = "DIR")]
+    llvm_tools_path: Option<PathBuf>,
+}
+
+/// Prints every tool with its version, failing if a required one is missing
+pub fn run(options: &Options) -> anyhow::Result<()> {
+    let capabilities = capabilities(options.llvm_major, options.llvm_tools_path.as_deref());
+    println!("{} {}", env!("CARGO_PKG_NAME"), capabilities.version);
+
+    for tool in &capabilities.tools {
+        match &tool.version {
+            Some(version) => println!("  ok       {} {version}", tool.name),
+            None if tool.optional => println!("  missing  {}, optional", tool.name),
+            None => println!("  missing  {}", tool.name),
+        }
+    }
+    println!("intermediates: {}", Intermediates::root().display());
+
+    let missing = capabilities
+        .tools
+        .iter()
+        .filter(|tool| !tool.optional && tool.version.is_none())
+        .count();
+    if missing > 0 {
+        anyhow::bail!("{missing} required tool(s) missing");
+    }
+    Ok(())
+}
@@ -0,0 +1,80 @@
+//! Images embedded into host objects for single binary deployments
+//!
+//! With `--emit object` the linker writes a host object file with a single
+//! image, the PTX or the [bundle](crate::bundle), in the [`SECTION`] section.
+//! Linking such objects into the host executable concatenates their images
+//! into one section, which [`crate::loader::embedded`] looks them up in at
+//! runtime.
+//!
+//! # Format
+//!
+//! All integers are little endian. Every record starts and ends 8 byte
+//! aligned, and readers skip zero padding between records.
+//!
+//! | Field       | Size          | Description                              |
+//! |-------------|---------------|------------------------------------------|
+//! | magic       | 8             | `PTXLIMAG`                               |
+//! | name length | 8             | length of the image name                 |
+//! | data length | 8             | length of the image                      |
+//! | name        | name length   | UTF-8 image name, padded to 8 bytes      |
+//! | data        | data length   | the image, padded to 8 bytes             |
+
+/// The section the images are embedded in, a valid C identifier so that ELF
+/// linkers define `__start_` and `__stop_` symbols for it
+pub const SECTION: &str = "ptx_linker_images";
+
+/// The magic bytes at the start of every record
+pub const MAGIC: [u8; 8] = *b"PTXLIMAG";
+
+const ALIGN: usize = 8;
+
+/// Encodes an image named `name` as a record
+pub fn record(name: &str, data: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(3 * 8 + padded(name.len()) + padded(data.len()));
+    record.extend_from_slice(&MAGIC);
+    record.extend_from_slice(&(name.len() as u64).to_le_bytes());
+    record.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    record.extend_from_slice(name.as_bytes());
+    record.resize(padded(record.len()), 0);
+    record.extend_from_slice(data);
+    record.resize(padded(record.len()), 0);
+    record
+}
+
+/// Iterates over the name and data of the records in `section`, stopping at
+/// the first malformed record
+pub fn records(mut section: &[u8]) -> impl Iterator<Item = (&str, &[u8])> {
+    std::iter::from_fn(move || loop {
+        if section.len() < ALIGN {
+            return None;
+        }
+        if section[..ALIGN].iter().all(|&byte| byte == 0) {
+            section = &section[ALIGN..];
+            continue;
+        }
+        if section[..ALIGN] != MAGIC {
+            return None;
+        }
+
+        let len_at = |offset: usize| {
+            let len = u64::from_le_bytes(section.get(offset..offset + 8)?.try_into().ok()?);
+            usize::try_from(len).ok()
+        };
+        let name_len = len_at(8)?;
+        let data_len = len_at(16)?;
+
+        let name_start = 24;
+        let data_start = name_start + padded(name_len);
+        let name = std::str::from_utf8(section.get(name_start..name_start + name_len)?).ok()?;
+        let data = section.get(data_start..data_start.checked_add(data_len)?)?;
+
+        section = section
+            .get(padded(data_start + data_len)..)
+            .unwrap_or_default();
+        return Some((name, data));
+    })
+}
+
+fn padded(len: usize) -> usize {
+    (len + ALIGN - 1) / ALIGN * ALIGN
+}
@@ -0,0 +1,91 @@
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use super::symbol::Symbol;
+
+#[allow(clippy::module_name_repetitions)]
+/// The symbols the optimized module must export, no more and no fewer
+#[derive(Debug, Clone)]
+pub struct PublicApi {
+    path: PathBuf,
+    symbols: Vec<Symbol>,
+}
+
+impl PublicApi {
+    /// Reads the symbols listed one per line in the file at `path`, skipping
+    /// empty lines and comments starting with `#`
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = std::fs::read_to_string(path)
+            .context(format!("Failed to read public API: {}", path.display()))?;
+
+        let mut symbols = file
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(Symbol::new)
+            .collect::<Vec<_>>();
+        symbols.sort();
+        symbols.dedup();
+
+        Ok(PublicApi {
+            path: path.to_owned(),
+            symbols,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    /// Compares the `exported` symbols to the public API
+    pub fn check(&self, exported: &[Symbol]) -> Result<(), ApiMismatch> {
+        let unexpected = exported
+            .iter()
+            .filter(|symbol| !self.symbols.contains(symbol))
+            .cloned()
+            .collect::<Vec<_>>();
+        let missing = self
+            .symbols
+            .iter()
+            .filter(|symbol| !exported.contains(symbol))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if unexpected.is_empty() && missing.is_empty() {
+            return Ok(());
+        }
+        Err(ApiMismatch {
+            path: self.path.clone(),
+            unexpected,
+            missing,
+        })
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, thiserror::Error)]
+/// The optimized module exports symbols that are not part of the public API,
+/// or misses some of it
+#[error("exported symbols differ from the public API {}:{}", .path.display(), Changes(self))]
+pub struct ApiMismatch {
+    pub path: PathBuf,
+    pub unexpected: Vec<Symbol>,
+    pub missing: Vec<Symbol>,
+}
+
+/// The unexpected and missing symbols one per line
+struct Changes<'a>(&'a ApiMismatch);
+
+impl Display for Changes<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for symbol in &self.0.unexpected {
+            write!(f, "\n  exported but not listed: {}", symbol.demangled())?;
+        }
+        for symbol in &self.0.missing {
+            write!(f, "\n  listed but not exported: {}", symbol.demangled())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+//! Just enough of the `ar` format to find the bitcode members of an archive
+//! and to write one
+
+pub const MAGIC: &[u8] = b"!<arch>\n";
+const HEADER_LEN: usize = 60;
+
+/// The magic bytes of raw bitcode and of the bitcode wrapper
+pub const BITCODE_MAGIC: [[u8; 4]; 2] = [*b"BC\xC0\xDE", [0xDE, 0xC0, 0x17, 0x0B]];
+
+/// Whether any member of the `ar` archive is bitcode, `None` if it is not an
+/// archive
+///
+/// The members of an rlib without bitcode, e.g. of a proc-macro shim or an
+/// empty crate, are only its metadata and native objects.
+pub fn has_bitcode(archive: &[u8]) -> Option<bool> {
+    bitcode_members(archive).map(|members| !members.is_empty())
+}
+
+/// The data of the bitcode members of the `ar` archive, `None` if it is not
+/// an archive
+pub fn bitcode_members(archive: &[u8]) -> Option<Vec<&[u8]>> {
+    let mut rest = archive.strip_prefix(MAGIC)?;
+    let mut members = Vec::new();
+
+    while rest.len() >= HEADER_LEN {
+        let (header, data) = rest.split_at(HEADER_LEN);
+        let size = std::str::from_utf8(&header[48..58])
+            .ok()?
+            .trim()
+            .parse::<usize>()
+            .ok()?;
+        let member = data.get(..size)?;
+
+        // BSD archives store long names at the start of the data
+        let name = std::str::from_utf8(&header[..16]).ok()?;
+        let skip = match name.strip_prefix("#1/") {
+            Some(len) => len.trim().parse::<usize>().ok()?,
+            None => 0,
+        };
+        let member = member.get(skip..)?;
+        if member
+            .get(..4)
+            .is_some_and(|magic| BITCODE_MAGIC.iter().any(|bitcode| magic == bitcode))
+        {
+            members.push(member);
+        }
+
+        // Members are aligned to 2 bytes
+        rest = data.get(size + size % 2..).unwrap_or_default();
+    }
+
+    Some(members)
+}
+
+/// An `ar` archive of `members` given by name, which are at most 15 bytes
+///
+/// The archive has no symbol table, `llvm-link` and the linker only look at
+/// the bitcode members.
+pub fn write(members: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut archive = MAGIC.to_vec();
+    for (name, data) in members {
+        debug_assert!(name.len() < 16, "long member names are not supported");
+        let header = format!(
+            "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+            format!("{name}/"),
+            0,
+            0,
+            0,
+            644,
+            data.len()
+        );
+        debug_assert_eq!(header.len(), HEADER_LEN);
+        archive.extend_from_slice(header.as_bytes());
+        archive.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            archive.push(b'\n');
+        }
+    }
+    archive
+}
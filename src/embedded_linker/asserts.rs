@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+
+use super::globals::{global_name, split_operands};
+
+/// The CUDA runtime function printing a failed assertion and aborting the
+/// kernel
+pub const ASSERT_FAIL: &str = "__assertfail";
+
+/// The prefix of the strings of the constant pool of the assertions
+const POOL_PREFIX: &str = "ptx_linker.assert.";
+
+/// The message of the panics whose message is not a constant string, e.g.
+/// formatted ones
+const DEFAULT_MESSAGE: &str = "panicked";
+
+/// The lowered calls to the panic functions of `core`
+#[derive(Debug, Default)]
+pub struct Lowered {
+    pub ir: String,
+    pub asserts: usize,
+    /// The lowered calls without a debug location, reported without their
+    /// file and line
+    pub unlocated: usize,
+}
+
+/// Replaces the calls to `core::panicking` in the textual IR of a module with
+/// calls to [`ASSERT_FAIL`]
+///
+/// The file, line and function are taken from the debug location of the call
+/// and the message from its constant `&str` argument, if it has one. All
+/// strings are stored once in a pool of private constants.
+pub fn lower(ir: &str) -> Lowered {
+    let strings = constant_strings(ir);
+    let metadata = metadata(ir);
+    let ptr = if ir.contains(" ptr ") || ir.contains("(ptr ") {
+        "ptr"
+    } else {
+        "i8*"
+    };
+
+    let mut pool = Pool::default();
+    let mut lowered = Lowered {
+        ir: String::with_capacity(ir.len()),
+        ..Lowered::default()
+    };
+
+    for line in ir.split_inclusive('\n') {
+        let Some(call) = panic_call(line) else {
+            lowered.ir.push_str(line);
+            continue;
+        };
+
+        let message = call
+            .message
+            .and_then(|(name, len)| {
+                let bytes = strings.get(name)?;
+                Some(String::from_utf8_lossy(&bytes[..len.min(bytes.len())]).into_owned())
+            })
+            .unwrap_or_else(|| default_message(call.function).to_owned());
+        let location = call.dbg.and_then(|dbg| location(&metadata, dbg));
+        let (file, line_number, function) = location.unwrap_or_else(|| {
+            lowered.unlocated += 1;
+            ("<unknown>".to_owned(), 0, "<unknown>".to_owned())
+        });
+
+        let dbg = call
+            .dbg
+            .map(|dbg| format!(", !dbg !{dbg}"))
+            .unwrap_or_default();
+        lowered.ir.push_str(&format!(
+            "  call void @{ASSERT_FAIL}({}, {}, i32 {line_number}, {}, i64 1){dbg}\n",
+            pool.operand(&message, ptr),
+            pool.operand(&file, ptr),
+            pool.operand(&function, ptr),
+        ));
+        lowered.asserts += 1;
+    }
+
+    if lowered.asserts > 0 {
+        if !lowered.ir.ends_with('\n') {
+            lowered.ir.push('\n');
+        }
+        lowered.ir.push('\n');
+        lowered.ir.push_str(&pool.globals);
+        let declared = ir
+            .lines()
+            .any(|line| line.starts_with("declare ") && line.contains(&format!("@{ASSERT_FAIL}(")));
+        if !declared {
+            lowered.ir.push_str(&format!(
+                "declare void @{ASSERT_FAIL}({ptr}, {ptr}, i32, {ptr}, i64)\n"
+            ));
+        }
+    }
+
+    lowered
+}
+
+/// A call to a panic function of `core`
+struct PanicCall<'a> {
+    /// The name of the panic function, e.g. `panic` or `assert_failed`
+    function: &'a str,
+    /// The global holding the message and its length
+    message: Option<(&'a str, usize)>,
+    /// The id of the `!dbg` location
+    dbg: Option<&'a str>,
+}
+
+/// The call to a function of `core::panicking` on `line`, if it is one
+fn panic_call(line: &str) -> Option<PanicCall<'_>> {
+    let trimmed = line.trim();
+    let call = trimmed
+        .strip_prefix("call void @")
+        .or_else(|| trimmed.strip_prefix("tail call void @"))?;
+    let (callee, rest) = global_name(call)?;
+    let function = match itanium_path(callee)?.as_slice() {
+        ["core", "panicking", function, ..] => *function,
+        _ => return None,
+    };
+
+    let arguments = rest.strip_prefix('(')?;
+    let operands = split_operands(&arguments[..arguments.rfind(')')?]);
+    let message = match operands.as_slice() {
+        [text, len, ..] => text
+            .find('@')
+            .and_then(|index| global_name(&text[index + 1..]))
+            .zip(
+                len.split_whitespace()
+                    .last()
+                    .and_then(|len| len.parse().ok()),
+            )
+            .map(|((name, _), len)| (name, len)),
+        _ => None,
+    };
+    let dbg = rest
+        .find("!dbg !")
+        .map(|index| &rest[index + "!dbg !".len()..])
+        .map(|dbg| &dbg[..dbg.find(|c: char| !c.is_ascii_digit()).unwrap_or(dbg.len())]);
+
+    Some(PanicCall {
+        function,
+        message,
+        dbg,
+    })
+}
+
+/// The message of a panic function called without a constant message
+fn default_message(function: &str) -> &'static str {
+    match function {
+        "assert_failed" => "assertion failed",
+        "panic_bounds_check" => "index out of bounds",
+        _ => DEFAULT_MESSAGE,
+    }
+}
+
+/// The path segments of a legacy Itanium mangled Rust symbol, without its hash
+fn itanium_path(symbol: &str) -> Option<Vec<&str>> {
+    let mut rest = symbol.strip_prefix("_ZN")?;
+    let mut segments = Vec::new();
+    while !rest.starts_with('E') {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let len = rest[..digits].parse::<usize>().ok()?;
+        segments.push(rest.get(digits..digits + len)?);
+        rest = &rest[digits + len..];
+    }
+    Some(segments)
+}
+
+/// The deduplicated strings referenced by the lowered assertions
+#[derive(Default)]
+struct Pool {
+    names: HashMap<String, String>,
+    globals: String,
+}
+
+impl Pool {
+    /// The pointer operand to the null terminated `text`
+    fn operand(&mut self, text: &str, ptr: &str) -> String {
+        let len = text.len() + 1;
+        let name = if let Some(name) = self.names.get(text) {
+            name.clone()
+        } else {
+            let name = format!("\"{POOL_PREFIX}{}\"", self.names.len());
+            self.globals.push_str(&format!(
+                "@{name} = private unnamed_addr constant [{len} x i8] c\"{}\\00\", align 1\n",
+                escape(text)
+            ));
+            self.names.insert(text.to_owned(), name.clone());
+            name
+        };
+
+        if ptr == "ptr" {
+            format!("ptr @{name}")
+        } else {
+            format!(
+                "i8* getelementptr inbounds ([{len} x i8], [{len} x i8]* @{name}, i64 0, i64 0)"
+            )
+        }
+    }
+}
+
+/// Escapes `text` for a `c"..."` constant
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_graphic() && byte != b'"' && byte != b'\\' || byte == b' ' {
+            escaped.push(char::from(byte));
+        } else {
+            escaped.push_str(&format!("\\{byte:02X}"));
+        }
+    }
+    escaped
+}
+
+/// The contents of the `c"..."` constants of the module by global name
+fn constant_strings(ir: &str) -> HashMap<&str, Vec<u8>> {
+    ir.lines()
+        .filter_map(|line| {
+            let (name, rest) = global_name(line.strip_prefix('@')?)?;
+            if !rest.contains(" constant ") {
+                return None;
+            }
+            let start = rest.find("c\"")? + 2;
+            let end = start + rest[start..].find('"')?;
+            Some((name, unescape(&rest[start..end])))
+        })
+        .collect()
+}
+
+/// The bytes of the contents of a `c"..."` constant
+fn unescape(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = (byte == b'\\')
+            .then(|| std::str::from_utf8(tail.get(..2)?).ok())
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        if let Some(escaped) = escaped {
+            bytes.push(escaped);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    bytes
+}
+
+/// The bodies of the metadata nodes of the module by id, e.g. `DIFile(...)`
+/// for `!3 = !DIFile(...)`
+fn metadata(ir: &str) -> HashMap<&str, &str> {
+    ir.lines()
+        .filter_map(|line| {
+            let (id, body) = line.strip_prefix('!')?.split_once(" = ")?;
+            let body = body.strip_prefix("distinct ").unwrap_or(body);
+            Some((id, body.strip_prefix('!')?))
+        })
+        .collect()
+}
+
+/// The file, line and function name of the `DILocation` `dbg`
+fn location(metadata: &HashMap<&str, &str>, dbg: &str) -> Option<(String, u32, String)> {
+    let location = metadata.get(dbg)?.strip_prefix("DILocation(")?;
+    let line = field(location, "line")?.parse().ok()?;
+
+    // Walk up the lexical blocks to the function
+    let mut scope = *metadata.get(field(location, "scope")?.strip_prefix('!')?)?;
+    for _ in 0..metadata.len() {
+        if scope.starts_with("DISubprogram(") {
+            break;
+        }
+        scope = metadata.get(field(scope, "scope")?.strip_prefix('!')?)?;
+    }
+
+    let file = metadata
+        .get(field(scope, "file")?.strip_prefix('!')?)?
+        .strip_prefix("DIFile(")?;
+    Some((
+        unquote(field(file, "filename")?),
+        line,
+        unquote(field(scope, "name")?),
+    ))
+}
+
+/// The value of the field `key` of the body of a metadata node
+fn field<'a>(body: &'a str, key: &str) -> Option<&'a str> {
+    let pattern = format!("{key}: ");
+    let mut offset = 0;
+    let start = loop {
+        let index = offset + body[offset..].find(&pattern)?;
+        if index == 0 || body[..index].ends_with(['(', ' ']) {
+            break index + pattern.len();
+        }
+        offset = index + pattern.len();
+    };
+
+    let value = &body[start..];
+    let end = if let Some(quoted) = value.strip_prefix('"') {
+        quoted.find('"')? + 2
+    } else {
+        value.find([',', ')']).unwrap_or(value.len())
+    };
+    Some(&value[..end])
+}
+
+/// The contents of a quoted metadata string
+fn unquote(text: &str) -> String {
+    let text = text.trim_matches('"');
+    String::from_utf8_lossy(&unescape(text)).into_owned()
+}
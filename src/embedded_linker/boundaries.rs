@@ -0,0 +1,131 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+
+use super::globals::global_name;
+use super::symbol::demangle;
+use crate::passes::kernels;
+
+/// The functions called by kernels that are kept out of line, so that
+/// profilers sampling call stacks attribute time to them
+#[derive(Debug, Default)]
+pub struct Report {
+    /// The functions with their number of IR instructions and the kernels
+    /// calling them, largest first
+    functions: Vec<(String, usize, Vec<String>)>,
+}
+
+impl Report {
+    /// Chooses the functions called directly by a kernel in the textual IR of
+    /// a module with at least `min_instructions` instructions
+    pub fn choose(ir: &str, min_instructions: usize) -> Self {
+        let kernels = kernels::defined(ir);
+        let functions = functions(ir);
+
+        let mut callers = BTreeMap::<&str, Vec<String>>::new();
+        for kernel in &kernels {
+            let Some(function) = functions.get(kernel.as_str()) else {
+                continue;
+            };
+            for &callee in &function.callees {
+                if !kernels.iter().any(|kernel| kernel == callee)
+                    && functions
+                        .get(callee)
+                        .is_some_and(|callee| callee.instructions >= min_instructions)
+                {
+                    callers.entry(callee).or_default().push(kernel.clone());
+                }
+            }
+        }
+
+        let mut functions = callers
+            .into_iter()
+            .map(|(name, kernels)| (name.to_owned(), functions[name].instructions, kernels))
+            .collect::<Vec<_>>();
+        functions.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Report { functions }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.functions.iter().map(|(name, ..)| name.as_str())
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} function(s) kept out of line", self.functions.len())?;
+        for (name, instructions, kernels) in &self.functions {
+            let kernels = kernels
+                .iter()
+                .map(|kernel| demangle(kernel))
+                .collect::<Vec<_>>();
+            writeln!(
+                f,
+                "  {instructions:>8} {}, called by {}",
+                demangle(name),
+                kernels.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A function defined in a module
+struct Function<'a> {
+    instructions: usize,
+    /// The functions called directly, without duplicates
+    callees: BTreeSet<&'a str>,
+}
+
+/// The functions defined in the textual IR of a module by name
+fn functions(ir: &str) -> BTreeMap<&str, Function<'_>> {
+    let mut functions = BTreeMap::new();
+    let mut current: Option<(&str, Function)> = None;
+
+    for line in ir.lines() {
+        if line.starts_with("define ") {
+            current = line
+                .find('@')
+                .and_then(|index| global_name(&line[index + 1..]))
+                .map(|(name, _)| {
+                    let function = Function {
+                        instructions: 0,
+                        callees: BTreeSet::new(),
+                    };
+                    (name, function)
+                });
+            continue;
+        }
+        let Some((name, function)) = &mut current else {
+            continue;
+        };
+        if line.starts_with('}') {
+            functions.extend(current.take());
+            continue;
+        }
+
+        let trimmed = line.trim();
+        let is_label = trimmed
+            .split_whitespace()
+            .next()
+            .is_some_and(|token| token.ends_with(':'));
+        if trimmed.is_empty() || trimmed.starts_with(';') || is_label {
+            continue;
+        }
+        function.instructions += 1;
+
+        let callee = trimmed
+            .find("call ")
+            .and_then(|index| Some(index + trimmed[index..].find('@')? + 1))
+            .and_then(|index| global_name(&trimmed[index..]))
+            .filter(|(callee, rest)| rest.starts_with('(') && callee != name);
+        if let Some((callee, _)) = callee {
+            function.callees.insert(callee);
+        }
+    }
+
+    functions
+}
@@ -0,0 +1,101 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+
+use super::limits::ARCHS;
+use super::tools::{self, ToolLocator};
+use super::{EmitKind, Target};
+
+/// The external tools the linker runs
+const LLVM_TOOLS: [&str; 7] = [
+    "llvm-link",
+    "opt",
+    "llc",
+    "llvm-nm",
+    "llvm-dis",
+    "llvm-as",
+    "llvm-lto",
+];
+pub const CUDA_TOOLS: [&str; 4] = ["ptxas", "nvlink", "cuobjdump", "fatbinary"];
+
+/// What the linker supports, for wrapper tools to populate their interfaces
+/// and validate their configuration without trial links
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Capabilities {
+    /// The version of the linker
+    pub version: &'static str,
+    /// The supported target triples
+    pub targets: Vec<&'static str>,
+    /// The target cpus whose limits are known, others are passed to `llc`
+    /// unchecked
+    pub archs: Vec<&'static str>,
+    /// The kinds of `--emit`
+    pub emit_kinds: Vec<&'static str>,
+    pub tools: Vec<Tool>,
+}
+
+/// An external tool and its version, if it was found
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Tool {
+    /// The path of the command, or its name if it was not found
+    pub name: String,
+    pub version: Option<String>,
+    /// Whether the tool is only needed by some features, like `llvm-lto` for
+    /// ThinLTO or the tools of the CUDA toolkit for cubins and fatbins
+    pub optional: bool,
+}
+
+/// Queries the capabilities of the linker, running every external tool once
+/// to detect its version
+///
+/// The LLVM tools are those of `llvm_major`, or of the LLVM version of
+/// `rustc`, in `tools_dir` if it is set.
+pub fn capabilities(llvm_major: Option<u32>, tools_dir: Option<&Path>) -> Capabilities {
+    let locator = ToolLocator::get(llvm_major, tools_dir)
+        .map_err(|err| tracing::debug!("unable to locate the LLVM tools: {err}"))
+        .ok();
+
+    let llvm_tools = LLVM_TOOLS.iter().map(|tool| {
+        let path = locator.map_or_else(|| PathBuf::from(tool), |locator| locator.path(tool));
+        (path.display().to_string(), *tool == "llvm-lto")
+    });
+    let cuda_tools = CUDA_TOOLS
+        .iter()
+        .map(|tool| (tools::cuda_tool(tool).display().to_string(), true));
+
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        targets: Target::value_variants()
+            .iter()
+            .map(|target| target.triple())
+            .collect(),
+        archs: ARCHS.to_vec(),
+        emit_kinds: EmitKind::ALL.iter().map(|kind| kind.name()).collect(),
+        tools: llvm_tools
+            .chain(cuda_tools)
+            .map(|(name, optional)| Tool {
+                version: tool_version(&name),
+                name,
+                optional,
+            })
+            .collect(),
+    }
+}
+
+/// The version printed by `tool --version`, e.g. `16.0.6` for
+/// `LLVM version 16.0.6` or `12.2.140` for
+/// `Cuda compilation tools, release 12.2, V12.2.140`
+pub fn tool_version(tool: impl AsRef<OsStr>) -> Option<String> {
+    let output = std::process::Command::new(tool)
+        .arg("--version")
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    let line = stdout
+        .lines()
+        .find(|line| line.contains("LLVM version") || line.contains("release"))?;
+    let version = line.split_whitespace().last()?;
+    Some(version.trim_start_matches('V').to_owned())
+}
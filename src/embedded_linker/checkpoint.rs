@@ -0,0 +1,111 @@
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use super::hash::Hasher;
+
+/// A stage of the link pipeline
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    /// Prune the inputs and merge them with `llvm-link`
+    Link,
+    /// Internalize, optimize and inline the merged module with `opt`
+    Optimize,
+    /// Generate code for every target cpu with `llc`
+    Compile,
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Stage::Link => "link",
+            Stage::Optimize => "optimize",
+            Stage::Compile => "compile",
+        })
+    }
+}
+
+/// A completed stage whose output can be reused
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Entry {
+    stage: Stage,
+    /// The hash of everything the stage output depends on
+    fingerprint: String,
+    /// The hash of the stage output
+    output: String,
+}
+
+/// The checkpoints of the completed stages, persisted with the intermediates
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct Checkpoints {
+    #[serde(skip)]
+    path: PathBuf,
+    stages: Vec<Entry>,
+}
+
+impl Checkpoints {
+    /// Loads the checkpoints at `path`, starting over if they are missing or
+    /// unreadable
+    pub fn load(path: PathBuf) -> Self {
+        let checkpoints = std::fs::read(&path)
+            .ok()
+            .and_then(|content| serde_json::from_slice::<Checkpoints>(&content).ok())
+            .unwrap_or_default();
+
+        Checkpoints {
+            path,
+            ..checkpoints
+        }
+    }
+
+    /// Checkpoints at `path` without any completed stages
+    pub fn new(path: PathBuf) -> Self {
+        Checkpoints {
+            path,
+            stages: Vec::new(),
+        }
+    }
+
+    /// Whether `stage` was completed with the same `fingerprint` and its
+    /// `output` is unchanged since
+    pub fn is_complete(&self, stage: Stage, fingerprint: &str, output: &Path) -> bool {
+        self.stages.iter().any(|entry| {
+            entry.stage == stage
+                && entry.fingerprint == fingerprint
+                && Hasher::default()
+                    .file(output)
+                    .is_ok_and(|hash| hash.finish() == entry.output)
+        })
+    }
+
+    /// Records that `stage` completed with `fingerprint`, writing `output`
+    ///
+    /// Checkpoints of later stages are dropped, as they depend on this one.
+    pub fn record(
+        &mut self,
+        stage: Stage,
+        fingerprint: String,
+        output: &Path,
+    ) -> anyhow::Result<()> {
+        let output = Hasher::default()
+            .file(output)
+            .context(format!("Failed to hash stage output: {}", output.display()))?
+            .finish();
+
+        if let Some(index) = self.stages.iter().position(|entry| entry.stage == stage) {
+            self.stages.truncate(index);
+        }
+        self.stages.push(Entry {
+            stage,
+            fingerprint,
+            output,
+        });
+
+        std::fs::write(&self.path, serde_json::to_vec_pretty(self)?).context(format!(
+            "Failed to write checkpoints: {}",
+            self.path.display()
+        ))
+    }
+}
@@ -0,0 +1,116 @@
+//! Inputs defining the same symbol
+//!
+//! Several inputs may define the same symbol differently, most commonly the
+//! intrinsics of `compiler_builtins` linked into more than one rlib. Linking
+//! them fails, unless the definitions of all but the first input are renamed.
+
+use super::globals::reference_spans;
+use super::symbol::Symbol;
+
+/// Whether a diagnostic of the linker reports a symbol defined by several
+/// inputs, e.g. `Linking globals named 'foo': symbol multiply defined!`
+pub fn is_multiply_defined(message: &str) -> bool {
+    message.contains("symbol multiply defined")
+}
+
+/// Whether a symbol of the type letter `kind` conflicts with another
+/// definition, unlike weak and common symbols
+pub fn is_strong(kind: u8) -> bool {
+    kind.is_ascii_uppercase() && !matches!(kind, b'W' | b'V' | b'C')
+}
+
+/// The name `symbol` of the input at `index` is renamed to
+pub fn renamed(symbol: &Symbol, index: usize) -> Symbol {
+    let mut renamed = symbol.as_bytes().to_vec();
+    renamed.extend_from_slice(format!(".ptx_linker.{index}").as_bytes());
+    Symbol::new(renamed)
+}
+
+/// Renames the global `symbol` to `renamed` in the textual IR of a module,
+/// both its definition and all references
+pub fn rename(ir: &str, symbol: &Symbol, renamed: &Symbol) -> String {
+    let name = ir_name(symbol.as_bytes());
+    let replacement = format!("@\"{}\"", ir_name(renamed.as_bytes()));
+
+    let mut output = String::with_capacity(ir.len());
+    for line in ir.split_inclusive('\n') {
+        let mut end = 0;
+        for (range, _) in reference_spans(line)
+            .into_iter()
+            .filter(|(_, reference)| *reference == name)
+        {
+            output.push_str(&line[end..range.start]);
+            output.push_str(&replacement);
+            end = range.end;
+        }
+        output.push_str(&line[end..]);
+    }
+    output
+}
+
+/// A name as written in textual IR without the quotes, with backslashes
+/// doubled and the bytes that are not printable escaped as `\XX`
+fn ir_name(name: &[u8]) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for &byte in name {
+        if byte == b'\\' {
+            escaped.push_str("\\\\");
+        } else if byte.is_ascii_graphic() && byte != b'"' || byte == b' ' {
+            escaped.push(char::from(byte));
+        } else {
+            escaped.push_str(&format!("\\{byte:02X}"));
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renames_plain_names() {
+        let ir = "define void @foo() {\n  call void @foo()\n  call void @foobar()\n  ret void\n}\n";
+        let renamed = rename(ir, &Symbol::new("foo"), &renamed(&Symbol::new("foo"), 1));
+        assert_eq!(
+            renamed,
+            "define void @\"foo.ptx_linker.1\"() {\n  call void @\"foo.ptx_linker.1\"()\n  \
+             call void @foobar()\n  ret void\n}\n"
+        );
+    }
+
+    #[test]
+    fn renames_escaped_names() {
+        // As printed by `llvm-dis` for `caf\xc3\xa9` and `a\b\xc3\xa9 c"`
+        let ir = "define void @\"caf\\C3\\A9\"() {\n  call void @\"a\\\\b\\C3\\A9 c\\22\"()\n  \
+                  ret void\n}\n";
+
+        let cafe = Symbol::new("café");
+        let renamed_cafe = rename(ir, &cafe, &renamed(&cafe, 2));
+        assert!(renamed_cafe.starts_with("define void @\"caf\\C3\\A9.ptx_linker.2\"()"));
+
+        let other = Symbol::new("a\\bé c\"");
+        let renamed_other = rename(ir, &other, &renamed(&other, 1));
+        assert!(renamed_other.contains("call void @\"a\\\\b\\C3\\A9 c\\22.ptx_linker.1\"()"));
+    }
+
+    #[test]
+    fn renames_non_utf8_names() {
+        let symbol = Symbol::new(b"caf\xe9".to_vec());
+        let ir = "@\"caf\\E9\" = global i32 0\n@x = global i32* @\"caf\\E9\"\n";
+        assert_eq!(
+            rename(ir, &symbol, &renamed(&symbol, 3)),
+            "@\"caf\\E9.ptx_linker.3\" = global i32 0\n@x = global i32* @\"caf\\E9.ptx_linker.3\"\n"
+        );
+    }
+
+    #[test]
+    fn leaves_string_literals() {
+        let ir = "@s = constant [5 x i8] c\"@foo\\00\"\n@foo = global i32 0\n";
+        let symbol = Symbol::new("foo");
+        assert_eq!(
+            rename(ir, &symbol, &renamed(&symbol, 1)),
+            "@s = constant [5 x i8] c\"@foo\\00\"\n@\"foo.ptx_linker.1\" = global i32 0\n"
+        );
+    }
+}
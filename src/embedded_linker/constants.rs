@@ -0,0 +1,300 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::globals::{global_name, reference_spans};
+
+/// The prefix of the arrays the constants are coalesced into, followed by
+/// their address space
+const ARRAY_PREFIX: &str = "ptx_linker.constants.";
+
+/// The small constant globals of a module coalesced into one array per
+/// address space
+#[derive(Debug, Default)]
+pub struct Coalesced {
+    pub ir: String,
+    /// The number of coalesced constants
+    pub constants: usize,
+    /// The number of arrays they were coalesced into
+    pub arrays: usize,
+    /// The size of the arrays including the padding between the constants
+    pub bytes: u64,
+}
+
+/// A constant global that can be coalesced
+struct Constant<'a> {
+    name: &'a str,
+    address_space: u32,
+    ty: &'a str,
+    initializer: &'a str,
+    layout: Layout,
+}
+
+/// Packs the local constant globals of at most `max_size` bytes in the
+/// textual IR of a module into a packed struct per address space and
+/// replaces their references with the addresses of its fields
+///
+/// Only constants without pointers in their type or initializer, section or
+/// debug information are coalesced, and none referenced by metadata or the
+/// `llvm.` globals like `llvm.used`. Each keeps its alignment.
+pub fn coalesce(ir: &str, max_size: u64) -> Coalesced {
+    let pinned = ir
+        .lines()
+        .filter(|line| line.starts_with('!') || line.starts_with("@llvm."))
+        .flat_map(|line| reference_spans(line).into_iter().map(|(_, name)| name))
+        .collect::<BTreeSet<_>>();
+
+    let mut spaces = BTreeMap::<u32, Vec<Constant>>::new();
+    for line in ir.lines() {
+        if let Some(constant) = constant(line) {
+            if constant.layout.size <= max_size && !pinned.contains(constant.name) {
+                spaces
+                    .entry(constant.address_space)
+                    .or_default()
+                    .push(constant);
+            }
+        }
+    }
+    spaces.retain(|_, constants| constants.len() > 1);
+    if spaces.is_empty() {
+        return Coalesced {
+            ir: ir.to_owned(),
+            ..Coalesced::default()
+        };
+    }
+
+    let opaque = ir.contains(" ptr ") || ir.contains("(ptr ");
+    let mut coalesced = Coalesced::default();
+    let mut arrays = String::new();
+    let mut replacements = BTreeMap::new();
+
+    for (address_space, mut constants) in spaces {
+        // Sorting by alignment avoids most of the padding
+        constants.sort_by(|a, b| b.layout.align.cmp(&a.layout.align));
+
+        let mut fields = Vec::new();
+        let mut initializers = Vec::new();
+        let mut offset = 0;
+        let mut field_indices = Vec::new();
+        for constant in &constants {
+            let padding = align_to(offset, constant.layout.align) - offset;
+            if padding > 0 {
+                fields.push(format!("[{padding} x i8]"));
+                initializers.push(format!("[{padding} x i8] zeroinitializer"));
+            }
+            field_indices.push(fields.len());
+            fields.push(constant.ty.to_owned());
+            initializers.push(format!("{} {}", constant.ty, constant.initializer));
+            offset += padding + constant.layout.size;
+        }
+
+        let name = format!("{ARRAY_PREFIX}{address_space}");
+        let ty = format!("<{{ {} }}>", fields.join(", "));
+        let space = if address_space == 0 {
+            String::new()
+        } else {
+            format!(" addrspace({address_space})")
+        };
+        let ptr = if opaque {
+            format!("ptr{space}")
+        } else {
+            format!("{ty}{space}*")
+        };
+        let align = constants[0].layout.align;
+        arrays.push_str(&format!(
+            "@\"{name}\" = private unnamed_addr{space} constant {ty} <{{ {} }}>, align {align}\n",
+            initializers.join(", ")
+        ));
+
+        for (constant, field) in constants.iter().zip(field_indices) {
+            replacements.insert(
+                constant.name,
+                format!("getelementptr inbounds ({ty}, {ptr} @\"{name}\", i32 0, i32 {field})"),
+            );
+        }
+        coalesced.constants += constants.len();
+        coalesced.arrays += 1;
+        coalesced.bytes += offset;
+    }
+
+    coalesced.ir.reserve(ir.len() + arrays.len());
+    for line in ir.split_inclusive('\n') {
+        if constant(line).is_some_and(|constant| replacements.contains_key(constant.name)) {
+            continue;
+        }
+        let mut end = 0;
+        for (range, name) in reference_spans(line) {
+            if let Some(replacement) = replacements.get(name) {
+                coalesced.ir.push_str(&line[end..range.start]);
+                coalesced.ir.push_str(replacement);
+                end = range.end;
+            }
+        }
+        coalesced.ir.push_str(&line[end..]);
+    }
+    if !coalesced.ir.ends_with('\n') {
+        coalesced.ir.push('\n');
+    }
+    coalesced.ir.push('\n');
+    coalesced.ir.push_str(&arrays);
+    coalesced
+}
+
+/// The local constant global defined on `line` if it can be coalesced, e.g.
+/// `@alloc_1 = private unnamed_addr constant <{ [4 x i8] }> <{ [4 x i8] c"abcd" }>, align 1`
+fn constant(line: &str) -> Option<Constant<'_>> {
+    let (name, rest) = global_name(line.strip_prefix('@')?)?;
+    let rest = rest.trim_start().strip_prefix('=')?;
+    let (properties, rest) = rest.split_once(" constant ")?;
+
+    let mut local = false;
+    let mut address_space = 0;
+    for word in properties.split_whitespace() {
+        match word {
+            "private" | "internal" => local = true,
+            "unnamed_addr" | "local_unnamed_addr" | "dso_local" => {}
+            _ => {
+                address_space = word
+                    .strip_prefix("addrspace(")?
+                    .strip_suffix(')')?
+                    .parse()
+                    .ok()?;
+            }
+        }
+    }
+    if !local || name.starts_with("llvm.") {
+        return None;
+    }
+
+    let (layout, after_type) = parse_type(rest)?;
+    let ty = rest[..rest.len() - after_type.len()].trim();
+    let mut parts = split_top_level(after_type).into_iter();
+    let initializer = parts.next()?.trim();
+    // Sections, comdats and attached metadata like debug information stay
+    let align = parts
+        .map(|part| part.trim().strip_prefix("align ")?.parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?
+        .into_iter()
+        .max();
+    if initializer.is_empty() || !reference_spans(initializer).is_empty() {
+        return None;
+    }
+
+    Some(Constant {
+        name,
+        address_space,
+        ty,
+        initializer,
+        layout: Layout {
+            size: layout.size,
+            align: align.unwrap_or(layout.align).max(layout.align),
+        },
+    })
+}
+
+/// The size and alignment of a type in memory
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+    size: u64,
+    align: u64,
+}
+
+/// The layout of the type at the start of `text` and the text after it, if
+/// it is made of integers, floats, arrays and structs on nvptx
+fn parse_type(text: &str) -> Option<(Layout, &str)> {
+    let text = text.trim_start();
+    let (layout, rest) = if let Some(rest) = text.strip_prefix('[') {
+        let (count, rest) = rest.trim_start().split_once(" x ")?;
+        let count = count.parse::<u64>().ok()?;
+        let (element, rest) = parse_type(rest)?;
+        let layout = Layout {
+            size: element.size.checked_mul(count)?,
+            align: element.align,
+        };
+        (layout, rest.trim_start().strip_prefix(']')?)
+    } else if let Some(rest) = text.strip_prefix("<{") {
+        parse_struct(rest, "}>", true)?
+    } else if let Some(rest) = text.strip_prefix('{') {
+        parse_struct(rest, "}", false)?
+    } else {
+        let end = text
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(text.len());
+        let bytes = match &text[..end] {
+            "i1" | "i8" => 1,
+            "i16" | "half" | "bfloat" => 2,
+            "i32" | "float" => 4,
+            "i64" | "double" => 8,
+            "i128" => 16,
+            _ => return None,
+        };
+        let layout = Layout {
+            size: bytes,
+            align: bytes,
+        };
+        (layout, &text[end..])
+    };
+
+    // Pointers to the type
+    let following = rest.trim_start();
+    if following.starts_with(['*', '(']) || following.starts_with("addrspace") {
+        return None;
+    }
+    Some((layout, rest))
+}
+
+/// The layout of the struct whose fields start `text`, up to `end`, and the
+/// text after it
+fn parse_struct<'a>(text: &'a str, end: &str, packed: bool) -> Option<(Layout, &'a str)> {
+    let mut layout = Layout { size: 0, align: 1 };
+    let mut rest = text.trim_start();
+    if let Some(after) = rest.strip_prefix(end) {
+        return Some((layout, after));
+    }
+
+    loop {
+        let (field, after) = parse_type(rest)?;
+        if !packed {
+            layout.size = align_to(layout.size, field.align);
+            layout.align = layout.align.max(field.align);
+        }
+        layout.size = layout.size.checked_add(field.size)?;
+
+        let after = after.trim_start();
+        if let Some(after) = after.strip_prefix(',') {
+            rest = after;
+        } else {
+            rest = after.strip_prefix(end)?;
+            break;
+        }
+    }
+    layout.size = align_to(layout.size, layout.align);
+    Some((layout, rest))
+}
+
+/// Rounds `offset` up to a multiple of `align`
+fn align_to(offset: u64, align: u64) -> u64 {
+    (offset + align - 1) / align * align
+}
+
+/// Splits `text` at the commas outside of any parentheses, brackets, braces
+/// or string literals
+fn split_top_level(text: &str) -> Vec<&str> {
+    let mut split = Vec::new();
+    let mut depth = 0usize;
+    let mut quoted = false;
+    let mut start = 0;
+    for (index, byte) in text.bytes().enumerate() {
+        match byte {
+            b'"' => quoted = !quoted,
+            _ if quoted => {}
+            b'(' | b'[' | b'{' | b'<' => depth += 1,
+            b')' | b']' | b'}' | b'>' => depth = depth.saturating_sub(1),
+            b',' if depth == 0 => {
+                split.push(&text[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    split.push(&text[start..]);
+    split
+}
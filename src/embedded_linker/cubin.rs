@@ -0,0 +1,230 @@
+//! The device code containers of the CUDA toolkit
+//!
+//! Only as much is parsed as is needed to list their contents: the symbols of
+//! a cubin, a 64-bit little endian ELF object, and the images of a fatbin.
+
+use super::rdc::{ELF_MAGIC, FATBIN_MAGIC};
+
+/// The `st_other` flag of the symbols of kernels
+const STO_CUDA_ENTRY: u8 = 0x10;
+/// The first ELF ABI version of cubins that stores the SM version in the
+/// second byte of the flags
+const ABI_VERSION_SM_SHIFTED: u8 = 8;
+/// The fatbin flag of images compressed by `fatbinary --compress`
+const FATBIN_COMPRESSED: u64 = 0x2000;
+
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+/// A cubin or fatbin could not be parsed
+pub enum Error {
+    #[error("not a 64-bit little endian ELF object")]
+    NotElf,
+    #[error("not a fatbin")]
+    NotFatbin,
+    #[error("truncated {0}")]
+    Truncated(&'static str),
+}
+
+/// The kernels and device functions of a cubin
+#[derive(Debug, Clone, Default)]
+pub struct Cubin {
+    /// The architecture, e.g. `sm_80`
+    pub arch: String,
+    pub kernels: Vec<String>,
+    /// The global functions that are not kernels
+    pub functions: Vec<String>,
+}
+
+impl Cubin {
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        let elf = Reader(bytes, "cubin");
+        if bytes.get(..4) != Some(&ELF_MAGIC[..]) || elf.u8(4)? != 2 || elf.u8(5)? != 1 {
+            return Err(Error::NotElf);
+        }
+
+        let flags = elf.u32(48)?;
+        let sm = if elf.u8(8)? >= ABI_VERSION_SM_SHIFTED {
+            (flags >> 8) & 0xff
+        } else {
+            flags & 0xff
+        };
+        let mut cubin = Cubin {
+            arch: format!("sm_{sm}"),
+            ..Cubin::default()
+        };
+
+        let sections = elf.usize(40)?;
+        let section_size = usize::from(elf.u16(58)?);
+        let section = |index: usize| {
+            let header = index
+                .checked_mul(section_size)
+                .and_then(|offset| offset.checked_add(sections))
+                .filter(|&header| header <= bytes.len())
+                .ok_or(Error::Truncated("cubin"))?;
+            Section::parse(&elf, header)
+        };
+        let section_count = usize::from(elf.u16(60)?);
+
+        for index in 0..section_count {
+            let header = section(index)?;
+            if header.kind != SHT_SYMTAB {
+                continue;
+            }
+
+            let strings = section(header.link)?;
+            let end = header
+                .offset
+                .checked_add(header.size)
+                .filter(|&end| end <= bytes.len())
+                .ok_or(Error::Truncated("cubin"))?;
+            for symbol in (header.offset..end).step_by(SYMBOL_SIZE) {
+                let info = elf.u8(symbol + 4)?;
+                let is_global_function = info & 0xf == STT_FUNC && info >> 4 != STB_LOCAL;
+                if !is_global_function {
+                    continue;
+                }
+                let name = elf
+                    .str(strings.offset.saturating_add(elf.u32(symbol)? as usize))?
+                    .to_owned();
+                if elf.u8(symbol + 5)? & STO_CUDA_ENTRY == 0 {
+                    cubin.functions.push(name);
+                } else {
+                    cubin.kernels.push(name);
+                }
+            }
+        }
+
+        cubin.kernels.sort();
+        cubin.functions.sort();
+        Ok(cubin)
+    }
+}
+
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+const STB_LOCAL: u8 = 0;
+const SYMBOL_SIZE: usize = 24;
+
+/// The fields of an ELF section header used here
+struct Section {
+    kind: u32,
+    offset: usize,
+    size: usize,
+    link: usize,
+}
+
+impl Section {
+    fn parse(elf: &Reader, header: usize) -> Result<Self, Error> {
+        Ok(Section {
+            kind: elf.u32(header + 4)?,
+            offset: elf.usize(header + 24)?,
+            size: elf.usize(header + 32)?,
+            link: elf.u32(header + 40)? as usize,
+        })
+    }
+}
+
+/// The kind of an image in a fatbin
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ImageKind {
+    Ptx,
+    Cubin,
+}
+
+/// An image in a fatbin
+#[derive(Debug, Clone)]
+pub struct Image<'a> {
+    pub kind: ImageKind,
+    /// The architecture, e.g. `sm_80`
+    pub arch: String,
+    /// The payload, which is padded with zeros
+    pub data: &'a [u8],
+    pub compressed: bool,
+}
+
+/// The images of a fatbin
+pub fn fatbin_images(bytes: &[u8]) -> Result<Vec<Image<'_>>, Error> {
+    let fatbin = Reader(bytes, "fatbin");
+    if bytes.get(..4) != Some(&FATBIN_MAGIC[..]) {
+        return Err(Error::NotFatbin);
+    }
+
+    let mut images = Vec::new();
+    let mut start = 0;
+    // Fatbins may be concatenated
+    while bytes.get(start..start + 4) == Some(&FATBIN_MAGIC[..]) {
+        let mut image = start + usize::from(fatbin.u16(start + 6)?);
+        // Offsets within the data are far from overflowing
+        let end = image
+            .checked_add(fatbin.usize(start + 8)?)
+            .filter(|&end| end <= bytes.len())
+            .ok_or(Error::Truncated("fatbin"))?;
+        while image < end {
+            let header_size = fatbin.u32(image + 4)? as usize;
+            if header_size == 0 {
+                return Err(Error::Truncated("fatbin"));
+            }
+            let size = fatbin.usize(image + 8)?;
+            let data = (image + header_size)
+                .checked_add(size)
+                .and_then(|data_end| bytes.get(image + header_size..data_end))
+                .ok_or(Error::Truncated("fatbin"))?;
+            images.push(Image {
+                kind: if fatbin.u16(image)? == 1 {
+                    ImageKind::Ptx
+                } else {
+                    ImageKind::Cubin
+                },
+                arch: format!("sm_{}", fatbin.u32(image + 28)?),
+                data,
+                compressed: fatbin.u64(image + 40)? & FATBIN_COMPRESSED != 0,
+            });
+            image += header_size + size;
+        }
+        start = end;
+    }
+
+    Ok(images)
+}
+
+/// Little endian fields at absolute offsets
+struct Reader<'a>(&'a [u8], &'static str);
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&self, offset: usize) -> Result<[u8; N], Error> {
+        offset
+            .checked_add(N)
+            .and_then(|end| self.0.get(offset..end))
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(Error::Truncated(self.1))
+    }
+
+    fn u8(&self, offset: usize) -> Result<u8, Error> {
+        Ok(self.bytes::<1>(offset)?[0])
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, Error> {
+        self.bytes(offset).map(u16::from_le_bytes)
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, Error> {
+        self.bytes(offset).map(u32::from_le_bytes)
+    }
+
+    fn u64(&self, offset: usize) -> Result<u64, Error> {
+        self.bytes(offset).map(u64::from_le_bytes)
+    }
+
+    fn usize(&self, offset: usize) -> Result<usize, Error> {
+        usize::try_from(self.u64(offset)?).map_err(|_| Error::Truncated(self.1))
+    }
+
+    /// The null terminated string at `offset`
+    fn str(&self, offset: usize) -> Result<&str, Error> {
+        let bytes = self.0.get(offset..).ok_or(Error::Truncated(self.1))?;
+        let end = bytes
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(Error::Truncated(self.1))?;
+        std::str::from_utf8(&bytes[..end]).map_err(|_| Error::Truncated(self.1))
+    }
+}
@@ -0,0 +1,152 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+/// How much debug information is kept with `--debug`
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, Copy, Default, Hash, Eq, PartialEq, clap::ValueEnum)]
+pub enum DebugInfo {
+    /// What the target supports, which is none on nvptx64
+    #[default]
+    Default,
+    /// All of it, fixed up for the DWARF the NVPTX backend and `ptxas` support
+    Full,
+    /// Only the line tables, for profilers like Nsight Compute to attribute
+    /// to source lines, like `-lineinfo` of nvcc
+    LineTables,
+}
+
+/// The version of the limited DWARF that `ptxas` reads
+const DWARF_VERSION: u32 = 2;
+
+/// The emission kinds of a compile unit with all debug information and with
+/// only the line tables
+const FULL_DEBUG: &str = "emissionKind: FullDebug";
+const LINE_TABLES_ONLY: &str = "emissionKind: LineTablesOnly";
+
+/// The debug information of a module fixed up for the NVPTX backend
+#[derive(Debug, Default)]
+pub struct FixedUp {
+    pub ir: String,
+    /// Whether the DWARF version was lowered
+    pub dwarf_version: bool,
+    /// The number of compile units reduced to line tables
+    pub line_tables: usize,
+    /// The number of dropped variable locations
+    pub dropped: usize,
+}
+
+/// Fixes up the debug information in the textual IR of a module for the
+/// NVPTX backend
+///
+/// The DWARF version is lowered to the one `ptxas` reads, and the locations
+/// of variables combined from several values are dropped, which limited DWARF
+/// cannot describe. With `line_tables_only` the compile units only emit line
+/// tables, otherwise everything else is kept.
+pub fn fix_up(ir: &str, line_tables_only: bool) -> FixedUp {
+    let mut fixed = FixedUp {
+        ir: String::with_capacity(ir.len()),
+        ..FixedUp::default()
+    };
+
+    for line in ir.split_inclusive('\n') {
+        if is_unsupported_location(line) {
+            fixed.dropped += 1;
+            continue;
+        }
+        match dwarf_version_flag(line) {
+            Some((prefix, version)) if version > DWARF_VERSION => {
+                fixed.ir.push_str(&format!("{prefix}{DWARF_VERSION}}}\n"));
+                fixed.dwarf_version = true;
+            }
+            _ if line_tables_only
+                && line.contains("!DICompileUnit(")
+                && line.contains(FULL_DEBUG) =>
+            {
+                fixed
+                    .ir
+                    .push_str(&line.replace(FULL_DEBUG, LINE_TABLES_ONLY));
+                fixed.line_tables += 1;
+            }
+            _ => fixed.ir.push_str(line),
+        }
+    }
+    fixed
+}
+
+/// Whether `line` is a call of a debug intrinsic locating a variable with
+/// several values
+fn is_unsupported_location(line: &str) -> bool {
+    let line = line.trim_start();
+    (line.starts_with("call void @llvm.dbg.") || line.starts_with("tail call void @llvm.dbg."))
+        && line.contains("!DIArgList(")
+}
+
+/// The text up to the value and the value of a `"Dwarf Version"` module flag
+/// like `!0 = !{i32 7, !"Dwarf Version", i32 4}`
+fn dwarf_version_flag(line: &str) -> Option<(&str, u32)> {
+    let start = line.find("!\"Dwarf Version\", i32 ")? + "!\"Dwarf Version\", i32 ".len();
+    let version = line[start..].trim_end().strip_suffix('}')?;
+    Some((&line[..start], version.parse().ok()?))
+}
+
+/// The path the debug information split off the PTX at `ptx_path` is written
+/// to, e.g. `kernel.ptx.debug`
+pub fn path(ptx_path: &Path) -> PathBuf {
+    let mut path = ptx_path.as_os_str().to_owned();
+    path.push(".debug");
+    PathBuf::from(path)
+}
+
+/// Moves the DWARF sections of the PTX at `ptx_path` into its [`path`]
+pub fn split(ptx_path: &Path) -> anyhow::Result<()> {
+    let ptx = std::fs::read_to_string(ptx_path)
+        .context(format!("Failed to read PTX: {}", ptx_path.display()))?;
+    let (code, sections) = split_sections(&ptx);
+
+    let debug_path = path(ptx_path);
+    tracing::info!(
+        "Moving {} bytes of debug information to {}",
+        sections.len(),
+        debug_path.display()
+    );
+
+    std::fs::write(&debug_path, sections).context(format!(
+        "Failed to write debug information: {}",
+        debug_path.display()
+    ))?;
+    std::fs::write(ptx_path, code).context(format!("Failed to write PTX: {}", ptx_path.display()))
+}
+
+/// Splits the `.section .debug_*` blocks holding the DWARF off a PTX module
+///
+/// Returns the PTX without them and the sections. The `.file` and `.loc`
+/// directives stay in the PTX, so that line information remains available.
+fn split_sections(ptx: &str) -> (String, String) {
+    let mut code = String::with_capacity(ptx.len());
+    let mut sections = String::new();
+    let mut in_section = false;
+    let mut depth = 0usize;
+
+    for line in ptx.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if !in_section && trimmed.starts_with(".section") && trimmed.contains(".debug_") {
+            in_section = true;
+        }
+
+        if !in_section {
+            code.push_str(line);
+            continue;
+        }
+
+        sections.push_str(line);
+        let opened = line.matches('{').count();
+        let closed = line.matches('}').count();
+        depth = (depth + opened).saturating_sub(closed);
+        if closed > 0 && depth == 0 {
+            in_section = false;
+        }
+    }
+
+    (code, sections)
+}
@@ -0,0 +1,520 @@
+use std::fmt::{Display, Formatter};
+
+use super::interrupt::Interrupted;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+    Remark,
+}
+
+/// Messages LLVM prints without a severity marker that are warnings
+const UNMARKED_WARNINGS: [&str; 2] = [
+    "is not a recognized processor",
+    "is not a recognized feature",
+];
+
+impl Severity {
+    const MARKERS: [(&'static str, Severity); 4] = [
+        ("error: ", Severity::Error),
+        ("warning: ", Severity::Warning),
+        ("note: ", Severity::Note),
+        ("remark: ", Severity::Remark),
+    ];
+
+    /// Severity words of the CUDA toolkit tools like `ptxas` and `nvlink`
+    const CUDA_MARKERS: [(&'static str, Severity); 4] = [
+        ("fatal", Severity::Error),
+        ("error", Severity::Error),
+        ("warning", Severity::Warning),
+        ("info", Severity::Note),
+    ];
+}
+
+/// A source location reported by an LLVM tool
+#[derive(Debug, Clone, Eq, PartialEq, serde::Serialize)]
+pub struct Location {
+    pub file: String,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.file)?;
+        if let Some(line) = self.line {
+            write!(f, ":{line}")?;
+        }
+        if let Some(column) = self.column {
+            write!(f, ":{column}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A single diagnostic parsed from the stderr of an external LLVM tool, or
+/// reported by the linker itself
+#[derive(Debug, Clone, Eq, PartialEq, serde::Serialize)]
+pub struct Diagnostic {
+    pub tool: String,
+    pub severity: Severity,
+    pub message: String,
+    pub location: Option<Location>,
+}
+
+impl Diagnostic {
+    /// Parses the stderr `output` of `tool` into diagnostics
+    ///
+    /// Indented lines, the source snippet following a located diagnostic and
+    /// stack dumps are appended to the message of the preceding diagnostic.
+    /// Repeated diagnostics are only reported once.
+    pub fn parse(tool: &str, output: &str) -> Vec<Diagnostic> {
+        let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+        for line in output.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if let Some(diagnostic) = Self::parse_line(tool, line) {
+                if !diagnostics.contains(&diagnostic) {
+                    diagnostics.push(diagnostic);
+                }
+                continue;
+            }
+
+            match diagnostics.last_mut() {
+                Some(diagnostic) if Self::is_continuation(diagnostic, line) => {
+                    diagnostic.message.push('\n');
+                    diagnostic.message.push_str(line);
+                }
+                _ => {
+                    let severity = if UNMARKED_WARNINGS
+                        .iter()
+                        .any(|warning| line.contains(warning))
+                    {
+                        Severity::Warning
+                    } else {
+                        Severity::Note
+                    };
+                    let diagnostic = Diagnostic {
+                        tool: tool.to_owned(),
+                        severity,
+                        message: line.to_owned(),
+                        location: None,
+                    };
+                    if !diagnostics.contains(&diagnostic) {
+                        diagnostics.push(diagnostic);
+                    }
+                }
+            }
+        }
+
+        diagnostics
+    }
+
+    fn is_continuation(previous: &Diagnostic, line: &str) -> bool {
+        line.starts_with(char::is_whitespace)
+            || line.starts_with('#')
+            || line.starts_with("Stack dump")
+            || line.starts_with("PLEASE ")
+            || previous.message.contains("Stack dump")
+            || (previous.location.is_some() && !previous.message.contains('\n'))
+    }
+
+    /// Parses lines like `opt: input.bc:3:5: error: message`
+    fn parse_line(tool: &str, line: &str) -> Option<Diagnostic> {
+        if let Some(diagnostic) = Self::parse_cuda_line(tool, line) {
+            return Some(diagnostic);
+        }
+
+        if let Some(message) = line.strip_prefix("LLVM ERROR: ") {
+            return Some(Diagnostic {
+                tool: tool.to_owned(),
+                severity: Severity::Error,
+                message: message.to_owned(),
+                location: None,
+            });
+        }
+
+        let (start, marker, severity) = Severity::MARKERS
+            .iter()
+            .filter_map(|&(marker, severity)| {
+                let start = if line.starts_with(marker) {
+                    0
+                } else {
+                    line.find(&format!(": {marker}"))? + 2
+                };
+                Some((start, marker, severity))
+            })
+            .min_by_key(|&(start, ..)| start)?;
+
+        let message = &line[start + marker.len()..];
+
+        // Tools sometimes wrap diagnostics of their own, e.g.
+        // `llc: error: llc: input.ll:1:1: error: message`
+        if let Some(inner) = Self::parse_line(tool, message) {
+            if inner.location.is_some() {
+                return Some(inner);
+            }
+        }
+
+        let location = line[..start]
+            .trim_end_matches(": ")
+            .rsplit(": ")
+            .next()
+            .filter(|segment| !segment.is_empty() && !Self::is_tool_name(tool, segment))
+            .map(Self::parse_location);
+
+        Some(Diagnostic {
+            tool: tool.to_owned(),
+            severity,
+            message: message.to_owned(),
+            location,
+        })
+    }
+
+    /// Parses lines like `ptxas input.ptx, line 3; error   : message`
+    fn parse_cuda_line(tool: &str, line: &str) -> Option<Diagnostic> {
+        let rest = line.strip_prefix(tool)?.strip_prefix(' ')?;
+        let (prefix, message) = rest.split_once(':')?;
+
+        let (location, marker) = prefix.trim_end().rsplit_once(' ').unwrap_or(("", prefix));
+        let severity = Severity::CUDA_MARKERS
+            .iter()
+            .find(|&&(cuda_marker, _)| cuda_marker == marker.trim())?
+            .1;
+
+        let location = location.trim().trim_end_matches(';');
+        let location = (!location.is_empty()).then(|| match location.split_once(", line ") {
+            Some((file, line)) => Location {
+                file: file.to_owned(),
+                line: line.parse().ok(),
+                column: None,
+            },
+            None => Location {
+                file: location.to_owned(),
+                line: None,
+                column: None,
+            },
+        });
+
+        Some(Diagnostic {
+            tool: tool.to_owned(),
+            severity,
+            message: message.trim().to_owned(),
+            location,
+        })
+    }
+
+    /// Whether `segment` is the (possibly versioned) name or path of `tool`
+    fn is_tool_name(tool: &str, segment: &str) -> bool {
+        segment
+            .rsplit(['/', '\\'])
+            .next()
+            .is_some_and(|name| name.starts_with(tool))
+    }
+
+    fn parse_location(segment: &str) -> Location {
+        let mut parts = segment.rsplitn(3, ':');
+        let last = parts.next().and_then(|part| part.parse().ok());
+        let second = parts.next();
+
+        match (second, last, parts.next()) {
+            (Some(line), Some(column), Some(file)) if line.parse::<u32>().is_ok() => Location {
+                file: file.to_owned(),
+                line: line.parse().ok(),
+                column: Some(column),
+            },
+            (Some(_), Some(line), _) => Location {
+                file: segment[..segment.rfind(':').unwrap_or(segment.len())].to_owned(),
+                line: Some(line),
+                column: None,
+            },
+            _ => Location {
+                file: segment.to_owned(),
+                line: None,
+                column: None,
+            },
+        }
+    }
+
+    /// Reports the diagnostic through `tracing`
+    pub fn emit(&self) {
+        let location = self
+            .location
+            .as_ref()
+            .map(|location| format!("{location}: "))
+            .unwrap_or_default();
+
+        match self.severity {
+            Severity::Error => tracing::error!("{}: {location}{}", self.tool, self.message),
+            Severity::Warning => tracing::warn!("{}: {location}{}", self.tool, self.message),
+            Severity::Note | Severity::Remark => {
+                tracing::info!("{}: {location}{}", self.tool, self.message);
+            }
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+/// Independent errors found in one run, reported together instead of only the
+/// first of them
+#[error("{} errors:{}", .0.len(), List(.0))]
+pub struct Errors(Vec<anyhow::Error>);
+
+impl Errors {
+    /// Fails with all of the `errors` unless there are none, with a single
+    /// one as is and with an interruption instead of any others
+    pub fn check(mut errors: Vec<anyhow::Error>) -> anyhow::Result<()> {
+        if let Some(index) = errors.iter().position(anyhow::Error::is::<Interrupted>) {
+            return Err(errors.swap_remove(index));
+        }
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(Errors(errors).into()),
+        }
+    }
+}
+
+/// The errors listed one per line, with their causes
+struct List<'a>(&'a [anyhow::Error]);
+
+impl Display for List<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for error in self.0 {
+            write!(f, "\n  {error:#}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Warnings of the external tools that fail the link
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Deny {
+    /// The warnings of all tools, given as `warnings`
+    Warnings,
+    /// The warnings of a single tool, e.g. `llc-warnings`
+    ToolWarnings(String),
+}
+
+impl Deny {
+    /// Whether the `diagnostic` is denied
+    pub fn matches(&self, diagnostic: &Diagnostic) -> bool {
+        diagnostic.severity == Severity::Warning
+            && match self {
+                Deny::Warnings => true,
+                Deny::ToolWarnings(tool) => &diagnostic.tool == tool,
+            }
+    }
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+/// The warnings to deny are unknown
+#[error("unknown warnings `{0}`, expected `warnings` or `TOOL-warnings`")]
+pub struct UnknownDeny(String);
+
+impl std::str::FromStr for Deny {
+    type Err = UnknownDeny;
+
+    fn from_str(s: &str) -> Result<Deny, UnknownDeny> {
+        match s.strip_suffix("-warnings") {
+            _ if s == "warnings" => Ok(Deny::Warnings),
+            Some(tool) if !tool.is_empty() => Ok(Deny::ToolWarnings(tool.to_owned())),
+            _ => Err(UnknownDeny(s.to_owned())),
+        }
+    }
+}
+
+/// How diagnostics are reported after a link
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum MessageFormat {
+    /// Only log diagnostics as they occur
+    #[default]
+    Human,
+    /// Additionally print every diagnostic as a JSON object on its own line to
+    /// stderr
+    Json,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(file: &str, line: Option<u32>, column: Option<u32>) -> Location {
+        Location {
+            file: file.to_owned(),
+            line,
+            column,
+        }
+    }
+
+    #[test]
+    fn parses_wrapped_llc_errors() {
+        let output = "llc: error: llc: bad.ll:2:7: error: value doesn't match function result \
+                      type 'void'\n  ret i32 0\n      ^\n";
+        assert_eq!(
+            Diagnostic::parse("llc", output),
+            [Diagnostic {
+                tool: "llc".to_owned(),
+                severity: Severity::Error,
+                message: "value doesn't match function result type 'void'\n  ret i32 0\n      ^"
+                    .to_owned(),
+                location: Some(location("bad.ll", Some(2), Some(7))),
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_located_errors() {
+        for tool in ["opt", "llvm-as"] {
+            let output = format!(
+                "{tool}: bad.ll:2:7: error: value doesn't match function result type 'void'\n  \
+                 ret i32 0\n      ^\n"
+            );
+            let diagnostics = Diagnostic::parse(tool, &output);
+            assert_eq!(diagnostics.len(), 1);
+            assert_eq!(diagnostics[0].severity, Severity::Error);
+            assert_eq!(
+                diagnostics[0].location,
+                Some(location("bad.ll", Some(2), Some(7)))
+            );
+            assert!(diagnostics[0].message.ends_with("\n  ret i32 0\n      ^"));
+        }
+    }
+
+    #[test]
+    fn parses_versioned_tool_paths() {
+        let diagnostics = Diagnostic::parse(
+            "llc",
+            "/usr/lib/llvm-18/bin/llc-18: warning: ignoring debug info with an invalid version\n",
+        );
+        assert_eq!(
+            diagnostics,
+            [Diagnostic {
+                tool: "llc".to_owned(),
+                severity: Severity::Warning,
+                message: "ignoring debug info with an invalid version".to_owned(),
+                location: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_llvm_link_errors() {
+        assert_eq!(
+            Diagnostic::parse(
+                "llvm-link",
+                "error: Linking globals named 'f': symbol multiply defined!\n"
+            ),
+            [Diagnostic {
+                tool: "llvm-link".to_owned(),
+                severity: Severity::Error,
+                message: "Linking globals named 'f': symbol multiply defined!".to_owned(),
+                location: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_unmarked_warnings_once() {
+        let line = "'sm_99' is not a recognized processor for this target (ignoring processor)\n";
+        let feature = "'+bogus' is not a recognized feature for this target (ignoring feature)\n";
+        let output = format!("{}{}", line.repeat(6), feature.repeat(3));
+
+        let diagnostics = Diagnostic::parse("llc", &output);
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics
+            .iter()
+            .all(|diagnostic| diagnostic.severity == Severity::Warning
+                && diagnostic.location.is_none()));
+        assert_eq!(diagnostics[0].message, line.trim_end());
+        assert_eq!(diagnostics[1].message, feature.trim_end());
+    }
+
+    #[test]
+    fn appends_stack_dumps() {
+        let output = "LLVM ERROR: Symbol name with unsupported characters\n\
+            PLEASE submit a bug report to https://github.com/llvm/llvm-project/issues/ and \
+            include the crash backtrace.\n\
+            Stack dump:\n\
+            0.\tProgram arguments: llc -march=nvptx64 esc.ll -o -\n\
+            1.\tRunning pass 'Function Pass Manager' on module 'esc.ll'.\n\
+            2.\tRunning pass 'NVPTX Assembly Printer' on function '@\"a\\\\b\"'\n \
+            #0 0x00007f250aaa5291 llvm::sys::PrintStackTrace(llvm::raw_ostream&, int) \
+            (/lib/x86_64-linux-gnu/libLLVM-14.so.1+0xea5291)\n \
+            #1 0x00007f250aaa2fbe llvm::sys::RunSignalHandlers() \
+            (/lib/x86_64-linux-gnu/libLLVM-14.so.1+0xea2fbe)\n";
+
+        let diagnostics = Diagnostic::parse("llc", output);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert!(diagnostics[0]
+            .message
+            .starts_with("Symbol name with unsupported characters\nPLEASE submit"));
+        assert!(diagnostics[0].message.contains("\n2.\tRunning pass 'NVPTX"));
+        assert!(diagnostics[0].message.ends_with(
+            "RunSignalHandlers() \
+            (/lib/x86_64-linux-gnu/libLLVM-14.so.1+0xea2fbe)"
+        ));
+    }
+
+    #[test]
+    fn parses_cuda_locations() {
+        let output = "ptxas /tmp/kernel.ptx, line 12; error   : Unknown symbol 'foo'\n\
+            ptxas /tmp/kernel.ptx, line 30; warning : Double is not supported. Demoting to float\n\
+            ptxas fatal   : Ptx assembly aborted due to errors\n";
+        assert_eq!(
+            Diagnostic::parse("ptxas", output),
+            [
+                Diagnostic {
+                    tool: "ptxas".to_owned(),
+                    severity: Severity::Error,
+                    message: "Unknown symbol 'foo'".to_owned(),
+                    location: Some(location("/tmp/kernel.ptx", Some(12), None)),
+                },
+                Diagnostic {
+                    tool: "ptxas".to_owned(),
+                    severity: Severity::Warning,
+                    message: "Double is not supported. Demoting to float".to_owned(),
+                    location: Some(location("/tmp/kernel.ptx", Some(30), None)),
+                },
+                Diagnostic {
+                    tool: "ptxas".to_owned(),
+                    severity: Severity::Error,
+                    message: "Ptx assembly aborted due to errors".to_owned(),
+                    location: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_cuda_infos() {
+        let output = "ptxas info    : 0 bytes gmem\n\
+            ptxas info    : Compiling entry function 'kernel' for 'sm_80'\n";
+        let diagnostics = Diagnostic::parse("ptxas", output);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].severity, Severity::Note);
+        assert_eq!(diagnostics[0].message, "0 bytes gmem");
+        assert_eq!(
+            diagnostics[1].message,
+            "Compiling entry function 'kernel' for 'sm_80'"
+        );
+
+        let diagnostics = Diagnostic::parse(
+            "nvlink",
+            "nvlink error   : Undefined reference to 'bar' in '/tmp/kernel.cubin'\n",
+        );
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(
+            diagnostics[0].message,
+            "Undefined reference to 'bar' in '/tmp/kernel.cubin'"
+        );
+        assert_eq!(diagnostics[0].location, None);
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use super::globals::global_name;
+use super::symbol::demangle;
+
+/// The functions that differ between two modules
+#[derive(Debug, Clone, Default)]
+pub struct Diff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl Diff {
+    /// Compares the function definitions in the textual IR of two modules
+    ///
+    /// Debug locations, metadata and attribute group numbers are ignored, as
+    /// they change with unrelated code.
+    pub fn between(previous: &str, current: &str) -> Self {
+        let previous = functions(previous);
+        let current = functions(current);
+
+        let mut diff = Diff::default();
+        for (name, body) in &current {
+            match previous.get(name) {
+                None => diff.added.push((*name).to_owned()),
+                Some(previous) if previous != body => diff.changed.push((*name).to_owned()),
+                Some(_) => {}
+            }
+        }
+        diff.removed = previous
+            .keys()
+            .filter(|name| !current.contains_key(*name))
+            .map(|name| (*name).to_owned())
+            .collect();
+
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl Display for Diff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no functions changed");
+        }
+
+        let mut first = true;
+        for (what, names) in [
+            ("changed", &self.changed),
+            ("added", &self.added),
+            ("removed", &self.removed),
+        ] {
+            if names.is_empty() {
+                continue;
+            }
+            if !first {
+                write!(f, "; ")?;
+            }
+            first = false;
+            let names = names.iter().map(|name| demangle(name)).collect::<Vec<_>>();
+            write!(f, "{} {what}: {}", names.len(), names.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The normalized bodies of the functions defined in `ir` by name
+fn functions(ir: &str) -> BTreeMap<&str, String> {
+    let mut functions = BTreeMap::new();
+    let mut current: Option<(&str, String)> = None;
+
+    for line in ir.lines() {
+        if let Some((_, body)) = &mut current {
+            if line == "}" {
+                let (name, body) = current.take().expect("inside a function");
+                functions.insert(name, body);
+            } else {
+                body.push_str(&normalize(line));
+                body.push('\n');
+            }
+            continue;
+        }
+
+        if !line.starts_with("define ") {
+            continue;
+        }
+        let Some((name, _)) = line
+            .find('@')
+            .and_then(|index| global_name(&line[index + 1..]))
+        else {
+            continue;
+        };
+        let mut body = normalize(line);
+        body.push('\n');
+        current = Some((name, body));
+    }
+
+    functions
+}
+
+/// Removes metadata attachments and attribute group references from a line
+fn normalize(line: &str) -> String {
+    let mut normalized = String::with_capacity(line.len());
+    let mut words = line.split(' ').peekable();
+
+    while let Some(word) = words.next() {
+        let word = word.trim_end_matches(',');
+        // `!dbg !12`, `!range !3` and the like, as well as `#0`
+        if word.starts_with('!') && word.len() > 1 {
+            if words.peek().is_some_and(|next| next.starts_with('!')) {
+                words.next();
+            }
+            continue;
+        }
+        if word.strip_prefix('#').is_some_and(|group| {
+            !group.is_empty() && group.bytes().all(|byte| byte.is_ascii_digit())
+        }) {
+            continue;
+        }
+        if !normalized.is_empty() {
+            normalized.push(' ');
+        }
+        normalized.push_str(word);
+    }
+
+    normalized
+}
@@ -0,0 +1,101 @@
+use super::globals::global_name;
+
+/// The kernel synthesized by [`DtorPolicy::FiniKernel`]
+pub const FINI_KERNEL: &str = "__ptx_linker_fini";
+
+const GLOBAL_DTORS: &str = "llvm.global_dtors";
+
+/// How destructors registered in `llvm.global_dtors` are handled, which the
+/// NVPTX backend cannot run
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub enum DtorPolicy {
+    /// Remove the registrations with a warning, the destructors never run
+    #[default]
+    Drop,
+    /// Fail the link
+    Error,
+    /// Remove the registrations and call the destructors from a
+    /// `__ptx_linker_fini` kernel instead, which the host launches before
+    /// unloading the module
+    ///
+    /// The kernel is emitted even without any destructors, so that it can
+    /// always be launched.
+    FiniKernel,
+}
+
+/// Whether the `bitcode` may register destructors
+///
+/// The symbol names of a module are stored verbatim in its string table, so a
+/// module without the name certainly has no destructors.
+pub fn may_register(bitcode: &[u8]) -> bool {
+    bitcode
+        .windows(GLOBAL_DTORS.len())
+        .any(|window| window == GLOBAL_DTORS.as_bytes())
+}
+
+/// Removes the `llvm.global_dtors` registrations from the textual IR of a
+/// module, optionally calling them from a [`FINI_KERNEL`] instead
+///
+/// Returns the new IR and the names of the destructors.
+pub fn lower(ir: &str, fini_kernel: bool) -> (String, Vec<String>) {
+    let mut lowered = String::with_capacity(ir.len());
+    let mut dtors = Vec::new();
+
+    for line in ir.split_inclusive('\n') {
+        match line
+            .strip_prefix('@')
+            .and_then(|line| line.strip_prefix(GLOBAL_DTORS))
+        {
+            Some(rest) if rest.trim_start().starts_with('=') => dtors = registrations(rest),
+            _ => lowered.push_str(line),
+        }
+    }
+
+    // Destructors run from the highest to the lowest priority
+    dtors.sort_by_key(|(priority, _, _)| std::cmp::Reverse(*priority));
+
+    if fini_kernel {
+        if !lowered.ends_with('\n') {
+            lowered.push('\n');
+        }
+        lowered.push_str(&format!("\ndefine ptx_kernel void @{FINI_KERNEL}() {{\n"));
+        for (_, reference, _) in &dtors {
+            lowered.push_str(&format!("  call void {reference}()\n"));
+        }
+        lowered.push_str("  ret void\n}\n");
+    }
+
+    let names = dtors.into_iter().map(|(_, _, name)| name).collect();
+    (lowered, names)
+}
+
+/// The priority, the reference as written in the IR and the name of every
+/// destructor in the initializer of `llvm.global_dtors`, e.g.
+/// `[{ i32, ptr, ptr } { i32 65535, ptr @fini, ptr null }]`
+fn registrations(initializer: &str) -> Vec<(u32, &str, String)> {
+    let mut registrations = Vec::new();
+
+    for (index, _) in initializer.match_indices("{ i32 ") {
+        let entry = &initializer[index + "{ i32 ".len()..];
+        let digits = entry
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(entry.len());
+        let Ok(priority) = entry[..digits].parse::<u32>() else {
+            // The element type `{ i32, ptr, ptr }`
+            continue;
+        };
+        let Some(reference) = entry[digits..].strip_prefix(", ptr @") else {
+            // A null destructor
+            continue;
+        };
+        let Some((name, rest)) = global_name(reference) else {
+            continue;
+        };
+
+        let start = index + "{ i32 ".len() + digits + ", ptr ".len();
+        let end = initializer.len() - rest.len();
+        registrations.push((priority, &initializer[start..end], name.to_owned()));
+    }
+
+    registrations
+}
@@ -0,0 +1,122 @@
+use std::path::PathBuf;
+
+#[allow(clippy::module_name_repetitions)]
+/// A kind of output the linker can emit
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum EmitKind {
+    /// PTX assembly, or a cubin in relocatable device code mode
+    Asm,
+    /// Cubins for all target cpus and a fallback PTX, see [`crate::bundle`]
+    Bundle,
+    /// A cubin assembled from the PTX with `ptxas` for every target cpu
+    Cubin,
+    /// A fatbin of the cubins for all target cpus and a fallback PTX, built
+    /// with `fatbinary`
+    Fatbin,
+    /// A host object embedding the PTX or the bundle, see [`crate::embed`]
+    Object,
+    /// The symbols left externally visible, for auditing
+    KeepSymbols,
+    /// The named metadata kept with `--gc-keep-exported-metadata` as JSON
+    Metadata,
+    /// An archive of the optimized bitcode, linked by later links like an rlib
+    /// instead of the inputs
+    DeviceRlib,
+    /// The optimized module as textual IR
+    LlvmIr,
+    /// The optimized module as bitcode
+    LlvmBc,
+    /// A Rust module with the names, launch bounds and parameter structs of
+    /// the kernels, for host crates
+    RustBindings,
+}
+
+impl EmitKind {
+    pub const ALL: [EmitKind; 11] = [
+        EmitKind::Asm,
+        EmitKind::Bundle,
+        EmitKind::Cubin,
+        EmitKind::Fatbin,
+        EmitKind::Object,
+        EmitKind::KeepSymbols,
+        EmitKind::Metadata,
+        EmitKind::DeviceRlib,
+        EmitKind::LlvmIr,
+        EmitKind::LlvmBc,
+        EmitKind::RustBindings,
+    ];
+
+    /// The name of the kind in `--emit`
+    pub fn name(self) -> &'static str {
+        match self {
+            EmitKind::Asm => "asm",
+            EmitKind::Bundle => "bundle",
+            EmitKind::Cubin => "cubin",
+            EmitKind::Fatbin => "fatbin",
+            EmitKind::Object => "object",
+            EmitKind::KeepSymbols => "keep-symbols",
+            EmitKind::Metadata => "metadata",
+            EmitKind::DeviceRlib => "device-rlib",
+            EmitKind::LlvmIr => "llvm-ir",
+            EmitKind::LlvmBc => "llvm-bc",
+            EmitKind::RustBindings => "rust-bindings",
+        }
+    }
+
+    /// Whether the output is written from the optimized module, without code
+    /// generation
+    pub fn is_bitcode(self) -> bool {
+        matches!(
+            self,
+            EmitKind::DeviceRlib | EmitKind::LlvmIr | EmitKind::LlvmBc
+        )
+    }
+
+    /// The file extension of the output if it is not written to `-o`
+    pub fn extension(self) -> &'static str {
+        match self {
+            EmitKind::Asm => "ptx",
+            EmitKind::Bundle => "ptxbundle",
+            EmitKind::Cubin => "cubin",
+            EmitKind::Fatbin => "fatbin",
+            EmitKind::Object => "o",
+            EmitKind::KeepSymbols => "symbols",
+            EmitKind::Metadata => "metadata.json",
+            EmitKind::DeviceRlib => "rlib",
+            EmitKind::LlvmIr => "ll",
+            EmitKind::LlvmBc => "bc",
+            EmitKind::RustBindings => "rs",
+        }
+    }
+}
+
+/// An output to emit, optionally written to an explicit path
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Emit {
+    pub kind: EmitKind,
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+/// The emit kind is not supported by this linker
+#[error("unknown emit kind `{0}`")]
+pub struct UnknownEmitKind(String);
+
+impl std::str::FromStr for Emit {
+    type Err = UnknownEmitKind;
+
+    /// Parses `kind` or `kind=path`
+    fn from_str(s: &str) -> Result<Emit, UnknownEmitKind> {
+        let (kind, path) = match s.split_once('=') {
+            Some((kind, path)) => (kind, Some(PathBuf::from(path))),
+            None => (s, None),
+        };
+
+        let kind = EmitKind::ALL
+            .into_iter()
+            .find(|known| known.name() == kind)
+            .ok_or_else(|| UnknownEmitKind(kind.to_owned()))?;
+
+        Ok(Emit { kind, path })
+    }
+}
@@ -0,0 +1,31 @@
+#[derive(Debug, Clone, thiserror::Error)]
+/// A tool does not support a target cpu, e.g. as it is older than the cpu
+#[error("{tool} does not support the target cpu {cpu}")]
+pub struct CpuRejected {
+    pub tool: &'static str,
+    pub cpu: String,
+}
+
+impl CpuRejected {
+    /// Fails if the stderr of `tool` says that it rejected `cpu`
+    ///
+    /// `llc` only warns that `'sm_100' is not a recognized processor` and
+    /// emits PTX that cannot be assembled, `ptxas` and `nvlink` fail for
+    /// unknown cpus and for PTX of a newer ISA version than they support.
+    pub fn check(tool: &'static str, cpu: &str, stderr: &[u8]) -> Result<(), CpuRejected> {
+        let stderr = String::from_utf8_lossy(stderr);
+        if stderr.contains(&format!("'{cpu}'")) || stderr.contains("Unsupported .version") {
+            return Err(CpuRejected {
+                tool,
+                cpu: cpu.to_owned(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether the diagnostic `message` is the warning of `llc` about a cpu it
+    /// does not know, which falls back instead of being denied
+    pub fn is_unknown_cpu(message: &str) -> bool {
+        message.contains("is not a recognized processor")
+    }
+}
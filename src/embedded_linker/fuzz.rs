@@ -0,0 +1,46 @@
+//! Entry points for fuzzing the parsers of the inputs and artifacts
+//!
+//! Each takes arbitrary bytes and runs them through one parser, discarding
+//! the result. None may panic or hang on malformed input, which the harnesses
+//! in `fuzz/` check with `cargo fuzz`.
+
+use super::inputs::InputKind;
+use super::{archive, cubin, nm, ptx};
+use crate::bundle::Bundle;
+use crate::manifest::Manifest;
+
+/// Detects the kind of an input from its start
+pub fn input_kind(data: &[u8]) {
+    let _ = InputKind::from_start(data);
+}
+
+/// Finds the bitcode members of an archive like an rlib
+pub fn archive(data: &[u8]) {
+    let _ = archive::bitcode_members(data);
+}
+
+/// Parses the structure of a PTX module
+pub fn ptx(data: &[u8]) {
+    let _ = ptx::parse(&String::from_utf8_lossy(data));
+}
+
+/// Parses a cubin and the images of a fatbin
+pub fn cubin(data: &[u8]) {
+    let _ = cubin::Cubin::parse(data);
+    let _ = cubin::fatbin_images(data);
+}
+
+/// Parses a bundle of cubins and PTX
+pub fn bundle(data: &[u8]) {
+    let _ = Bundle::parse(data);
+}
+
+/// Parses a manifest of the outputs of a link
+pub fn manifest(data: &[u8]) {
+    let _ = Manifest::from_slice(data);
+}
+
+/// Parses the symbols listed by `llvm-nm`
+pub fn nm(data: &[u8]) {
+    let _ = nm::parse(data);
+}
@@ -0,0 +1,251 @@
+use std::fmt::{Display, Formatter};
+
+use super::symbol::demangle;
+
+/// How the device code accesses a global variable
+#[derive(Debug, Default, Clone, Copy)]
+struct Access {
+    read: bool,
+    written: bool,
+    /// Whether its address is used other than by loads and stores, e.g.
+    /// passed to a function, so that its accesses cannot be known
+    escaped: bool,
+}
+
+/// The mutable global variables defined in an LLVM IR module that the device
+/// code never reads or never writes
+///
+/// Accesses by the host, e.g. through `cuMemcpyFromSymbol`, are not visible
+/// in the module.
+#[derive(Debug, Default)]
+pub struct Report {
+    globals: Vec<(String, Access)>,
+}
+
+impl Report {
+    /// Analyzes the textual IR of a module as printed by `llvm-dis`
+    pub fn from_ir(ir: &str) -> Self {
+        let lines = ir.lines().map(strip_comment).collect::<Vec<_>>();
+
+        let mut globals = lines
+            .iter()
+            .filter_map(|line| mutable_global(line))
+            .filter(|name| !name.starts_with("llvm."))
+            .map(|name| (name.to_owned(), Access::default()))
+            .collect::<Vec<_>>();
+
+        let mut in_function = false;
+        for line in lines.iter().map(|line| line.trim()) {
+            if line.starts_with("define ") {
+                in_function = true;
+                continue;
+            }
+            if in_function && line == "}" {
+                in_function = false;
+                continue;
+            }
+            if line.starts_with('!') || line.starts_with("declare ") {
+                continue;
+            }
+
+            let mut access = |name: &str, update: fn(&mut Access)| {
+                if let Some((_, access)) = globals.iter_mut().find(|(global, _)| global == name) {
+                    update(access);
+                }
+            };
+
+            if !in_function {
+                // Referenced by the initializer of another global
+                let references = line.find('=').map_or("", |index| &line[index + 1..]);
+                for name in references_in(references) {
+                    access(name, |access| access.escaped = true);
+                }
+                continue;
+            }
+
+            let instruction = line
+                .strip_prefix('%')
+                .and_then(|line| Some(line[line.find(" = ")? + 3..].trim_start()))
+                .unwrap_or(line);
+            let opcode = instruction.split_whitespace().next().unwrap_or_default();
+            let operands = split_operands(&instruction[opcode.len()..]);
+
+            match opcode {
+                "store" => {
+                    for name in references_in(operands.first().copied().unwrap_or_default()) {
+                        access(name, |access| access.escaped = true);
+                    }
+                    for name in references_in(operands.get(1).copied().unwrap_or_default()) {
+                        access(name, |access| access.written = true);
+                    }
+                }
+                "load" => {
+                    for name in references_in(operands.get(1).copied().unwrap_or_default()) {
+                        access(name, |access| access.read = true);
+                    }
+                }
+                "atomicrmw" | "cmpxchg" => {
+                    for name in references_in(instruction) {
+                        access(name, |access| {
+                            access.read = true;
+                            access.written = true;
+                        });
+                    }
+                }
+                _ => {
+                    for name in references_in(instruction) {
+                        access(name, |access| access.escaped = true);
+                    }
+                }
+            }
+        }
+
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        Report { globals }
+    }
+
+    fn matching(&self, filter: fn(Access) -> bool) -> impl Iterator<Item = &str> {
+        self.globals
+            .iter()
+            .filter(move |(_, access)| !access.escaped && filter(*access))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// The globals that are written but never read, e.g. stale debug counters
+    pub fn write_only(&self) -> impl Iterator<Item = &str> {
+        self.matching(|access| access.written && !access.read)
+    }
+
+    /// The globals that are read but never written, which could be constant
+    pub fn read_only(&self) -> impl Iterator<Item = &str> {
+        self.matching(|access| access.read && !access.written)
+    }
+
+    /// The globals that are never accessed
+    pub fn unused(&self) -> impl Iterator<Item = &str> {
+        self.matching(|access| !access.read && !access.written)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.write_only().next().is_none()
+            && self.read_only().next().is_none()
+            && self.unused().next().is_none()
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} mutable globals", self.globals.len())?;
+        for (title, globals) in [
+            (
+                "written but never read",
+                self.write_only().collect::<Vec<_>>(),
+            ),
+            ("read but never written", self.read_only().collect()),
+            ("never accessed", self.unused().collect()),
+        ] {
+            if !globals.is_empty() {
+                writeln!(f, "  {title}:")?;
+                for name in globals {
+                    writeln!(f, "    {}", demangle(name))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Removes a trailing `; comment` outside of string literals
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (index, byte) in line.bytes().enumerate() {
+        match byte {
+            b'"' => quoted = !quoted,
+            b';' if !quoted => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// The name of the mutable global defined on `line`, e.g.
+/// `@counter = addrspace(1) global i32 0, align 4`
+fn mutable_global(line: &str) -> Option<&str> {
+    let (name, rest) = global_name(line.strip_prefix('@')?)?;
+    let rest = rest.trim_start().strip_prefix('=')?;
+
+    for word in rest.split_whitespace() {
+        match word {
+            "global" => return Some(name),
+            "constant" | "external" | "extern_weak" | "alias" | "ifunc" => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits a plain or quoted global name off the start of `rest`
+pub fn global_name(rest: &str) -> Option<(&str, &str)> {
+    if let Some(quoted) = rest.strip_prefix('"') {
+        let end = quoted.find('"')?;
+        return Some((&quoted[..end], &quoted[end + 1..]));
+    }
+
+    let end = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || "-$._".contains(c)))
+        .unwrap_or(rest.len());
+    (end > 0).then(|| (&rest[..end], &rest[end..]))
+}
+
+/// The names of all globals referenced in `text`
+pub fn references_in(text: &str) -> impl Iterator<Item = &str> {
+    text.match_indices('@')
+        .filter_map(|(index, _)| Some(global_name(&text[index + 1..])?.0))
+}
+
+/// The globals referenced in `text` with the range of each reference,
+/// skipping string literals and quoted local names
+pub fn reference_spans(text: &str) -> Vec<(std::ops::Range<usize>, &str)> {
+    let mut references = Vec::new();
+    let mut index = 0;
+    while let Some(offset) = text[index..].find(['@', '"']) {
+        let start = index + offset;
+        if text[start..].starts_with('"') {
+            let Some(end) = text[start + 1..].find('"') else {
+                break;
+            };
+            index = start + end + 2;
+            continue;
+        }
+        match global_name(&text[start + 1..]) {
+            Some((name, rest)) => {
+                let end = text.len() - rest.len();
+                references.push((start..end, name));
+                index = end;
+            }
+            None => index = start + 1,
+        }
+    }
+    references
+}
+
+/// Splits the operands of an instruction at the commas outside of any
+/// parentheses, brackets or braces
+pub fn split_operands(operands: &str) -> Vec<&str> {
+    let mut split = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (index, byte) in operands.bytes().enumerate() {
+        match byte {
+            b'(' | b'[' | b'{' | b'<' => depth += 1,
+            b')' | b']' | b'}' | b'>' => depth = depth.saturating_sub(1),
+            b',' if depth == 0 => {
+                split.push(&operands[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    split.push(&operands[start..]);
+    split
+}
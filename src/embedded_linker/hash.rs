@@ -0,0 +1,54 @@
+use std::io::Read;
+use std::path::Path;
+
+/// A 64-bit FNV-1a hasher
+///
+/// Unlike `std::hash::DefaultHasher` its output is stable across Rust
+/// versions and platforms, so hashes can be persisted.
+#[derive(Debug, Clone, Copy)]
+pub struct Hasher(u64);
+
+impl Default for Hasher {
+    fn default() -> Self {
+        Hasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher {
+    pub fn update(&mut self, bytes: &[u8]) -> &mut Self {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+        self
+    }
+
+    /// Hashes a length prefixed field, so that consecutive fields cannot be
+    /// confused, e.g. `ab` + `c` and `a` + `bc`
+    pub fn field(&mut self, bytes: impl AsRef<[u8]>) -> &mut Self {
+        let bytes = bytes.as_ref();
+        self.update(&(bytes.len() as u64).to_le_bytes())
+            .update(bytes)
+    }
+
+    /// Hashes the contents of the file at `path`
+    pub fn file(&mut self, path: &Path) -> std::io::Result<&mut Self> {
+        let mut file = std::fs::File::open(path)?;
+        let mut buffer = vec![0; 64 * 1024];
+        let mut len = 0u64;
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            self.update(&buffer[..read]);
+            len += read as u64;
+        }
+        Ok(self.update(&len.to_le_bytes()))
+    }
+
+    /// The hash as 16 hexadecimal digits
+    pub fn finish(self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::{Display, Formatter};
+
+use super::globals::{global_name, references_in};
+use super::symbol::{demangle, Symbol};
+use crate::passes::kernels;
+
+/// The functions of the C library that need an operating system
+const OS_FUNCTIONS: [&str; 24] = [
+    "syscall",
+    "open",
+    "open64",
+    "close",
+    "read",
+    "write",
+    "mmap",
+    "munmap",
+    "fork",
+    "exit",
+    "getenv",
+    "getpid",
+    "sysconf",
+    "sched_yield",
+    "nanosleep",
+    "clock_gettime",
+    "gettimeofday",
+    "signal",
+    "sigaction",
+    "dlopen",
+    "dlsym",
+    "__errno_location",
+    "__tls_get_addr",
+    "__cxa_thread_atexit_impl",
+];
+
+/// The modules of `std` that need an operating system
+const STD_MODULES: [&str; 10] = [
+    "std::thread::",
+    "std::sys::",
+    "std::sys_common::",
+    "std::fs::",
+    "std::net::",
+    "std::process::",
+    "std::env::",
+    "std::os::",
+    "std::io::stdio::",
+    "std::time::",
+];
+
+/// A construct that only exists on the host
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Construct {
+    /// A thread-local global
+    ThreadLocal(String),
+    /// A function of the C library or of `std` that needs an operating system
+    Function(String),
+}
+
+impl Display for Construct {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Construct::ThreadLocal(name) => write!(f, "thread-local `{}`", demangle(name)),
+            Construct::Function(name) => write!(f, "host function `{}`", demangle(name)),
+        }
+    }
+}
+
+/// A use of a host-only construct by device code
+#[derive(Debug, Clone)]
+pub struct Use {
+    pub construct: Construct,
+    /// The shortest call chain from a kernel or kept symbol to the function
+    /// using the construct
+    pub chain: Vec<String>,
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+/// Device code uses constructs that only exist on the host, e.g. of a crate
+/// using `std`, which the backend fails on with obscure errors
+#[error("device code uses host-only code:{}", List(.0))]
+pub struct HostCode(pub Vec<Use>);
+
+/// The uses one per line
+struct List<'a>(&'a [Use]);
+
+impl Display for List<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for host_use in self.0 {
+            let chain = host_use
+                .chain
+                .iter()
+                .map(|function| demangle(function))
+                .collect::<Vec<_>>();
+            write!(
+                f,
+                "\n  {}, used by {}",
+                host_use.construct,
+                chain.join(" -> ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Finds the host-only constructs used by the functions reachable from the
+/// kernels and the `kept` symbols in the textual IR of a module
+pub fn find(ir: &str, kept: &[Symbol]) -> Vec<Use> {
+    let thread_locals = thread_locals(ir);
+    let functions = functions(ir);
+
+    let mut roots = kernels::defined(ir);
+    roots.extend(
+        functions
+            .keys()
+            .filter(|name| {
+                kept.iter()
+                    .any(|symbol| symbol.as_bytes() == name.as_bytes())
+            })
+            .map(|&name| name.to_owned()),
+    );
+
+    // Breadth first, so that every function is reached by a shortest chain
+    let mut callers = BTreeMap::<&str, Option<&str>>::new();
+    let mut queue = VecDeque::new();
+    for root in &roots {
+        if let Some((&name, _)) = functions.get_key_value(root.as_str()) {
+            if callers.insert(name, None).is_none() {
+                queue.push_back(name);
+            }
+        }
+    }
+
+    let mut uses = BTreeMap::<Construct, Vec<String>>::new();
+    while let Some(function) = queue.pop_front() {
+        for &reference in &functions[function] {
+            let construct = if thread_locals.contains(reference) {
+                Construct::ThreadLocal(reference.to_owned())
+            } else if is_host_function(reference) {
+                Construct::Function(reference.to_owned())
+            } else {
+                if functions.contains_key(reference) && !callers.contains_key(reference) {
+                    callers.insert(reference, Some(function));
+                    queue.push_back(reference);
+                }
+                continue;
+            };
+
+            uses.entry(construct).or_insert_with(|| {
+                let mut chain = vec![function.to_owned()];
+                let mut current = function;
+                while let Some(caller) = callers[current] {
+                    chain.push(caller.to_owned());
+                    current = caller;
+                }
+                chain.reverse();
+                chain
+            });
+        }
+    }
+
+    uses.into_iter()
+        .map(|(construct, chain)| Use { construct, chain })
+        .collect()
+}
+
+/// Whether the function `name` needs an operating system
+fn is_host_function(name: &str) -> bool {
+    if OS_FUNCTIONS.contains(&name) || name.starts_with("pthread_") {
+        return true;
+    }
+    let demangled = demangle(name);
+    let path = demangled.trim_start_matches('<');
+    STD_MODULES.iter().any(|module| path.starts_with(module))
+}
+
+/// The names of the thread-local globals in the textual IR of a module
+fn thread_locals(ir: &str) -> BTreeSet<&str> {
+    ir.lines()
+        .filter_map(|line| {
+            let (name, rest) = global_name(line.strip_prefix('@')?)?;
+            let definition = rest.trim_start().strip_prefix('=')?;
+            definition
+                .split_whitespace()
+                .take_while(|word| !matches!(*word, "global" | "constant"))
+                .any(|word| word.starts_with("thread_local"))
+                .then_some(name)
+        })
+        .collect()
+}
+
+/// The globals referenced by the body of every function defined in the
+/// textual IR of a module
+fn functions(ir: &str) -> BTreeMap<&str, BTreeSet<&str>> {
+    let mut functions = BTreeMap::new();
+    let mut current: Option<(&str, BTreeSet<&str>)> = None;
+
+    for line in ir.lines() {
+        if line.starts_with("define ") {
+            current = line
+                .find('@')
+                .and_then(|index| global_name(&line[index + 1..]))
+                .map(|(name, _)| (name, BTreeSet::new()));
+            continue;
+        }
+        let Some((name, references)) = &mut current else {
+            continue;
+        };
+        if line.starts_with('}') {
+            functions.extend(current.take());
+            continue;
+        }
+
+        references.extend(references_in(line).filter(|reference| reference != name));
+    }
+
+    functions
+}
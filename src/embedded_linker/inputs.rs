@@ -0,0 +1,62 @@
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::Context;
+
+use super::archive;
+use super::rdc;
+
+/// How many bytes are read to detect the kind of an input
+const PEEK_LEN: u64 = 4096;
+
+/// The kind of an input file, detected from its content
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum InputKind {
+    Bitcode,
+    /// An `ar` archive, e.g. an rlib
+    Archive,
+    /// A cubin or a fatbin
+    DeviceLib,
+    Ptx,
+}
+
+impl InputKind {
+    /// Detects the kind of the input at `path` from its magic number, or from
+    /// the `.version` directive of PTX
+    pub fn detect(path: &Path) -> anyhow::Result<Self> {
+        let mut start = Vec::new();
+        std::fs::File::open(path)
+            .and_then(|file| file.take(PEEK_LEN).read_to_end(&mut start))
+            .context(format!("Failed to read input: {}", path.display()))?;
+
+        Self::from_start(&start).context(format!(
+            "unknown kind of input {}, expected bitcode, an archive, a cubin, a fatbin or PTX",
+            path.display()
+        ))
+    }
+
+    /// Detects the kind of an input from the first bytes of its content,
+    /// `None` if it is of no known kind
+    pub fn from_start(start: &[u8]) -> Option<Self> {
+        let magic = start.get(..4).unwrap_or_default();
+        if archive::BITCODE_MAGIC
+            .iter()
+            .any(|bitcode| magic == bitcode)
+        {
+            return Some(InputKind::Bitcode);
+        }
+        if start.starts_with(archive::MAGIC) {
+            return Some(InputKind::Archive);
+        }
+        if magic == rdc::ELF_MAGIC || magic == rdc::FATBIN_MAGIC {
+            return Some(InputKind::DeviceLib);
+        }
+
+        let is_ptx = String::from_utf8_lossy(start)
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with("//"))
+            .is_some_and(|line| line.starts_with(".version"));
+        is_ptx.then_some(InputKind::Ptx)
+    }
+}
@@ -0,0 +1,291 @@
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use super::archive;
+use super::cubin::{self, Cubin};
+use super::globals::global_name;
+use super::inputs::InputKind;
+use super::ptx::{self, FunctionKind};
+use super::rdc::ELF_MAGIC;
+use super::symbol::demangle;
+use super::tools::ToolLocator;
+use crate::bundle::{self, Bundle, EntryKind};
+use crate::passes::kernels;
+
+/// The kind of an artifact, or of an image in it
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ArtifactKind {
+    Rlib,
+    /// A static archive of bitcode
+    Archive,
+    Bitcode,
+    Ptx,
+    Cubin,
+    Fatbin,
+    Bundle,
+}
+
+impl Display for ArtifactKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ArtifactKind::Rlib => "rlib",
+            ArtifactKind::Archive => "archive",
+            ArtifactKind::Bitcode => "bitcode",
+            ArtifactKind::Ptx => "PTX",
+            ArtifactKind::Cubin => "cubin",
+            ArtifactKind::Fatbin => "fatbin",
+            ArtifactKind::Bundle => "bundle",
+        })
+    }
+}
+
+/// The code of an artifact for a single target, or a single bitcode member
+/// of an archive
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub kind: ArtifactKind,
+    /// The target cpu, e.g. `sm_80`
+    pub arch: Option<String>,
+    pub size: usize,
+    pub kernels: Vec<String>,
+    /// The other global symbols defined by the image
+    pub symbols: Vec<String>,
+    /// Whether the image is compressed and cannot be inspected further
+    pub compressed: bool,
+}
+
+/// The contents of a link input or output
+#[derive(Debug, Clone)]
+pub struct Inspection {
+    pub path: PathBuf,
+    pub kind: ArtifactKind,
+    pub size: usize,
+    pub images: Vec<Image>,
+}
+
+impl Inspection {
+    /// Identifies the kind of the artifact at `path` and lists its images
+    ///
+    /// Bitcode is disassembled with the LLVM tools of `llvm_major`, or of the
+    /// LLVM version of `rustc`, in `tools_dir` if it is set.
+    pub fn new(
+        path: &Path,
+        llvm_major: Option<u32>,
+        tools_dir: Option<&Path>,
+    ) -> anyhow::Result<Self> {
+        let bytes =
+            std::fs::read(path).context(format!("Failed to read artifact: {}", path.display()))?;
+
+        let kind = if bytes.starts_with(&bundle::MAGIC) {
+            ArtifactKind::Bundle
+        } else {
+            match InputKind::detect(path)? {
+                InputKind::Bitcode => ArtifactKind::Bitcode,
+                InputKind::Archive if path.extension().is_some_and(|ext| ext == "rlib") => {
+                    ArtifactKind::Rlib
+                }
+                InputKind::Archive => ArtifactKind::Archive,
+                InputKind::DeviceLib if bytes.starts_with(&ELF_MAGIC) => ArtifactKind::Cubin,
+                InputKind::DeviceLib => ArtifactKind::Fatbin,
+                InputKind::Ptx => ArtifactKind::Ptx,
+            }
+        };
+
+        let images = match kind {
+            ArtifactKind::Bitcode => vec![bitcode_image(&bytes, llvm_major, tools_dir)?],
+            ArtifactKind::Rlib | ArtifactKind::Archive => archive::bitcode_members(&bytes)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|member| bitcode_image(member, llvm_major, tools_dir))
+                .collect::<anyhow::Result<_>>()?,
+            ArtifactKind::Ptx => vec![ptx_image(&bytes)?],
+            ArtifactKind::Cubin => vec![cubin_image(&bytes)?],
+            ArtifactKind::Fatbin => cubin::fatbin_images(&bytes)?
+                .into_iter()
+                .map(fatbin_image)
+                .collect::<anyhow::Result<_>>()?,
+            ArtifactKind::Bundle => Bundle::parse(&bytes)?
+                .entries
+                .iter()
+                .map(|entry| match entry.kind {
+                    EntryKind::Ptx => ptx_image(&entry.data),
+                    EntryKind::Cubin => cubin_image(&entry.data),
+                })
+                .collect::<anyhow::Result<_>>()?,
+        };
+
+        Ok(Inspection {
+            path: path.to_owned(),
+            kind,
+            size: bytes.len(),
+            images,
+        })
+    }
+}
+
+impl Display for Inspection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{}: {}, {} bytes, {} image(s)",
+            self.path.display(),
+            self.kind,
+            self.size,
+            self.images.len()
+        )?;
+        for image in &self.images {
+            write!(f, "  {}", image.kind)?;
+            if let Some(arch) = &image.arch {
+                write!(f, " for {arch}")?;
+            }
+            write!(f, ", {} bytes", image.size)?;
+            if image.compressed {
+                write!(f, ", compressed")?;
+            }
+            writeln!(f)?;
+
+            for (title, names) in [("kernels", &image.kernels), ("symbols", &image.symbols)] {
+                if names.is_empty() {
+                    continue;
+                }
+                writeln!(f, "    {title} ({}):", names.len())?;
+                for name in names {
+                    writeln!(f, "      {}", demangle(name))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Image {
+    fn new(kind: ArtifactKind, data: &[u8]) -> Self {
+        Image {
+            kind,
+            arch: None,
+            size: data.len(),
+            kernels: Vec::new(),
+            symbols: Vec::new(),
+            compressed: false,
+        }
+    }
+}
+
+fn ptx_image(data: &[u8]) -> anyhow::Result<Image> {
+    // Images in fatbins are padded with zeros
+    let text = String::from_utf8_lossy(data);
+    let module = ptx::parse(text.trim_end_matches('\0')).context("Failed to parse PTX")?;
+
+    let mut image = Image::new(ArtifactKind::Ptx, data);
+    image.arch = module
+        .target
+        .split(',')
+        .next()
+        .map(|target| target.trim().to_owned());
+    for function in module.functions.into_iter().filter(|f| f.defined) {
+        match function.kind {
+            FunctionKind::Entry => image.kernels.push(function.name),
+            FunctionKind::Func => image.symbols.push(function.name),
+        }
+    }
+    Ok(image)
+}
+
+fn fatbin_image(image: cubin::Image) -> anyhow::Result<Image> {
+    let mut inspected = match image.kind {
+        cubin::ImageKind::Ptx if !image.compressed => ptx_image(image.data)?,
+        cubin::ImageKind::Cubin if !image.compressed => cubin_image(image.data)?,
+        cubin::ImageKind::Ptx => Image::new(ArtifactKind::Ptx, image.data),
+        cubin::ImageKind::Cubin => Image::new(ArtifactKind::Cubin, image.data),
+    };
+    inspected.compressed = image.compressed;
+    inspected.arch.get_or_insert(image.arch);
+    Ok(inspected)
+}
+
+fn cubin_image(data: &[u8]) -> anyhow::Result<Image> {
+    let cubin = Cubin::parse(data)?;
+    let mut image = Image::new(ArtifactKind::Cubin, data);
+    image.arch = Some(cubin.arch);
+    image.kernels = cubin.kernels;
+    image.symbols = cubin.functions;
+    Ok(image)
+}
+
+fn bitcode_image(
+    data: &[u8],
+    llvm_major: Option<u32>,
+    tools_dir: Option<&Path>,
+) -> anyhow::Result<Image> {
+    let mut image = Image::new(ArtifactKind::Bitcode, data);
+    let llvm_dis = ToolLocator::get(llvm_major, tools_dir)?.path("llvm-dis");
+    let mut child = std::process::Command::new(&llvm_dis)
+        .args(["-", "-o", "-"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .context(format!("Failed to run {}", llvm_dis.display()))?;
+    // Write from another thread, as the output may fill the pipe first
+    let mut stdin = child.stdin.take().context("llvm-dis has no stdin")?;
+    let data = data.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&data));
+    let output = child.wait_with_output()?;
+    writer
+        .join()
+        .map_err(|_| anyhow::anyhow!("writing to llvm-dis panicked"))??;
+    if !output.status.success() {
+        anyhow::bail!(
+            "llvm-dis failed to disassemble bitcode: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let ir = String::from_utf8_lossy(&output.stdout);
+
+    let mut cpus = ir
+        .match_indices("\"target-cpu\"=\"")
+        .filter_map(|(index, pattern)| {
+            let cpu = &ir[index + pattern.len()..];
+            Some(cpu[..cpu.find('"')?].to_owned())
+        })
+        .collect::<Vec<_>>();
+    cpus.sort();
+    cpus.dedup();
+    image.arch = (!cpus.is_empty()).then(|| cpus.join(", "));
+    image.kernels = kernels::defined(&ir);
+    image.symbols = defined_symbols(&ir)
+        .into_iter()
+        .filter(|symbol| !image.kernels.contains(symbol))
+        .collect();
+    Ok(image)
+}
+
+/// The global functions and variables defined in the textual IR of a module
+fn defined_symbols(ir: &str) -> Vec<String> {
+    let mut symbols = ir
+        .lines()
+        .filter_map(|line| {
+            let (definition, name) = if let Some(define) = line.strip_prefix("define ") {
+                let index = define.find('@')?;
+                (&define[..index], global_name(&define[index + 1..])?.0)
+            } else {
+                let (name, rest) = global_name(line.strip_prefix('@')?)?;
+                let definition = rest.strip_prefix(" = ")?;
+                if definition.starts_with("external ") || definition.starts_with("extern_weak ") {
+                    return None;
+                }
+                (definition, name)
+            };
+            let local = ["internal ", "private "]
+                .iter()
+                .any(|linkage| definition.starts_with(linkage));
+            (!local).then(|| name.to_owned())
+        })
+        .collect::<Vec<_>>();
+    symbols.sort();
+    symbols.dedup();
+    symbols
+}
@@ -0,0 +1,230 @@
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::Context;
+
+use super::hash::Hasher;
+
+/// The directory the intermediates of a link are written to
+///
+/// It is named after the absolute path of the output, e.g.
+/// `$TMPDIR/rust-ptx-linker/kernel.ptx-0123456789abcdef` for
+/// `/work/kernel.ptx`, so that a later link of the same output finds them
+/// again, e.g. to resume. Its files are named after their content, e.g.
+/// `linked.bc` or `sm_80.cubin`.
+///
+/// The directory is removed when dropped unless it is kept, also when the link
+/// fails or panics. It is `Send` and `Sync`, but only one session per process
+/// may use it at a time, so that concurrent links of the same output do not
+/// overwrite each other's files.
+///
+/// Files that links of any output can reuse, like the inputs optimized on
+/// their own, are stored in a cache shared by all links next to the
+/// directories of the outputs, which is never removed.
+#[derive(Debug)]
+pub struct Intermediates {
+    dir: PathBuf,
+    keep: bool,
+}
+
+/// The intermediates directories used by the sessions of this process
+static CLAIMED: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+impl Intermediates {
+    /// Claims the directory of the intermediates of `out_path`, failing if
+    /// another session of this process links the same output
+    pub fn new(out_path: &Path) -> anyhow::Result<Self> {
+        let absolute =
+            std::env::current_dir().map_or_else(|_| out_path.to_owned(), |dir| dir.join(out_path));
+        let mut hasher = Hasher::default();
+        hasher.field(absolute.to_string_lossy().as_bytes());
+
+        let mut name = out_path.file_name().unwrap_or_default().to_owned();
+        name.push("-");
+        name.push(hasher.finish());
+        let dir = Self::root().join(name);
+
+        if !CLAIMED.lock().unwrap().insert(dir.clone()) {
+            anyhow::bail!(
+                "{} is already being linked by another session",
+                absolute.display()
+            );
+        }
+        Ok(Intermediates { dir, keep: false })
+    }
+
+    /// The directory containing the intermediates directories of all outputs
+    pub fn root() -> PathBuf {
+        std::env::temp_dir().join(env!("CARGO_PKG_NAME"))
+    }
+
+    /// The directory of the files shared by the links of all outputs
+    pub fn cache_dir() -> PathBuf {
+        Self::root().join("cache")
+    }
+
+    pub fn set_keep(&mut self, keep: bool) {
+        self.keep = keep;
+    }
+
+    /// The path of the intermediate `name`, optionally for a single `cpu`
+    pub fn path(&self, cpu: Option<&str>, name: impl AsRef<OsStr>) -> PathBuf {
+        match cpu {
+            Some(cpu) => {
+                let mut file_name = OsStr::new(cpu).to_owned();
+                file_name.push(".");
+                file_name.push(name);
+                self.dir.join(file_name)
+            }
+            None => self.dir.join(name.as_ref()),
+        }
+    }
+
+    /// Creates the directory if it does not exist yet
+    pub fn create(&self) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.dir).context(format!(
+            "Failed to create the intermediates directory: {}",
+            self.dir.display()
+        ))
+    }
+
+    /// Copies the cached file `key` to the intermediate at `path`, returning
+    /// whether it is cached
+    ///
+    /// The key names everything the content of the file depends on.
+    pub fn load_cached(&self, key: impl AsRef<OsStr>, path: &Path) -> anyhow::Result<bool> {
+        let cache_path = Self::cache_dir().join(key.as_ref());
+        match std::fs::copy(&cache_path, path) {
+            Ok(_) => {
+                tracing::debug!("reusing {} as {}", cache_path.display(), path.display());
+                Ok(true)
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err).context(format!(
+                "Failed to copy {} to {}",
+                cache_path.display(),
+                path.display()
+            )),
+        }
+    }
+
+    /// Stores the intermediate at `path` in the cache as `key`
+    ///
+    /// It is copied next to `path` first and then moved into the cache, so
+    /// that concurrent links never read a partially written file.
+    pub fn store_cached(&self, key: impl AsRef<OsStr>, path: &Path) -> anyhow::Result<()> {
+        let cache_dir = Self::cache_dir();
+        std::fs::create_dir_all(&cache_dir).context(format!(
+            "Failed to create the cache directory: {}",
+            cache_dir.display()
+        ))?;
+
+        let mut partial_path = path.as_os_str().to_owned();
+        partial_path.push(".partial");
+        let cache_path = cache_dir.join(key.as_ref());
+        std::fs::copy(path, &partial_path)
+            .and_then(|_| std::fs::rename(&partial_path, &cache_path))
+            .context(format!(
+                "Failed to store {} in the cache: {}",
+                path.display(),
+                cache_path.display()
+            ))?;
+        Ok(())
+    }
+
+    /// Removes the directory with all intermediates
+    pub fn remove(&self) {
+        match std::fs::remove_dir_all(&self.dir) {
+            Ok(()) => tracing::debug!("removed {}", self.dir.display()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => tracing::warn!("failed to remove {}: {err}", self.dir.display()),
+        }
+    }
+}
+
+impl Drop for Intermediates {
+    fn drop(&mut self) {
+        if self.dir.exists() {
+            if self.keep {
+                tracing::info!("kept intermediates in {}", self.dir.display());
+            } else {
+                self.remove();
+            }
+        }
+        CLAIMED.lock().unwrap().remove(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The intermediates `Session` names after their content
+    const NAMES: [&str; 4] = ["linked.bc", "optimized.bc", "symbols.txt", "math.bc"];
+
+    #[test]
+    fn does_not_overwrite_the_output() {
+        // `-o kernel.o` used to make the linked module overwrite the output
+        for out_path in ["kernel.o", "kernel.bc", "linked.bc", "out/symbols.txt"] {
+            let out_path = Path::new(out_path);
+            let absolute = std::env::current_dir().unwrap().join(out_path);
+            let intermediates = Intermediates::new(out_path).unwrap();
+
+            for name in NAMES {
+                let path = intermediates.path(None, name);
+                assert!(path.starts_with(Intermediates::root()));
+                assert_ne!(path, out_path);
+                assert_ne!(path, absolute);
+            }
+            assert_ne!(intermediates.path(Some("sm_80"), "ptx"), absolute);
+        }
+    }
+
+    #[test]
+    fn separates_outputs_sharing_a_stem() {
+        let ptx = Intermediates::new(Path::new("stem/kernel.ptx")).unwrap();
+        let cubin = Intermediates::new(Path::new("stem/kernel.cubin")).unwrap();
+        let other_dir = Intermediates::new(Path::new("other/kernel.ptx")).unwrap();
+
+        for name in NAMES {
+            assert_ne!(ptx.path(None, name), cubin.path(None, name));
+            assert_ne!(ptx.path(None, name), other_dir.path(None, name));
+        }
+        assert_ne!(
+            ptx.path(Some("sm_80"), "cubin"),
+            cubin.path(Some("sm_80"), "cubin")
+        );
+    }
+
+    #[test]
+    fn claims_the_output_once() {
+        let out_path = Path::new("claimed/kernel.ptx");
+        let intermediates = Intermediates::new(out_path).unwrap();
+        assert!(Intermediates::new(out_path).is_err());
+
+        drop(intermediates);
+        assert!(Intermediates::new(out_path).is_ok());
+    }
+
+    #[test]
+    fn caches_across_outputs() {
+        let key = format!("test-{}.bc", std::process::id());
+        let first = Intermediates::new(Path::new("cached/first.ptx")).unwrap();
+        first.create().unwrap();
+        let path = first.path(None, "0.thinlto.bc");
+        std::fs::write(&path, "optimized").unwrap();
+        first.store_cached(&key, &path).unwrap();
+        drop(first);
+
+        let second = Intermediates::new(Path::new("cached/second.ptx")).unwrap();
+        second.create().unwrap();
+        let path = second.path(None, "0.thinlto.bc");
+        assert!(!second.load_cached(format!("{key}.missing"), &path).unwrap());
+        assert!(second.load_cached(&key, &path).unwrap());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "optimized");
+
+        std::fs::remove_file(Intermediates::cache_dir().join(key)).unwrap();
+    }
+}
@@ -0,0 +1,37 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by the signal handler once the link should be aborted
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+/// The link was aborted by SIGINT or SIGTERM
+#[error("interrupted")]
+pub struct Interrupted;
+
+impl Interrupted {
+    /// The exit code of an interrupted link, as for shells killed by SIGINT
+    pub const EXIT_CODE: i32 = 130;
+
+    /// Abort running tools on SIGINT and SIGTERM
+    ///
+    /// The running tool is killed and the link fails with [`Interrupted`], so
+    /// the caller can remove partial outputs. A second signal exits
+    /// immediately.
+    pub fn install_handler() -> Result<(), ctrlc::Error> {
+        ctrlc::set_handler(|| {
+            if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                std::process::exit(Self::EXIT_CODE);
+            }
+            tracing::warn!("interrupted, aborting the link");
+        })
+    }
+
+    /// Fails if a signal was received
+    pub fn check() -> Result<(), Interrupted> {
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            Err(Interrupted)
+        } else {
+            Ok(())
+        }
+    }
+}
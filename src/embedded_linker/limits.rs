@@ -0,0 +1,143 @@
+use super::ptx::{Function, FunctionKind, Module};
+use super::symbol::demangle;
+
+/// The hardware limits of an architecture that launch bounds must respect
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    threads_per_sm: u32,
+    blocks_per_sm: u32,
+}
+
+/// The architectures whose limits are known
+pub const ARCHS: [&str; 19] = [
+    "sm_30", "sm_32", "sm_35", "sm_37", "sm_50", "sm_52", "sm_53", "sm_60", "sm_61", "sm_62",
+    "sm_70", "sm_72", "sm_75", "sm_80", "sm_86", "sm_87", "sm_89", "sm_90", "sm_90a",
+];
+
+/// The most threads of a block, in total and per dimension
+const THREADS_PER_BLOCK: u32 = 1024;
+const BLOCK_DIMENSIONS: [u32; 3] = [1024, 1024, 64];
+/// The most registers of a thread and of a multiprocessor
+const REGISTERS_PER_THREAD: u32 = 255;
+const REGISTERS_PER_SM: u32 = 64 * 1024;
+
+impl Limits {
+    /// The limits of `arch`, e.g. `sm_80`, if it is known
+    ///
+    /// Unknown newer architectures get the limits of the newest known one.
+    pub fn for_arch(arch: &str) -> Option<Self> {
+        let version = crate::bundle::arch_version(arch)?;
+        let (threads_per_sm, blocks_per_sm) = match version {
+            0..=29 => return None,
+            30..=49 | 87 => (2048, 16),
+            75 => (1024, 16),
+            86 => (1536, 16),
+            89 => (1536, 24),
+            _ => (2048, 32),
+        };
+        Some(Limits {
+            threads_per_sm,
+            blocks_per_sm,
+        })
+    }
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+/// The launch bounds of a kernel can never be satisfied on the target
+#[error("launch bounds of kernel `{}` exceed the limits of {arch}: {reason}", demangle(.kernel))]
+pub struct LaunchBoundsError {
+    kernel: String,
+    arch: String,
+    reason: String,
+}
+
+/// Checks the launch bounds of all kernels of a PTX `module` against the
+/// limits of its target architecture, returning the violations of every kernel
+pub fn check(module: &Module) -> Vec<LaunchBoundsError> {
+    // The target may be followed by options, e.g. `sm_80, debug`
+    let arch = module.target.split(',').next().unwrap_or_default().trim();
+    let Some(limits) = Limits::for_arch(arch) else {
+        tracing::debug!("unknown limits of {arch}, skipping launch bounds checks");
+        return Vec::new();
+    };
+
+    module
+        .functions
+        .iter()
+        .filter(|kernel| kernel.kind == FunctionKind::Entry)
+        .filter_map(|kernel| {
+            check_kernel(kernel, limits)
+                .err()
+                .map(|reason| LaunchBoundsError {
+                    kernel: kernel.name.clone(),
+                    arch: arch.to_owned(),
+                    reason,
+                })
+        })
+        .collect()
+}
+
+fn check_kernel(kernel: &Function, limits: Limits) -> Result<(), String> {
+    let bounds = &kernel.launch_bounds;
+    let threads = bounds.threads().map(|dimensions| {
+        (
+            dimensions,
+            dimensions.iter().map(|&d| u64::from(d)).product::<u64>(),
+        )
+    });
+
+    if let Some((dimensions, total)) = threads {
+        if total > u64::from(THREADS_PER_BLOCK) {
+            return Err(format!(
+                "{total} threads per block, at most {THREADS_PER_BLOCK} are supported"
+            ));
+        }
+        if let Some((dimension, limit)) = dimensions
+            .iter()
+            .zip(BLOCK_DIMENSIONS)
+            .find(|(dimension, limit)| **dimension > *limit)
+        {
+            return Err(format!(
+                "block dimension of {dimension} threads, at most {limit} are supported"
+            ));
+        }
+    }
+
+    if let Some(registers) = bounds.max_registers {
+        if registers > REGISTERS_PER_THREAD {
+            return Err(format!(
+                "{registers} registers per thread, at most {REGISTERS_PER_THREAD} are supported"
+            ));
+        }
+    }
+
+    let Some(blocks) = bounds.min_blocks else {
+        return Ok(());
+    };
+    if blocks > limits.blocks_per_sm {
+        return Err(format!(
+            "{blocks} resident blocks per multiprocessor, at most {} are supported",
+            limits.blocks_per_sm
+        ));
+    }
+
+    if let Some((_, threads)) = threads {
+        let resident = threads * u64::from(blocks);
+        if resident > u64::from(limits.threads_per_sm) {
+            return Err(format!(
+                "{blocks} resident blocks of {threads} threads per multiprocessor, at most {} threads are supported",
+                limits.threads_per_sm
+            ));
+        }
+        if let Some(registers) = bounds.max_registers {
+            let used = resident * u64::from(registers);
+            if used > u64::from(REGISTERS_PER_SM) {
+                return Err(format!(
+                    "{blocks} resident blocks of {threads} threads with {registers} registers each need {used} registers per multiprocessor, at most {REGISTERS_PER_SM} are available"
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
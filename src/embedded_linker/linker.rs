@@ -1,20 +1,210 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use anyhow::Context;
 use tracing::info;
 
-use crate::{Optimization, Target};
+use super::api::PublicApi;
+use super::archive;
+use super::asserts;
+use super::boundaries;
+use super::capabilities::{self, CUDA_TOOLS};
+use super::checkpoint::{Checkpoints, Stage};
+use super::conflicts;
+use super::constants;
+use super::debug::{self, DebugInfo};
+use super::diagnostics::{Deny, Diagnostic, Errors, Location, Severity};
+use super::diff::Diff;
+use super::dtors::{self, DtorPolicy};
+use super::emit::{Emit, EmitKind};
+use super::fallback::CpuRejected;
+use super::globals;
+use super::hash::Hasher;
+use super::host_code::{self, HostCode};
+use super::inputs::InputKind;
+use super::intermediates::Intermediates;
+use super::interrupt::Interrupted;
+use super::limits;
+use super::markers;
+use super::math;
+use super::memory;
+use super::metadata;
+use super::nm;
+use super::object;
+use super::panic;
+use super::postprocess::{self, PostProcessor};
+use super::ptx;
+use super::rdc::{DeviceLib, DeviceLibKind};
+use super::remarks;
+use super::rust_bindings;
+use super::signing::{self, Signer};
+use super::size;
+use super::symbol::{self, KeepSymbolsFormat, Symbol};
+use super::target;
+use super::target_features::{self, FunctionFeatures, TargetFeature};
+use super::tools::ToolLocator;
+use super::trace;
+use super::traps;
+use super::undefined::{self, Reference, UndefinedReferences};
+use super::unreachable::{self, Unreachable};
+use super::unwind;
+use crate::bundle::{self, Bundle};
+use crate::kernel_metadata::{self, KernelMetadata};
+use crate::manifest::{Artifact, Manifest, RenamedSymbol, SharedKernel, StageHash};
+use crate::passes::{self, intrinsics, kernels};
+use crate::{Optimization, Pipeline, Step, Target};
 
+/// The passes optimizing across the inputs after merging them in the
+/// [`Pipeline::Premerge`] and [`Pipeline::Thin`] pipelines
+const PREMERGE_CROSS_MODULE_PASSES: &str =
+    "cgscc(inline),function(sroa,early-cse,instcombine,simplifycfg),globalopt";
+
+/// A link of one output
+///
+/// Sessions are `Send` and `Sync`, so that servers can share them between
+/// threads, and sessions of different outputs can link concurrently in one
+/// process, each in its own intermediates directory. Creating a session for
+/// an output another session of the process links fails.
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Debug)]
 pub struct Session {
     target: Target,
-    cpu: Option<String>,
-    symbols: Vec<String>,
+    cpus: Vec<String>,
+    symbols: Vec<Symbol>,
     bitcode: Vec<PathBuf>,
+    /// The files the inputs in `bitcode` were added from, e.g. the rlibs, and
+    /// their bitcode before pruning
+    sources: Vec<(PathBuf, PathBuf)>,
+    /// Indices into `bitcode` of the inputs whose symbols are not kept
+    prunable: Vec<usize>,
+    /// Whether to remove unreachable functions from the prunable inputs
+    /// before linking them
+    prune: bool,
+
+    /// Whether to emit relocatable device code linked with `nvlink`
+    rdc: bool,
+    device_libs: Vec<DeviceLib>,
+
+    /// The outputs to emit
+    emit: Vec<Emit>,
+    /// The target triple of host objects, the default of `llc` if unset
+    host_target: Option<String>,
+    /// The optimization level of `llc`, its default if unset
+    codegen_optimization: Option<Optimization>,
+    /// Whether to log the instructions per function and crate of the PTX
+    size_report: bool,
+    /// Whether to log the globals the device code never reads or writes
+    globals_report: bool,
+    /// Whether to log a summary of the optimization remarks per kernel
+    remarks_summary: bool,
+    /// How much of the requested debug information is kept
+    debug_info: DebugInfo,
+    /// Whether to move the debug information of the PTX into separate files
+    split_debug: bool,
+    /// How destructors registered by the device code are handled
+    dtor_policy: DtorPolicy,
+    /// Whether host-only code used by the device code fails the link
+    strict_host_code: bool,
+    /// Whether to check the structure of the emitted PTX
+    verify_ptx: bool,
+    /// The format of the emitted keep-set
+    keep_symbols_format: KeepSymbolsFormat,
+    /// The module of an earlier build to compare the linked module to
+    ir_diff: Option<PathBuf>,
+    /// The symbols the optimized module must export
+    public_api: Option<PublicApi>,
+    /// The path to write the metadata of the kernels to
+    kernel_metadata: Option<PathBuf>,
+    /// Whether to write the manifest of the outputs next to the output
+    write_manifest: bool,
+    /// Run on the outputs after a successful link
+    post_processors: Vec<Box<dyn PostProcessor>>,
+    /// Signs the outputs after they were post-processed
+    signer: Option<Signer>,
+    /// Named metadata whose referenced globals are kept
+    exported_metadata: Vec<String>,
+    /// Target features passed to `llc` for the whole module
+    target_features: Vec<TargetFeature>,
+    /// Target features overriding those of the module for single functions
+    function_features: Vec<FunctionFeatures>,
+    /// How `unreachable` is lowered, left to `llc` if unset
+    unreachable: Option<Unreachable>,
+    /// Whether panics are reported with `__assertfail`
+    device_asserts: bool,
+    /// Whether the panic functions of `core` are replaced with a trap
+    panic_trap: bool,
+    /// The minimum number of instructions of the callees of kernels kept out
+    /// of line for profiling
+    profile_boundaries: Option<usize>,
+    /// The size in bytes up to which constant globals are coalesced
+    coalesce_constants: Option<u64>,
+    /// Whether symbols defined by several inputs are renamed instead of
+    /// failing the link
+    rename_on_conflict: bool,
+    /// The symbols renamed as several inputs define them
+    renamed: Vec<RenamedSymbol>,
+    /// The fingerprint of the options of the link, recorded in the manifest
+    fingerprint: Option<String>,
+    /// The index of libdevice in `bitcode` if it is linked, whose
+    /// `__nvvm_reflect` queries are folded before optimizing and which the
+    /// math intrinsics of the other inputs are mapped to
+    libdevice: Option<usize>,
+    /// The target cpu compiled for instead of those the tools reject
+    fallback_cpu: Option<String>,
+    /// The newest target cpu the inputs were compiled for, which is compiled
+    /// for if no target cpu is given
+    inferred_cpu: Option<String>,
+    /// The target cpus compiled for the fallback cpu
+    fallen_back: Mutex<Vec<String>>,
+    /// The external tools run by the session
+    used_tools: Mutex<BTreeSet<String>>,
+    /// The hashes of the outputs of the steps that ran, in order
+    stage_hashes: Mutex<Vec<StageHash>>,
+    /// The tool versions the link must use, by tool
+    locked: Option<BTreeMap<String, String>>,
+    /// Glob patterns of the defined symbols to keep
+    export_patterns: Vec<String>,
+
+    /// Symbols whose linking decisions are logged
+    trace: trace::Symbols,
+    /// The traced symbols defined by the inputs
+    traced: Vec<Symbol>,
 
-    version: String,
+    tools: &'static ToolLocator,
+
+    /// Diagnostics reported by the external tools
+    diagnostics: Mutex<Vec<Diagnostic>>,
+    /// The warnings that are promoted to errors
+    deny: Vec<Deny>,
+
+    /// The memory usage of the linker and its tools
+    memory: memory::Monitor,
+    /// Whether to log the duration of each stage and the peak memory usage
+    timings: bool,
+    /// Whether to reuse the checkpoints of an earlier run
+    resume: bool,
+    /// How the inputs are optimized
+    pipeline: Pipeline,
+    /// The pass pipeline run instead of the one of the optimization level
+    passes: Option<String>,
+    /// Passes run after the optimization pipeline
+    extra_passes: Option<String>,
+    /// The globs of the functions kept out of line
+    no_inline: Vec<String>,
+    /// The globs of the functions inlined even if not all are
+    force_inline: Vec<String>,
+    /// The optional steps that are skipped
+    skipped: Vec<Step>,
+    /// The step after which the link stops, writing its output to `out_path`
+    stop_after: Option<Step>,
 
     // Output files
+    intermediates: Intermediates,
     link_path: PathBuf,
     opt_path: PathBuf,
     sym_path: PathBuf,
@@ -24,64 +214,84 @@ pub struct Session {
 impl Session {
     pub fn new(
         target: crate::Target,
-        cpu: Option<String>,
+        cpus: Vec<String>,
         out_path: PathBuf,
+        llvm_major: Option<u32>,
+        llvm_tools_path: Option<&Path>,
     ) -> anyhow::Result<Self> {
-        let link_path = out_path.with_extension("o");
-        let opt_path = out_path.with_extension("optimized.o");
-        let sym_path = out_path.with_extension("symbols.txt");
-
-        let version_output = std::process::Command::new("rustc")
-            .arg("--version")
-            .arg("--verbose")
-            .output()?;
-        let version_output = String::from_utf8(version_output.stdout).unwrap();
-
-        let mut llvm_version = None;
-
-        for line in version_output.lines() {
-            if let Some(version) = str::strip_prefix(line, "LLVM version: ") {
-                if let Some((version, _)) = version.split_once('.') {
-                    llvm_version = Some(String::from(version));
-                    break;
-                }
-            }
-        }
-
-        let Some(llvm_version) = llvm_version else {
-            anyhow::bail!("unable to determine LLVM version from:\n{version_output}");
-        };
-
-        let version = if let Ok(version_output) = std::process::Command::new(format!("llvm-link-{llvm_version}"))
-            .arg("--version")
-            .output()
-        {
-            tracing::info!(
-                "using specific llvm-link-{llvm_version} with version:\n{}",
-                String::from_utf8(version_output.stdout).unwrap(),
-            );
-
-            format!("-{llvm_version}")
-        } else if let Ok(version_output) = std::process::Command::new("llvm-link")
-            .arg("--version")
-            .output()
-        {
-            tracing::info!(
-                "using default llvm-link with version:\n{}",
-                String::from_utf8(version_output.stdout).unwrap(),
-            );
+        let intermediates = Intermediates::new(&out_path)?;
+        let link_path = intermediates.path(None, "linked.bc");
+        let opt_path = intermediates.path(None, "optimized.bc");
+        let sym_path = intermediates.path(None, "symbols.txt");
 
-            String::new()
-        } else {
-            anyhow::bail!("unable to determine find either llvm-link-{llvm_version} or llvm-link");
-        };
+        let tools = ToolLocator::get(llvm_major, llvm_tools_path)?;
 
         Ok(Session {
             target,
-            cpu,
+            cpus,
             symbols: Vec::new(),
             bitcode: Vec::new(),
-            version,
+            sources: Vec::new(),
+            prunable: Vec::new(),
+            prune: true,
+            rdc: false,
+            device_libs: Vec::new(),
+            emit: vec![Emit {
+                kind: EmitKind::Asm,
+                path: None,
+            }],
+            host_target: None,
+            codegen_optimization: None,
+            size_report: false,
+            globals_report: false,
+            remarks_summary: false,
+            debug_info: DebugInfo::Default,
+            split_debug: false,
+            dtor_policy: DtorPolicy::default(),
+            verify_ptx: false,
+            keep_symbols_format: KeepSymbolsFormat::default(),
+            ir_diff: None,
+            public_api: None,
+            kernel_metadata: None,
+            write_manifest: false,
+            post_processors: Vec::new(),
+            signer: None,
+            exported_metadata: Vec::new(),
+            target_features: Vec::new(),
+            function_features: Vec::new(),
+            unreachable: None,
+            device_asserts: false,
+            panic_trap: false,
+            strict_host_code: false,
+            profile_boundaries: None,
+            coalesce_constants: None,
+            rename_on_conflict: false,
+            renamed: Vec::new(),
+            fingerprint: None,
+            libdevice: None,
+            fallback_cpu: None,
+            inferred_cpu: None,
+            fallen_back: Mutex::default(),
+            used_tools: Mutex::default(),
+            stage_hashes: Mutex::default(),
+            locked: None,
+            export_patterns: Vec::new(),
+            trace: trace::Symbols::default(),
+            traced: Vec::new(),
+            tools,
+            diagnostics: Mutex::default(),
+            deny: Vec::new(),
+            memory: memory::Monitor::default(),
+            timings: false,
+            resume: false,
+            pipeline: Pipeline::default(),
+            passes: None,
+            extra_passes: None,
+            no_inline: Vec::new(),
+            force_inline: Vec::new(),
+            skipped: Vec::new(),
+            stop_after: None,
+            intermediates,
             link_path,
             opt_path,
             sym_path,
@@ -89,35 +299,136 @@ impl Session {
         })
     }
 
-    /// Link a rlib into a bitcode object and add it to the list of files ready
-    /// to be linked
-    pub fn link_rlib(&mut self, path: impl AsRef<Path>, keep_symbols: bool) -> anyhow::Result<()> {
-        let output_file_link = path.as_ref().with_extension("o");
-        tracing::info!(
-            "Linking rlib: {} into bitcode: {}",
-            path.as_ref().display(),
-            output_file_link.display(),
-        );
+    /// Link a rlib or an `ar` archive of bitcode members, e.g. built from the
+    /// output of `clang --cuda-device-only -emit-llvm`, into a bitcode object
+    /// and add it to the list of files ready to be linked
+    ///
+    /// Archive members that are not bitcode are ignored, and archives without
+    /// any bitcode are skipped with a note.
+    pub fn link_archive(
+        &mut self,
+        path: impl AsRef<Path>,
+        keep_symbols: bool,
+    ) -> anyhow::Result<()> {
+        let archive = std::fs::read(path.as_ref()).context(format!(
+            "Failed to read archive: {}",
+            path.as_ref().display()
+        ))?;
+        if archive::has_bitcode(&archive) == Some(false) {
+            let diagnostic = Diagnostic {
+                tool: env!("CARGO_PKG_NAME").to_owned(),
+                severity: Severity::Note,
+                message: "skipping archive without bitcode".to_owned(),
+                location: Some(Location {
+                    file: path.as_ref().display().to_string(),
+                    line: None,
+                    column: None,
+                }),
+            };
+            diagnostic.emit();
+            self.diagnostics.lock().unwrap().push(diagnostic);
+            return Ok(());
+        }
 
-        let link_output = std::process::Command::new(format!("llvm-link{}", self.version))
-            .arg(path.as_ref())
-            .arg("-o")
-            .arg(&output_file_link)
-            .arg("--ignore-non-bitcode")
-            .output()
-            .unwrap();
+        self.intermediates.create()?;
+        let name = self.archive_name(path.as_ref(), &archive);
+        let output_file_link = self.intermediates.path(None, &name);
+        if self.intermediates.load_cached(&name, &output_file_link)? {
+            tracing::info!(
+                "Reusing bitcode of archive: {} linked by an earlier link: {}",
+                path.as_ref().display(),
+                output_file_link.display(),
+            );
+        } else {
+            tracing::info!(
+                "Linking archive: {} into bitcode: {}",
+                path.as_ref().display(),
+                output_file_link.display(),
+            );
+            self.link_archive_members(path.as_ref(), &archive, &output_file_link)?;
+            self.intermediates.store_cached(&name, &output_file_link)?;
+        }
+        self.add_input(path.as_ref(), output_file_link, keep_symbols)
+    }
+
+    /// The name of the bitcode the members of the `archive` read from `path`
+    /// are linked into, e.g. `libfoo-0123456789abcdef.o`
+    ///
+    /// It is named after the hash of the archive and the LLVM version, so
+    /// that archives with the same stem never overwrite each other and later
+    /// links of any output reuse the bitcode from the cache.
+    fn archive_name(&self, path: &Path, archive: &[u8]) -> OsString {
+        let mut hasher = Hasher::default();
+        hasher.field(self.tools.llvm_major()).field(archive);
+
+        let mut name = path.file_stem().unwrap_or_default().to_owned();
+        name.push(format!("-{}.o", hasher.finish()));
+        name
+    }
+
+    /// Link the bitcode members of the `archive` read from `path` into
+    /// `output`
+    #[cfg(feature = "llvm")]
+    fn link_archive_members(
+        &self,
+        path: &Path,
+        archive: &[u8],
+        output: &Path,
+    ) -> anyhow::Result<()> {
+        let members = archive::bitcode_members(archive)
+            .context(format!("Not an archive: {}", path.display()))?;
+        let modules = members
+            .into_iter()
+            .map(|member| (path, member))
+            .collect::<Vec<_>>();
+        self.link_in_process(&modules, output)
+    }
+
+    /// Link the bitcode members of the archive at `path` into `output`
+    #[cfg(not(feature = "llvm"))]
+    fn link_archive_members(
+        &self,
+        path: &Path,
+        _archive: &[u8],
+        output: &Path,
+    ) -> anyhow::Result<()> {
+        let link_output = self.run_tool(
+            "llvm-link",
+            self.tools
+                .command("llvm-link")
+                .arg(path)
+                .arg("-o")
+                .arg(output)
+                .arg("--ignore-non-bitcode"),
+            None,
+        )?;
 
         if !link_output.status.success() {
-            tracing::error!(
-                "llvm-link returned with Exit status: {}\n stdout: {}\n stderr: {}",
-                link_output.status,
-                String::from_utf8(link_output.stdout).unwrap(),
-                String::from_utf8(link_output.stderr).unwrap(),
-            );
-            anyhow::bail!("llvm-link failed to link file {}", path.as_ref().display());
+            anyhow::bail!("llvm-link failed to link file {}", path.display());
         }
 
-        self.add_bitcode(output_file_link, keep_symbols)
+        Ok(())
+    }
+
+    /// Add an input of any kind, detected from its content
+    ///
+    /// Bitcode is added like with [`Session::add_bitcode`], keeping its
+    /// symbols, archives like with [`Session::link_archive`], not keeping
+    /// them, and cubins and fatbins as device libraries.
+    pub fn add_file(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let kind = InputKind::detect(path)?;
+        tracing::debug!("detected {kind:?} input: {}", path.display());
+
+        match kind {
+            InputKind::Bitcode => self.add_bitcode(path, true),
+            InputKind::Archive => self.link_archive(path, false),
+            InputKind::DeviceLib => self.add_device_lib(path),
+            InputKind::Ptx => anyhow::bail!(
+                "PTX input {} cannot be linked, only bitcode, archives and device libraries",
+                path.display()
+            ),
+        }
     }
 
     /// Add a bitcode module ready to be linked
@@ -126,243 +437,3696 @@ impl Session {
         path: impl AsRef<Path>,
         keep_symbols: bool,
     ) -> anyhow::Result<()> {
-        if keep_symbols {
-            let nm_output = std::process::Command::new(format!("llvm-nm{}", self.version))
-                .arg("--extern-only")
-                .arg("--export-symbols")
-                .arg(path.as_ref())
-                .output()
-                .unwrap();
-
-            if !nm_output.status.success() {
-                tracing::error!(
-                    "llvm-nm returned with Exit status: {}\n stdout: {}\n stderr: {}",
-                    nm_output.status,
-                    String::from_utf8(nm_output.stdout).unwrap(),
-                    String::from_utf8(nm_output.stderr).unwrap(),
-                );
-                anyhow::bail!(
-                    "llvm-nm failed to return symbols from file {}",
-                    path.as_ref().display()
+        self.add_input(path.as_ref(), path.as_ref(), keep_symbols)
+    }
+
+    /// Add the single precision math functions built into the linker, which
+    /// the inputs may define themselves instead
+    ///
+    /// It should be added before the other inputs, so that the linked module
+    /// takes their data layout instead of llvm-link warning about its missing
+    /// one.
+    pub fn add_builtin_math(&mut self) -> anyhow::Result<()> {
+        self.intermediates.create()?;
+        let path = self.intermediates.path(None, "math.bc");
+        self.write_ir(&math::builtin_ir(self.target), &path)?;
+        tracing::debug!("linking the builtin math functions");
+        self.add_bitcode(path, false)
+    }
+
+    /// Link CUDA's libdevice at `path`, or the one of the CUDA toolkit in
+    /// `$CUDA_HOME` or `$CUDA_PATH`, so that the device code can call its math
+    /// functions like `__nv_sinf`
+    ///
+    /// Its `__nvvm_reflect` queries, e.g. for flushing denormals, are folded
+    /// before optimizing so that only the taken branches are kept. The calls
+    /// of the other inputs to math intrinsics the backend cannot select, like
+    /// `llvm.sin.f32`, are mapped to their libdevice equivalents.
+    pub fn add_libdevice(&mut self, path: Option<&Path>) -> anyhow::Result<()> {
+        let path = match path {
+            Some(path) => path.to_owned(),
+            None => super::tools::cuda_libdevice().context(
+                "unable to find libdevice in $CUDA_HOME/nvvm/libdevice or $CUDA_PATH/nvvm/libdevice, \
+                 give its path with --libdevice",
+            )?,
+        };
+        tracing::info!("linking libdevice: {}", path.display());
+        self.libdevice = Some(self.bitcode.len());
+        self.add_bitcode(path, false)
+    }
+
+    /// Add the bitcode module at `path` linked from the input `source`
+    fn add_input(
+        &mut self,
+        source: &Path,
+        path: impl AsRef<Path>,
+        keep_symbols: bool,
+    ) -> anyhow::Result<()> {
+        if keep_symbols || self.trace.is_enabled() {
+            let entries = self.defined_symbols(path.as_ref(), true)?;
+
+            for entry in &entries {
+                self.trace.log(
+                    &entry.name,
+                    format_args!("defined in {}", path.as_ref().display()),
                 );
+                if self.trace.matches(&entry.name) {
+                    self.traced.push(entry.name.clone());
+                }
             }
 
-            let symbol_string = String::from_utf8(nm_output.stdout).unwrap();
-            let symbols = symbol_string
-                .split_whitespace()
-                .filter(|s| {
-                    *s != "__rg_oom" && *s != "rust_begin_unwind" && !s.starts_with("__rust_")
-                })
-                .map(String::from)
-                .collect::<Vec<_>>();
-            info!(
-                "Extracted {} symbols from {:?}: {:?}",
-                symbols.len(),
-                path.as_ref(),
-                symbols
-            );
-            self.symbols.extend(symbols);
+            if keep_symbols {
+                let mut symbols = entries
+                    .into_iter()
+                    .map(|symbol| symbol.name)
+                    .filter(|s| {
+                        let keep = s.as_bytes() != b"__rg_oom"
+                            && s.as_bytes() != b"rust_begin_unwind"
+                            && !s.starts_with("__rust_");
+                        if !keep {
+                            self.trace
+                                .log(s, "not kept since it is provided by the Rust runtime");
+                        }
+                        keep
+                    })
+                    .collect::<Vec<_>>();
+                symbols.sort();
+                symbols.dedup();
+                info!(
+                    "Extracted {} symbols from {:?}: {:?}",
+                    symbols.len(),
+                    path.as_ref(),
+                    symbols.iter().map(Symbol::demangled).collect::<Vec<_>>()
+                );
+                for symbol in &symbols {
+                    self.trace.log(symbol, "added to the keep-set");
+                }
+                self.symbols.extend(symbols);
+            }
         }
 
+        if !keep_symbols {
+            self.prunable.push(self.bitcode.len());
+        }
+        self.sources
+            .push((source.to_owned(), path.as_ref().to_owned()));
         self.bitcode.push(path.as_ref().to_owned());
         Ok(())
     }
 
-    fn link(&mut self) -> anyhow::Result<()> {
-        tracing::info!(
-            "Linking {} bitcode files using llvm-link",
-            self.bitcode.len()
-        );
+    /// Keep the global `symbol` even if no input added with `keep_symbols`
+    /// defines it
+    pub fn keep_symbol(&mut self, symbol: impl Into<Vec<u8>>) {
+        let symbol = Symbol::new(symbol);
+        self.trace.log(&symbol, "added to the keep-set");
+        self.symbols.push(symbol);
+    }
 
-        let llvm_link_output = std::process::Command::new(format!("llvm-link{}", self.version))
-            .args(&self.bitcode)
-            .arg("-o")
-            .arg(&self.link_path)
-            .output()
-            .unwrap();
+    /// Keep the symbols listed in the file at `path`, one per line
+    ///
+    /// Lines may be glob patterns where `*` matches any sequence and `?` any
+    /// single byte, which keep all matching symbols defined by any input, e.g.
+    /// kernels that are registered dynamically. Empty lines and lines
+    /// starting with `#` are ignored.
+    pub fn export_symbols_file(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let file = std::fs::read_to_string(path)
+            .context(format!("Failed to read symbol file: {}", path.display()))?;
 
-        if !llvm_link_output.status.success() {
-            tracing::error!(
-                "llvm-link returned with Exit status: {}\n stdout: {}\n stderr: {}",
-                llvm_link_output.status,
-                String::from_utf8(llvm_link_output.stdout).unwrap(),
-                String::from_utf8(llvm_link_output.stderr).unwrap(),
-            );
-            anyhow::bail!("llvm-link failed to link bitcode files {:?}", self.bitcode);
+        for line in file.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.contains(['*', '?']) {
+                self.export_patterns.push(line.to_owned());
+            } else {
+                self.keep_symbol(line);
+            }
         }
 
         Ok(())
     }
 
-    /// Optimize using `opt`
+    /// Compile to relocatable device code and link it with `nvlink` into a
+    /// cubin, together with any device libraries
+    pub fn set_rdc(&mut self, rdc: bool) {
+        self.rdc = rdc;
+    }
+
+    /// Log every linking decision about the symbols matching the glob
+    /// `patterns`
     ///
-    /// Before this can be called `link` needs to be called
-    fn optimize(
-        &mut self,
-        optimization: Optimization,
-        mut internalize: bool,
-        mut debug: bool,
-        mut inline: bool,
-    ) -> anyhow::Result<()> {
-        let mut passes = format!("default<{optimization}>");
+    /// Needs to be set before adding any inputs.
+    pub fn set_trace_symbols(&mut self, patterns: Vec<String>) {
+        self.trace = trace::Symbols::new(patterns);
+    }
 
-        // FIXME(@kjetilkjeka) The whole corelib currently cannot be compiled for
-        // nvptx64 so everything relies on not using the troublesome symbols and
-        // removing them during linking
-        if !internalize && self.target == crate::Target::Nvptx64NvidiaCuda {
-            tracing::warn!("nvptx64 target detected - internalizing symbols");
-            internalize = true;
-        }
+    /// Remove unreachable functions from inputs whose symbols are not kept
+    /// before linking them, enabled by default
+    pub fn set_prune(&mut self, prune: bool) {
+        self.prune = prune;
+    }
 
-        // FIXME(@kjetilkjeka) Debug symbol generation is broken for nvptx64 so we must
-        // remove them even in debug mode
-        if debug && self.target == crate::Target::Nvptx64NvidiaCuda {
-            tracing::warn!("nvptx64 target detected - stripping debug symbols");
-            debug = false;
-        }
+    /// Abort once the linker and its tools use more than `limit_mb` megabytes
+    /// of memory
+    pub fn set_max_memory(&mut self, limit_mb: Option<u64>) {
+        self.memory.set_limit(limit_mb);
+    }
 
-        if !inline && self.target == crate::Target::Nvptx64NvidiaCuda {
-            tracing::warn!("nvptx64 target detected - inlining all symbols");
-            inline = true;
-        }
+    /// Kill and fail on any tool that runs longer than `timeout`
+    pub fn set_tool_timeout(&mut self, timeout: Option<Duration>) {
+        self.memory.set_timeout(timeout);
+    }
 
-        if internalize {
-            passes.push_str(",internalize,globaldce");
-            let symbol_file_content = self.symbols.iter().fold(String::new(), |s, x| s + x + "\n");
-            std::fs::write(&self.sym_path, symbol_file_content).context(format!(
-                "Failed to write symbol file: {}",
-                self.sym_path.display()
-            ))?;
-        }
+    /// Build emitted host objects for the target triple `host_target` instead
+    /// of the default target of `llc`
+    pub fn set_host_target(&mut self, host_target: Option<String>) {
+        self.host_target = host_target;
+    }
 
-        tracing::info!("optimizing bitcode with passes: {}", passes);
-        let mut opt_cmd = std::process::Command::new(format!("opt{}", self.version));
-        opt_cmd
-            .arg(&self.link_path)
-            .arg("-o")
-            .arg(&self.opt_path)
-            .arg(format!(
-                "--internalize-public-api-file={}",
-                self.sym_path.display()
-            ))
-            .arg(format!("--passes={passes}"));
+    /// Generate code with `llc` at the level of `optimization` instead of the
+    /// default of `llc`, independently of the optimization level of `opt`
+    pub fn set_codegen_optimization(&mut self, optimization: Option<Optimization>) {
+        self.codegen_optimization = optimization;
+    }
 
-        if !debug {
-            opt_cmd.arg("--strip-debug");
-        }
+    /// Log the mutable globals of the linked module that the device code
+    /// writes but never reads, reads but never writes, or never accesses
+    pub fn set_globals_report(&mut self, globals_report: bool) {
+        self.globals_report = globals_report;
+    }
 
-        let opt_output = opt_cmd.output().unwrap();
+    /// Keep as much of the requested debug information as `debug_info` says,
+    /// e.g. all of it on nvptx64, which strips it by default
+    pub fn set_debug_info(&mut self, debug_info: DebugInfo) {
+        self.debug_info = debug_info;
+    }
 
-        if !opt_output.status.success() {
-            tracing::error!(
-                "opt returned with Exit status: {}\n stdout: {}\n stderr: {}",
-                opt_output.status,
-                String::from_utf8(opt_output.stdout).unwrap(),
-                String::from_utf8(opt_output.stderr).unwrap(),
-            );
-            anyhow::bail!("opt failed optimize bitcode: {}", self.link_path.display());
-        };
+    /// Write the DWARF sections of the PTX to a separate `.debug` file next
+    /// to it, if debug information is kept
+    pub fn set_split_debug(&mut self, split_debug: bool) {
+        self.split_debug = split_debug;
+    }
 
-        if !inline {
-            return Ok(());
+    /// Select how destructors registered by the device code are handled
+    pub fn set_dtor_policy(&mut self, dtor_policy: DtorPolicy) {
+        self.dtor_policy = dtor_policy;
+    }
+
+    /// Check the structure of the emitted PTX before it is used, see
+    /// [`ptx::parse`]
+    pub fn set_verify_ptx(&mut self, verify_ptx: bool) {
+        self.verify_ptx = verify_ptx;
+    }
+
+    /// The format of the keep-set emitted with [`EmitKind::KeepSymbols`]
+    pub fn set_keep_symbols_format(&mut self, format: KeepSymbolsFormat) {
+        self.keep_symbols_format = format;
+    }
+
+    /// Log the functions of the optimized module that differ from the bitcode
+    /// module `previous` of an earlier build
+    pub fn set_ir_diff(&mut self, previous: Option<PathBuf>) {
+        self.ir_diff = previous;
+    }
+
+    /// Fail the link if the optimized module exports any symbol not listed in
+    /// the file at `path`, one per line, or misses a listed one
+    pub fn set_public_api(&mut self, path: &Path) -> anyhow::Result<()> {
+        self.public_api = Some(PublicApi::load(path)?);
+        Ok(())
+    }
+
+    /// Write the names, parameters, launch bounds and PTX ISA versions of the
+    /// kernels of the final module as JSON to `path`
+    pub fn set_kernel_metadata(&mut self, path: Option<PathBuf>) {
+        self.kernel_metadata = path;
+    }
+
+    /// Promote the `deny` warnings of the external tools to errors that fail
+    /// the link
+    pub fn deny(&mut self, deny: Deny) {
+        self.deny.push(deny);
+    }
+
+    /// Compile the whole module with the target `feature`, e.g. `+ptx80` to
+    /// emit a newer PTX ISA version than the one of the target cpu
+    pub fn add_target_feature(&mut self, feature: TargetFeature) {
+        self.target_features.push(feature);
+    }
+
+    /// Compile the function named in `features` with its target features
+    /// added to those of the module, e.g. to use newer instructions in a single
+    /// kernel guarded at runtime
+    pub fn override_target_features(&mut self, features: FunctionFeatures) {
+        self.function_features.push(features);
+    }
+
+    /// Lower `unreachable` terminators as `unreachable` says instead of
+    /// leaving them to `llc`
+    pub fn set_unreachable(&mut self, unreachable: Option<Unreachable>) {
+        self.unreachable = unreachable;
+    }
+
+    /// Report panics of the device code, e.g. failed `assert!`s, with their
+    /// message, file and line through `__assertfail` instead of trapping
+    ///
+    /// The file and line are only known for inputs with debug information.
+    pub fn set_device_asserts(&mut self, device_asserts: bool) {
+        self.device_asserts = device_asserts;
+    }
+
+    /// Replace the panic functions of `core` with a trap, so that the panic
+    /// formatting code they pull in is removed
+    ///
+    /// Panics reported with [`Session::set_device_asserts`] still call
+    /// `__assertfail`.
+    pub fn set_panic_trap(&mut self, panic_trap: bool) {
+        self.panic_trap = panic_trap;
+    }
+
+    /// Fail the link instead of warning when the device code uses host-only
+    /// code, like thread-locals or the threads and files of `std`
+    pub fn set_strict_host_code(&mut self, strict_host_code: bool) {
+        self.strict_host_code = strict_host_code;
+    }
+
+    /// Keep the functions called by kernels with at least `min_instructions`
+    /// IR instructions out of line instead of inlining them, so that profilers
+    /// sampling call stacks show them
+    pub fn set_profile_boundaries(&mut self, min_instructions: Option<usize>) {
+        self.profile_boundaries = min_instructions;
+    }
+
+    /// Coalesce the local constant globals of at most `max_size` bytes into
+    /// a single array per address space, which saves a PTX declaration each
+    /// and keeps lookup tables close together
+    pub fn set_coalesce_constants(&mut self, max_size: Option<u64>) {
+        self.coalesce_constants = max_size;
+    }
+
+    /// Rename the symbols that several inputs define, e.g. duplicated
+    /// intrinsics of `compiler_builtins`, in all inputs but the first instead
+    /// of failing the link
+    ///
+    /// Only symbols that are internalized can be renamed, the link still
+    /// fails for kernels and kept symbols.
+    pub fn set_rename_on_conflict(&mut self, rename_on_conflict: bool) {
+        self.rename_on_conflict = rename_on_conflict;
+    }
+
+    /// Compile for `cpu` instead of the target cpus that `llc`, `ptxas` or
+    /// `nvlink` reject, e.g. as the toolchain is older than them
+    pub fn set_fallback_cpu(&mut self, cpu: Option<String>) {
+        self.fallback_cpu = cpu;
+    }
+
+    /// Write the manifest of the outputs as JSON next to the output, e.g.
+    /// `kernel.ptx.outputs.json` for `kernel.ptx`
+    ///
+    /// It is also written for post-processors, which are passed its path.
+    pub fn set_write_manifest(&mut self, write_manifest: bool) {
+        self.write_manifest = write_manifest;
+    }
+
+    /// Run `post_processor` on every output after a successful link
+    pub fn add_post_processor(&mut self, post_processor: impl PostProcessor + 'static) {
+        self.post_processors.push(Box::new(post_processor));
+    }
+
+    /// Sign every output with the Ed25519 private key in the PKCS#8 PEM file at
+    /// `path`, writing detached signatures next to them
+    pub fn set_sign_key(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        self.signer = Some(Signer::load(path.as_ref())?);
+        Ok(())
+    }
+
+    /// Refuse to link with other versions of the tools than those recorded
+    /// in the output manifest at `path` of an earlier link
+    pub fn set_locked(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let manifest =
+            std::fs::read(path).context(format!("Failed to read manifest: {}", path.display()))?;
+        let manifest = Manifest::from_slice(&manifest)
+            .context(format!("Failed to read manifest: {}", path.display()))?;
+        if manifest.tools.is_empty() {
+            anyhow::bail!("the manifest records no tool versions: {}", path.display());
         }
+        self.locked = Some(manifest.tools);
+        Ok(())
+    }
 
-        let nm_output = std::process::Command::new(format!("llvm-nm{}", self.version))
-            .arg("--format=just-symbols")
-            .arg("--defined-only")
-            .arg(&self.opt_path)
-            .output()
-            .unwrap();
+    /// Keep the named metadata `name`, e.g. `my.kernels`, by keeping the
+    /// globals it references, and emit it with [`EmitKind::Metadata`]
+    pub fn keep_exported_metadata(&mut self, name: impl Into<String>) {
+        self.exported_metadata.push(name.into());
+    }
 
-        if !nm_output.status.success() {
-            tracing::error!(
-                "llvm-nm returned with Exit status: {}\n stdout: {}\n stderr: {}",
-                nm_output.status,
-                String::from_utf8(nm_output.stdout).unwrap(),
-                String::from_utf8(nm_output.stderr).unwrap(),
-            );
-            anyhow::bail!(
-                "llvm-nm failed to return symbols from file {}",
-                self.opt_path.display()
-            );
+    /// Optimize with the pass pipeline `passes`, e.g.
+    /// `default<O3>,loop-unroll`, instead of the one of the optimization
+    /// level and the [`Pipeline`]
+    ///
+    /// Internalizing still runs `internalize,globaldce` after it.
+    pub fn set_passes(&mut self, passes: Option<String>) {
+        self.passes = passes;
+    }
+
+    /// Run the comma separated `passes` after the optimization pipeline
+    pub fn set_extra_passes(&mut self, passes: Option<String>) {
+        self.extra_passes = passes;
+    }
+
+    /// Keep the functions matching the glob `pattern`, by name or demangled
+    /// path, out of line, e.g. large cold functions that would blow up the
+    /// kernels they are inlined into
+    ///
+    /// This takes precedence over [`Session::force_inline`].
+    pub fn no_inline(&mut self, pattern: impl Into<String>) {
+        self.no_inline.push(pattern.into());
+    }
+
+    /// Inline the functions matching the glob `pattern`, by name or demangled
+    /// path, into all their callers, even for targets whose functions are not
+    /// all inlined
+    pub fn force_inline(&mut self, pattern: impl Into<String>) {
+        self.force_inline.push(pattern.into());
+    }
+
+    /// Skip the optional `step`, e.g. to bisect a miscompilation
+    pub fn skip_step(&mut self, step: Step) {
+        if !self.skipped.contains(&step) {
+            self.skipped.push(step);
         }
-        let symbol_string = String::from_utf8(nm_output.stdout).unwrap();
+    }
 
-        let passes =
-            format!("default<{optimization}>,forceattrs,always-inline,gvn,globalopt,mem2reg,dse,globalopt");
+    /// Run only `step` and the steps needed to produce its input, writing its
+    /// output to the output file
+    ///
+    /// The output of [`Step::Prune`] are the pruned bitcode inputs, written to
+    /// the output file if there is a single one and to numbered files next to
+    /// it otherwise, e.g. `out.0.bc` and `out.1.bc` for `out.bc`. The outputs of
+    /// the other steps before [`Step::Codegen`] are bitcode modules.
+    pub fn set_only_step(&mut self, step: Step) {
+        for other in [Step::Prune, Step::Internalize, Step::Optimize, Step::Inline] {
+            if other != step {
+                self.skip_step(other);
+            }
+        }
+        self.stop_after = Some(step);
+    }
 
-        tracing::info!("inlining bitcode with passes: {}", passes);
-        let mut opt_cmd = std::process::Command::new(format!("opt{}", self.version));
-        opt_cmd
-            .arg(&self.opt_path)
-            .arg("-o")
-            .arg(&self.opt_path)
-            .arg(format!("--passes={passes}"));
+    fn is_skipped(&self, step: Step) -> bool {
+        self.skipped.contains(&step)
+    }
+
+    /// Select how the inputs are optimized, see [`Pipeline`]
+    pub fn set_pipeline(&mut self, pipeline: Pipeline) {
+        self.pipeline = pipeline;
+    }
+
+    /// Keep the intermediates when the session is dropped instead of removing
+    /// them, e.g. for debugging or for later links to reuse
+    pub fn set_keep_intermediates(&mut self, keep: bool) {
+        self.intermediates.set_keep(keep);
+    }
+
+    /// Skip the stages completed by an earlier run with the same inputs and
+    /// options whose outputs are unchanged
+    pub fn set_resume(&mut self, resume: bool) {
+        self.resume = resume;
+    }
+
+    /// Log the duration of each stage and the peak memory usage
+    pub fn set_timings(&mut self, timings: bool) {
+        self.timings = timings;
+        self.memory.set_track(timings);
+    }
 
-        for symbol in symbol_string.split_whitespace() {
-            opt_cmd.arg(format!("--force-attribute={symbol}:alwaysinline"));
+    /// Select the outputs to emit, PTX assembly by default
+    pub fn set_emit(&mut self, emit: Vec<Emit>) {
+        if !emit.is_empty() {
+            self.emit = emit;
         }
+    }
 
-        let opt_output = opt_cmd.output().unwrap();
+    /// Log the number of PTX instructions per function and per crate
+    pub fn set_size_report(&mut self, size_report: bool) {
+        self.size_report = size_report;
+    }
 
-        if !opt_output.status.success() {
-            tracing::error!(
-                "opt returned with Exit status: {}\n stdout: {}\n stderr: {}",
-                opt_output.status,
-                String::from_utf8(opt_output.stdout).unwrap(),
-                String::from_utf8(opt_output.stderr).unwrap(),
-            );
-            anyhow::bail!("opt failed inline bitcode: {}", self.opt_path.display());
-        };
+    /// Log what the optimizer did and failed to do in each kernel, digested
+    /// from the optimization remarks of `opt`
+    pub fn set_remarks_summary(&mut self, remarks_summary: bool) {
+        self.remarks_summary = remarks_summary;
+    }
 
+    /// Add a prebuilt cubin or fatbin with relocatable device code to be linked
+    /// in relocatable device code mode
+    pub fn add_device_lib(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let device_lib = DeviceLib::open(path.as_ref())?;
+        tracing::info!(
+            "Adding {:?} device library: {}",
+            device_lib.kind,
+            device_lib.path.display()
+        );
+        self.device_libs.push(device_lib);
         Ok(())
     }
 
-    /// Compile to native format using `llc`
+    /// Internalize and remove everything unreachable from the prunable inputs
     ///
-    /// Before this can be called `optimize` needs to be called
-    fn compile(&mut self) -> anyhow::Result<()> {
-        let mut lcc_command = std::process::Command::new(format!("llc{}", self.version));
-
-        if let Some(mcpu) = &self.cpu {
-            lcc_command.arg("--mcpu").arg(mcpu);
+    /// The roots are the kept symbols and everything referenced by any input.
+    /// This is a conservative but cheap approximation of what is removed after
+    /// linking anyway, which reduces the size of the merged module.
+    fn prune(&mut self) -> anyhow::Result<()> {
+        if self.prunable.is_empty() {
+            return Ok(());
         }
 
-        let lcc_output = lcc_command
-            .arg(&self.opt_path)
-            .arg("-o")
-            .arg(&self.out_path)
-            .output()
-            .unwrap();
-
-        if !lcc_output.status.success() {
-            tracing::error!(
-                "llc returned with Exit status: {}\n stdout: {}\n stderr: {}",
-                lcc_output.status,
-                String::from_utf8(lcc_output.stdout).unwrap(),
-                String::from_utf8(lcc_output.stderr).unwrap(),
-            );
+        let nm_output = self.run_tool(
+            "llvm-nm",
+            self.tools
+                .command("llvm-nm")
+                .args(nm::FORMAT_ARGS)
+                .arg("--undefined-only")
+                .args(&self.bitcode),
+            None,
+        )?;
 
+        if !nm_output.status.success() {
             anyhow::bail!(
-                "llc failed to compile {} into {}",
-                self.opt_path.display(),
-                self.out_path.display()
+                "llvm-nm failed to return symbols from files {:?}",
+                self.bitcode
             );
         }
 
-        Ok(())
-    }
+        // Kernels are only called by the host, so they are roots themselves
+        let mut kernels = Vec::new();
+        for &index in &self.prunable {
+            kernels.extend(kernels::defined(&self.disassemble(&self.bitcode[index])?));
+        }
 
-    /// Links, optimizes and compiles to the native format
-    pub fn lto(
-        &mut self,
+        let mut roots = nm::parse(&nm_output.stdout)
+            .into_iter()
+            .map(|entry| entry.name)
+            .chain(self.symbols.iter().cloned())
+            .chain(kernels.into_iter().map(Symbol::new))
+            .collect::<Vec<_>>();
+        roots.sort();
+        roots.dedup();
+
+        let roots_path = self.intermediates.path(None, "roots.txt");
+        std::fs::write(&roots_path, KeepSymbolsFormat::LlvmApi.encode(&roots)).context(format!(
+            "Failed to write symbol file: {}",
+            roots_path.display()
+        ))?;
+
+        tracing::info!(
+            "Pruning {} inputs using {} roots",
+            self.prunable.len(),
+            roots.len()
+        );
+
+        for &index in &self.prunable {
+            let path = &self.bitcode[index];
+            let pruned_path = self.intermediates.path(None, format!("{index}.pruned.bc"));
+
+            let opt_output = self.run_tool(
+                "opt",
+                self.tools
+                    .command("opt")
+                    .arg(path)
+                    .arg("-o")
+                    .arg(&pruned_path)
+                    .arg(path_arg("--internalize-public-api-file=", &roots_path))
+                    .args(self.pass_args("internalize,globaldce")?),
+                None,
+            )?;
+
+            if !opt_output.status.success() {
+                anyhow::bail!("opt failed to prune bitcode: {}", path.display());
+            }
+
+            let size = |path: &Path| std::fs::metadata(path).map_or(0, |metadata| metadata.len());
+            tracing::info!(
+                "Pruned {}: {} -> {} bytes",
+                path.display(),
+                size(path),
+                size(&pruned_path)
+            );
+
+            self.bitcode[index] = pruned_path;
+        }
+
+        Ok(())
+    }
+
+    /// Optimize every input on its own with `default<O>` before merging them,
+    /// at most `jobs` at a time
+    fn premerge(&mut self, optimization: Optimization, jobs: NonZeroUsize) -> anyhow::Result<()> {
+        let modules = self
+            .bitcode
+            .iter()
+            .enumerate()
+            .map(|(index, path)| {
+                let premerged_path = self
+                    .intermediates
+                    .path(None, format!("{index}.premerged.bc"));
+                (path.clone(), premerged_path)
+            })
+            .collect::<Vec<_>>();
+
+        tracing::info!(
+            "optimizing {} inputs before merging using {} job(s)",
+            modules.len(),
+            jobs.get().min(modules.len())
+        );
+
+        run_parallel(&modules, jobs, |(path, premerged_path)| {
+            let opt_output = self.run_tool(
+                "opt",
+                self.tools
+                    .command("opt")
+                    .arg(path)
+                    .arg("-o")
+                    .arg(premerged_path)
+                    .args(self.pass_args(&format!("default<{optimization}>"))?),
+                None,
+            )?;
+
+            if !opt_output.status.success() {
+                anyhow::bail!("opt failed to optimize bitcode: {}", path.display());
+            }
+
+            Ok(())
+        })?;
+
+        self.bitcode = modules
+            .into_iter()
+            .map(|(_, premerged_path)| premerged_path)
+            .collect();
+        Ok(())
+    }
+
+    /// Import the functions every input calls from the others, guided by the
+    /// combined summary index of all inputs, and optimize them in parallel
+    ///
+    /// The optimized inputs are cached, so that an input is only optimized
+    /// again if it or the functions imported into it changed since any earlier
+    /// link and the link time scales with the number of changed inputs.
+    fn thin_optimize(
+        &mut self,
+        optimization: Optimization,
+        jobs: NonZeroUsize,
+    ) -> anyhow::Result<()> {
+        let modules = (0..self.bitcode.len())
+            .map(|index| {
+                [
+                    self.bitcode[index].clone(),
+                    self.intermediates.path(None, format!("{index}.summary.bc")),
+                    self.intermediates
+                        .path(None, format!("{index}.imported.bc")),
+                    self.intermediates.path(None, format!("{index}.thinlto.bc")),
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        tracing::info!(
+            "summarizing {} inputs using {} job(s)",
+            modules.len(),
+            jobs.get().min(modules.len())
+        );
+        run_parallel(&modules, jobs, |[path, summary_path, ..]| {
+            let opt_output = self.run_tool(
+                "opt",
+                self.tools
+                    .command("opt")
+                    .arg(path)
+                    .arg("-o")
+                    .arg(summary_path)
+                    .arg("--thinlto-bc"),
+                None,
+            )?;
+
+            if !opt_output.status.success() {
+                anyhow::bail!("opt failed to summarize bitcode: {}", path.display());
+            }
+
+            Ok(())
+        })?;
+
+        let index_path = self.intermediates.path(None, "thinlto.index.bc");
+        let thin_link_output = self.run_tool(
+            "llvm-lto",
+            self.tools
+                .command("llvm-lto")
+                .arg("--thinlto-action=thinlink")
+                .args(modules.iter().map(|[_, summary_path, ..]| summary_path))
+                .arg("-o")
+                .arg(&index_path),
+            None,
+        )?;
+
+        if !thin_link_output.status.success() {
+            anyhow::bail!(
+                "llvm-lto failed to link the summaries of {:?}",
+                self.bitcode
+            );
+        }
+
+        let reused = AtomicUsize::new(0);
+        run_parallel(&modules, jobs, |module| {
+            if self.thin_backend(module, &index_path, optimization)? {
+                reused.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(())
+        })?;
+
+        tracing::info!(
+            "reused {} of {} inputs optimized by an earlier link",
+            reused.into_inner(),
+            modules.len()
+        );
+
+        self.bitcode = modules
+            .into_iter()
+            .map(|[.., optimized_path]| optimized_path)
+            .collect();
+        Ok(())
+    }
+
+    /// Import into and optimize a single `[input, summary, imported,
+    /// optimized]` module of [`Session::thin_optimize`], returning whether the
+    /// module optimized by an earlier link was reused
+    fn thin_backend(
+        &self,
+        [path, summary_path, imported_path, optimized_path]: &[PathBuf; 4],
+        index_path: &Path,
+        optimization: Optimization,
+    ) -> anyhow::Result<bool> {
+        let import_output = self.run_tool(
+            "llvm-lto",
+            self.tools
+                .command("llvm-lto")
+                .arg("--thinlto-action=import")
+                .arg(path_arg("--thinlto-index=", index_path))
+                .arg(summary_path)
+                .arg("-o")
+                .arg(imported_path),
+            None,
+        )?;
+
+        if !import_output.status.success() {
+            anyhow::bail!("llvm-lto failed to import into bitcode: {}", path.display());
+        }
+
+        let pass_args = self.pass_args(&format!("default<{optimization}>"))?;
+        let mut hasher = Hasher::default();
+        hasher
+            .field(self.tools.llvm_major())
+            .field(pass_args.join(" "))
+            .file(imported_path)
+            .context(format!(
+                "Failed to hash bitcode: {}",
+                imported_path.display()
+            ))?;
+        let key = format!("{}.thinlto.bc", hasher.finish());
+        if self.intermediates.load_cached(&key, optimized_path)? {
+            return Ok(true);
+        }
+
+        let opt_output = self.run_tool(
+            "opt",
+            self.tools
+                .command("opt")
+                .arg(imported_path)
+                .arg("-o")
+                .arg(optimized_path)
+                .args(pass_args),
+            None,
+        )?;
+
+        if !opt_output.status.success() {
+            anyhow::bail!("opt failed to optimize bitcode: {}", path.display());
+        }
+
+        self.intermediates.store_cached(key, optimized_path)?;
+        Ok(false)
+    }
+
+    #[cfg(feature = "llvm")]
+    fn link(&mut self) -> anyhow::Result<()> {
+        tracing::info!("Linking {} bitcode files in process", self.bitcode.len());
+
+        let bitcode = self
+            .bitcode
+            .iter()
+            .map(|path| {
+                std::fs::read(path).context(format!("Failed to read bitcode: {}", path.display()))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let modules = self
+            .bitcode
+            .iter()
+            .map(PathBuf::as_path)
+            .zip(bitcode.iter().map(Vec::as_slice))
+            .collect::<Vec<_>>();
+        self.link_in_process(&modules, &self.link_path)
+    }
+
+    /// Link the bitcode `modules` with LLVM into `output`
+    #[cfg(feature = "llvm")]
+    fn link_in_process(&self, modules: &[(&Path, &[u8])], output: &Path) -> anyhow::Result<()> {
+        Interrupted::check()?;
+
+        let mut diagnostics = Vec::new();
+        let linked = super::llvm::link(modules, &mut diagnostics);
+        self.record_diagnostics(super::llvm::TOOL, diagnostics)?;
+
+        std::fs::write(output, linked?).context(format!(
+            "Failed to write linked bitcode: {}",
+            output.display()
+        ))
+    }
+
+    #[cfg(not(feature = "llvm"))]
+    fn link(&mut self) -> anyhow::Result<()> {
+        tracing::info!(
+            "Linking {} bitcode files using llvm-link",
+            self.bitcode.len()
+        );
+
+        let llvm_link_output = self.run_tool(
+            "llvm-link",
+            self.tools
+                .command("llvm-link")
+                .args(&self.bitcode)
+                .arg("-o")
+                .arg(&self.link_path),
+            None,
+        )?;
+
+        if !llvm_link_output.status.success() {
+            anyhow::bail!("llvm-link failed to link bitcode files {:?}", self.bitcode);
+        }
+
+        Ok(())
+    }
+
+    /// Link, and if the inputs conflict in their panic strategy link them
+    /// again with all of them forced to abort, and with
+    /// [`Session::set_rename_on_conflict`] if they define the same symbols
+    /// with those renamed
+    fn link_reconciling(&mut self) -> anyhow::Result<()> {
+        let mut forced_abort = false;
+        let mut renamed = false;
+        loop {
+            let reported = self.diagnostics.lock().unwrap().len();
+            let Err(err) = self.link() else {
+                return Ok(());
+            };
+
+            let diagnostics = self.diagnostics.lock().unwrap()[reported..].to_vec();
+            let flag = diagnostics.iter().find_map(|diagnostic| {
+                panic::conflicting_flag(&diagnostic.message).map(str::to_owned)
+            });
+            let multiply_defined = diagnostics
+                .iter()
+                .any(|diagnostic| conflicts::is_multiply_defined(&diagnostic.message));
+
+            match flag {
+                Some(flag) if !forced_abort => {
+                    // The link is retried, so the conflict is not an error of
+                    // the session
+                    self.diagnostics.lock().unwrap().truncate(reported);
+                    self.force_abort(&flag)?;
+                    forced_abort = true;
+                }
+                _ if multiply_defined && self.rename_on_conflict && !renamed => {
+                    self.diagnostics.lock().unwrap().truncate(reported);
+                    self.rename_conflicts()?;
+                    renamed = true;
+                }
+                _ if multiply_defined && !renamed => return self.report_conflicts(err),
+                _ => return Err(err),
+            }
+        }
+    }
+
+    /// Force the panic strategy module flag `flag` of all inputs to abort
+    fn force_abort(&mut self, flag: &str) -> anyhow::Result<()> {
+        tracing::warn!(
+            "the inputs were built with both panic=abort and panic=unwind, linking them all with panic=abort as device code cannot unwind"
+        );
+        for index in 0..self.bitcode.len() {
+            let Some(ir) = panic::force_abort(&self.disassemble(&self.bitcode[index])?, flag)
+            else {
+                continue;
+            };
+            let path = self.intermediates.path(None, format!("{index}.abort.bc"));
+            tracing::info!("forcing panic=abort: {}", self.bitcode[index].display());
+            self.write_ir(&ir, &path)?;
+            self.bitcode[index] = path;
+        }
+        Ok(())
+    }
+
+    /// Rewrite the calls of the inputs to math intrinsics the backend cannot
+    /// select into calls to their libdevice equivalents
+    ///
+    /// This runs before pruning, which would otherwise remove the unreferenced
+    /// libdevice functions.
+    fn map_intrinsics(&mut self) -> anyhow::Result<()> {
+        let Some(libdevice) = self.libdevice else {
+            return Ok(());
+        };
+        let mut intrinsics = BTreeSet::new();
+        let mut calls = 0;
+        for index in 0..self.bitcode.len() {
+            if index == libdevice {
+                continue;
+            }
+            let mapped = intrinsics::map(&self.disassemble(&self.bitcode[index])?);
+            if mapped.calls == 0 {
+                continue;
+            }
+            let path = self
+                .intermediates
+                .path(None, format!("{index}.intrinsics.bc"));
+            self.write_ir(&mapped.ir, &path)?;
+            self.bitcode[index] = path;
+            calls += mapped.calls;
+            intrinsics.extend(mapped.intrinsics);
+        }
+        if calls > 0 {
+            tracing::info!(
+                "mapping {calls} call(s) to math intrinsics to libdevice: {}",
+                intrinsics.into_iter().collect::<Vec<_>>().join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Warn about the math intrinsics the linked module `ir` calls that the
+    /// backend cannot select, unless they are mapped to libdevice
+    fn check_intrinsics(&self, ir: &str) {
+        if self.libdevice.is_some() {
+            return;
+        }
+        let called = intrinsics::called(ir);
+        if !called.is_empty() {
+            tracing::warn!(
+                "llc cannot select the math intrinsics {}, link libdevice with --math libdevice to call its equivalents",
+                called.into_iter().collect::<Vec<_>>().join(", ")
+            );
+        }
+    }
+
+    /// The symbols that several inputs define, with the indices of those
+    /// inputs
+    fn conflicting_definitions(&self) -> anyhow::Result<BTreeMap<Symbol, Vec<usize>>> {
+        let mut definitions = BTreeMap::<Symbol, Vec<usize>>::new();
+        for (index, path) in self.bitcode.iter().enumerate() {
+            for entry in self.defined_symbols(path, true)? {
+                if conflicts::is_strong(entry.kind) {
+                    definitions.entry(entry.name).or_default().push(index);
+                }
+            }
+        }
+        definitions.retain(|_, inputs| {
+            inputs.dedup();
+            inputs.len() > 1
+        });
+        Ok(definitions)
+    }
+
+    /// Fail the link that failed with `err` on a symbol defined by several
+    /// inputs with all such symbols, not only the first one found
+    fn report_conflicts(&self, err: anyhow::Error) -> anyhow::Result<()> {
+        let conflicts = self.conflicting_definitions()?;
+        if conflicts.len() < 2 {
+            return Err(err);
+        }
+        Errors::check(
+            conflicts
+                .into_iter()
+                .map(|(symbol, inputs)| {
+                    anyhow::anyhow!(
+                        "the symbol {} is defined by several inputs: {}",
+                        symbol.demangled(),
+                        self.input_list(&inputs)
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    /// The sources of the inputs with the `indices`, separated by commas
+    fn input_list(&self, indices: &[usize]) -> String {
+        indices
+            .iter()
+            .map(|&index| self.sources[index].0.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Rename the symbols that several inputs define in all inputs but the
+    /// first that defines them, unless they are kept
+    fn rename_conflicts(&mut self) -> anyhow::Result<()> {
+        let mut by_input = BTreeMap::<usize, Vec<Symbol>>::new();
+        for (symbol, inputs) in self.conflicting_definitions()? {
+            if self.symbols.contains(&symbol) {
+                anyhow::bail!(
+                    "the kept symbol {} is defined by several inputs and cannot be renamed: {}",
+                    symbol.demangled(),
+                    self.input_list(&inputs)
+                );
+            }
+            for index in inputs.into_iter().skip(1) {
+                by_input.entry(index).or_default().push(symbol.clone());
+            }
+        }
+
+        for (index, symbols) in by_input {
+            let input = self.sources[index].0.clone();
+            let mut ir = self.disassemble(&self.bitcode[index])?;
+            let kernels = kernels::defined(&ir);
+            for symbol in symbols {
+                if kernels
+                    .iter()
+                    .any(|kernel| kernel.as_bytes() == symbol.as_bytes())
+                {
+                    anyhow::bail!(
+                        "the kernel {} is defined by several inputs and cannot be renamed",
+                        symbol.demangled()
+                    );
+                }
+                let renamed = conflicts::renamed(&symbol, index);
+                tracing::warn!(
+                    "renaming {} defined by several inputs to {renamed} in {}",
+                    symbol.demangled(),
+                    input.display()
+                );
+                self.trace.log(
+                    &symbol,
+                    format_args!("renamed to {renamed} in {}", input.display()),
+                );
+                ir = conflicts::rename(&ir, &symbol, &renamed);
+                self.renamed.push(RenamedSymbol {
+                    symbol: symbol.to_string_lossy().into_owned(),
+                    renamed: renamed.to_string_lossy().into_owned(),
+                    input: input.clone(),
+                });
+            }
+
+            let path = self.intermediates.path(None, format!("{index}.renamed.bc"));
+            self.write_ir(&ir, &path)?;
+            self.bitcode[index] = path;
+        }
+        Ok(())
+    }
+
+    /// Log the accesses to the globals of the linked module, before they are
+    /// optimized away
+    ///
+    /// Before this can be called `link` needs to be called
+    fn report_globals(&self) -> anyhow::Result<()> {
+        let report = globals::Report::from_ir(&self.disassemble(&self.link_path)?);
+        if report.is_empty() {
+            tracing::info!("globals: {report}");
+        } else {
+            tracing::warn!("globals accessed only partially by device code: {report}");
+        }
+
+        Ok(())
+    }
+
+    /// Optimize using `opt`
+    ///
+    /// Before this can be called `link` needs to be called
+    fn optimize(
+        &mut self,
+        optimization: Optimization,
+        mut internalize: bool,
+        mut debug: bool,
+        mut inline: bool,
+    ) -> anyhow::Result<()> {
+        let mut passes = self.optimize_passes(optimization, debug);
+        let boundaries = self.choose_boundaries()?;
+        let no_inline = self.no_inline_symbols()?;
+        if !boundaries.is_empty() || !no_inline.is_empty() {
+            // Before any pass can inline them
+            passes.insert(0, "forceattrs".to_owned());
+        }
+
+        // FIXME(@kjetilkjeka) The whole corelib currently cannot be compiled for
+        // nvptx64 so everything relies on not using the troublesome symbols and
+        // removing them during linking
+        if self.is_skipped(Step::Internalize) {
+            internalize = false;
+        } else if !internalize && self.target == crate::Target::Nvptx64NvidiaCuda {
+            tracing::warn!("nvptx64 target detected - internalizing symbols");
+            internalize = true;
+        }
+
+        if debug && !self.keeps_debug(debug) {
+            tracing::warn!(
+                "nvptx64 target detected - stripping debug symbols, keep them with --debug=full"
+            );
+            debug = false;
+        }
+
+        if self.is_skipped(Step::Inline) || self.pipeline == Pipeline::PerModule {
+            inline = false;
+        } else if !inline && self.target == crate::Target::Nvptx64NvidiaCuda {
+            tracing::warn!("nvptx64 target detected - inlining all symbols");
+            inline = true;
+        }
+
+        if internalize {
+            passes.push("internalize,globaldce".to_owned());
+            std::fs::write(
+                &self.sym_path,
+                KeepSymbolsFormat::LlvmApi.encode(&self.symbols),
+            )
+            .context(format!(
+                "Failed to write symbol file: {}",
+                self.sym_path.display()
+            ))?;
+        }
+
+        let passes = passes.join(",");
+        tracing::info!("optimizing bitcode with passes: {}", passes);
+        let mut opt_cmd = self.tools.command("opt");
+        opt_cmd
+            .arg(&self.link_path)
+            .arg("-o")
+            .arg(&self.opt_path)
+            .arg(path_arg("--internalize-public-api-file=", &self.sym_path));
+        for symbol in &boundaries {
+            self.trace
+                .log(symbol, "kept out of line as a profile boundary");
+            opt_cmd.arg(symbol.to_arg("--force-attribute=", ":noinline"));
+        }
+        for symbol in &no_inline {
+            self.trace.log(symbol, "kept out of line by --no-inline");
+            opt_cmd.arg(symbol.to_arg("--force-attribute=", ":noinline"));
+        }
+        if !passes.is_empty() {
+            opt_cmd.args(self.pass_args(&passes)?);
+        }
+        if self.remarks_summary {
+            opt_cmd.arg(path_arg(
+                "--pass-remarks-output=",
+                &self.remarks_path("optimize"),
+            ));
+        }
+
+        if !debug {
+            opt_cmd.arg("--strip-debug");
+        }
+
+        let opt_output = self.run_tool("opt", &mut opt_cmd, None)?;
+
+        if !opt_output.status.success() {
+            anyhow::bail!("opt failed optimize bitcode: {}", self.link_path.display());
+        };
+        let step = if self.is_skipped(Step::Optimize) && internalize {
+            Step::Internalize
+        } else {
+            Step::Optimize
+        };
+        self.record_stage_hash(step, None, &[&self.opt_path])?;
+
+        let force_inline = !self.force_inline.is_empty() && !self.is_skipped(Step::Inline);
+        if !inline && !force_inline && !self.trace.is_enabled() {
+            return Ok(());
+        }
+
+        let optimized = self.defined_symbols(&self.opt_path, false)?;
+        self.trace_optimized(&optimized, if internalize { "globaldce" } else { "opt" });
+
+        if !inline && !force_inline {
+            return Ok(());
+        }
+
+        let out_of_line = [boundaries, no_inline].concat();
+        self.inline(optimization, &optimized, &out_of_line, inline)
+    }
+
+    /// The passes optimizing the linked module with the pipeline, and those
+    /// needed for libdevice and the requested `debug` information
+    fn optimize_passes(&self, optimization: Optimization, debug: bool) -> Vec<String> {
+        let mut passes = Vec::new();
+        if !self.is_skipped(Step::Optimize) {
+            passes.extend(match (&self.passes, self.pipeline) {
+                (Some(passes), _) => Some(passes.clone()),
+                (None, Pipeline::Merge) => Some(format!("default<{optimization}>")),
+                // The inputs were already optimized, so only optimize across them
+                (None, Pipeline::Premerge | Pipeline::Thin) => {
+                    Some(PREMERGE_CROSS_MODULE_PASSES.to_owned())
+                }
+                (None, Pipeline::PerModule) => None,
+            });
+            passes.extend(self.extra_passes.clone());
+        }
+
+        if self.libdevice.is_some() {
+            passes.insert(0, "function(nvvm-reflect)".to_owned());
+        }
+        if debug && self.debug_info == DebugInfo::LineTables {
+            passes.insert(0, "strip-nonlinetable-debuginfo".to_owned());
+        }
+        passes
+    }
+
+    /// The functions of the linked module matching the globs of
+    /// [`Session::no_inline`]
+    ///
+    /// Before this can be called `link` needs to be called
+    fn no_inline_symbols(&self) -> anyhow::Result<Vec<Symbol>> {
+        if self.no_inline.is_empty() || self.is_skipped(Step::Inline) {
+            return Ok(Vec::new());
+        }
+        let defined = self.defined_symbols(&self.link_path, false)?;
+        Ok(matching_functions(&defined, &self.no_inline, "no-inline"))
+    }
+
+    /// Inline the `optimized` functions into all their callers, `all` of them
+    /// or those matching the globs of [`Session::force_inline`], except for
+    /// those kept `out_of_line`
+    ///
+    /// Before this can be called `optimize` needs to be called
+    fn inline(
+        &self,
+        optimization: Optimization,
+        optimized: &[nm::Entry],
+        out_of_line: &[Symbol],
+        all: bool,
+    ) -> anyhow::Result<()> {
+        let inlined = if all {
+            optimized.iter().map(|entry| entry.name.clone()).collect()
+        } else {
+            matching_functions(optimized, &self.force_inline, "force-inline")
+        };
+
+        let passes = if self.is_skipped(Step::Optimize) {
+            "forceattrs,always-inline".to_owned()
+        } else {
+            let pipeline = self
+                .passes
+                .clone()
+                .unwrap_or_else(|| format!("default<{optimization}>"));
+            format!("{pipeline},forceattrs,always-inline,gvn,globalopt,mem2reg,dse,globalopt")
+        };
+
+        tracing::info!("inlining bitcode with passes: {}", passes);
+        let mut opt_cmd = self.tools.command("opt");
+        opt_cmd
+            .arg(&self.opt_path)
+            .arg("-o")
+            .arg(&self.opt_path)
+            .args(self.pass_args(&passes)?);
+        if self.remarks_summary {
+            opt_cmd.arg(path_arg(
+                "--pass-remarks-output=",
+                &self.remarks_path("inline"),
+            ));
+        }
+
+        for symbol in inlined
+            .iter()
+            .filter(|symbol| !out_of_line.contains(symbol))
+        {
+            self.trace.log(symbol, "forced to be always inlined");
+            opt_cmd.arg(symbol.to_arg("--force-attribute=", ":alwaysinline"));
+        }
+
+        let opt_output = self.run_tool("opt", &mut opt_cmd, None)?;
+
+        if !opt_output.status.success() {
+            anyhow::bail!("opt failed inline bitcode: {}", self.opt_path.display());
+        };
+        self.record_stage_hash(Step::Inline, None, &[&self.opt_path])?;
+
+        if self.trace.is_enabled() {
+            let inlined = self.defined_symbols(&self.opt_path, false)?;
+            for symbol in optimized {
+                if !inlined.iter().any(|entry| entry.name == symbol.name) {
+                    self.trace
+                        .log(&symbol.name, "inlined into all callers and removed");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The callees of kernels that are kept out of line for profiling
+    ///
+    /// Before this can be called `link` needs to be called
+    fn choose_boundaries(&self) -> anyhow::Result<Vec<Symbol>> {
+        let Some(min_instructions) = self.profile_boundaries else {
+            return Ok(Vec::new());
+        };
+        let report =
+            boundaries::Report::choose(&self.disassemble(&self.link_path)?, min_instructions);
+        if report.is_empty() {
+            tracing::info!(
+                "no kernel calls a function of at least {min_instructions} instructions to keep out of line"
+            );
+        } else {
+            tracing::info!("profile boundaries: {report}");
+        }
+
+        Ok(report.names().map(Symbol::new).collect())
+    }
+
+    /// Handle the destructors registered in the optimized module according to
+    /// the [`DtorPolicy`], as the NVPTX backend cannot compile them
+    ///
+    /// Before this can be called `optimize` needs to be called
+    fn lower_dtors(&self) -> anyhow::Result<()> {
+        let fini_kernel = self.dtor_policy == DtorPolicy::FiniKernel;
+        let module = std::fs::read(&self.opt_path).context(format!(
+            "Failed to read optimized bitcode: {}",
+            self.opt_path.display()
+        ))?;
+        if !fini_kernel && !dtors::may_register(&module) {
+            return Ok(());
+        }
+
+        let dis_output = self.run_tool(
+            "llvm-dis",
+            self.tools.command("llvm-dis").arg("-").arg("-o").arg("-"),
+            Some(&module),
+        )?;
+
+        if !dis_output.status.success() {
+            anyhow::bail!(
+                "llvm-dis failed to disassemble bitcode: {}",
+                self.opt_path.display()
+            );
+        }
+
+        let (ir, names) = dtors::lower(&String::from_utf8_lossy(&dis_output.stdout), fini_kernel);
+        if names.is_empty() && !fini_kernel {
+            return Ok(());
+        }
+
+        let demangled = names
+            .iter()
+            .map(|name| symbol::demangle(name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        match self.dtor_policy {
+            DtorPolicy::Drop => {
+                tracing::warn!("dropping destructors which cannot run on the device: {demangled}");
+            }
+            DtorPolicy::Error => {
+                anyhow::bail!("destructors cannot run on the device: {demangled}")
+            }
+            DtorPolicy::FiniKernel => tracing::info!(
+                "calling {} destructor(s) from {}",
+                names.len(),
+                dtors::FINI_KERNEL
+            ),
+        }
+
+        self.write_ir(&ir, &self.opt_path)
+    }
+
+    /// Rewrite the linked module for what the device cannot run and check it
+    /// for what cannot be rewritten
+    ///
+    /// The rewrites and checks share a single disassembly of the module, which
+    /// is only assembled again if any of them changed it. Before this can be
+    /// called `link` needs to be called
+    fn rewrite_linked(&self) -> anyhow::Result<()> {
+        self.check_triples()?;
+
+        let mut ir = self.disassemble(&self.link_path)?;
+        let mut rewritten = false;
+        if self.debug_info != DebugInfo::Default {
+            rewritten |= self.fix_up_debug(&mut ir);
+        }
+        rewritten |= prune_unwinding(&mut ir);
+        self.check_host_code(&ir)?;
+        self.check_intrinsics(&ir);
+        if self.device_asserts {
+            rewritten |= lower_asserts(&mut ir);
+        }
+        if self.panic_trap {
+            rewritten |= trap_panics(&mut ir);
+        }
+
+        if rewritten {
+            self.write_ir(&ir, &self.link_path)?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite the optimized module for what `llc` cannot handle or handles
+    /// differently than requested
+    ///
+    /// Before this can be called `optimize` needs to be called
+    fn rewrite_optimized(&self) -> anyhow::Result<()> {
+        self.lower_dtors()?;
+        if !self.function_features.is_empty() {
+            self.apply_function_features()?;
+        }
+        if let Some(mode) = self.unreachable {
+            self.lower_unreachable(mode)?;
+        }
+        if let Some(max_size) = self.coalesce_constants {
+            self.coalesce_constants(max_size)?;
+        }
+        Ok(())
+    }
+
+    /// Override the target features of single functions of the optimized
+    /// module
+    ///
+    /// Before this can be called `optimize` needs to be called
+    fn apply_function_features(&self) -> anyhow::Result<()> {
+        let (ir, missing) =
+            target_features::apply(&self.disassemble(&self.opt_path)?, &self.function_features);
+        if !missing.is_empty() {
+            tracing::warn!(
+                "not overriding the target features of functions that are not defined after optimizing: {}",
+                missing.join(", ")
+            );
+        }
+        for features in &self.function_features {
+            if !missing.contains(&features.function.as_str()) {
+                tracing::info!("overriding target features: {features}");
+            }
+        }
+
+        self.write_ir(&ir, &self.opt_path)
+    }
+
+    /// Fix up the debug information of the linked module `ir` for what the
+    /// NVPTX backend and `ptxas` support, reducing it to the line tables if
+    /// only those are kept
+    ///
+    /// Returns whether `ir` was changed.
+    fn fix_up_debug(&self, ir: &mut String) -> bool {
+        let fixed = debug::fix_up(ir, self.debug_info == DebugInfo::LineTables);
+        if !fixed.dwarf_version && fixed.line_tables == 0 && fixed.dropped == 0 {
+            return false;
+        }
+        if fixed.dwarf_version {
+            tracing::info!("lowering the debug information to limited DWARF for ptxas");
+        }
+        if fixed.line_tables > 0 {
+            tracing::info!(
+                "keeping only the line tables of {} compile unit(s)",
+                fixed.line_tables
+            );
+        }
+        if fixed.dropped > 0 && self.debug_info == DebugInfo::Full {
+            tracing::warn!(
+                "dropping {} variable location(s) that limited DWARF cannot describe",
+                fixed.dropped
+            );
+        }
+        *ir = fixed.ir;
+        true
+    }
+
+    /// Fail with all inputs compiled for another target, if linking them
+    /// reported mismatching target triples
+    ///
+    /// The linker only reports the inputs whose triple differs from the first
+    /// one, so all inputs are checked against the target.
+    fn check_triples(&self) -> anyhow::Result<()> {
+        if !self
+            .diagnostics
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|diagnostic| diagnostic.message.contains("different target triples"))
+        {
+            return Ok(());
+        }
+
+        let mut errors = Vec::new();
+        for (bitcode, (source, _)) in self.bitcode.iter().zip(&self.sources) {
+            let ir = self.disassemble(bitcode)?;
+            let Some(triple) = target::module_triple(&ir) else {
+                continue;
+            };
+            if triple == self.target.triple() {
+                continue;
+            }
+            let diagnostic = Diagnostic {
+                tool: env!("CARGO_PKG_NAME").to_owned(),
+                severity: Severity::Error,
+                message: format!(
+                    "input compiled for {triple} instead of {}",
+                    self.target.triple()
+                ),
+                location: Some(Location {
+                    file: source.display().to_string(),
+                    line: None,
+                    column: None,
+                }),
+            };
+            diagnostic.emit();
+            errors.push(anyhow::anyhow!(
+                "{}: {}",
+                source.display(),
+                diagnostic.message
+            ));
+            self.diagnostics.lock().unwrap().push(diagnostic);
+        }
+        Errors::check(errors)
+    }
+
+    /// Warn about, or with [`Session::set_strict_host_code`] fail on, host-only
+    /// code used by the kernels and kept symbols of the linked module `ir`,
+    /// like thread-locals or the threads and files of `std`
+    fn check_host_code(&self, ir: &str) -> anyhow::Result<()> {
+        let uses = host_code::find(ir, &self.symbols);
+        if uses.is_empty() {
+            return Ok(());
+        }
+
+        let host_code = HostCode(uses);
+        if self.strict_host_code {
+            return Err(host_code.into());
+        }
+        tracing::warn!("{host_code}");
+        Ok(())
+    }
+
+    /// Lower the `unreachable` terminators of the optimized module
+    ///
+    /// Before this can be called `optimize` needs to be called
+    fn lower_unreachable(&self, mode: Unreachable) -> anyhow::Result<()> {
+        let (ir, count) = unreachable::lower(&self.disassemble(&self.opt_path)?, mode);
+        if count == 0 {
+            return Ok(());
+        }
+        tracing::info!("lowering {count} unreachable terminator(s) to {mode}");
+
+        self.write_ir(&ir, &self.opt_path)
+    }
+
+    /// Coalesce the small constant globals of the optimized module
+    ///
+    /// Before this can be called `optimize` needs to be called
+    fn coalesce_constants(&self, max_size: u64) -> anyhow::Result<()> {
+        let coalesced = constants::coalesce(&self.disassemble(&self.opt_path)?, max_size);
+        if coalesced.constants == 0 {
+            return Ok(());
+        }
+        tracing::info!(
+            "coalescing {} constant(s) of at most {max_size} bytes into {} array(s) of {} bytes",
+            coalesced.constants,
+            coalesced.arrays,
+            coalesced.bytes
+        );
+
+        self.write_ir(&coalesced.ir, &self.opt_path)
+    }
+
+    /// Assemble the textual `ir` into bitcode at `path`
+    fn write_ir(&self, ir: &str, path: &Path) -> anyhow::Result<()> {
+        let as_output = self.run_tool(
+            "llvm-as",
+            self.tools.command("llvm-as").arg("-").arg("-o").arg(path),
+            Some(ir.as_bytes()),
+        )?;
+
+        if !as_output.status.success() {
+            anyhow::bail!("llvm-as failed to assemble bitcode: {}", path.display());
+        }
+
+        Ok(())
+    }
+
+    /// Keep the kernels of the linked module, which the inputs that are not
+    /// whole define with the `ptx_kernel` calling convention without keeping
+    /// their symbols
+    ///
+    /// Before this can be called `link` needs to be called
+    fn keep_kernels(&mut self) -> anyhow::Result<()> {
+        let kernels = kernels::unkept(&self.disassemble(&self.link_path)?, &self.symbols);
+        if kernels.is_empty() {
+            return Ok(());
+        }
+
+        tracing::debug!("keeping {} kernel(s) of the linked module", kernels.len());
+        for kernel in kernels {
+            self.trace.log(&kernel, "added to the keep-set as a kernel");
+            self.symbols.push(kernel);
+        }
+
+        Ok(())
+    }
+
+    /// Fail if the optimized module uses symbols that no input defines, which
+    /// would only fail later in `ptxas`
+    ///
+    /// With `--rdc` they may be defined by the device libraries and are left
+    /// to `nvlink`. Before this can be called `optimize` needs to be called
+    fn check_undefined(&self) -> anyhow::Result<()> {
+        let undefined = self
+            .undefined_symbols(&self.opt_path)?
+            .into_iter()
+            .filter(|symbol| !undefined::is_provided(symbol))
+            .collect::<Vec<_>>();
+        if undefined.is_empty() {
+            return Ok(());
+        }
+        if self.rdc {
+            tracing::info!("leaving {} undefined symbol(s) to nvlink", undefined.len());
+            return Ok(());
+        }
+
+        let mut references = undefined
+            .into_iter()
+            .map(|symbol| Reference {
+                symbol,
+                inputs: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+        for (bitcode, (source, _)) in self.bitcode.iter().zip(&self.sources) {
+            let declared = self.undefined_symbols(bitcode)?;
+            for reference in &mut references {
+                if declared.contains(&reference.symbol) && !reference.inputs.contains(source) {
+                    reference.inputs.push(source.clone());
+                }
+            }
+        }
+
+        for reference in &references {
+            let diagnostic = Diagnostic {
+                tool: env!("CARGO_PKG_NAME").to_owned(),
+                severity: Severity::Error,
+                message: format!("undefined reference to `{}`", reference.symbol.demangled()),
+                location: reference.inputs.first().map(|input| Location {
+                    file: input.display().to_string(),
+                    line: None,
+                    column: None,
+                }),
+            };
+            diagnostic.emit();
+            self.diagnostics.lock().unwrap().push(diagnostic);
+        }
+
+        Err(UndefinedReferences(references).into())
+    }
+
+    /// Fail with the launch bounds of all kernels of the PTX `module` at
+    /// `ptx_path` that exceed the limits of its target architecture
+    fn check_limits(&self, module: &ptx::Module, ptx_path: &Path) -> anyhow::Result<()> {
+        let errors = limits::check(module);
+        for error in &errors {
+            let diagnostic = Diagnostic {
+                tool: env!("CARGO_PKG_NAME").to_owned(),
+                severity: Severity::Error,
+                message: error.to_string(),
+                location: Some(Location {
+                    file: ptx_path.display().to_string(),
+                    line: None,
+                    column: None,
+                }),
+            };
+            diagnostic.emit();
+            self.diagnostics.lock().unwrap().push(diagnostic);
+        }
+        Errors::check(errors.into_iter().map(anyhow::Error::from).collect())
+    }
+
+    /// Warn if no kernels are left after optimizing while prunable inputs
+    /// defined some, which were removed as their symbols were not kept
+    ///
+    /// Before this can be called `optimize` needs to be called
+    fn check_kernels(&self) -> anyhow::Result<()> {
+        let optimized = kernels::defined(&self.disassemble(&self.opt_path)?);
+        if optimized.iter().any(|kernel| kernel != dtors::FINI_KERNEL) {
+            tracing::debug!("{} kernel(s) left after optimizing", optimized.len());
+            return Ok(());
+        }
+
+        for &index in &self.prunable {
+            let (source, bitcode) = &self.sources[index];
+            let removed = kernels::defined(&self.disassemble(bitcode)?);
+            if removed.is_empty() {
+                continue;
+            }
+
+            let file_name = source.file_name().unwrap_or_default().to_string_lossy();
+            let (input, flag) = match kernels::crate_name(&file_name) {
+                Some(name) => (format!("crate `{name}`"), "--whole-rlib"),
+                None => ("input".to_owned(), "--whole-archive"),
+            };
+            tracing::warn!(
+                "no kernels are left, but the {input} defines the kernel(s) {}, which were removed as its symbols are not kept; pass {} with `{flag}` to keep them",
+                removed
+                    .iter()
+                    .map(|kernel| symbol::demangle(kernel))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                source.display()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Write the symbols left externally visible by the optimized module to
+    /// `path`
+    ///
+    /// Before this can be called `optimize` needs to be called
+    fn write_keep_symbols(&self, path: &Path) -> anyhow::Result<()> {
+        let symbols = self.exported_symbols(&self.opt_path)?;
+
+        tracing::info!(
+            "writing {} externally visible symbols to {}",
+            symbols.len(),
+            path.display()
+        );
+        std::fs::write(path, self.keep_symbols_format.encode(&symbols))
+            .context(format!("Failed to write symbol file: {}", path.display()))
+    }
+
+    /// The symbols left externally visible by the module at `path`, sorted
+    fn exported_symbols(&self, path: &Path) -> anyhow::Result<Vec<Symbol>> {
+        let mut symbols = self
+            .defined_symbols(path, true)?
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect::<Vec<_>>();
+        symbols.sort();
+        symbols.dedup();
+        Ok(symbols)
+    }
+
+    /// Compare the last written module to the one of an earlier build, write
+    /// its keep-set, check it against the public API and summarize the
+    /// optimization remarks, as requested
+    fn report_module(&self) -> anyhow::Result<()> {
+        // Stopping after merging leaves only the linked module
+        let module = if self.stop_after == Some(Step::Merge) {
+            None
+        } else {
+            Some(&self.opt_path)
+        };
+
+        if let Some(previous) = &self.ir_diff {
+            self.report_ir_diff(previous, module.unwrap_or(&self.link_path))?;
+        }
+        if let (Some(path), Some(_)) = (self.emit_path(EmitKind::KeepSymbols), module) {
+            self.write_keep_symbols(&path)?;
+        }
+        if let (Some(api), Some(module)) = (&self.public_api, module) {
+            let exported = self.exported_symbols(module)?;
+            api.check(&exported)?;
+            tracing::info!(
+                "exporting exactly the {} symbol(s) of the public API",
+                api.len()
+            );
+        }
+        if let Some(path) = self.emit_path(EmitKind::Metadata) {
+            self.write_metadata(module.unwrap_or(&self.link_path), &path)?;
+        }
+        if let (true, Some(module)) = (self.remarks_summary, module) {
+            self.report_remarks(module)?;
+        }
+
+        Ok(())
+    }
+
+    /// The optimization remarks written by the `opt` run of `step`
+    fn remarks_path(&self, step: &str) -> PathBuf {
+        self.intermediates
+            .path(None, format!("{step}.remarks.yaml"))
+    }
+
+    /// Log the summary of the optimization remarks about the kernels of the
+    /// optimized `module`
+    ///
+    /// A resumed optimize stage wrote no remarks.
+    fn report_remarks(&self, module: &Path) -> anyhow::Result<()> {
+        let mut yaml = String::new();
+        for step in ["optimize", "inline"] {
+            let path = self.remarks_path(step);
+            if path.exists() {
+                yaml.push_str(&std::fs::read_to_string(&path).context(format!(
+                    "Failed to read optimization remarks: {}",
+                    path.display()
+                ))?);
+            }
+        }
+
+        let kernels = kernels::defined(&self.disassemble(module)?);
+        let summary = remarks::Summary::from_yaml(&yaml, &kernels);
+        if summary.is_empty() {
+            tracing::info!("no optimization remarks about the kernels");
+        } else {
+            tracing::info!("optimization remarks:\n{summary}");
+        }
+        Ok(())
+    }
+
+    /// Add the symbols defined by the inputs that match the export patterns to
+    /// the keep-set
+    fn keep_exported_patterns(&mut self) -> anyhow::Result<()> {
+        let mut matched = vec![false; self.export_patterns.len()];
+        for path in &self.bitcode {
+            for entry in self.defined_symbols(path, true)? {
+                let Some(index) = self
+                    .export_patterns
+                    .iter()
+                    .position(|pattern| entry.name.matches_glob(pattern))
+                else {
+                    continue;
+                };
+                matched[index] = true;
+                self.trace.log(
+                    &entry.name,
+                    format_args!(
+                        "matches the exported pattern `{}`, added to the keep-set",
+                        self.export_patterns[index]
+                    ),
+                );
+                self.symbols.push(entry.name);
+            }
+        }
+
+        for (pattern, _) in self
+            .export_patterns
+            .iter()
+            .zip(matched)
+            .filter(|(_, matched)| !matched)
+        {
+            tracing::warn!("the exported pattern `{pattern}` matches no symbol");
+        }
+        Ok(())
+    }
+
+    /// Add the globals referenced by the exported named metadata of the inputs
+    /// to the keep-set, as DCE does not consider metadata a use
+    fn keep_metadata_references(&mut self) -> anyhow::Result<()> {
+        for path in &self.bitcode {
+            let ir = self.disassemble(path)?;
+            for name in &self.exported_metadata {
+                let Some(nodes) = metadata::named(&ir, name) else {
+                    continue;
+                };
+                for reference in metadata::references(&nodes) {
+                    let symbol = Symbol::new(reference);
+                    self.trace.log(
+                        &symbol,
+                        format_args!("referenced by !{name}, added to the keep-set"),
+                    );
+                    self.symbols.push(symbol);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the exported named metadata of `module` to `path` as a JSON
+    /// object of the operands of their nodes by name
+    fn write_metadata(&self, module: &Path, path: &Path) -> anyhow::Result<()> {
+        let ir = self.disassemble(module)?;
+        let mut exported = serde_json::Map::new();
+        for name in &self.exported_metadata {
+            if let Some(nodes) = metadata::named(&ir, name) {
+                exported.insert(name.clone(), nodes.into());
+            } else {
+                tracing::warn!("named metadata !{name} is not defined by any input");
+            }
+        }
+
+        std::fs::write(path, serde_json::to_vec_pretty(&exported)?)
+            .context(format!("Failed to write metadata: {}", path.display()))
+    }
+
+    /// Log the functions of `module` that differ from those of `previous`
+    fn report_ir_diff(&self, previous: &Path, module: &Path) -> anyhow::Result<()> {
+        let diff = Diff::between(&self.disassemble(previous)?, &self.disassemble(module)?);
+        if diff.is_empty() {
+            tracing::info!("IR diff against {}: {diff}", previous.display());
+        } else {
+            tracing::warn!("IR diff against {}: {diff}", previous.display());
+        }
+        Ok(())
+    }
+
+    /// The textual IR of the bitcode at `path`
+    fn disassemble(&self, path: &Path) -> anyhow::Result<String> {
+        let dis_output = self.run_tool(
+            "llvm-dis",
+            self.tools.command("llvm-dis").arg(path).arg("-o").arg("-"),
+            None,
+        )?;
+
+        if !dis_output.status.success() {
+            anyhow::bail!("llvm-dis failed to disassemble bitcode: {}", path.display());
+        }
+
+        Ok(String::from_utf8_lossy(&dis_output.stdout).into_owned())
+    }
+
+    /// Whether debug information is kept when it is requested by `debug`
+    fn keeps_debug(&self, debug: bool) -> bool {
+        // FIXME(@kjetilkjeka) Debug symbol generation is broken for nvptx64 so we must
+        // remove them even in debug mode, unless they are fixed up
+        debug
+            && (self.target != crate::Target::Nvptx64NvidiaCuda
+                || self.debug_info != DebugInfo::Default)
+    }
+
+    /// Logs what `opt` did to the traced symbols given the symbols `optimized`
+    /// still defined afterwards
+    fn trace_optimized(&self, optimized: &[nm::Entry], dce: &str) {
+        for symbol in &self.traced {
+            match optimized.iter().find(|entry| &entry.name == symbol) {
+                None => self.trace.log(symbol, format_args!("removed by {dce}")),
+                Some(entry) if entry.kind.is_ascii_lowercase() => {
+                    self.trace.log(symbol, "internalized");
+                }
+                Some(_) => self.trace.log(symbol, "exported"),
+            }
+        }
+    }
+
+    /// The symbols the bitcode at `path` uses without defining them
+    fn undefined_symbols(&self, path: &Path) -> anyhow::Result<Vec<Symbol>> {
+        let nm_output = self.run_tool(
+            "llvm-nm",
+            self.tools
+                .command("llvm-nm")
+                .args(nm::FORMAT_ARGS)
+                .arg("--undefined-only")
+                .arg(path),
+            None,
+        )?;
+
+        if !nm_output.status.success() {
+            anyhow::bail!(
+                "llvm-nm failed to return symbols from file {}",
+                path.display()
+            );
+        }
+
+        Ok(nm::parse(&nm_output.stdout)
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect())
+    }
+
+    /// The symbols defined in the bitcode at `path`, optionally only the
+    /// external ones, read with LLVM
+    #[cfg(feature = "llvm")]
+    fn defined_symbols(&self, path: &Path, extern_only: bool) -> anyhow::Result<Vec<nm::Entry>> {
+        let bitcode =
+            std::fs::read(path).context(format!("Failed to read bitcode: {}", path.display()))?;
+
+        let mut diagnostics = Vec::new();
+        let entries = super::llvm::defined_symbols(path, &bitcode, extern_only, &mut diagnostics);
+        self.record_diagnostics(super::llvm::TOOL, diagnostics)?;
+        entries
+    }
+
+    /// The symbols defined in the bitcode at `path`, optionally only the
+    /// external ones, listed by `llvm-nm`
+    #[cfg(not(feature = "llvm"))]
+    fn defined_symbols(&self, path: &Path, extern_only: bool) -> anyhow::Result<Vec<nm::Entry>> {
+        let mut nm_command = self.tools.command("llvm-nm");
+        nm_command.args(nm::FORMAT_ARGS).arg("--defined-only");
+
+        if extern_only {
+            nm_command.arg("--extern-only");
+        }
+
+        let nm_output = self.run_tool("llvm-nm", nm_command.arg(path), None)?;
+
+        if !nm_output.status.success() {
+            anyhow::bail!(
+                "llvm-nm failed to return symbols from file {}",
+                path.display()
+            );
+        }
+
+        Ok(nm::parse(&nm_output.stdout))
+    }
+
+    /// Check that the requested outputs can be produced before doing any work,
+    /// reporting all conflicting options at once
+    fn validate(&self) -> anyhow::Result<()> {
+        let mut errors = Vec::new();
+        if self.rdc && self.cpus.is_empty() {
+            errors.push("relocatable device code requires a target cpu".to_owned());
+        }
+        if !self.rdc && !self.device_libs.is_empty() {
+            errors
+                .push("device libraries can only be linked as relocatable device code".to_owned());
+        }
+
+        let mut emit_paths = self
+            .emit
+            .iter()
+            .filter_map(|emit| self.emit_path(emit.kind))
+            .collect::<Vec<_>>();
+        emit_paths.sort();
+        if let Some(path) = emit_paths.windows(2).find(|paths| paths[0] == paths[1]) {
+            errors.push(format!(
+                "multiple outputs would be written to {}, give them explicit paths with --emit KIND=PATH",
+                path[0].display()
+            ));
+        }
+
+        let bundle = self.emit_path(EmitKind::Bundle).is_some();
+        if bundle && self.cpus.is_empty() {
+            errors.push("bundles require at least one target cpu".to_owned());
+        }
+        if bundle && self.rdc {
+            errors.push("bundles cannot be emitted as relocatable device code".to_owned());
+        }
+        if self.emit_path(EmitKind::Object).is_some() {
+            if self.rdc {
+                errors.push(
+                    "relocatable device code cannot be embedded into host objects".to_owned(),
+                );
+            }
+            if self.cpus.len() > 1 && !bundle {
+                errors.push(
+                    "embedding code for multiple target cpus requires emitting a bundle".to_owned(),
+                );
+            }
+        }
+        if self.emit_path(EmitKind::Cubin).is_some() {
+            if self.cpus.is_empty() {
+                errors.push("cubins require at least one target cpu".to_owned());
+            }
+            if self.rdc {
+                errors.push(
+                    "relocatable device code is emitted as a cubin with --emit asm".to_owned(),
+                );
+            }
+        }
+        if self.emit_path(EmitKind::Fatbin).is_some() {
+            if self.cpus.is_empty() {
+                errors.push("fatbins require at least one target cpu".to_owned());
+            }
+            if self.rdc {
+                errors.push("fatbins cannot be emitted as relocatable device code".to_owned());
+            }
+        }
+        for step in self.skipped.iter().filter(|step| !step.is_optional()) {
+            errors.push(format!("the {step} step cannot be skipped"));
+        }
+        if self.fallback_cpu.is_some() && self.cpus.is_empty() {
+            errors.push("a fallback cpu requires a target cpu".to_owned());
+        }
+        if self.split_debug && self.rdc {
+            errors.push("debug information cannot be split off relocatable device code".to_owned());
+        }
+        errors.extend(self.check_ptx_versions());
+        for cpu in &self.cpus {
+            errors.extend(
+                self.target_features
+                    .iter()
+                    .filter_map(|feature| feature.check_cpu(cpu).err()),
+            );
+        }
+
+        Errors::check(errors.into_iter().map(anyhow::Error::msg).collect())
+    }
+
+    /// The errors of the PTX ISA versions selected by the target features,
+    /// which must be a single one that LLVM emits
+    fn check_ptx_versions(&self) -> Vec<String> {
+        let mut versions = self
+            .target_features
+            .iter()
+            .filter(|feature| feature.is_enabled())
+            .filter_map(TargetFeature::ptx_version)
+            .collect::<Vec<_>>();
+        versions.sort();
+        versions.dedup();
+
+        let mut errors = Vec::new();
+        if versions.len() > 1 {
+            errors.push(format!(
+                "several PTX ISA versions are selected: {}",
+                versions
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        if let Ok(llvm_major) = self.tools.llvm_major().parse() {
+            errors.extend(
+                versions
+                    .iter()
+                    .filter_map(|version| version.check_llvm(llvm_major).err()),
+            );
+        }
+        errors
+    }
+
+    /// Fail if the versions of the tools differ from the locked ones
+    fn check_locked(&self) -> anyhow::Result<()> {
+        let Some(locked) = &self.locked else {
+            return Ok(());
+        };
+
+        let differing = locked
+            .iter()
+            .filter_map(|(tool, version)| {
+                let found = self.tool_version(tool);
+                (found.as_ref() != Some(version)).then(|| {
+                    format!(
+                        "{tool} {} instead of {version}",
+                        found.as_deref().unwrap_or("not found")
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        if !differing.is_empty() {
+            anyhow::bail!(
+                "the tool versions differ from the locked ones: {}",
+                differing.join(", ")
+            );
+        }
+
+        tracing::info!("using the locked versions of {} tool(s)", locked.len());
+        Ok(())
+    }
+
+    /// The version of the external `tool`, e.g. `llc`, run by the session
+    fn tool_version(&self, tool: &str) -> Option<String> {
+        if CUDA_TOOLS.contains(&tool) {
+            capabilities::tool_version(super::tools::cuda_tool(tool))
+        } else {
+            capabilities::tool_version(self.tools.path(tool))
+        }
+    }
+
+    /// Compile to native format using `llc`, producing one output per target cpu
+    ///
+    /// The optimized module is read once and shared by all codegen jobs, of
+    /// which at most `jobs` run concurrently.
+    ///
+    /// Before this can be called `optimize` needs to be called
+    fn compile(&mut self, jobs: NonZeroUsize) -> anyhow::Result<()> {
+        let bundle_path = self.emit_path(EmitKind::Bundle);
+        self.infer_cpu()?;
+
+        let module = std::fs::read(&self.opt_path).context(format!(
+            "Failed to read optimized bitcode: {}",
+            self.opt_path.display()
+        ))?;
+
+        let cpus = if self.cpus.is_empty() {
+            vec![None]
+        } else {
+            self.cpus.iter().map(|cpu| Some(cpu.as_str())).collect()
+        };
+
+        let workers = jobs.get().min(cpus.len());
+        tracing::info!(
+            "compiling {} target(s) using {workers} codegen job(s)",
+            cpus.len()
+        );
+
+        run_parallel(&cpus, jobs, |cpu| self.compile_one(*cpu, &module))?;
+        for cpu in &cpus {
+            self.record_stage_hash(Step::Codegen, *cpu, &[&self.ptx_path(*cpu)])?;
+        }
+        let bindings_path = self.emit_path(EmitKind::RustBindings);
+        if self.kernel_metadata.is_some() || bindings_path.is_some() {
+            let metadata = self.kernel_metadata(&cpus)?;
+            if let Some(path) = &self.kernel_metadata {
+                std::fs::write(path, metadata.to_vec()).context(format!(
+                    "Failed to write kernel metadata: {}",
+                    path.display()
+                ))?;
+                tracing::info!(
+                    "wrote the metadata of {} kernel(s) to {}",
+                    metadata.kernels.len(),
+                    path.display()
+                );
+            }
+            if let Some(path) = &bindings_path {
+                std::fs::write(path, rust_bindings::generate(&metadata))
+                    .context(format!("Failed to write Rust bindings: {}", path.display()))?;
+            }
+        }
+
+        if let Some(bundle_path) = &bundle_path {
+            self.write_bundle(bundle_path)?;
+        }
+        if let Some(fatbin_path) = self.emit_path(EmitKind::Fatbin) {
+            self.write_fatbin(&fatbin_path)?;
+        }
+
+        if let Some(object_path) = self.emit_path(EmitKind::Object) {
+            let image_path = bundle_path.unwrap_or_else(|| self.ptx_path(cpus[0]));
+            self.write_object(&image_path, &object_path)?;
+        }
+
+        Ok(())
+    }
+
+    /// The metadata of the kernels defined in the PTX for the `cpus`
+    ///
+    /// The parameters and launch bounds come from the optimized module shared
+    /// by all target cpus, only the PTX ISA versions differ.
+    fn kernel_metadata(&self, cpus: &[Option<&str>]) -> anyhow::Result<KernelMetadata> {
+        let mut metadata = KernelMetadata::new(self.target.triple());
+        for cpu in cpus {
+            let ptx_path = self.ptx_path(*cpu);
+            let ptx = std::fs::read_to_string(&ptx_path)
+                .context(format!("Failed to read PTX: {}", ptx_path.display()))?;
+            let module =
+                ptx::parse(&ptx).context(format!("Failed to parse PTX: {}", ptx_path.display()))?;
+            // The target cpu, possibly followed by `, debug`
+            let target = module.target.split(',').next().unwrap_or_default().trim();
+
+            for function in module.functions {
+                if function.kind != ptx::FunctionKind::Entry || !function.defined {
+                    continue;
+                }
+                let index = metadata
+                    .kernels
+                    .iter()
+                    .position(|kernel| kernel.name == function.name)
+                    .unwrap_or_else(|| {
+                        metadata
+                            .kernels
+                            .push(kernel_metadata::Kernel::from(&function));
+                        metadata.kernels.len() - 1
+                    });
+                metadata.kernels[index]
+                    .ptx_versions
+                    .insert(target.to_owned(), module.version.clone());
+            }
+        }
+
+        Ok(metadata)
+    }
+
+    /// Infer the target cpu to compile for without a target cpu from the
+    /// `"target-cpu"` and `+sm_` target features of the functions of the
+    /// optimized module, and warn if they disagree or require a newer cpu than
+    /// the given ones
+    ///
+    /// Before this can be called `optimize` needs to be called
+    fn infer_cpu(&mut self) -> anyhow::Result<()> {
+        let required = target_features::required_cpus(&self.disassemble(&self.opt_path)?);
+        let Some(newest) = required.last() else {
+            return Ok(());
+        };
+
+        if !self.cpus.is_empty() {
+            let newest_version = bundle::arch_version(newest);
+            for cpu in &self.cpus {
+                if bundle::arch_version(cpu) < newest_version {
+                    tracing::warn!(
+                        "compiling for {cpu}, while the inputs were compiled for {newest}"
+                    );
+                }
+            }
+            return Ok(());
+        }
+
+        if required.len() > 1 {
+            tracing::warn!(
+                "the inputs were compiled for different target cpus {}, compiling for the newest",
+                required.join(", ")
+            );
+        }
+        tracing::info!("compiling for the target cpu of the inputs: {newest}");
+        let errors = self
+            .target_features
+            .iter()
+            .filter_map(|feature| feature.check_cpu(newest).err())
+            .map(anyhow::Error::msg)
+            .collect();
+        self.inferred_cpu = Some(newest.clone());
+        Errors::check(errors)
+    }
+
+    /// Compile the in-memory optimized `module` for a single target cpu, or
+    /// for the fallback cpu if the tools reject it
+    fn compile_one(&self, cpu: Option<&str>, module: &[u8]) -> anyhow::Result<()> {
+        let result = self.compile_for(cpu, cpu.or(self.inferred_cpu.as_deref()), module);
+        let (Some(cpu), Some(fallback)) = (cpu, &self.fallback_cpu) else {
+            return result;
+        };
+
+        match result {
+            Err(err) if fallback != cpu && err.is::<CpuRejected>() => {
+                tracing::warn!("{err}, compiling for the fallback cpu {fallback} instead");
+                self.compile_for(Some(cpu), Some(fallback), module)?;
+                self.fallen_back.lock().unwrap().push(cpu.to_owned());
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Compile the in-memory optimized `module` for `arch` into the outputs of
+    /// the target cpu `cpu`
+    fn compile_for(
+        &self,
+        cpu: Option<&str>,
+        arch: Option<&str>,
+        module: &[u8],
+    ) -> anyhow::Result<()> {
+        let ptx_path = self.ptx_path(cpu);
+
+        let mut lcc_command = self.tools.command("llc");
+
+        if let Some(optimization) = self.codegen_optimization {
+            lcc_command.arg(format!("-O{}", optimization.codegen_level()));
+        }
+        if let Some(mcpu) = arch {
+            lcc_command.arg("--mcpu").arg(mcpu);
+        }
+        if !self.target_features.is_empty() {
+            let features = self
+                .target_features
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            lcc_command.arg(format!("-mattr={}", features.join(",")));
+        }
+
+        let lcc_output = self.run_tool(
+            "llc",
+            lcc_command.arg("-").arg("-o").arg(&ptx_path),
+            Some(module),
+        )?;
+
+        // Without a fallback the PTX is still emitted for the JIT of the driver
+        if let (Some(arch), Some(_)) = (arch, &self.fallback_cpu) {
+            CpuRejected::check("llc", arch, &lcc_output.stderr)?;
+        }
+        if !lcc_output.status.success() {
+            anyhow::bail!(
+                "llc failed to compile {} into {}",
+                self.opt_path.display(),
+                ptx_path.display()
+            );
+        }
+
+        if self.split_debug {
+            debug::split(&ptx_path)?;
+        }
+        markers::insert(&ptx_path)?;
+
+        let ptx = std::fs::read(&ptx_path)
+            .context(format!("Failed to read PTX: {}", ptx_path.display()))?;
+        match ptx::parse(&String::from_utf8_lossy(&ptx)) {
+            Ok(module) => {
+                tracing::debug!(
+                    "verified {} functions in {}",
+                    module.functions.len(),
+                    ptx_path.display()
+                );
+                for function in &module.functions {
+                    tracing::trace!(
+                        "{:?} {} at line {} with parameters {:?}",
+                        function.kind,
+                        function.name,
+                        function.line,
+                        function.params
+                    );
+                }
+                self.check_limits(&module, &ptx_path)?;
+            }
+            Err(err) if self.verify_ptx => {
+                return Err(err).context(format!("Invalid PTX: {}", ptx_path.display()));
+            }
+            Err(err) => tracing::debug!(
+                "not checking launch bounds of {}: {err}",
+                ptx_path.display()
+            ),
+        }
+
+        if self.size_report {
+            let ptx = std::fs::read(&ptx_path)
+                .context(format!("Failed to read PTX: {}", ptx_path.display()))?;
+            let report = size::Report::from_ptx(&String::from_utf8_lossy(&ptx));
+            tracing::info!("size of {}: {report}", ptx_path.display());
+        }
+
+        let (Some(cpu), Some(arch)) = (cpu, arch) else {
+            return Ok(());
+        };
+
+        if self.rdc {
+            if let Some(asm_path) = self.emit_path(EmitKind::Asm) {
+                self.link_rdc(cpu, arch, &ptx_path, &self.cpu_path(&asm_path, cpu))?;
+            }
+        }
+
+        if [EmitKind::Bundle, EmitKind::Cubin, EmitKind::Fatbin]
+            .into_iter()
+            .any(|kind| self.emit_path(kind).is_some())
+        {
+            self.assemble(arch, &ptx_path, &self.cubin_path(cpu), false)?;
+        }
+
+        Ok(())
+    }
+
+    /// Assemble `ptx_path` into a cubin with `ptxas`
+    fn assemble(
+        &self,
+        cpu: &str,
+        ptx_path: &Path,
+        cubin_path: &Path,
+        relocatable: bool,
+    ) -> anyhow::Result<()> {
+        let mut ptxas_command = std::process::Command::new(super::tools::cuda_tool("ptxas"));
+
+        if relocatable {
+            ptxas_command.arg("--compile-only");
+        }
+        match self.debug_info {
+            DebugInfo::Default => {}
+            DebugInfo::Full => {
+                ptxas_command.arg("--device-debug");
+            }
+            DebugInfo::LineTables => {
+                ptxas_command.arg("--generate-line-info");
+            }
+        }
+
+        let ptxas_output = self.run_tool(
+            "ptxas",
+            ptxas_command
+                .arg("--gpu-name")
+                .arg(cpu)
+                .arg(ptx_path)
+                .arg("--output-file")
+                .arg(cubin_path),
+            None,
+        )?;
+
+        if !ptxas_output.status.success() {
+            CpuRejected::check("ptxas", cpu, &ptxas_output.stderr)?;
+            anyhow::bail!("ptxas failed to assemble {} for {cpu}", ptx_path.display());
+        }
+
+        Ok(())
+    }
+
+    /// Write a fatbin of the cubins for all target cpus and the PTX for the
+    /// oldest one as the JIT fallback with `fatbinary`
+    ///
+    /// Before this can be called `compile_one` needs to be called for all cpus
+    fn write_fatbin(&self, fatbin_path: &Path) -> anyhow::Result<()> {
+        let image = |kind: &str, cpu: &str, path: &Path| {
+            let sm = cpu.strip_prefix("sm_").unwrap_or(cpu);
+            path_arg(&format!("--image3=kind={kind},sm={sm},file="), path)
+        };
+
+        let mut fatbinary_command =
+            std::process::Command::new(super::tools::cuda_tool("fatbinary"));
+        fatbinary_command
+            .arg("-64")
+            .arg(path_arg("--create=", fatbin_path));
+        let compiled = self.compiled_archs();
+        for (cpu, arch) in &compiled {
+            fatbinary_command.arg(image("elf", arch, &self.cubin_path(cpu)));
+        }
+        if let Some((cpu, arch)) = compiled
+            .iter()
+            .min_by_key(|(_, arch)| bundle::arch_version(arch).unwrap_or(u32::MAX))
+        {
+            fatbinary_command.arg(image("ptx", arch, &self.ptx_path(Some(cpu))));
+        }
+
+        tracing::info!(
+            "Writing fatbin for {} target(s): {}",
+            self.cpus.len(),
+            fatbin_path.display()
+        );
+        let fatbinary_output = self.run_tool("fatbinary", &mut fatbinary_command, None)?;
+
+        if !fatbinary_output.status.success() {
+            anyhow::bail!("fatbinary failed to write {}", fatbin_path.display());
+        }
+
+        Ok(())
+    }
+
+    /// The target cpus and the cpus they were compiled for, which differ for
+    /// those that fell back, once for every compiled cpu
+    ///
+    /// Before this can be called `compile_one` needs to be called for all cpus
+    fn compiled_archs(&self) -> Vec<(&str, &str)> {
+        let fallen_back = self.fallen_back.lock().unwrap();
+        let mut compiled: Vec<(&str, &str)> = Vec::new();
+        for cpu in &self.cpus {
+            let arch = match &self.fallback_cpu {
+                Some(fallback) if fallen_back.contains(cpu) => fallback,
+                _ => cpu,
+            };
+            if !compiled.iter().any(|(_, other)| other == arch) {
+                compiled.push((cpu, arch));
+            }
+        }
+        compiled
+    }
+
+    /// Write a bundle of the cubins for all target cpus and the PTX for the
+    /// oldest one as the JIT fallback
+    ///
+    /// Before this can be called `compile_one` needs to be called for all cpus
+    fn write_bundle(&self, bundle_path: &Path) -> anyhow::Result<()> {
+        let mut bundle = Bundle::default();
+
+        let compiled = self.compiled_archs();
+        for (cpu, arch) in &compiled {
+            let cubin_path = self.cubin_path(cpu);
+            bundle.entries.push(bundle::Entry {
+                kind: bundle::EntryKind::Cubin,
+                arch: (*arch).to_owned(),
+                data: std::fs::read(&cubin_path)
+                    .context(format!("Failed to read cubin: {}", cubin_path.display()))?,
+            });
+        }
+
+        if let Some((cpu, arch)) = compiled
+            .iter()
+            .min_by_key(|(_, arch)| bundle::arch_version(arch).unwrap_or(u32::MAX))
+        {
+            let ptx_path = self.ptx_path(Some(cpu));
+            bundle.entries.push(bundle::Entry {
+                kind: bundle::EntryKind::Ptx,
+                arch: (*arch).to_owned(),
+                data: std::fs::read(&ptx_path)
+                    .context(format!("Failed to read PTX: {}", ptx_path.display()))?,
+            });
+        }
+
+        tracing::info!(
+            "Writing bundle with {} entries: {}",
+            bundle.entries.len(),
+            bundle_path.display()
+        );
+
+        let file = std::fs::File::create(bundle_path).context(format!(
+            "Failed to create bundle: {}",
+            bundle_path.display()
+        ))?;
+        bundle
+            .write(std::io::BufWriter::new(file))
+            .context(format!("Failed to write bundle: {}", bundle_path.display()))
+    }
+
+    /// Write the optimized bitcode as the only member of an archive, which
+    /// later links take like an rlib
+    ///
+    /// Before this can be called `optimize` needs to be called
+    fn write_device_rlib(&self, path: &Path) -> anyhow::Result<()> {
+        let bitcode = std::fs::read(&self.opt_path).context(format!(
+            "Failed to read optimized bitcode: {}",
+            self.opt_path.display()
+        ))?;
+
+        tracing::info!("Writing device rlib: {}", path.display());
+        std::fs::write(path, archive::write(&[("optimized.bc", &bitcode)]))
+            .context(format!("Failed to write device rlib: {}", path.display()))
+    }
+
+    /// Write the outputs of the optimized module: the device rlib, the
+    /// textual IR and the bitcode
+    fn write_optimized_outputs(&self) -> anyhow::Result<()> {
+        if let Some(path) = self.emit_path(EmitKind::DeviceRlib) {
+            self.write_device_rlib(&path)?;
+        }
+        if let Some(path) = self.emit_path(EmitKind::LlvmIr) {
+            tracing::info!("Writing optimized IR: {}", path.display());
+            let ir = self.disassemble(&self.opt_path)?;
+            std::fs::write(&path, ir).context(format!("Failed to write IR: {}", path.display()))?;
+        }
+        if let Some(path) = self.emit_path(EmitKind::LlvmBc) {
+            tracing::info!("Writing optimized bitcode: {}", path.display());
+            std::fs::copy(&self.opt_path, &path)
+                .context(format!("Failed to write bitcode: {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Write a host object embedding the image at `image_path`, named after
+    /// the output file, see [`crate::embed`]
+    fn write_object(&self, image_path: &Path, object_path: &Path) -> anyhow::Result<()> {
+        let image = std::fs::read(image_path)
+            .context(format!("Failed to read image: {}", image_path.display()))?;
+        let name = self
+            .out_path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+
+        tracing::info!(
+            "Embedding {} as `{name}` into host object {}",
+            image_path.display(),
+            object_path.display()
+        );
+
+        let mut llc_command = self.tools.command("llc");
+        if let Some(host_target) = &self.host_target {
+            llc_command.arg(format!("--mtriple={host_target}"));
+        }
+
+        let llc_output = self.run_tool(
+            "llc",
+            llc_command
+                .arg("--filetype=obj")
+                .arg("-")
+                .arg("-o")
+                .arg(object_path),
+            Some(object::ir(&name, &image).as_bytes()),
+        )?;
+
+        if !llc_output.status.success() {
+            anyhow::bail!("llc failed to write host object {}", object_path.display());
+        }
+
+        Ok(())
+    }
+
+    /// Assemble `ptx_path` into relocatable device code for `arch` with
+    /// `ptxas` and link it with the device libraries into a cubin using
+    /// `nvlink`, as the output for the target cpu `cpu`
+    fn link_rdc(
+        &self,
+        cpu: &str,
+        arch: &str,
+        ptx_path: &Path,
+        out_path: &Path,
+    ) -> anyhow::Result<()> {
+        let object_path = self.intermediates.path(Some(cpu), "rdc.cubin");
+        self.assemble(arch, ptx_path, &object_path, true)?;
+
+        let mut objects = vec![object_path];
+        for (index, device_lib) in self.device_libs.iter().enumerate() {
+            let object = match device_lib.kind {
+                DeviceLibKind::Cubin => device_lib.path.clone(),
+                DeviceLibKind::Fatbin => self.extract_cubin(device_lib, index, cpu, arch)?,
+            };
+            objects.push(object);
+        }
+
+        tracing::info!(
+            "Linking {} relocatable device code objects for {arch} using nvlink",
+            objects.len()
+        );
+
+        let nvlink_output = self.run_tool(
+            "nvlink",
+            std::process::Command::new(super::tools::cuda_tool("nvlink"))
+                .arg(format!("--arch={arch}"))
+                .args(&objects)
+                .arg("--output-file")
+                .arg(out_path),
+            None,
+        )?;
+
+        if !nvlink_output.status.success() {
+            CpuRejected::check("nvlink", arch, &nvlink_output.stderr)?;
+            anyhow::bail!(
+                "nvlink failed to link relocatable device code into {}",
+                out_path.display()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Extract the relocatable cubin for `arch` from a fatbin device library
+    /// using `cuobjdump`, for the target cpu `cpu`
+    fn extract_cubin(
+        &self,
+        device_lib: &DeviceLib,
+        index: usize,
+        cpu: &str,
+        arch: &str,
+    ) -> anyhow::Result<PathBuf> {
+        let extract_dir = self.extract_dir(device_lib, index, cpu);
+        // Remove cubins extracted by earlier links
+        if extract_dir.exists() {
+            std::fs::remove_dir_all(&extract_dir).context(format!(
+                "Failed to remove directory: {}",
+                extract_dir.display()
+            ))?;
+        }
+        std::fs::create_dir_all(&extract_dir).context(format!(
+            "Failed to create directory: {}",
+            extract_dir.display()
+        ))?;
+
+        let cuobjdump_output = self.run_tool(
+            "cuobjdump",
+            std::process::Command::new(super::tools::cuda_tool("cuobjdump"))
+                .arg("--extract-elf")
+                .arg("all")
+                .arg(&device_lib.path)
+                .current_dir(&extract_dir),
+            None,
+        )?;
+
+        if !cuobjdump_output.status.success() {
+            anyhow::bail!(
+                "cuobjdump failed to extract device code from {}",
+                device_lib.path.display()
+            );
+        }
+
+        std::fs::read_dir(&extract_dir)?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .find(|path| {
+                path.file_name().is_some_and(|name| {
+                    DeviceLib::is_extracted_cubin_for(&name.to_string_lossy(), arch)
+                })
+            })
+            .context(format!(
+                "device library {} contains no relocatable device code for {arch}",
+                device_lib.path.display()
+            ))
+    }
+
+    /// The directory the cubins of the `index`th device library are extracted
+    /// into for `cpu`
+    fn extract_dir(&self, device_lib: &DeviceLib, index: usize, cpu: &str) -> PathBuf {
+        let mut name = OsString::from(format!("{index}."));
+        name.push(device_lib.path.file_name().unwrap_or_default());
+        name.push(".extracted");
+        self.intermediates.path(Some(cpu), name)
+    }
+
+    /// The arguments of `opt` running the pass `pipeline`, as legacy pass
+    /// flags for the `opt` of old LLVM versions
+    fn pass_args(&self, pipeline: &str) -> Result<Vec<String>, passes::UnsupportedPipeline> {
+        passes::args(pipeline, self.tools.opt_major())
+    }
+
+    /// Runs an external `tool`, optionally feeding `stdin` to it
+    ///
+    /// Its stderr is parsed into diagnostics which are logged and recorded for
+    /// the session. Callers are responsible for checking the exit status.
+    fn run_tool(
+        &self,
+        tool: &str,
+        command: &mut std::process::Command,
+        stdin: Option<&[u8]>,
+    ) -> anyhow::Result<std::process::Output> {
+        Interrupted::check()?;
+        self.used_tools.lock().unwrap().insert(tool.to_owned());
+
+        let child = command
+            .stdin(if stdin.is_some() {
+                std::process::Stdio::piped()
+            } else {
+                std::process::Stdio::null()
+            })
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .context(format!("Failed to run {tool}"))?;
+
+        let output = self
+            .memory
+            .wait_with_output(tool, child, stdin.map(<[u8]>::to_vec))
+            .context(format!("Failed to run {tool} to completion"))?;
+
+        self.record_diagnostics(
+            tool,
+            Diagnostic::parse(tool, &String::from_utf8_lossy(&output.stderr)),
+        )?;
+
+        if !output.status.success() {
+            tracing::error!("{tool} returned with Exit status: {}", output.status);
+        }
+
+        Ok(output)
+    }
+
+    /// Logs and records the `diagnostics` of `tool` for the session, failing
+    /// if any of its warnings are denied
+    fn record_diagnostics(
+        &self,
+        tool: &str,
+        mut diagnostics: Vec<Diagnostic>,
+    ) -> anyhow::Result<()> {
+        // Rejected cpus fall back instead, see `Session::compile_for`
+        let falls_back = |diagnostic: &Diagnostic| {
+            self.fallback_cpu.is_some() && CpuRejected::is_unknown_cpu(&diagnostic.message)
+        };
+
+        let mut denied = Vec::new();
+        for diagnostic in &mut diagnostics {
+            if !falls_back(diagnostic) && self.deny.iter().any(|deny| deny.matches(diagnostic)) {
+                diagnostic.severity = Severity::Error;
+                denied.push(diagnostic.message.clone());
+            }
+            diagnostic.emit();
+        }
+        self.diagnostics.lock().unwrap().extend(diagnostics);
+
+        if !denied.is_empty() {
+            anyhow::bail!("denied warnings of {tool}: {}", denied.join("; "));
+        }
+
+        Ok(())
+    }
+
+    /// Removes all outputs and intermediates the session may have written,
+    /// e.g. after it was [`Interrupted`]
+    pub fn remove_outputs(&self) {
+        self.intermediates.remove();
+
+        let mut paths = vec![output_sibling(&self.out_path, "outputs.json")];
+        let cpus = if self.cpus.is_empty() {
+            vec![None]
+        } else {
+            self.cpus.iter().map(|cpu| Some(cpu.as_str())).collect()
+        };
+        for cpu in cpus {
+            paths.push(self.ptx_path(cpu));
+            paths.push(debug::path(&self.ptx_path(cpu)));
+            for emit in &self.emit {
+                if let Some(path) = self.emit_path(emit.kind) {
+                    let cpu_path =
+                        cpu.map_or_else(|| path.clone(), |cpu| self.cpu_path(&path, cpu));
+                    paths.push(signing::signature_path(&cpu_path));
+                    paths.push(cpu_path);
+                    paths.push(signing::signature_path(&path));
+                    paths.push(path);
+                }
+            }
+        }
+
+        for path in paths {
+            match std::fs::remove_file(&path) {
+                Ok(()) => tracing::debug!("removed {}", path.display()),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => tracing::warn!("failed to remove {}: {err}", path.display()),
+            }
+        }
+    }
+
+    /// All diagnostics reported by external tools and the linker so far
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.lock().unwrap().clone()
+    }
+
+    /// The output path for `cpu` when building for multiple target cpus,
+    /// e.g. `kernel.sm_80.ptx` for `kernel.ptx`
+    fn cpu_path(&self, path: &Path, cpu: &str) -> PathBuf {
+        if self.cpus.len() <= 1 {
+            return path.to_owned();
+        }
+        with_cpu(path, cpu)
+    }
+
+    /// The path `llc` writes the PTX for `cpu` to, which is the emitted
+    /// assembly unless that is a cubin in relocatable device code mode
+    fn ptx_path(&self, cpu: Option<&str>) -> PathBuf {
+        match self.emit_path(EmitKind::Asm) {
+            Some(asm_path) if !self.rdc => match cpu {
+                Some(cpu) => self.cpu_path(&asm_path, cpu),
+                None => asm_path,
+            },
+            _ => self.intermediates.path(cpu, "ptx"),
+        }
+    }
+
+    /// The path of the cubin assembled from the PTX for `cpu`, the emitted
+    /// cubin if there is one
+    fn cubin_path(&self, cpu: &str) -> PathBuf {
+        match self.emit_path(EmitKind::Cubin) {
+            Some(cubin_path) => self.cpu_path(&cubin_path, cpu),
+            None => self.intermediates.path(Some(cpu), "cubin"),
+        }
+    }
+
+    /// Post-process and sign the outputs, recording them and the versions of
+    /// the tools in a manifest next to the output
+    fn finish_outputs(&self) -> anyhow::Result<()> {
+        let manifest_path = output_sibling(&self.out_path, "outputs.json");
+        let write = |manifest: &Manifest| {
+            std::fs::write(&manifest_path, manifest.to_vec()).context(format!(
+                "Failed to write the output manifest: {}",
+                manifest_path.display()
+            ))
+        };
+
+        let mut manifest = Manifest::new(self.target.triple());
+        manifest.artifacts = self.artifacts()?;
+        manifest.stage_hashes = self.stage_hashes();
+        manifest.renamed_symbols = self.renamed.clone();
+        manifest.fingerprint = self.fingerprint.clone();
+        manifest.tools = self
+            .used_tools
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|tool| Some((tool.clone(), self.tool_version(tool)?)))
+            .collect();
+        if !self.rdc && self.cpus.len() > 1 {
+            manifest.shared_kernels = shared_kernels(&manifest.artifacts)?;
+            tracing::info!(
+                "{} kernel(s) are identical for several target cpus",
+                manifest.shared_kernels.len()
+            );
+        }
+        let write_manifest = self.write_manifest || !self.post_processors.is_empty();
+        if write_manifest {
+            write(&manifest)?;
+        }
+        postprocess::run(&self.post_processors, &manifest, &manifest_path)?;
+
+        if let Some(signer) = &self.signer {
+            for artifact in &mut manifest.artifacts {
+                artifact.signature = Some(signer.sign(&artifact.path)?);
+            }
+            tracing::info!("signed {} output(s)", manifest.artifacts.len());
+            if write_manifest {
+                write(&manifest)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Record the hash of the files at `paths` written by `step`, for the
+    /// manifest and `--print stage-hashes`
+    fn record_stage_hash(
+        &self,
+        step: Step,
+        cpu: Option<&str>,
+        paths: &[&Path],
+    ) -> anyhow::Result<()> {
+        let mut hasher = Hasher::default();
+        for path in paths {
+            hasher.file(path).context(format!(
+                "Failed to hash the output of the {step} step: {}",
+                path.display()
+            ))?;
+        }
+        let hash = hasher.finish();
+        tracing::debug!("{step} step output hash: {hash}");
+
+        self.stage_hashes.lock().unwrap().push(StageHash {
+            stage: step.to_string(),
+            cpu: cpu.map(str::to_owned),
+            hash,
+        });
+        Ok(())
+    }
+
+    /// The hashes of the outputs of the steps that ran, in order
+    ///
+    /// Steps of stages resumed from a checkpoint did not run and have no hash.
+    pub fn stage_hashes(&self) -> Vec<StageHash> {
+        self.stage_hashes.lock().unwrap().clone()
+    }
+
+    /// All emitted outputs, one per target cpu for the assembly and cubins
+    fn artifacts(&self) -> anyhow::Result<Vec<Artifact>> {
+        let mut artifacts = Vec::new();
+        for emit in &self.emit {
+            let Some(path) = self.emit_path(emit.kind) else {
+                continue;
+            };
+            let cpus =
+                if matches!(emit.kind, EmitKind::Asm | EmitKind::Cubin) && self.cpus.len() > 1 {
+                    self.cpus.iter().map(Some).collect()
+                } else {
+                    vec![None]
+                };
+
+            for cpu in cpus {
+                let path = cpu.map_or_else(|| path.clone(), |cpu| self.cpu_path(&path, cpu));
+                let ptx = emit.kind == EmitKind::Asm && !self.rdc;
+                let kernels = if ptx { ptx_kernels(&path)? } else { Vec::new() };
+                artifacts.push(Artifact {
+                    kind: emit.kind.name().to_owned(),
+                    cpu: cpu.cloned(),
+                    debug: (ptx && self.split_debug).then(|| debug::path(&path)),
+                    path,
+                    signature: None,
+                    kernels,
+                });
+            }
+        }
+        Ok(artifacts)
+    }
+
+    /// The path of the emitted output of `kind`, if it is emitted
+    ///
+    /// The first emitted output is written to `-o` unless it has an explicit
+    /// path, all others are written next to it with their own extension.
+    fn emit_path(&self, kind: EmitKind) -> Option<PathBuf> {
+        let (index, emit) = self
+            .emit
+            .iter()
+            .enumerate()
+            .find(|(_, emit)| emit.kind == kind)?;
+
+        Some(match &emit.path {
+            Some(path) => path.clone(),
+            None if index == 0 => self.out_path.clone(),
+            None => self.out_path.with_extension(kind.extension()),
+        })
+    }
+
+    /// A canonical fingerprint of all options that affect the outputs, e.g.
+    /// as part of the key of a build cache
+    ///
+    /// Options that only affect diagnostics, like the size report, timings or
+    /// the number of jobs, are left out, as are the contents of the inputs.
+    /// The symbols kept for exported patterns and metadata are found in the
+    /// inputs first, as [`Session::lto`] does.
+    pub fn fingerprint(
+        &mut self,
+        optimization: Optimization,
+        internalize: bool,
+        debug: bool,
+        inline: bool,
+    ) -> anyhow::Result<String> {
+        self.intermediates.create()?;
+        self.prepare(debug)?;
+        Ok(self.options_fingerprint(optimization, internalize, debug, inline))
+    }
+
+    /// The fingerprint of the options once the session is prepared
+    fn options_fingerprint(
+        &self,
+        optimization: Optimization,
+        internalize: bool,
+        debug: bool,
+        inline: bool,
+    ) -> String {
+        let mut cpus = self.cpus.iter().collect::<Vec<_>>();
+        cpus.sort();
+        cpus.dedup();
+        let mut symbols = self.symbols.iter().collect::<Vec<_>>();
+        symbols.sort();
+        symbols.dedup();
+        let mut skipped = self.skipped.clone();
+        skipped.sort();
+
+        let mut hasher = Hasher::default();
+        hasher
+            .field(self.target.triple())
+            .field(self.tools.llvm_major())
+            .field(format!("{optimization}"))
+            .update(&[
+                u8::from(internalize),
+                u8::from(debug),
+                u8::from(inline),
+                u8::from(self.prune),
+                u8::from(self.rdc),
+                u8::from(self.split_debug),
+                u8::from(self.libdevice.is_some()),
+                u8::from(self.rename_on_conflict),
+            ])
+            .field(format!("{:?}", self.pipeline))
+            .field(format!("{:?}", self.debug_info))
+            .field(format!("{:?}", self.dtor_policy))
+            .field(format!("{:?}", self.unreachable))
+            .field(self.fallback_cpu.as_deref().unwrap_or_default())
+            .field(
+                self.profile_boundaries
+                    .map(|min| min.to_string())
+                    .unwrap_or_default(),
+            )
+            .field(
+                self.coalesce_constants
+                    .map(|max| max.to_string())
+                    .unwrap_or_default(),
+            )
+            .field(self.export_patterns.join("\n"))
+            .field(format!("{:?}", self.keep_symbols_format))
+            .field(self.exported_metadata.join(","))
+            .field(joined(&self.target_features, ","))
+            .field(joined(&self.function_features, " "))
+            .field(
+                self.signer
+                    .as_ref()
+                    .map(Signer::public_key)
+                    .unwrap_or_default(),
+            )
+            .field(self.passes.as_deref().unwrap_or_default())
+            .field(self.extra_passes.as_deref().unwrap_or_default())
+            .field(self.no_inline.join("\n"))
+            .field(self.force_inline.join("\n"))
+            .field(self.host_target.as_deref().unwrap_or_default())
+            .field(
+                self.codegen_optimization
+                    .map(Optimization::codegen_level)
+                    .map(String::from)
+                    .unwrap_or_default(),
+            )
+            .field(
+                self.stop_after
+                    .map(|step| step.to_string())
+                    .unwrap_or_default(),
+            );
+
+        hasher.field(cpus.len().to_le_bytes());
+        for cpu in cpus {
+            hasher.field(cpu);
+        }
+        hasher.field(symbols.len().to_le_bytes());
+        for symbol in symbols {
+            hasher.field(symbol.as_bytes());
+        }
+        hasher.field(skipped.len().to_le_bytes());
+        for step in skipped {
+            hasher.field(step.to_string());
+        }
+        hasher.field(self.emit.len().to_le_bytes());
+        for emit in &self.emit {
+            let path = self.emit_path(emit.kind).unwrap_or_default();
+            hasher
+                .field(emit.kind.extension())
+                .field(path.as_os_str().to_string_lossy().as_bytes());
+        }
+        hasher.field(self.device_libs.len().to_le_bytes());
+        for device_lib in &self.device_libs {
+            hasher.field(device_lib.path.as_os_str().to_string_lossy().as_bytes());
+        }
+
+        hasher.finish()
+    }
+
+    /// The fingerprint of everything the linked module depends on
+    fn link_fingerprint(
+        &self,
+        optimization: Optimization,
+        internalize: bool,
+    ) -> anyhow::Result<String> {
+        let mut hasher = Hasher::default();
+        hasher.field(self.tools.llvm_major()).update(&[
+            u8::from(self.prune && internalize),
+            u8::from(self.device_asserts),
+            u8::from(self.panic_trap),
+        ]);
+        if !self.is_skipped(Step::Optimize) {
+            match self.pipeline {
+                Pipeline::Merge => {}
+                Pipeline::Premerge | Pipeline::PerModule => {
+                    hasher.field(format!("premerge {optimization}"));
+                }
+                Pipeline::Thin => {
+                    hasher.field(format!("thin {optimization}"));
+                }
+            }
+        }
+        for step in &self.skipped {
+            hasher.field(step.to_string());
+        }
+
+        for path in &self.bitcode {
+            hasher
+                .field(path.as_os_str().to_string_lossy().as_bytes())
+                .file(path)
+                .context(format!("Failed to hash input: {}", path.display()))?;
+        }
+        for symbol in &self.symbols {
+            hasher.field(symbol.as_bytes());
+        }
+
+        Ok(hasher.finish())
+    }
+
+    /// Write the bitcode module produced by `step` to the output file
+    fn write_step_output(&self, step: Step) -> anyhow::Result<()> {
+        if step == Step::Prune {
+            return self.write_pruned_inputs();
+        }
+
+        let module = if step == Step::Merge {
+            &self.link_path
+        } else {
+            &self.opt_path
+        };
+        tracing::info!(
+            "stopping after the {step} step, writing bitcode: {}",
+            self.out_path.display()
+        );
+        std::fs::copy(module, &self.out_path).context(format!(
+            "Failed to write bitcode: {}",
+            self.out_path.display()
+        ))?;
+        Ok(())
+    }
+
+    /// Writes the bitcode inputs after [`Step::Prune`], see
+    /// [`Session::set_only_step`]
+    fn write_pruned_inputs(&self) -> anyhow::Result<()> {
+        let paths = match self.bitcode.as_slice() {
+            [_] => vec![self.out_path.clone()],
+            inputs => (0..inputs.len())
+                .map(|index| with_cpu(&self.out_path, &index.to_string()))
+                .collect(),
+        };
+
+        for (input, path) in self.bitcode.iter().zip(&paths) {
+            tracing::info!(
+                "stopping after the prune step, writing bitcode: {}",
+                path.display()
+            );
+            std::fs::copy(input, path)
+                .context(format!("Failed to write bitcode: {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Links, optimizes and compiles to the native format
+    ///
+    /// The linked and the optimized module are checkpointed, so that with
+    /// [`Session::set_resume`] a later run with the same inputs and options
+    /// continues after the last completed stage.
+    pub fn lto(
+        &mut self,
         optimization: crate::Optimization,
         internalize: bool,
         debug: bool,
         inline: bool,
+        jobs: NonZeroUsize,
     ) -> anyhow::Result<()> {
-        self.link()?;
-        self.optimize(optimization, internalize, debug, inline)?;
-        self.compile()
+        self.intermediates.create()?;
+        self.prepare(debug)?;
+
+        let link_fingerprint = self.link_fingerprint(optimization, internalize)?;
+        let fingerprint = self.options_fingerprint(optimization, internalize, debug, inline);
+        tracing::debug!("options fingerprint: {fingerprint}");
+        self.fingerprint = Some(fingerprint.clone());
+        let optimize_fingerprint = {
+            let mut hasher = Hasher::default();
+            hasher.field(&link_fingerprint).field(&fingerprint);
+            hasher.finish()
+        };
+
+        let checkpoint_path = self.intermediates.path(None, "checkpoints.json");
+        let mut checkpoints = if self.resume {
+            Checkpoints::load(checkpoint_path)
+        } else {
+            Checkpoints::new(checkpoint_path)
+        };
+
+        let mut timings: Vec<(Stage, Duration)> = Vec::new();
+        let mut resume = self.resume;
+        let stages = [
+            (Stage::Link, link_fingerprint, self.link_path.clone()),
+            (Stage::Optimize, optimize_fingerprint, self.opt_path.clone()),
+        ];
+
+        for (stage, fingerprint, output) in stages {
+            if stage == Stage::Optimize && self.stop_after == Some(Step::Merge) {
+                break;
+            }
+            if resume && checkpoints.is_complete(stage, &fingerprint, &output) {
+                tracing::info!("resuming after the {stage} stage: {}", output.display());
+                continue;
+            }
+            resume = false;
+
+            let start = Instant::now();
+            match stage {
+                Stage::Link => {
+                    self.map_intrinsics()?;
+                    if self.prune && internalize && !self.is_skipped(Step::Prune) {
+                        self.prune()?;
+                        let inputs = self
+                            .bitcode
+                            .iter()
+                            .map(PathBuf::as_path)
+                            .collect::<Vec<_>>();
+                        self.record_stage_hash(Step::Prune, None, &inputs)?;
+                    }
+                    if self.stop_after == Some(Step::Prune) {
+                        return self.write_step_output(Step::Prune);
+                    }
+                    if !self.is_skipped(Step::Optimize) {
+                        match self.pipeline {
+                            Pipeline::Merge => {}
+                            Pipeline::Premerge | Pipeline::PerModule => {
+                                self.premerge(optimization, jobs)?;
+                            }
+                            Pipeline::Thin => self.thin_optimize(optimization, jobs)?,
+                        }
+                    }
+                    self.link_reconciling()?;
+                    self.rewrite_linked()?;
+                    self.record_stage_hash(Step::Merge, None, &[&self.link_path])?;
+                }
+                Stage::Optimize => {
+                    if internalize {
+                        self.keep_kernels()?;
+                    }
+                    self.optimize(optimization, internalize, debug, inline)?;
+                    self.rewrite_optimized()?;
+                    if !self.rdc && !self.prunable.is_empty() {
+                        self.check_kernels()?;
+                    }
+                }
+                Stage::Compile => unreachable!("the compile stage is not checkpointed"),
+            }
+            timings.push((stage, start.elapsed()));
+            self.memory.sample(None)?;
+
+            checkpoints.record(stage, fingerprint, &output)?;
+
+            if stage == Stage::Link && self.globals_report {
+                self.report_globals()?;
+            }
+        }
+
+        self.report_module()?;
+
+        if let Some(step) = self.stop_after.filter(|step| *step != Step::Codegen) {
+            return self.write_step_output(step);
+        }
+
+        self.write_optimized_outputs()?;
+
+        if self.emit.iter().any(|emit| !emit.kind.is_bitcode()) {
+            self.check_undefined()?;
+            let start = Instant::now();
+            self.compile(jobs)?;
+            timings.push((Stage::Compile, start.elapsed()));
+
+            self.memory.sample(None)?;
+        } else {
+            tracing::info!("only emitting the optimized module, skipping code generation");
+        }
+
+        self.finish_outputs()?;
+
+        if self.timings {
+            self.log_timings(&timings);
+        }
+
+        Ok(())
+    }
+
+    /// Validate the options and complete the keep-set before linking
+    fn prepare(&mut self, debug: bool) -> anyhow::Result<()> {
+        self.validate()?;
+        self.check_locked()?;
+        if !self.exported_metadata.is_empty() {
+            self.keep_metadata_references()?;
+        }
+        if !self.export_patterns.is_empty() {
+            self.keep_exported_patterns()?;
+        }
+
+        if self.split_debug && !self.keeps_debug(debug) {
+            tracing::warn!("debug information is not kept, nothing to split");
+            self.split_debug = false;
+        }
+        Ok(())
+    }
+
+    /// Log the duration of the `timings` of the stages and the peak memory
+    fn log_timings(&self, timings: &[(Stage, Duration)]) {
+        for (stage, duration) in timings {
+            tracing::info!("{stage}: {:.3}s", duration.as_secs_f64());
+        }
+        let linker = memory::Monitor::peak_linker()
+            .map(|peak| format!(" (linker: {} MB)", peak / 1024 / 1024))
+            .unwrap_or_default();
+        tracing::info!(
+            "peak memory: {} MB{linker}",
+            self.memory.peak() / 1024 / 1024
+        );
+    }
+}
+
+/// The functions of `entries` matching any of the globs of the `what`
+/// `patterns`, warning about the patterns that match none
+fn matching_functions(entries: &[nm::Entry], patterns: &[String], what: &str) -> Vec<Symbol> {
+    let mut matched = vec![false; patterns.len()];
+    let mut functions = Vec::new();
+    for entry in entries
+        .iter()
+        .filter(|entry| entry.kind.eq_ignore_ascii_case(&b't'))
+    {
+        if let Some(index) = patterns
+            .iter()
+            .position(|pattern| entry.name.matches_glob_or_path(pattern))
+        {
+            matched[index] = true;
+            functions.push(entry.name.clone());
+        }
+    }
+
+    for (pattern, _) in patterns.iter().zip(matched).filter(|(_, matched)| !matched) {
+        tracing::warn!("the {what} pattern `{pattern}` matches no function");
+    }
+    functions
+}
+
+/// The displayed `items` joined with `separator`
+fn joined<T: std::fmt::Display>(items: &[T], separator: &str) -> String {
+    items
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// The `prefix` directly followed by `path` as a single argument, keeping
+/// non-UTF-8 paths intact
+fn path_arg(prefix: &str, path: &Path) -> OsString {
+    let mut arg = OsString::from(prefix);
+    arg.push(path);
+    arg
+}
+
+/// `path` with `cpu` inserted before its extension, e.g. `kernel.sm_80.ptx`
+/// for `kernel.ptx`
+fn with_cpu(path: &Path, cpu: &str) -> PathBuf {
+    match path.extension() {
+        Some(extension) => {
+            let mut cpu_extension = OsString::from(cpu);
+            cpu_extension.push(".");
+            cpu_extension.push(extension);
+            path.with_extension(cpu_extension)
+        }
+        None => path.with_extension(cpu),
+    }
+}
+
+/// The path of a file next to the output, e.g. `kernel.ptx.outputs.json` for
+/// `kernel.ptx`
+fn output_sibling(out_path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = out_path.file_name().unwrap_or_default().to_owned();
+    file_name.push(".");
+    file_name.push(suffix);
+    out_path.with_file_name(file_name)
+}
+
+/// Remove the exception handling of `panic=unwind` inputs from the linked
+/// module `ir`, which the backend cannot lower
+///
+/// Returns whether `ir` was changed.
+fn prune_unwinding(ir: &mut String) -> bool {
+    let pruned = unwind::prune(ir);
+    if pruned.is_empty() {
+        return false;
+    }
+    tracing::info!(
+        "removing exception handling: {} invoke(s) and {} landing pad(s) in {} function(s)",
+        pruned.invokes,
+        pruned.landing_pads,
+        pruned.functions
+    );
+    *ir = pruned.ir;
+    true
+}
+
+/// Replace the calls to the panic functions of `core` in the linked module
+/// `ir` with calls to `__assertfail`
+///
+/// This runs before optimizing, which strips the debug locations on nvptx64.
+/// Returns whether `ir` was changed.
+fn lower_asserts(ir: &mut String) -> bool {
+    let lowered = asserts::lower(ir);
+    if lowered.asserts == 0 {
+        return false;
+    }
+    tracing::info!(
+        "reporting {} panic(s) with {}",
+        lowered.asserts,
+        asserts::ASSERT_FAIL
+    );
+    if lowered.unlocated > 0 {
+        tracing::warn!(
+            "{} panic(s) have no debug location and are reported without their file and line",
+            lowered.unlocated
+        );
+    }
+    *ir = lowered.ir;
+    true
+}
+
+/// Replace the bodies of the panic functions of the linked module `ir` with a
+/// trap
+///
+/// This runs before optimizing, which then removes the formatting code they
+/// called. Returns whether `ir` was changed.
+fn trap_panics(ir: &mut String) -> bool {
+    let trapped = traps::replace(ir);
+    if trapped.functions.is_empty() {
+        return false;
+    }
+    tracing::info!(
+        "replacing {} panic function(s) with a trap: {}",
+        trapped.functions.len(),
+        trapped
+            .functions
+            .iter()
+            .map(|name| symbol::demangle(name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    *ir = trapped.ir;
+    true
+}
+
+/// The kernels defined in the PTX at `path`
+fn ptx_kernels(path: &Path) -> anyhow::Result<Vec<String>> {
+    let ptx =
+        std::fs::read_to_string(path).context(format!("Failed to read PTX: {}", path.display()))?;
+    let module = ptx::parse(&ptx).context(format!("Failed to parse PTX: {}", path.display()))?;
+    Ok(module
+        .functions
+        .into_iter()
+        .filter(|function| function.kind == ptx::FunctionKind::Entry && function.defined)
+        .map(|function| function.name)
+        .collect())
+}
+
+/// The kernels with identical PTX in the assembly of several target cpus
+fn shared_kernels(artifacts: &[Artifact]) -> anyhow::Result<Vec<SharedKernel>> {
+    // The cpus of every distinct PTX of a kernel, in the order of the cpus
+    let mut variants: Vec<(String, String, Vec<String>)> = Vec::new();
+
+    for artifact in artifacts {
+        let Some(cpu) = artifact.cpu.as_ref().filter(|_| artifact.kind == EmitKind::Asm.name())
+        else {
+            continue;
+        };
+        let ptx = std::fs::read_to_string(&artifact.path)
+            .context(format!("Failed to read PTX: {}", artifact.path.display()))?;
+
+        for (name, body) in markers::kernels(&ptx) {
+            match variants
+                .iter_mut()
+                .find(|(other, other_body, _)| other == name && other_body == body)
+            {
+                Some((_, _, cpus)) => cpus.push(cpu.clone()),
+                None => variants.push((name.to_owned(), body.to_owned(), vec![cpu.clone()])),
+            }
+        }
+    }
+
+    Ok(variants
+        .into_iter()
+        .filter(|(_, _, cpus)| cpus.len() > 1)
+        .map(|(name, _, cpus)| SharedKernel { name, cpus })
+        .collect())
+}
+
+/// Runs `job` for every item on a pool of at most `jobs` threads, failing with
+/// the errors of all failed items in their order
+fn run_parallel<T: Sync>(
+    items: &[T],
+    jobs: NonZeroUsize,
+    job: impl Fn(&T) -> anyhow::Result<()> + Sync,
+) -> anyhow::Result<()> {
+    let next = AtomicUsize::new(0);
+    let mut errors = std::thread::scope(|scope| {
+        let handles = (0..jobs.get().min(items.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut errors = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(index) else {
+                            return errors;
+                        };
+                        if let Err(err) = job(item) {
+                            errors.push((index, err));
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("job panicked"))
+            .collect::<Vec<_>>()
+    });
+    errors.sort_by_key(|(index, _)| *index);
+    Errors::check(errors.into_iter().map(|(_, err)| err).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_spaces_in_paths() {
+        let path = Path::new("out dir/my kernel.ptx");
+        assert_eq!(
+            with_cpu(path, "sm_80"),
+            Path::new("out dir/my kernel.sm_80.ptx")
+        );
+        assert_eq!(
+            output_sibling(path, "outputs.json"),
+            Path::new("out dir/my kernel.ptx.outputs.json")
+        );
+        assert_eq!(
+            path_arg(
+                "--internalize-public-api-file=",
+                Path::new("out dir/a b.symbols")
+            ),
+            OsString::from("--internalize-public-api-file=out dir/a b.symbols")
+        );
+    }
+
+    #[test]
+    fn inserts_cpus_without_extensions() {
+        assert_eq!(
+            with_cpu(Path::new("kernel"), "sm_70"),
+            Path::new("kernel.sm_70")
+        );
+        assert_eq!(
+            with_cpu(Path::new("v1.2/kernel.cubin"), "sm_70"),
+            Path::new("v1.2/kernel.sm_70.cubin")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_non_utf8_paths() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(OsStr::from_bytes(b"/tmp/\xff dir/k\xe9rnel.p\xfex"));
+        assert_eq!(
+            with_cpu(path, "sm_80").as_os_str().as_bytes(),
+            b"/tmp/\xff dir/k\xe9rnel.sm_80.p\xfex"
+        );
+        assert_eq!(
+            output_sibling(path, "outputs.json").as_os_str().as_bytes(),
+            b"/tmp/\xff dir/k\xe9rnel.p\xfex.outputs.json"
+        );
+        assert_eq!(
+            path_arg("--internalize-public-api-file=", path).as_bytes(),
+            b"--internalize-public-api-file=/tmp/\xff dir/k\xe9rnel.p\xfex"
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn keeps_verbatim_long_paths() {
+        let dir = format!(r"\\?\C:\{}", "long_directory_name_".repeat(16));
+        let path = PathBuf::from(format!(r"{dir}\kernel.ptx"));
+        assert_eq!(
+            with_cpu(&path, "sm_80"),
+            PathBuf::from(format!(r"{dir}\kernel.sm_80.ptx"))
+        );
+        assert_eq!(
+            output_sibling(&path, "outputs.json"),
+            PathBuf::from(format!(r"{dir}\kernel.ptx.outputs.json"))
+        );
+    }
+
+    #[test]
+    fn sessions_are_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Session>();
+        assert_send_sync::<ToolLocator>();
+        assert_send_sync::<Intermediates>();
+    }
+
+    #[test]
+    fn creates_sessions_concurrently() {
+        // Empty tools are enough to create sessions, which only locate them
+        let tools_dir = std::env::temp_dir().join(format!(
+            "{}-test-tools-{}",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&tools_dir).unwrap();
+        for tool in ["llvm-link", "opt"] {
+            let file_name = format!("{tool}{}", std::env::consts::EXE_SUFFIX);
+            std::fs::write(tools_dir.join(file_name), "").unwrap();
+        }
+
+        // Eight threads create sessions for four outputs, two for each of them
+        let results = std::thread::scope(|scope| {
+            let handles = (0..8)
+                .map(|index| {
+                    let tools_dir = &tools_dir;
+                    scope.spawn(move || {
+                        Session::new(
+                            Target::Nvptx64NvidiaCuda,
+                            vec!["sm_70".to_owned()],
+                            PathBuf::from(format!("concurrent/{}.ptx", index % 4)),
+                            Some(14),
+                            Some(tools_dir),
+                        )
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        std::fs::remove_dir_all(&tools_dir).unwrap();
+
+        let (sessions, rejected): (Vec<_>, Vec<_>) = results.into_iter().partition(Result::is_ok);
+        let sessions = sessions.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(sessions.len(), 4);
+        assert_eq!(rejected.len(), 4);
+
+        let link_paths = sessions
+            .iter()
+            .map(|session| &session.link_path)
+            .collect::<BTreeSet<_>>();
+        assert_eq!(link_paths.len(), 4);
+        assert!(sessions
+            .iter()
+            .all(|session| std::ptr::eq(session.tools, sessions[0].tools)));
+
+        // Threads sharing a session record their diagnostics in it
+        let session = &sessions[0];
+        std::thread::scope(|scope| {
+            for tool in ["llc", "ptxas"] {
+                scope.spawn(move || {
+                    let diagnostics = Diagnostic::parse(tool, &format!("{tool}: warning: slow\n"));
+                    session.record_diagnostics(tool, diagnostics).unwrap();
+                });
+            }
+        });
+        assert_eq!(session.diagnostics.lock().unwrap().len(), 2);
     }
 }
@@ -0,0 +1,297 @@
+//! In-process linking of bitcode with the LLVM library instead of `llvm-link`,
+//! and reading its symbols instead of `llvm-nm`
+
+use std::ffi::{c_void, CStr, CString};
+use std::marker::PhantomData;
+use std::path::Path;
+
+use anyhow::Context as _;
+use llvm_sys::analysis::{LLVMVerifierFailureAction, LLVMVerifyModule};
+use llvm_sys::bit_reader::LLVMParseBitcodeInContext2;
+use llvm_sys::bit_writer::LLVMWriteBitcodeToMemoryBuffer;
+use llvm_sys::core::{
+    LLVMAliasGetAliasee, LLVMContextCreate, LLVMContextDispose, LLVMContextSetDiagnosticHandler,
+    LLVMCreateMemoryBufferWithMemoryRange, LLVMDisposeMemoryBuffer, LLVMDisposeMessage,
+    LLVMDisposeModule, LLVMGetBufferSize, LLVMGetBufferStart, LLVMGetDiagInfoDescription,
+    LLVMGetDiagInfoSeverity, LLVMGetFirstFunction, LLVMGetFirstGlobal, LLVMGetFirstGlobalAlias,
+    LLVMGetLinkage, LLVMGetNextFunction, LLVMGetNextGlobal, LLVMGetNextGlobalAlias, LLVMGetOperand,
+    LLVMGetValueName2, LLVMIsAConstantExpr, LLVMIsAFunction, LLVMIsDeclaration,
+};
+use llvm_sys::linker::LLVMLinkModules2;
+use llvm_sys::prelude::{LLVMContextRef, LLVMDiagnosticInfoRef, LLVMModuleRef, LLVMValueRef};
+use llvm_sys::{LLVMDiagnosticSeverity, LLVMLinkage};
+
+use super::diagnostics::{Diagnostic, Severity};
+use super::nm::Entry;
+use super::symbol::Symbol;
+
+/// The tool the diagnostics of in-process linking are reported for, so that
+/// they are denied like those of `llvm-link`
+pub const TOOL: &str = "llvm-link";
+
+/// Links the bitcode `modules`, named by the file they were read from, into a
+/// single verified module and returns its bitcode
+///
+/// The diagnostics of LLVM are added to `diagnostics`, also when linking
+/// fails.
+pub fn link(
+    modules: &[(&Path, &[u8])],
+    diagnostics: &mut Vec<Diagnostic>,
+) -> anyhow::Result<Vec<u8>> {
+    let context = Context::new(diagnostics);
+    let mut linked: Option<Module<'_>> = None;
+
+    for (path, bitcode) in modules {
+        let module = context
+            .parse(path, bitcode)
+            .context(format!("Failed to parse bitcode: {}", path.display()))?;
+
+        match &linked {
+            None => linked = Some(module),
+            Some(dest) => {
+                // The source module is destroyed by linking it
+                let failed = unsafe { LLVMLinkModules2(dest.raw, module.into_raw()) };
+                if failed != 0 {
+                    anyhow::bail!("failed to link bitcode: {}", path.display());
+                }
+            }
+        }
+    }
+
+    let linked = linked.context("no bitcode to link")?;
+    linked.verify()?;
+    Ok(linked.write())
+}
+
+/// The symbols defined by the `bitcode` read from `path`, optionally only the
+/// external ones
+///
+/// The symbols have the type letters `llvm-nm` gives them: `T` and `D` for
+/// functions and data, lowercase if they have local linkage, `W` for weak and
+/// `C` for common ones. Hidden symbols are external, since other modules
+/// linked into the same output may use them. Declarations, including
+/// `available_externally` definitions, and the `llvm.` globals of LLVM itself
+/// are not listed.
+pub fn defined_symbols(
+    path: &Path,
+    bitcode: &[u8],
+    extern_only: bool,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> anyhow::Result<Vec<Entry>> {
+    let context = Context::new(diagnostics);
+    let module = context
+        .parse(path, bitcode)
+        .context(format!("Failed to parse bitcode: {}", path.display()))?;
+
+    let mut entries = module.defined_symbols();
+    if extern_only {
+        entries.retain(|entry| entry.kind.is_ascii_uppercase());
+    }
+    Ok(entries)
+}
+
+/// An LLVM context recording its diagnostics
+struct Context<'a> {
+    raw: LLVMContextRef,
+    diagnostics: PhantomData<&'a mut Vec<Diagnostic>>,
+}
+
+impl<'a> Context<'a> {
+    fn new(diagnostics: &'a mut Vec<Diagnostic>) -> Self {
+        unsafe {
+            let raw = LLVMContextCreate();
+            LLVMContextSetDiagnosticHandler(
+                raw,
+                Some(handle_diagnostic),
+                (diagnostics as *mut Vec<Diagnostic>).cast(),
+            );
+            Context {
+                raw,
+                diagnostics: PhantomData,
+            }
+        }
+    }
+
+    /// Parses `bitcode`, `None` if it is malformed, which is reported as a
+    /// diagnostic
+    fn parse(&self, path: &Path, bitcode: &[u8]) -> Option<Module<'_>> {
+        let name = CString::new(path.to_string_lossy().as_bytes()).unwrap_or_default();
+        unsafe {
+            let buffer = LLVMCreateMemoryBufferWithMemoryRange(
+                bitcode.as_ptr().cast(),
+                bitcode.len(),
+                name.as_ptr(),
+                0,
+            );
+            // The module is fully materialized and does not borrow the buffer
+            let mut raw = std::ptr::null_mut();
+            let failed = LLVMParseBitcodeInContext2(self.raw, buffer, &mut raw);
+            LLVMDisposeMemoryBuffer(buffer);
+
+            (failed == 0).then_some(Module {
+                raw,
+                context: PhantomData,
+            })
+        }
+    }
+}
+
+impl Drop for Context<'_> {
+    fn drop(&mut self) {
+        unsafe { LLVMContextDispose(self.raw) }
+    }
+}
+
+/// A module owned by a [`Context`]
+struct Module<'a> {
+    raw: LLVMModuleRef,
+    context: PhantomData<&'a Context<'a>>,
+}
+
+impl Module<'_> {
+    fn into_raw(self) -> LLVMModuleRef {
+        let raw = self.raw;
+        std::mem::forget(self);
+        raw
+    }
+
+    fn verify(&self) -> anyhow::Result<()> {
+        let mut message = std::ptr::null_mut();
+        unsafe {
+            let failed = LLVMVerifyModule(
+                self.raw,
+                LLVMVerifierFailureAction::LLVMReturnStatusAction,
+                &mut message,
+            );
+            let message = take_message(message);
+            if failed != 0 {
+                anyhow::bail!("linked module is broken: {}", message.trim());
+            }
+        }
+        Ok(())
+    }
+
+    /// The symbols defined by the functions, global variables and aliases
+    fn defined_symbols(&self) -> Vec<Entry> {
+        type First = unsafe extern "C" fn(LLVMModuleRef) -> LLVMValueRef;
+        type Next = unsafe extern "C" fn(LLVMValueRef) -> LLVMValueRef;
+        // The lists of globals with whether they are aliases
+        let lists: [(First, Next, bool); 3] = [
+            (LLVMGetFirstFunction, LLVMGetNextFunction, false),
+            (LLVMGetFirstGlobal, LLVMGetNextGlobal, false),
+            (LLVMGetFirstGlobalAlias, LLVMGetNextGlobalAlias, true),
+        ];
+
+        let mut entries = Vec::new();
+        for (first, next, alias) in lists {
+            let mut global = unsafe { first(self.raw) };
+            while !global.is_null() {
+                entries.extend(unsafe { symbol_entry(global, alias) });
+                global = unsafe { next(global) };
+            }
+        }
+        entries
+    }
+
+    fn write(&self) -> Vec<u8> {
+        unsafe {
+            let buffer = LLVMWriteBitcodeToMemoryBuffer(self.raw);
+            let bitcode = std::slice::from_raw_parts(
+                LLVMGetBufferStart(buffer).cast::<u8>(),
+                LLVMGetBufferSize(buffer),
+            )
+            .to_vec();
+            LLVMDisposeMemoryBuffer(buffer);
+            bitcode
+        }
+    }
+}
+
+impl Drop for Module<'_> {
+    fn drop(&mut self) {
+        unsafe { LLVMDisposeModule(self.raw) }
+    }
+}
+
+/// The entry of the function, global variable or `alias` `global` if it
+/// defines a symbol
+unsafe fn symbol_entry(global: LLVMValueRef, alias: bool) -> Option<Entry> {
+    let mut len = 0;
+    let name = LLVMGetValueName2(global, &mut len);
+    let name = std::slice::from_raw_parts(name.cast::<u8>(), len);
+    if name.is_empty() || name.starts_with(b"llvm.") || LLVMIsDeclaration(global) != 0 {
+        return None;
+    }
+
+    // Aliases of functions are functions, also behind a pointer cast
+    let object = if alias {
+        let aliasee = LLVMAliasGetAliasee(global);
+        if LLVMIsAConstantExpr(aliasee).is_null() {
+            aliasee
+        } else {
+            LLVMGetOperand(aliasee, 0)
+        }
+    } else {
+        global
+    };
+    let function = !LLVMIsAFunction(object).is_null();
+
+    let kind = match LLVMGetLinkage(global) {
+        LLVMLinkage::LLVMAvailableExternallyLinkage => return None,
+        LLVMLinkage::LLVMCommonLinkage => b'C',
+        LLVMLinkage::LLVMLinkOnceAnyLinkage
+        | LLVMLinkage::LLVMLinkOnceODRLinkage
+        | LLVMLinkage::LLVMLinkOnceODRAutoHideLinkage
+        | LLVMLinkage::LLVMWeakAnyLinkage
+        | LLVMLinkage::LLVMWeakODRLinkage => b'W',
+        LLVMLinkage::LLVMInternalLinkage
+        | LLVMLinkage::LLVMPrivateLinkage
+        | LLVMLinkage::LLVMLinkerPrivateLinkage
+        | LLVMLinkage::LLVMLinkerPrivateWeakLinkage => {
+            if function {
+                b't'
+            } else {
+                b'd'
+            }
+        }
+        _ if function => b'T',
+        _ => b'D',
+    };
+
+    Some(Entry {
+        name: Symbol::new(name),
+        kind,
+    })
+}
+
+extern "C" fn handle_diagnostic(info: LLVMDiagnosticInfoRef, diagnostics: *mut c_void) {
+    let diagnostics = unsafe { &mut *diagnostics.cast::<Vec<Diagnostic>>() };
+    let (severity, message) = unsafe {
+        (
+            LLVMGetDiagInfoSeverity(info),
+            take_message(LLVMGetDiagInfoDescription(info)),
+        )
+    };
+
+    diagnostics.push(Diagnostic {
+        tool: TOOL.to_owned(),
+        severity: match severity {
+            LLVMDiagnosticSeverity::LLVMDSError => Severity::Error,
+            LLVMDiagnosticSeverity::LLVMDSWarning => Severity::Warning,
+            LLVMDiagnosticSeverity::LLVMDSRemark => Severity::Remark,
+            LLVMDiagnosticSeverity::LLVMDSNote => Severity::Note,
+        },
+        message,
+        location: None,
+    });
+}
+
+/// Copies and disposes a message allocated by LLVM, which may be null
+unsafe fn take_message(message: *mut std::ffi::c_char) -> String {
+    if message.is_null() {
+        return String::new();
+    }
+    let text = CStr::from_ptr(message).to_string_lossy().into_owned();
+    LLVMDisposeMessage(message);
+    text
+}
@@ -0,0 +1,69 @@
+use std::path::Path;
+
+use anyhow::Context;
+
+use super::size::function_name;
+
+/// The comment before every kernel in the emitted PTX, followed by its name
+pub const KERNEL_BEGIN: &str = "// PTX-LINKER-KERNEL-BEGIN";
+/// The comment after every kernel in the emitted PTX, followed by its name
+pub const KERNEL_END: &str = "// PTX-LINKER-KERNEL-END";
+
+/// Surrounds every kernel of the PTX at `ptx_path` with [`KERNEL_BEGIN`] and
+/// [`KERNEL_END`] markers
+pub fn insert(ptx_path: &Path) -> anyhow::Result<()> {
+    let ptx = std::fs::read_to_string(ptx_path)
+        .context(format!("Failed to read PTX: {}", ptx_path.display()))?;
+    std::fs::write(ptx_path, mark_kernels(&ptx))
+        .context(format!("Failed to write PTX: {}", ptx_path.display()))
+}
+
+/// The name and the PTX between the markers of every marked kernel
+pub fn kernels(ptx: &str) -> Vec<(&str, &str)> {
+    let mut kernels = Vec::new();
+
+    for (begin, _) in ptx.match_indices(KERNEL_BEGIN) {
+        let rest = &ptx[begin + KERNEL_BEGIN.len()..];
+        let (name, body) = rest.split_once('\n').unwrap_or((rest, ""));
+        let name = name.trim();
+        if let Some(end) = body.find(&format!("{KERNEL_END} {name}\n")) {
+            kernels.push((name, &body[..end]));
+        }
+    }
+
+    kernels
+}
+
+/// Inserts the markers around the definition of every `.entry`, from its
+/// declaration to the end of its body
+fn mark_kernels(ptx: &str) -> String {
+    let mut marked = String::with_capacity(ptx.len());
+    let mut kernel: Option<&str> = None;
+    let mut depth = 0usize;
+
+    for line in ptx.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if depth == 0 && kernel.is_none() && trimmed.contains(".entry ") {
+            kernel = function_name(trimmed);
+            if let Some(name) = kernel {
+                marked.push_str(&format!("{KERNEL_BEGIN} {name}\n"));
+            }
+        }
+
+        marked.push_str(line);
+        let closed = line.matches('}').count();
+        depth = (depth + line.matches('{').count()).saturating_sub(closed);
+
+        if let Some(name) = kernel {
+            if depth == 0 && (closed > 0 || trimmed.ends_with(';')) {
+                if !line.ends_with('\n') {
+                    marked.push('\n');
+                }
+                marked.push_str(&format!("{KERNEL_END} {name}\n"));
+                kernel = None;
+            }
+        }
+    }
+
+    marked
+}
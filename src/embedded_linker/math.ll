@@ -0,0 +1,389 @@
+; The single precision math functions linked with `--math builtin`
+;
+; They compute in double precision and round the result, which is accurate to
+; about one ulp, and follow C for NaNs, infinities and zeros.
+;
+; The functions are `weak_odr`, so that the definitions of the inputs take
+; precedence and the unused ones are removed.
+
+declare double @llvm.floor.f64(double)
+declare double @llvm.fabs.f64(double)
+declare double @llvm.fma.f64(double, double, double)
+
+; e^x for |x| <= 200, poison for NaN
+define internal double @__ptx_linker_exp(double %x) nounwind {
+  %over = fcmp ogt double %x, 2.000000e+02
+  %x1 = select i1 %over, double 2.000000e+02, double %x
+  %under = fcmp olt double %x1, -2.000000e+02
+  %x2 = select i1 %under, double -2.000000e+02, double %x1
+
+  ; x = k ln(2) + r with |r| <= ln(2) / 2
+  %t = fmul double %x2, 0x3FF71547652B82FE
+  %t1 = fadd double %t, 5.000000e-01
+  %kf = call double @llvm.floor.f64(double %t1)
+  %hi = fmul double %kf, 0x3FE62E42FEE00000
+  %r0 = fsub double %x2, %hi
+  %lo = fmul double %kf, 0x3DEA39EF35793C76
+  %r = fsub double %r0, %lo
+
+  ; The Taylor polynomial of e^r
+  %p11 = fmul double %r, 0x3E5AE64567F544E4
+  %p10 = fadd double %p11, 0x3E927E4FB7789F5C
+  %q10 = fmul double %p10, %r
+  %p9 = fadd double %q10, 0x3EC71DE3A556C734
+  %q9 = fmul double %p9, %r
+  %p8 = fadd double %q9, 0x3EFA01A01A01A01A
+  %q8 = fmul double %p8, %r
+  %p7 = fadd double %q8, 0x3F2A01A01A01A01A
+  %q7 = fmul double %p7, %r
+  %p6 = fadd double %q7, 0x3F56C16C16C16C17
+  %q6 = fmul double %p6, %r
+  %p5 = fadd double %q6, 0x3F81111111111111
+  %q5 = fmul double %p5, %r
+  %p4 = fadd double %q5, 0x3FA5555555555555
+  %q4 = fmul double %p4, %r
+  %p3 = fadd double %q4, 0x3FC5555555555555
+  %q3 = fmul double %p3, %r
+  %p2 = fadd double %q3, 5.000000e-01
+  %q2 = fmul double %p2, %r
+  %p1 = fadd double %q2, 1.000000e+00
+  %q1 = fmul double %p1, %r
+  %p0 = fadd double %q1, 1.000000e+00
+
+  ; e^x = 2^k e^r
+  %k = fptosi double %kf to i64
+  %biased = add i64 %k, 1023
+  %scale_bits = shl i64 %biased, 52
+  %scale = bitcast i64 %scale_bits to double
+  %result = fmul double %p0, %scale
+  ret double %result
+}
+
+; ln(x) for positive finite x
+define internal double @__ptx_linker_log(double %x) nounwind {
+  ; x = 2^e m with sqrt(2) / 2 < m <= sqrt(2)
+  %bits = bitcast double %x to i64
+  %biased = lshr i64 %bits, 52
+  %e0 = sub i64 %biased, 1023
+  %fraction = and i64 %bits, 4503599627370495
+  %m_bits = or i64 %fraction, 4607182418800017408
+  %m0 = bitcast i64 %m_bits to double
+  %large = fcmp ogt double %m0, 0x3FF6A09E667F3BCD
+  %half = fmul double %m0, 5.000000e-01
+  %m = select i1 %large, double %half, double %m0
+  %e1 = add i64 %e0, 1
+  %e = select i1 %large, i64 %e1, i64 %e0
+
+  ; ln(m) = 2 atanh(s) with s = (m - 1) / (m + 1) and |s| < 0.172
+  %num = fsub double %m, 1.000000e+00
+  %den = fadd double %m, 1.000000e+00
+  %s = fdiv double %num, %den
+  %s2 = fmul double %s, %s
+  %p15 = fmul double %s2, 0x3FC1111111111111
+  %p13 = fadd double %p15, 0x3FC3B13B13B13B14
+  %q13 = fmul double %p13, %s2
+  %p11 = fadd double %q13, 0x3FC745D1745D1746
+  %q11 = fmul double %p11, %s2
+  %p9 = fadd double %q11, 0x3FCC71C71C71C71C
+  %q9 = fmul double %p9, %s2
+  %p7 = fadd double %q9, 0x3FD2492492492492
+  %q7 = fmul double %p7, %s2
+  %p5 = fadd double %q7, 0x3FD999999999999A
+  %q5 = fmul double %p5, %s2
+  %p3 = fadd double %q5, 0x3FE5555555555555
+  %q3 = fmul double %p3, %s2
+  %p1 = fadd double %q3, 2.000000e+00
+  %log_m = fmul double %p1, %s
+
+  ; ln(x) = e ln(2) + ln(m)
+  %ef = sitofp i64 %e to double
+  %hi = fmul double %ef, 0x3FE62E42FEE00000
+  %lo = fmul double %ef, 0x3DEA39EF35793C76
+  %low = fadd double %log_m, %lo
+  %result = fadd double %hi, %low
+  ret double %result
+}
+
+; 2^e mod 2 pi for -3 <= e <= 104 as the sum of two doubles, returned by
+; functions as no pointer type is valid textual IR for every LLVM version
+define internal <108 x double> @__ptx_linker_two_pi_remainders_hi() nounwind {
+  ret <108 x double> <
+    double 0x3FC0000000000000, double 0x3FD0000000000000, double 0x3FE0000000000000,
+    double 0x3FF0000000000000, double 0x4000000000000000, double 0x4010000000000000,
+    double 0x3FFB7812AEEF4B9F, double 0x400B7812AEEF4B9F, double 0x3FE2B0BAD558F435,
+    double 0x3FF2B0BAD558F435, double 0x4002B0BAD558F435, double 0x4012B0BAD558F435,
+    double 0x40087EF4ACDB76A3, double 0x40187EF4ACDB76A3, double 0x4017DBEE0572C02E,
+    double 0x401695E0B6A15344, double 0x401409C618FE7971, double 0x400DE321BB718B92,
+    double 0x3FF304999CB579E8, double 0x400304999CB579E8, double 0x401304999CB579E8,
+    double 0x4009CE6FCA4D8D6E, double 0x3FC58E8EC12C0AA8, double 0x3FD58E8EC12C0AA8,
+    double 0x3FE58E8EC12C0AA8, double 0x3FF58E8EC12C0AA8, double 0x40058E8EC12C0AA8,
+    double 0x40158E8EC12C0AA8, double 0x4011FB222E13E839, double 0x4005A8920FC746B2,
+    double 0x4015A8920FC746B2, double 0x40122F28CB4A604B, double 0x400678AC84A126FA,
+    double 0x401678AC84A126FA, double 0x4013CF5DB4FE20DD, double 0x400CF9802B702942,
+    double 0x3FEEBC26B95FE14F, double 0x3FFEBC26B95FE14F, double 0x400EBC26B95FE14F,
+    double 0x3FF668AD946ED0DA, double 0x400668AD946ED0DA, double 0x401668AD946ED0DA,
+    double 0x4013AF5FD499749B, double 0x400C7988A9DD783B, double 0x3FEABC6AACCA5912,
+    double 0x3FFABC6AACCA5912, double 0x400ABC6AACCA5912, double 0x3FD9A6F58862BF99,
+    double 0x3FE9A6F58862BF99, double 0x3FF9A6F58862BF99, double 0x4009A6F58862BF99,
+    double 0x3FC09F4683D25023, double 0x3FD09F4683D25023, double 0x3FE09F4683D25023,
+    double 0x3FF09F4683D25023, double 0x40009F4683D25023, double 0x40109F4683D25023,
+    double 0x4000392366C0E65C, double 0x4010392366C0E65C, double 0x3FFD412DE4F67E7E,
+    double 0x400D412DE4F67E7E, double 0x3FF07CCA42C94599, double 0x40007CCA42C94599,
+    double 0x40107CCA42C94599, double 0x3FFF5E64C5397865, double 0x400F5E64C5397865,
+    double 0x3FF8F1A5C3D52D34, double 0x4008F1A5C3D52D34, double 0x4018F1A5C3D52D34,
+    double 0x4018C15033662D50, double 0x401860A512882D88, double 0x40179F4ED0CC2DF7,
+    double 0x40161CA24D542ED6, double 0x4013174946643093, double 0x400A192E7108681B,
+    double 0x3FCEE6639887604D, double 0x3FDEE6639887604D, double 0x3FEEE6639887604D,
+    double 0x3FFEE6639887604D, double 0x400EE6639887604D, double 0x3FF711A1110CCCD4,
+    double 0x400711A1110CCCD4, double 0x401711A1110CCCD4, double 0x40150146CDD56C8F,
+    double 0x4010E0924766AC06, double 0x40013E52751255E7, double 0x40113E52751255E7,
+    double 0x4002B5532BC0FD6B, double 0x4012B5532BC0FD6B, double 0x40089156067B9B7B,
+    double 0x40189156067B9B7B, double 0x401800B0B8B309DD, double 0x4016DF661D21E6A2,
+    double 0x40149CD0E5FFA02C, double 0x401017A677BB133F, double 0x3FFC35466CC7E59A,
+    double 0x400C35466CC7E59A, double 0x3FE89A58C41DC40C, double 0x3FF89A58C41DC40C,
+    double 0x40089A58C41DC40C, double 0x40189A58C41DC40C, double 0x401812B633F75AFF,
+    double 0x4017037113AA88E6, double 0x4014E4E6D310E4B4, double 0x4010A7D251DD9C4F,
+    double 0x40005B529EEE170C, double 0x40105B529EEE170C, double 0x3FFE52A7A6600402
+  >
+}
+
+define internal <108 x double> @__ptx_linker_two_pi_remainders_lo() nounwind {
+  ret <108 x double> <
+    double 0x0000000000000000, double 0x0000000000000000, double 0x0000000000000000,
+    double 0x0000000000000000, double 0x0000000000000000, double 0x0000000000000000,
+    double 0xBC7A62633145C06E, double 0xBC8A62633145C06E, double 0xBC4F5F7FB2E61132,
+    double 0xBC5F5F7FB2E61132, double 0xBC6F5F7FB2E61132, double 0xBC7F5F7FB2E61132,
+    double 0x3CA4DBD3AD1DC3A6, double 0x3CB4DBD3AD1DC3A6, double 0x3CB8118127272B44,
+    double 0x3CBE7CDC1B39FA82, double 0xBCB4AC6DFCA06703, double 0x3C9403F74EAB57CF,
+    double 0xBC9E90AA2EFAC07E, double 0xBCAE90AA2EFAC07E, double 0xBCBE90AA2EFAC07E,
+    double 0xBCAD8EF52213BA05, double 0x3C695C955AFD3E7B, double 0x3C795C955AFD3E7B,
+    double 0x3C895C955AFD3E7B, double 0x3C995C955AFD3E7B, double 0x3CA95C955AFD3E7B,
+    double 0x3CB95C955AFD3E7B, double 0xBCBEECFB7D19DF11, double 0xBCAF003A5A903450,
+    double 0xBCBF003A5A903450, double 0xBCAF4D35D069894F, double 0x3CAE1947F9043554,
+    double 0x3CBE1947F9043554, double 0xBCB57396410BF160, double 0x3C8B956A569E09D0,
+    double 0xBC86065CEB66CC97, double 0xBC96065CEB66CC97, double 0xBCA6065CEB66CC97,
+    double 0xBC9EB20C79ECA277, double 0xBCAEB20C79ECA277, double 0xBCBEB20C79ECA277,
+    double 0xBCAE147E4DDB41EB, double 0xBCAF754901DF3BE4, double 0xBC71B6F34F397EB5,
+    double 0xBC81B6F34F397EB5, double 0xBC91B6F34F397EB5, double 0x3C77E60254EE49EB,
+    double 0x3C87E60254EE49EB, double 0x3C97E60254EE49EB, double 0x3CA7E60254EE49EB,
+    double 0x3C4FEE10ECF6F246, double 0x3C5FEE10ECF6F246, double 0x3C6FEE10ECF6F246,
+    double 0x3C7FEE10ECF6F246, double 0x3C8FEE10ECF6F246, double 0x3C9FEE10ECF6F246,
+    double 0xBC7AF1DBC98E2E42, double 0xBC8AF1DBC98E2E42, double 0x3C9E758B6A2061A3,
+    double 0x3CAE758B6A2061A3, double 0xBC8984D6479FD323, double 0xBC9984D6479FD323,
+    double 0xBCA984D6479FD323, double 0x3C93540E152F4359, double 0x3CA3540E152F4359,
+    double 0x3C7ADE7E21AE7526, double 0x3C8ADE7E21AE7526, double 0x3C9ADE7E21AE7526,
+    double 0xBC90DB9C88F485CF, double 0xBCBA13F4778E9EEF, double 0xBC97383C88C66790,
+    double 0xBCBD424477778FCF, double 0xBCA8555E4406F74A, double 0xBCA3F708EE36A6A2,
+    double 0x3C58B437AD3F55E0, double 0x3C68B437AD3F55E0, double 0x3C78B437AD3F55E0,
+    double 0x3C88B437AD3F55E0, double 0x3C98B437AD3F55E0, double 0xBC95302971D2C45B,
+    double 0xBCA5302971D2C45B, double 0xBCB5302971D2C45B, double 0x3C8FCC374A30DA1A,
+    double 0xBCA36630C1104B01, double 0xBC94315BD0929C20, double 0xBCA4315BD0929C20,
+    double 0xBC975E080E9BE09A, double 0xBCA75E080E9BE09A, double 0xBCA2085C83607941,
+    double 0xBCB2085C83607941, double 0x3CA492418C5562EE, double 0x3C8760DACA08373A,
+    double 0xBCA79BDF01249C71, double 0x3CB6BDFACBC70789, double 0xBC90A8C26E1933D7,
+    double 0xBCA0A8C26E1933D7, double 0xBC827745096C7EEE, double 0xBC927745096C7EEE,
+    double 0xBCA27745096C7EEE, double 0xBCB27745096C7EEE, double 0x3CA2D69F74254C3B,
+    double 0x3C730794110F0348, double 0xBCAE8A6761E4F73C, double 0x3CAF9EE4D60D597B,
+    double 0x3CABF17D45F1FAE7, double 0x3CBBF17D45F1FAE7, double 0x3C98F351633E671F
+  >
+}
+
+; sin(x + shift pi / 2) for finite x
+define internal float @__ptx_linker_sin(float %x, double %shift) nounwind {
+  ; x = k pi / 2 + r with |r| <= pi / 4
+  %xd = fpext float %x to double
+  %t = fmul double %xd, 0x3FE45F306DC9C883
+  %t1 = fadd double %t, 5.000000e-01
+  %small_kf = call double @llvm.floor.f64(double %t1)
+  %hi = fmul double %small_kf, 0x3FF921FB54400000
+  %r0 = fsub double %xd, %hi
+  %lo = fmul double %small_kf, 0x3DD0B4611A626331
+  %small_r = fsub double %r0, %lo
+
+  ; Larger |x| = m 2^e are first reduced to m (2^e mod 2 pi), whose product
+  ; is kept in two doubles
+  %x_bits = bitcast float %x to i32
+  %exponent_bits = lshr i32 %x_bits, 23
+  %biased = and i32 %exponent_bits, 255
+  %index = sub i32 %biased, 147
+  %mantissa_bits = and i32 %x_bits, 8388607
+  %mantissa = or i32 %mantissa_bits, 8388608
+  %m = uitofp i32 %mantissa to double
+  %remainders_hi = call <108 x double> @__ptx_linker_two_pi_remainders_hi()
+  %remainders_lo = call <108 x double> @__ptx_linker_two_pi_remainders_lo()
+  %remainder_hi = extractelement <108 x double> %remainders_hi, i32 %index
+  %remainder_lo = extractelement <108 x double> %remainders_lo, i32 %index
+  %p = fmul double %m, %remainder_hi
+  %p_neg = fneg double %p
+  %p_error = call double @llvm.fma.f64(double %m, double %remainder_hi, double %p_neg)
+  %p_lo0 = fmul double %m, %remainder_lo
+  %p_lo = fadd double %p_lo0, %p_error
+  ; The parts of pi / 2 have 27 bits so that their products with k are exact
+  %lt = fmul double %p, 0x3FE45F306DC9C883
+  %lt1 = fadd double %lt, 5.000000e-01
+  %large_kf0 = call double @llvm.floor.f64(double %lt1)
+  %l1 = fmul double %large_kf0, 0x3FF921FB54000000
+  %lr1 = fsub double %p, %l1
+  %l2 = fmul double %large_kf0, 0x3E110B4610000000
+  %lr2 = fsub double %lr1, %l2
+  %l3 = fmul double %large_kf0, 0x3C5A626330000000
+  %lr3 = fsub double %lr2, %l3
+  %large_r0 = fadd double %lr3, %p_lo
+  %negative = icmp slt i32 %x_bits, 0
+  %large_r_neg = fneg double %large_r0
+  %large_r = select i1 %negative, double %large_r_neg, double %large_r0
+  %large_kf_neg = fneg double %large_kf0
+  %large_kf = select i1 %negative, double %large_kf_neg, double %large_kf0
+
+  %abs = call double @llvm.fabs.f64(double %xd)
+  %large = fcmp oge double %abs, 0x4130000000000000
+  %kf = select i1 %large, double %large_kf, double %small_kf
+  %r = select i1 %large, double %large_r, double %small_r
+  %r2 = fmul double %r, %r
+
+  ; The Taylor polynomials of sin(r) and cos(r)
+  %s6 = fmul double %r2, 0x3DE6124613A86D09
+  %s5 = fadd double %s6, 0xBE5AE64567F544E4
+  %t5 = fmul double %s5, %r2
+  %s4 = fadd double %t5, 0x3EC71DE3A556C734
+  %t4 = fmul double %s4, %r2
+  %s3 = fadd double %t4, 0xBF2A01A01A01A01A
+  %t3 = fmul double %s3, %r2
+  %s2 = fadd double %t3, 0x3F81111111111111
+  %t2 = fmul double %s2, %r2
+  %s1 = fadd double %t2, 0xBFC5555555555555
+  %t1s = fmul double %s1, %r2
+  %t0s = fmul double %t1s, %r
+  %sin = fadd double %t0s, %r
+
+  %c7 = fmul double %r2, 0xBDA93974A8C07C9D
+  %c6 = fadd double %c7, 0x3E21EED8EFF8D898
+  %u6 = fmul double %c6, %r2
+  %c5 = fadd double %u6, 0xBE927E4FB7789F5C
+  %u5 = fmul double %c5, %r2
+  %c4 = fadd double %u5, 0x3EFA01A01A01A01A
+  %u4 = fmul double %c4, %r2
+  %c3 = fadd double %u4, 0xBF56C16C16C16C17
+  %u3 = fmul double %c3, %r2
+  %c2 = fadd double %u3, 0x3FA5555555555555
+  %u2 = fmul double %c2, %r2
+  %c1 = fadd double %u2, -5.000000e-01
+  %u1 = fmul double %c1, %r2
+  %cos = fadd double %u1, 1.000000e+00
+
+  ; The quadrant k + shift modulo 4 selects between +-sin(r) and +-cos(r)
+  %quadrant = fadd double %kf, %shift
+  %quarter = fmul double %quadrant, 2.500000e-01
+  %turns = call double @llvm.floor.f64(double %quarter)
+  %whole = fmul double %turns, 4.000000e+00
+  %q = fsub double %quadrant, %whole
+  %qi = fptosi double %q to i32
+  %odd = and i32 %qi, 1
+  %use_cos = icmp ne i32 %odd, 0
+  %value = select i1 %use_cos, double %cos, double %sin
+  %upper = and i32 %qi, 2
+  %negate = icmp ne i32 %upper, 0
+  %negated = fneg double %value
+  %result = select i1 %negate, double %negated, double %value
+  %rounded = fptrunc double %result to float
+  ret float %rounded
+}
+
+define weak_odr float @sinf(float %x) nounwind {
+  %result = call float @__ptx_linker_sin(float %x, double 0.000000e+00)
+  %abs = call float @llvm.fabs.f32(float %x)
+  %finite = fcmp one float %abs, 0x7FF0000000000000
+  %checked = select i1 %finite, float %result, float 0x7FF8000000000000
+  ; sin(-0) = -0
+  %zero = fcmp oeq float %x, 0.000000e+00
+  %signed = select i1 %zero, float %x, float %checked
+  ret float %signed
+}
+
+define weak_odr float @cosf(float %x) nounwind {
+  %result = call float @__ptx_linker_sin(float %x, double 1.000000e+00)
+  %abs = call float @llvm.fabs.f32(float %x)
+  %finite = fcmp one float %abs, 0x7FF0000000000000
+  %checked = select i1 %finite, float %result, float 0x7FF8000000000000
+  ret float %checked
+}
+
+declare float @llvm.fabs.f32(float)
+
+define weak_odr float @expf(float %x) nounwind {
+  %xd = fpext float %x to double
+  %exp = call double @__ptx_linker_exp(double %xd)
+  %result = fptrunc double %exp to float
+  %nan = fcmp uno float %x, 0.000000e+00
+  %checked = select i1 %nan, float %x, float %result
+  ret float %checked
+}
+
+define weak_odr float @logf(float %x) nounwind {
+  %xd = fpext float %x to double
+  %log = call double @__ptx_linker_log(double %xd)
+  %result = fptrunc double %log to float
+  %zero = fcmp oeq float %x, 0.000000e+00
+  %r1 = select i1 %zero, float 0xFFF0000000000000, float %result
+  %negative = fcmp olt float %x, 0.000000e+00
+  %r2 = select i1 %negative, float 0x7FF8000000000000, float %r1
+  ; ln(inf) = inf and ln(NaN) = NaN
+  %infinite = fcmp oeq float %x, 0x7FF0000000000000
+  %nan = fcmp uno float %x, 0.000000e+00
+  %same = or i1 %infinite, %nan
+  %checked = select i1 %same, float %x, float %r2
+  ret float %checked
+}
+
+define weak_odr float @powf(float %x, float %y) nounwind {
+  %xd = fpext float %x to double
+  %yd = fpext float %y to double
+
+  ; |x|^y = e^(y ln|x|)
+  %abs = call double @llvm.fabs.f64(double %xd)
+  %log = call double @__ptx_linker_log(double %abs)
+  %zero = fcmp oeq double %abs, 0.000000e+00
+  %l1 = select i1 %zero, double 0xFFF0000000000000, double %log
+  %infinite = fcmp oeq double %abs, 0x7FF0000000000000
+  %l2 = select i1 %infinite, double 0x7FF0000000000000, double %l1
+  %t = fmul double %yd, %l2
+  %exp = call double @__ptx_linker_exp(double %t)
+
+  ; Negative x, including -0, negates the result for odd integer y
+  %floor = call double @llvm.floor.f64(double %yd)
+  %integer = fcmp oeq double %floor, %yd
+  %half = fmul double %yd, 5.000000e-01
+  %half_floor = call double @llvm.floor.f64(double %half)
+  %even = fcmp oeq double %half_floor, %half
+  %not_even = xor i1 %even, true
+  %odd = and i1 %integer, %not_even
+  %x_bits = bitcast float %x to i32
+  %sign = icmp slt i32 %x_bits, 0
+  %negate = and i1 %sign, %odd
+  %negated = fneg double %exp
+  %r1 = select i1 %negate, double %negated, double %exp
+
+  ; Negative finite x with non-integer y or NaN operands give NaN, while -inf
+  ; raised to them gives inf or 0 like inf
+  %negative = fcmp olt double %xd, 0.000000e+00
+  %fraction = xor i1 %integer, true
+  %finite = xor i1 %infinite, true
+  %negative_finite = and i1 %negative, %finite
+  %complex = and i1 %negative_finite, %fraction
+  %nan = fcmp uno double %xd, %yd
+  %invalid = or i1 %complex, %nan
+  %r2 = select i1 %invalid, double 0x7FF8000000000000, double %r1
+
+  ; y = 0, x = 1 and (-1)^inf give 1, also for NaN operands
+  %y_zero = fcmp oeq double %yd, 0.000000e+00
+  %x_one = fcmp oeq double %xd, 1.000000e+00
+  %undefined = fcmp uno double %t, 0.000000e+00
+  %one_y = or i1 %y_zero, %x_one
+  %not_nan = xor i1 %nan, true
+  %one_t = and i1 %undefined, %not_nan
+  %one = or i1 %one_y, %one_t
+  %r3 = select i1 %one, double 1.000000e+00, double %r2
+
+  %result = fptrunc double %r3 to float
+  ret float %result
+}
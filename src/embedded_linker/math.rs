@@ -0,0 +1,236 @@
+//! The math functions the device code calls, like `sinf`
+//!
+//! The NVPTX backend lowers no math library calls, so they must be defined by
+//! the inputs, by CUDA's libdevice or by the minimal library built into the
+//! linker.
+
+use super::Target;
+
+/// The single precision math functions built into the linker, as textual IR
+/// without a target triple
+const BUILTIN: &str = include_str!("math.ll");
+
+/// The names of the functions of [`BUILTIN`]
+pub const BUILTIN_FUNCTIONS: [&str; 5] = ["sinf", "cosf", "expf", "logf", "powf"];
+
+/// Where the math functions called by the device code are defined
+#[derive(Debug, Clone, Copy, Eq, PartialEq, clap::ValueEnum)]
+pub enum Math {
+    /// The `sinf`, `cosf`, `expf`, `logf` and `powf` built into the linker,
+    /// unless the inputs define them, which are only linked on request
+    Builtin,
+    /// CUDA's libdevice, given with `--libdevice`, in the configuration or
+    /// found in the CUDA toolkit
+    Libdevice,
+    /// Only the inputs
+    None,
+}
+
+/// The builtin math functions as textual IR for `target`
+pub fn builtin_ir(target: Target) -> String {
+    format!("target triple = \"{}\"\n\n{BUILTIN}", target.triple())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write;
+    use std::process::Command;
+
+    use super::*;
+
+    /// Prints the bits of a float in hex on a line of their own, without
+    /// pointers, like the functions themselves
+    const PRINT_BITS: &str = "
+declare i32 @putchar(i32)
+
+define internal void @print_bits(float %x) {
+entry:
+  %bits = bitcast float %x to i32
+  br label %loop
+loop:
+  %shift = phi i32 [28, %entry], [%next, %loop]
+  %shifted = lshr i32 %bits, %shift
+  %nibble = and i32 %shifted, 15
+  %is_letter = icmp ugt i32 %nibble, 9
+  %digit = add i32 %nibble, 48
+  %letter = add i32 %nibble, 87
+  %char = select i1 %is_letter, i32 %letter, i32 %digit
+  call i32 @putchar(i32 %char)
+  %next = sub i32 %shift, 4
+  %done = icmp eq i32 %shift, 0
+  br i1 %done, label %exit, label %loop
+exit:
+  call i32 @putchar(i32 10)
+  ret void
+}
+";
+
+    /// A float as an IR constant, which is written as the bits of the double
+    /// of the same value
+    fn constant(x: f32) -> String {
+        format!("0x{:016X}", f64::from(x).to_bits())
+    }
+
+    /// The results of calling `function` with each of `args` in `lli`, or
+    /// `None` if `lli` is not installed
+    fn evaluate(function: &str, args: &[&[f32]]) -> Option<Vec<f32>> {
+        let mut main = String::from("define i32 @main() {\n");
+        for (index, args) in args.iter().enumerate() {
+            let args = args
+                .iter()
+                .map(|arg| format!("float {}", constant(*arg)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(main, "  %r{index} = call float @{function}({args})").unwrap();
+            writeln!(main, "  call void @print_bits(float %r{index})").unwrap();
+        }
+        main.push_str("  ret i32 0\n}\n");
+
+        let path = std::env::temp_dir().join(format!(
+            "{}-test-math-{function}-{}.ll",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        std::fs::write(&path, format!("{BUILTIN}\n{PRINT_BITS}\n{main}")).unwrap();
+        let output = Command::new("lli").arg("-O0").arg(&path).output();
+        std::fs::remove_file(&path).unwrap();
+        let Ok(output) = output else {
+            eprintln!("skipping: lli is not installed");
+            return None;
+        };
+        assert!(
+            output.status.success(),
+            "lli failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        let results = String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(|line| f32::from_bits(u32::from_str_radix(line, 16).unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(results.len(), args.len());
+        Some(results)
+    }
+
+    /// Checks that `function` is within one ulp of `expected` of std, with the
+    /// same sign, and exactly like it for NaNs and infinities
+    ///
+    /// Results that underflow may still round to the smallest subnormal
+    /// instead of zero.
+    fn check(function: &str, args: &[&[f32]], expected: impl Fn(&[f32]) -> f32) {
+        let Some(results) = evaluate(function, args) else {
+            return;
+        };
+        for (args, result) in args.iter().zip(results) {
+            let expected = expected(args);
+            let close = if expected.is_nan() || result.is_nan() {
+                expected.is_nan() && result.is_nan()
+            } else if expected.is_infinite() || result.is_infinite() {
+                result.to_bits() == expected.to_bits()
+            } else {
+                result.is_sign_positive() == expected.is_sign_positive()
+                    && result.to_bits().abs_diff(expected.to_bits()) <= 1
+            };
+            assert!(
+                close,
+                "{function}{args:?} = {result:e}, expected {expected:e}"
+            );
+        }
+    }
+
+    const SPECIAL: [f32; 5] = [0.0, -0.0, f32::NAN, f32::INFINITY, f32::NEG_INFINITY];
+
+    fn unary(points: &[f32]) -> Vec<[f32; 1]> {
+        points.iter().chain(&SPECIAL).map(|x| [*x]).collect()
+    }
+
+    fn slices<const N: usize>(args: &[[f32; N]]) -> Vec<&[f32]> {
+        args.iter().map(<[f32; N]>::as_slice).collect()
+    }
+
+    #[test]
+    fn computes_sinf_and_cosf() {
+        let args = unary(&[
+            1e-20,
+            0.5,
+            1.0,
+            -1.0,
+            std::f32::consts::FRAC_PI_4,
+            std::f32::consts::PI,
+            -10.0,
+            100.0,
+            -1000.5,
+            123_456.79,
+            1e6,
+            -3.0e9,
+            1e20,
+            f32::MAX,
+        ]);
+        check("sinf", &slices(&args), |x| x[0].sin());
+        check("cosf", &slices(&args), |x| x[0].cos());
+    }
+
+    #[test]
+    fn computes_expf() {
+        let args = unary(&[
+            1e-10, 0.5, 1.0, -1.0, 10.0, -10.0, 88.7, 88.8, -87.0, -100.0, -103.0, -104.0, -110.0,
+        ]);
+        check("expf", &slices(&args), |x| x[0].exp());
+    }
+
+    #[test]
+    fn computes_logf() {
+        let args = unary(&[
+            1.0,
+            2.0,
+            0.5,
+            std::f32::consts::E,
+            1.000_001,
+            0.999_999,
+            1e-30,
+            1e-45,
+            3e38,
+            f32::MAX,
+            -1.0,
+            -1e-30,
+        ]);
+        check("logf", &slices(&args), |x| x[0].ln());
+    }
+
+    #[test]
+    fn computes_powf() {
+        let mut args = vec![
+            [2.0, 10.0],
+            [2.0, 0.5],
+            [10.0, 38.0],
+            [10.0, -38.0],
+            [1.0001, 10_000.0],
+            [0.5, 0.25],
+            [3.0, -2.5],
+            [2.0, 128.0],
+            [2.0, -150.0],
+            [-2.0, 3.0],
+            [-2.0, 2.0],
+            [-2.0, -3.0],
+            [-2.0, 0.5],
+            [-8.0, 1.0 / 3.0],
+            [-1.0, f32::INFINITY],
+            [-1.0, f32::NEG_INFINITY],
+            [-0.5, f32::INFINITY],
+            [-3.0, f32::NEG_INFINITY],
+            [f32::NEG_INFINITY, 3.0],
+            [f32::NEG_INFINITY, 2.0],
+            [f32::NEG_INFINITY, -3.0],
+            [f32::NEG_INFINITY, 0.5],
+            [1.0, f32::NAN],
+            [f32::NAN, 0.0],
+        ];
+        for x in [0.0, -0.0, 0.5, 2.0, f32::NAN, f32::INFINITY] {
+            for y in [-3.0, -2.0, -0.5, 0.0, 1.0, 3.0, f32::NAN, f32::INFINITY] {
+                args.push([x, y]);
+            }
+        }
+        check("powf", &slices(&args), |x| x[0].powf(x[1]));
+    }
+}
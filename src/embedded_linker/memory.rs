@@ -0,0 +1,291 @@
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::process::{Child, Output};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+
+use super::interrupt::Interrupted;
+
+/// How often running tools are checked for interruption and their memory
+/// usage is sampled
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+const MB: u64 = 1024 * 1024;
+
+/// The most stderr of a single tool that is kept for parsing diagnostics
+const MAX_CAPTURED_STDERR: usize = 4 * 1024 * 1024;
+
+/// Tracks the resident memory of the linker and the tools it runs, and
+/// limits how long each tool may run
+///
+/// Memory usage is read from `/proc` and thus only tracked on Linux.
+#[derive(Debug, Default)]
+pub struct Monitor {
+    /// The time after which a tool is killed
+    timeout: Option<Duration>,
+    /// The limit in bytes for the linker and all running tools combined
+    limit: Option<u64>,
+    /// Whether to sample the memory usage even without a limit
+    track: bool,
+    peak: AtomicU64,
+    /// The last sampled usage of every running tool by process id
+    children: Mutex<Vec<(u32, u64)>>,
+}
+
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+/// The linker and its tools used more memory than allowed
+#[error("memory usage of {} MB exceeds the limit of {} MB", .used / MB, .limit / MB)]
+pub struct LimitExceeded {
+    used: u64,
+    limit: u64,
+}
+
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+/// A tool did not finish in time
+#[error("timed out after {}s", .0.as_secs_f64())]
+pub struct TimedOut(Duration);
+
+impl Monitor {
+    /// Kill tools that run longer than `timeout`
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Limit the memory usage to `limit_mb` megabytes
+    pub fn set_limit(&mut self, limit_mb: Option<u64>) {
+        if limit_mb.is_some() && resident("self").is_none() {
+            tracing::warn!("memory usage cannot be tracked on this platform, ignoring the limit");
+        }
+
+        self.limit = limit_mb.map(|limit| limit.saturating_mul(MB));
+    }
+
+    /// Sample the memory usage of tools even without a limit to report the
+    /// peak usage
+    pub fn set_track(&mut self, track: bool) {
+        self.track = track;
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.limit.is_some() || self.track
+    }
+
+    /// The highest combined memory usage sampled so far in bytes
+    pub fn peak(&self) -> u64 {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    /// The peak memory usage of the linker itself in bytes
+    pub fn peak_linker() -> Option<u64> {
+        status_field("self", "VmHWM:")
+    }
+
+    /// Samples the usage of the linker and the tool `pid`, if it is running
+    pub fn sample(&self, pid: Option<u32>) -> Result<(), LimitExceeded> {
+        let mut children = self.children.lock().unwrap();
+        if let Some(pid) = pid {
+            let usage = resident(&pid.to_string()).unwrap_or(0);
+            match children.iter_mut().find(|(child, _)| *child == pid) {
+                Some((_, last)) => *last = usage,
+                None => children.push((pid, usage)),
+            }
+        }
+
+        let used =
+            resident("self").unwrap_or(0) + children.iter().map(|(_, usage)| usage).sum::<u64>();
+        drop(children);
+
+        self.peak.fetch_max(used, Ordering::Relaxed);
+
+        match self.limit {
+            Some(limit) if used > limit => Err(LimitExceeded { used, limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Waits for `child` to exit and collects its output like
+    /// [`Child::wait_with_output`], killing it once the memory limit is
+    /// exceeded, it times out or the link is interrupted
+    ///
+    /// `stdin` is written to the piped stdin of `tool` while its output is
+    /// read, so that neither blocks the other, and the pipe is closed
+    /// afterwards. The stderr of `tool` is logged at trace level as it is
+    /// written, and only its first [`MAX_CAPTURED_STDERR`] bytes are kept.
+    pub fn wait_with_output(
+        &self,
+        tool: &str,
+        mut child: Child,
+        stdin: Option<Vec<u8>>,
+    ) -> anyhow::Result<Output> {
+        let start = Instant::now();
+        let pid = child.id();
+        let pipe = child.stdin.take();
+        let stdin = stdin.and_then(|input| Some(write_in_background(pipe?, input)));
+        let stdout = child.stdout.take().map(read_in_background);
+        let stderr = child
+            .stderr
+            .take()
+            .map(|stderr| stream_in_background(stderr, tool.to_owned()));
+
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+
+            let result = match Interrupted::check() {
+                Ok(()) => match self.timeout {
+                    Some(timeout) if start.elapsed() > timeout => Err(TimedOut(timeout).into()),
+                    _ if self.is_enabled() => self.sample(Some(pid)).map_err(Into::into),
+                    _ => Ok(()),
+                },
+                result => result.map_err(Into::into),
+            };
+
+            if let Err(err) = result {
+                // The tool is being aborted anyway, so errors killing it are
+                // irrelevant
+                let _ = child.kill();
+                let _ = child.wait();
+                self.finish(pid);
+                return Err(err);
+            }
+
+            std::thread::sleep(POLL_INTERVAL);
+        };
+        self.finish(pid);
+
+        let join = |reader: Option<std::thread::JoinHandle<std::io::Result<Vec<u8>>>>| {
+            reader.map_or(Ok(Vec::new()), |reader| {
+                reader.join().expect("output reader panicked")
+            })
+        };
+
+        let output = Output {
+            status,
+            stdout: join(stdout).context("Failed to read stdout")?,
+            stderr: join(stderr).context("Failed to read stderr")?,
+        };
+
+        // A tool may exit without reading all of its input, which its exit
+        // status reports if it matters
+        if let Some(stdin) = stdin {
+            match stdin.join().expect("input writer panicked") {
+                Err(err) if err.kind() != ErrorKind::BrokenPipe => {
+                    return Err(err).context("Failed to write stdin");
+                }
+                _ => {}
+            }
+        }
+
+        Ok(output)
+    }
+
+    fn finish(&self, pid: u32) {
+        self.children
+            .lock()
+            .unwrap()
+            .retain(|(child, _)| *child != pid);
+    }
+}
+
+/// Logs the lines of `pipe` as they are written by `tool`, keeping at most
+/// [`MAX_CAPTURED_STDERR`] bytes of them
+fn stream_in_background(
+    pipe: impl Read + Send + 'static,
+    tool: String,
+) -> std::thread::JoinHandle<std::io::Result<Vec<u8>>> {
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        let mut captured = Vec::new();
+        let mut line = Vec::new();
+        let mut dropped = 0;
+
+        while reader.read_until(b'\n', &mut line)? > 0 {
+            tracing::trace!("{tool}: {}", String::from_utf8_lossy(&line).trim_end());
+
+            if captured.len() + line.len() <= MAX_CAPTURED_STDERR {
+                captured.extend_from_slice(&line);
+            } else {
+                dropped += line.len();
+            }
+            line.clear();
+        }
+
+        if dropped > 0 {
+            captured.extend_from_slice(
+                format!("note: {dropped} more bytes of output were not captured\n").as_bytes(),
+            );
+        }
+
+        Ok(captured)
+    })
+}
+
+/// Writes `input` to `pipe` and closes it
+fn write_in_background(
+    mut pipe: impl Write + Send + 'static,
+    input: Vec<u8>,
+) -> std::thread::JoinHandle<std::io::Result<()>> {
+    std::thread::spawn(move || pipe.write_all(&input))
+}
+
+fn read_in_background(
+    mut pipe: impl Read + Send + 'static,
+) -> std::thread::JoinHandle<std::io::Result<Vec<u8>>> {
+    std::thread::spawn(move || {
+        let mut buffer = Vec::new();
+        pipe.read_to_end(&mut buffer).map(|_| buffer)
+    })
+}
+
+/// The resident memory of process `pid` in bytes
+fn resident(pid: &str) -> Option<u64> {
+    status_field(pid, "VmRSS:")
+}
+
+/// Reads a memory `field` in kB from `/proc/<pid>/status` in bytes
+fn status_field(pid: &str, field: &str) -> Option<u64> {
+    let status = std::fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    let kb = status
+        .lines()
+        .find_map(|line| line.strip_prefix(field))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::{Command, Stdio};
+
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn passes_more_input_than_pipes_buffer() {
+        // Far more than a pipe buffers, so writing all input before reading
+        // any output would block `cat` and the linker on each other
+        let input = b"0123456789abcdef".repeat(1024 * 1024);
+        let child = Command::new("cat")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let monitor = Monitor {
+            timeout: Some(Duration::from_secs(60)),
+            ..Monitor::default()
+        };
+        let output = monitor
+            .wait_with_output("cat", child, Some(input.clone()))
+            .unwrap();
+        assert!(output.status.success());
+        assert!(output.stdout == input);
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+
+use super::globals::global_name;
+
+/// The operands of the nodes of the named metadata `name` in textual IR,
+/// e.g. `[["ptr @kernel", "!\"descriptor\"", "i32 3"]]` for
+/// `!my.kernels = !{!0}` and `!0 = !{ptr @kernel, !"descriptor", i32 3}`
+///
+/// Returns `None` if the module has no such named metadata.
+pub fn named(ir: &str, name: &str) -> Option<Vec<Vec<String>>> {
+    let mut nodes = BTreeMap::new();
+    let mut named = None;
+
+    for line in ir.lines() {
+        let Some((id, rest)) = line.strip_prefix('!').and_then(|line| line.split_once(" = "))
+        else {
+            continue;
+        };
+        let operands = rest
+            .trim_start_matches("distinct ")
+            .strip_prefix("!{")
+            .and_then(|rest| rest.strip_suffix('}'));
+        let Some(operands) = operands else {
+            continue;
+        };
+
+        if id == name {
+            named = Some(operands);
+        } else if id.bytes().all(|byte| byte.is_ascii_digit()) {
+            nodes.insert(id, operands);
+        }
+    }
+
+    let nodes = split_operands(named?)
+        .into_iter()
+        .map(|node| {
+            let operands = node
+                .strip_prefix('!')
+                .and_then(|id| nodes.get(id))
+                .map_or_else(Vec::new, |operands| split_operands(operands));
+            operands.into_iter().map(str::to_owned).collect()
+        })
+        .collect();
+    Some(nodes)
+}
+
+/// The globals referenced by the operands of metadata nodes, see [`named`]
+pub fn references(nodes: &[Vec<String>]) -> Vec<String> {
+    nodes
+        .iter()
+        .flatten()
+        .filter_map(|operand| {
+            let index = operand.find('@')?;
+            Some(global_name(&operand[index + 1..])?.0.to_owned())
+        })
+        .collect()
+}
+
+/// Splits a metadata operand list at the commas outside of strings and
+/// nested brackets
+fn split_operands(operands: &str) -> Vec<&str> {
+    let mut split = Vec::new();
+    let mut depth = 0usize;
+    let mut quoted = false;
+    let mut start = 0;
+    for (index, byte) in operands.bytes().enumerate() {
+        match byte {
+            b'"' => quoted = !quoted,
+            _ if quoted => {}
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' | b'}' => depth = depth.saturating_sub(1),
+            b',' if depth == 0 => {
+                split.push(operands[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    split.push(operands[start..].trim());
+    split.retain(|operand| !operand.is_empty());
+    split
+}
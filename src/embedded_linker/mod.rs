@@ -1,7 +1,78 @@
+mod api;
+mod archive;
+mod asserts;
+mod boundaries;
+mod capabilities;
+mod checkpoint;
+mod conflicts;
+mod constants;
+mod cubin;
+mod debug;
+mod diagnostics;
+mod diff;
+mod dtors;
+mod emit;
+mod fallback;
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
+pub(crate) mod globals;
+mod hash;
+mod host_code;
+mod inputs;
+mod inspect;
+mod intermediates;
+mod interrupt;
+mod limits;
 mod linker;
+#[cfg(feature = "llvm")]
+mod llvm;
+mod markers;
+mod math;
+mod memory;
+mod metadata;
+mod nm;
+mod object;
 mod opt;
+mod panic;
+mod postprocess;
+mod ptx;
+mod ptx_isa;
+mod rdc;
+mod remarks;
+mod rust_bindings;
+mod signing;
+mod size;
+mod symbol;
 mod target;
+mod target_features;
+mod tools;
+mod trace;
+mod traps;
+mod undefined;
+mod unreachable;
+mod unwind;
 
+pub use api::ApiMismatch;
+pub use capabilities::{capabilities, Capabilities, Tool};
+pub use checkpoint::Stage;
+pub use debug::DebugInfo;
+pub use diagnostics::{Deny, Diagnostic, Errors, Location, MessageFormat, Severity, UnknownDeny};
+pub use dtors::DtorPolicy;
+pub use emit::{Emit, EmitKind, UnknownEmitKind};
+pub use inputs::InputKind;
+pub use inspect::{ArtifactKind, Image, Inspection};
+pub use intermediates::Intermediates;
+pub use interrupt::Interrupted;
 pub use linker::Session;
-pub use opt::Optimization;
+pub use markers::{KERNEL_BEGIN, KERNEL_END};
+pub use math::Math;
+pub use opt::{Optimization, Pipeline, Step};
+pub use postprocess::{Command, PostProcessor};
+pub use ptx_isa::{InvalidPtxVersion, PtxVersion};
+pub use symbol::{KeepSymbolsFormat, Symbol};
 pub use target::Target;
+pub use target_features::{
+    FunctionFeatures, InvalidFunctionFeatures, InvalidTargetFeature, TargetFeature,
+};
+pub use tools::ToolLocator;
+pub use unreachable::Unreachable;
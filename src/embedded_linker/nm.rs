@@ -0,0 +1,148 @@
+use super::symbol::Symbol;
+
+/// The `llvm-nm` arguments selecting the output format understood by [`parse`]
+///
+/// The POSIX format is stable across LLVM versions and, unlike
+/// `--export-symbols`, available in all of them.
+pub const FORMAT_ARGS: [&str; 1] = ["--format=posix"];
+
+/// A symbol listed by `llvm-nm`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Entry {
+    pub name: Symbol,
+    /// The symbol type letter, e.g. `T` for a function in the text section
+    pub kind: u8,
+}
+
+/// Parses `llvm-nm --format=posix` output
+///
+/// Each symbol line has the form `name type [value [size]]`. Since names may
+/// contain spaces, lines are parsed from the right. The `file:` and
+/// `archive[member]:` headers printed for multiple inputs are skipped.
+pub fn parse(output: &[u8]) -> Vec<Entry> {
+    output
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .filter(|line| !line.is_empty() && !line.ends_with(b":"))
+        .filter_map(parse_line)
+        .collect()
+}
+
+fn parse_line(line: &[u8]) -> Option<Entry> {
+    let fields = line.split(|&b| b == b' ').collect::<Vec<_>>();
+
+    // The type is the leftmost candidate followed by at most a value and a
+    // size, so that single letter words at the end of a name are not mistaken
+    // for it
+    let type_index = (fields.len().saturating_sub(3)..fields.len())
+        .filter(|&i| i > 0)
+        .find(|&i| is_type(fields[i]) && fields[i + 1..].iter().all(|field| is_number(field)))?;
+
+    Some(Entry {
+        name: Symbol::new(
+            &line[..fields[..type_index]
+                .iter()
+                .map(|f| f.len() + 1)
+                .sum::<usize>()
+                - 1],
+        ),
+        kind: fields[type_index][0],
+    })
+}
+
+fn is_type(field: &[u8]) -> bool {
+    matches!(field, [kind] if kind.is_ascii_alphabetic() || *kind == b'?')
+}
+
+/// Values and sizes are hexadecimal, or dashes for bitcode symbols
+fn is_number(field: &[u8]) -> bool {
+    !field.is_empty()
+        && (field.iter().all(u8::is_ascii_hexdigit) || field.iter().all(|&b| b == b'-'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(output: &[u8]) -> Vec<(Vec<u8>, char)> {
+        parse(output)
+            .into_iter()
+            .map(|entry| (entry.name.as_bytes().to_vec(), char::from(entry.kind)))
+            .collect()
+    }
+
+    #[test]
+    fn parses_bitcode_symbols() {
+        // `llvm-nm --format=posix` of LLVM 14, 8 or 16 dashes by pointer size
+        let output = b"_ZN4test6kernel17h0123456789abcdefE T ---------------- 0\n\
+            other T ---------------- 0\n\
+            c C -------- 0\n\
+            ext U 0 0\n\
+            l d -------- 0\n\
+            w W -------- 0\n";
+        assert_eq!(
+            entries(output),
+            [
+                (b"_ZN4test6kernel17h0123456789abcdefE".to_vec(), 'T'),
+                (b"other".to_vec(), 'T'),
+                (b"c".to_vec(), 'C'),
+                (b"ext".to_vec(), 'U'),
+                (b"l".to_vec(), 'd'),
+                (b"w".to_vec(), 'W'),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_symbols_without_values() {
+        // Later versions print undefined symbols without value and size
+        assert_eq!(
+            entries(b"ext U\nfoo T 0000000000000010 000000000000001c\n"),
+            [(b"ext".to_vec(), 'U'), (b"foo".to_vec(), 'T')]
+        );
+    }
+
+    #[test]
+    fn parses_object_symbols() {
+        let output = b"_ZN2na7_$uf1$u13gr$uf6$$udf$e17h3c9cfb795a95ef50E T 0 6\n\
+            _ZN2na8caf$ue9$17h63127013d8fe6655E T 0 4\n";
+        assert_eq!(
+            entries(output),
+            [
+                (
+                    b"_ZN2na7_$uf1$u13gr$uf6$$udf$e17h3c9cfb795a95ef50E".to_vec(),
+                    'T'
+                ),
+                (b"_ZN2na8caf$ue9$17h63127013d8fe6655E".to_vec(), 'T'),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_spaces_and_bytes_of_names() {
+        let output = b"g\xe9 D -------- 0\nwith space T T -------- 0\r\nends in a U ---- 0\n";
+        assert_eq!(
+            entries(output),
+            [
+                (b"g\xe9".to_vec(), 'D'),
+                (b"with space T".to_vec(), 'T'),
+                (b"ends in a".to_vec(), 'U'),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_file_and_member_headers() {
+        // Several inputs in LLVM 14, and archive members in later versions
+        let output = b"\nsp.bc:\ng\xe9 D -------- 0\n\n/tmp/km/k.bc:\nother T ---------------- 0\n\
+            \nlib.a[k.bc]:\nkernel T ---------------- 0\n";
+        assert_eq!(
+            entries(output),
+            [
+                (b"g\xe9".to_vec(), 'D'),
+                (b"other".to_vec(), 'T'),
+                (b"kernel".to_vec(), 'T'),
+            ]
+        );
+    }
+}
@@ -0,0 +1,27 @@
+use std::fmt::Write;
+
+use crate::embed;
+
+/// The LLVM IR of a host module that embeds the image `data` named `name` in
+/// the [`embed::SECTION`] section
+///
+/// The image is internal, so that any number of them can be linked together,
+/// and kept alive by `llvm.used`.
+pub fn ir(name: &str, data: &[u8]) -> String {
+    let record = embed::record(name, data);
+
+    let mut ir = String::with_capacity(record.len() + 256);
+    let _ = write!(ir, "@image = internal constant [{} x i8] c\"", record.len());
+    for &byte in &record {
+        if (byte.is_ascii_graphic() || byte == b' ') && byte != b'"' && byte != b'\\' {
+            ir.push(char::from(byte));
+        } else {
+            let _ = write!(ir, "\\{byte:02X}");
+        }
+    }
+    let _ = writeln!(ir, "\", section \"{}\", align 8", embed::SECTION);
+    ir.push_str(
+        "@llvm.used = appending global [1 x ptr] [ptr @image], section \"llvm.metadata\"\n",
+    );
+    ir
+}
@@ -50,3 +50,75 @@ impl Display for Optimization {
         }
     }
 }
+
+impl Optimization {
+    /// The level of `llc -O` generating code like this level, which has no
+    /// size levels, so they generate code like `2` the way rustc does
+    pub fn codegen_level(self) -> char {
+        match self {
+            Optimization::O0 => '0',
+            Optimization::O1 => '1',
+            Optimization::O2 | Optimization::Os | Optimization::Oz => '2',
+            Optimization::O3 => '3',
+        }
+    }
+}
+
+/// How the inputs are optimized
+#[derive(Debug, Clone, Copy, Default, Hash, Eq, PartialEq, clap::ValueEnum)]
+pub enum Pipeline {
+    /// Merge all inputs and optimize the merged module with `default<O>`
+    #[default]
+    Merge,
+    /// Optimize every input with `default<O>` in parallel before merging them,
+    /// then only inline and clean up across them, trading some cross-module
+    /// optimization for parallelism on links with many inputs
+    Premerge,
+    /// Summary-based ThinLTO: import the functions every input calls from the
+    /// others, then optimize like [`Pipeline::Premerge`], reusing the inputs
+    /// optimized by an earlier link if they and their imports are unchanged
+    Thin,
+    /// Optimize every input with `default<O>` on its own without any
+    /// optimization or inlining across them after merging, only internalizing
+    /// and removing the unused symbols, which is the fastest but leaves calls
+    /// across inputs in place
+    PerModule,
+}
+
+/// A named step of the link pipeline, in the order they run
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Ord, PartialOrd, clap::ValueEnum)]
+pub enum Step {
+    /// Remove unreachable functions from the inputs that are not whole
+    Prune,
+    /// Merge the inputs into a single module with `llvm-link`
+    Merge,
+    /// Internalize all symbols that are not kept and remove the unused ones
+    Internalize,
+    /// Optimize with the optimization pipeline, including the premerge one
+    Optimize,
+    /// Inline all functions into the kernels
+    Inline,
+    /// Generate code for every target cpu
+    Codegen,
+}
+
+impl Step {
+    /// Whether the step can be skipped, the others are needed to produce any
+    /// output
+    pub fn is_optional(self) -> bool {
+        !matches!(self, Step::Merge | Step::Codegen)
+    }
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Step::Prune => "prune",
+            Step::Merge => "merge",
+            Step::Internalize => "internalize",
+            Step::Optimize => "optimize",
+            Step::Inline => "inline",
+            Step::Codegen => "codegen",
+        })
+    }
+}
@@ -0,0 +1,59 @@
+//! Inputs built with different panic strategies
+//!
+//! The panic strategy is recorded in a module flag with the `Error` behavior,
+//! so linking modules of `panic=abort` and `panic=unwind` builds fails. The
+//! device cannot unwind, so such inputs are all linked with abort semantics.
+
+/// The panic strategy flag whose values conflicted according to a diagnostic
+/// of the linker, e.g. `linking module flags 'panic-strategy': IDs have
+/// conflicting values in 'b.bc' and 'a.bc'`
+pub fn conflicting_flag(message: &str) -> Option<&str> {
+    if !message.contains("conflicting values") {
+        return None;
+    }
+    let name = message
+        .split_once("linking module flags '")?
+        .1
+        .split('\'')
+        .next()?;
+    name.to_ascii_lowercase().contains("panic").then_some(name)
+}
+
+/// Sets the panic strategy module flag `name` in the textual IR of a module
+/// to abort, `None` if it is not set to unwind
+///
+/// The strategy is either a string or the discriminant of rustc's
+/// `PanicStrategy`, where unwind is 0 and abort is 1.
+pub fn force_abort(ir: &str, name: &str) -> Option<String> {
+    let key = format!("!\"{name}\", ");
+    let mut forced = String::with_capacity(ir.len());
+    let mut changed = false;
+
+    for line in ir.split_inclusive('\n') {
+        let value = line
+            .starts_with('!')
+            .then(|| line.find(&key))
+            .flatten()
+            .map(|index| index + key.len());
+        let abort =
+            value.and_then(
+                |value| match line[value..].trim_end().strip_suffix('}')?.trim_end() {
+                    "i32 0" => Some("i32 1"),
+                    "!\"unwind\"" => Some("!\"abort\""),
+                    _ => None,
+                },
+            );
+
+        match value.zip(abort) {
+            Some((value, abort)) => {
+                forced.push_str(&line[..value]);
+                forced.push_str(abort);
+                forced.push_str("}\n");
+                changed = true;
+            }
+            None => forced.push_str(line),
+        }
+    }
+
+    changed.then_some(forced)
+}
@@ -0,0 +1,88 @@
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::manifest::{Artifact, Manifest};
+
+/// Processes the outputs of a successful link, e.g. to sign, compress or
+/// upload them
+///
+/// Post-processors run in the order they were added to the
+/// [`Session`](crate::Session), once per artifact, and the link fails if any
+/// of them does.
+pub trait PostProcessor: std::fmt::Debug + Send + Sync {
+    /// Processes the `artifact`, possibly rewriting it in place
+    ///
+    /// The `manifest` of all outputs is written to `manifest_path`.
+    fn process(
+        &self,
+        artifact: &Artifact,
+        manifest: &Manifest,
+        manifest_path: &Path,
+    ) -> anyhow::Result<()>;
+}
+
+/// A post-processor running an external command with the path of the
+/// artifact and of the JSON manifest as its last arguments
+#[derive(Debug, Clone)]
+pub struct Command {
+    program: String,
+    args: Vec<String>,
+}
+
+impl Command {
+    /// Splits a whitespace separated command line, `None` if it is empty
+    pub fn parse(command: &str) -> Option<Self> {
+        let mut words = command.split_whitespace().map(str::to_owned);
+        Some(Command {
+            program: words.next()?,
+            args: words.collect(),
+        })
+    }
+}
+
+impl PostProcessor for Command {
+    fn process(
+        &self,
+        artifact: &Artifact,
+        _manifest: &Manifest,
+        manifest_path: &Path,
+    ) -> anyhow::Result<()> {
+        let status = std::process::Command::new(&self.program)
+            .args(&self.args)
+            .arg(&artifact.path)
+            .arg(manifest_path)
+            .status()
+            .context(format!("Failed to run post-processor {}", self.program))?;
+
+        if !status.success() {
+            anyhow::bail!(
+                "post-processor {} failed on {} with {status}",
+                self.program,
+                artifact.path.display()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs every post-processor on every artifact of the `manifest` written to
+/// `manifest_path`
+pub fn run(
+    post_processors: &[Box<dyn PostProcessor>],
+    manifest: &Manifest,
+    manifest_path: &Path,
+) -> anyhow::Result<()> {
+    for post_processor in post_processors {
+        for artifact in &manifest.artifacts {
+            tracing::info!(
+                "post-processing {} with {post_processor:?}",
+                artifact.path.display()
+            );
+            post_processor.process(artifact, manifest, manifest_path)?;
+        }
+    }
+
+    Ok(())
+}
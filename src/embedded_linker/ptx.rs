@@ -0,0 +1,648 @@
+use std::collections::BTreeMap;
+
+use super::markers::{KERNEL_BEGIN, KERNEL_END};
+use super::size::function_name;
+use super::symbol::demangle;
+use crate::kernel_metadata;
+
+/// The kind of a PTX function
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FunctionKind {
+    /// A kernel declared with `.entry`
+    Entry,
+    /// A device function declared with `.func`
+    Func,
+}
+
+/// A function declared or defined in a PTX module
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub kind: FunctionKind,
+    pub name: String,
+    pub params: Vec<Param>,
+    /// Whether the function has a body
+    pub defined: bool,
+    /// The line its declaration starts on
+    pub line: usize,
+    pub launch_bounds: LaunchBounds,
+}
+
+/// A parameter of a function, e.g. `.param .align 8 .b8 name[16]`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Param {
+    pub name: String,
+    /// The fundamental type of the parameter or of its elements, e.g. `.u64`
+    pub ty: String,
+    /// The size in bytes, of all elements of an array
+    pub size: u32,
+    /// The alignment in bytes, the size of an element unless given with
+    /// `.align`
+    pub align: u32,
+}
+
+/// The performance tuning directives of a kernel
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct LaunchBounds {
+    /// The maximum number of threads per block of `.maxntid`
+    pub max_threads: Option<[u32; 3]>,
+    /// The exact number of threads per block of `.reqntid`
+    pub required_threads: Option<[u32; 3]>,
+    /// The minimum number of resident blocks per multiprocessor of
+    /// `.minnctapersm`
+    pub min_blocks: Option<u32>,
+    /// The maximum number of registers per thread of `.maxnreg`
+    pub max_registers: Option<u32>,
+}
+
+impl LaunchBounds {
+    /// Parses the directives following the parameter list of a kernel
+    fn parse(directives: &str) -> Self {
+        let mut bounds = LaunchBounds::default();
+        let mut words = directives
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|word| !word.is_empty())
+            .peekable();
+
+        while let Some(word) = words.next() {
+            let mut values = Vec::new();
+            while let Some(value) = words.peek().and_then(|value| value.parse::<u32>().ok()) {
+                values.push(value);
+                words.next();
+            }
+
+            // Omitted dimensions are 1
+            let dimensions = || {
+                let mut dimensions = [1; 3];
+                for (dimension, value) in dimensions.iter_mut().zip(&values) {
+                    *dimension = *value;
+                }
+                (!values.is_empty()).then_some(dimensions)
+            };
+            match word {
+                ".maxntid" => bounds.max_threads = dimensions(),
+                ".reqntid" => bounds.required_threads = dimensions(),
+                ".minnctapersm" => bounds.min_blocks = values.first().copied(),
+                ".maxnreg" => bounds.max_registers = values.first().copied(),
+                _ => {}
+            }
+        }
+
+        bounds
+    }
+
+    /// The most threads per block the kernel can be launched with
+    pub fn threads(&self) -> Option<[u32; 3]> {
+        self.required_threads.or(self.max_threads)
+    }
+}
+
+impl From<&Function> for kernel_metadata::Kernel {
+    /// The metadata of a kernel without the PTX ISA versions
+    fn from(function: &Function) -> Self {
+        let bounds = &function.launch_bounds;
+        kernel_metadata::Kernel {
+            name: function.name.clone(),
+            // Independent of `--no-demangle`, and without the hash
+            demangled: format!("{:#}", rustc_demangle::demangle(&function.name)),
+            params: function
+                .params
+                .iter()
+                .map(|param| kernel_metadata::Param {
+                    name: param.name.clone(),
+                    ty: param.ty.clone(),
+                    size: param.size,
+                    align: param.align,
+                })
+                .collect(),
+            launch_bounds: kernel_metadata::LaunchBounds {
+                max_threads: bounds.max_threads,
+                required_threads: bounds.required_threads,
+                min_blocks: bounds.min_blocks,
+                max_registers: bounds.max_registers,
+            },
+            ptx_versions: BTreeMap::new(),
+        }
+    }
+}
+
+/// The structure of a PTX module
+#[derive(Debug, Clone, Default)]
+pub struct Module {
+    pub version: String,
+    pub target: String,
+    pub address_size: String,
+    pub functions: Vec<Function>,
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+/// The PTX is malformed
+pub enum Error {
+    #[error("missing `{0}` directive")]
+    MissingDirective(&'static str),
+    #[error("line {line}: `{directive}` directive is duplicated or out of order")]
+    MisplacedDirective {
+        directive: &'static str,
+        line: usize,
+    },
+    #[error("line {0}: unbalanced braces")]
+    UnbalancedBraces(usize),
+    #[error("line {line}: malformed declaration of `{}`", demangle(.name))]
+    MalformedDeclaration { name: String, line: usize },
+    #[error("line {line}: malformed parameter list of `{}`", demangle(.name))]
+    MalformedParams { name: String, line: usize },
+    #[error("line {line}: parameter `{param}` of `{}` is duplicated", demangle(.name))]
+    DuplicateParam {
+        name: String,
+        param: String,
+        line: usize,
+    },
+    #[error("line {line}: `{}` is defined more than once", demangle(.name))]
+    DuplicateDefinition { name: String, line: usize },
+    #[error("line {0}: kernel marker does not match the kernel it surrounds")]
+    MismatchedMarker(usize),
+    #[error("unterminated declaration or body at the end of the module")]
+    Truncated,
+}
+
+/// The header directives in the order they must appear in
+const HEADER: [&str; 3] = [".version", ".target", ".address_size"];
+
+/// Parses the structure of a PTX module and checks it for the corruption
+/// post-processing could cause
+///
+/// Checks that the header directives are present and in order, that braces
+/// are balanced, that the parameter lists are well-formed and that no
+/// function, in particular no `.entry`, is defined twice. Instructions are
+/// not checked, that is left to `ptxas`.
+pub fn parse(ptx: &str) -> Result<Module, Error> {
+    let mut module = Module::default();
+    let mut header = 0;
+    let mut depth = 0usize;
+    // The declaration being parsed and the line it starts on
+    let mut declaration: Option<(String, usize)> = None;
+    // The kernel surrounded by markers and the line of its begin marker
+    let mut marked: Option<(&str, usize)> = None;
+
+    for (index, line) in ptx.lines().enumerate() {
+        let number = index + 1;
+        let trimmed = line.trim();
+
+        if let Some(name) = trimmed.strip_prefix(KERNEL_BEGIN) {
+            if marked.is_some() || depth != 0 {
+                return Err(Error::MismatchedMarker(number));
+            }
+            marked = Some((name.trim(), number));
+            continue;
+        }
+        if let Some(name) = trimmed.strip_prefix(KERNEL_END) {
+            match marked.take() {
+                Some((begin, _)) if begin == name.trim() && depth == 0 => {
+                    let defined = module.functions.last().filter(|function| {
+                        function.kind == FunctionKind::Entry && function.name == begin
+                    });
+                    if defined.is_none() {
+                        return Err(Error::MismatchedMarker(number));
+                    }
+                }
+                _ => return Err(Error::MismatchedMarker(number)),
+            }
+            continue;
+        }
+
+        let code = strip_comment(trimmed).trim();
+        if code.is_empty() {
+            continue;
+        }
+
+        if depth == 0 && declaration.is_none() {
+            if let Some(position) = HEADER.iter().position(|directive| {
+                code.strip_prefix(directive)
+                    .is_some_and(|rest| rest.starts_with(char::is_whitespace))
+            }) {
+                if position != header {
+                    return Err(Error::MisplacedDirective {
+                        directive: HEADER[position],
+                        line: number,
+                    });
+                }
+                header += 1;
+
+                let value = code[HEADER[position].len()..].trim().to_owned();
+                match position {
+                    0 => module.version = value,
+                    1 => module.target = value,
+                    _ => module.address_size = value,
+                }
+                continue;
+            }
+
+            if code.contains(".entry ") || code.contains(".func ") {
+                declaration = Some((String::new(), number));
+            }
+        }
+
+        if let Some((text, start)) = &mut declaration {
+            let body = code.find('{');
+            text.push_str(&code[..body.unwrap_or(code.len())]);
+            text.push(' ');
+
+            if body.is_some() || code.ends_with(';') {
+                let function = parse_declaration(text, *start, body.is_some())?;
+                if let Some((name, line)) = marked {
+                    if function.kind != FunctionKind::Entry || function.name != name {
+                        return Err(Error::MismatchedMarker(line));
+                    }
+                }
+                if function.defined
+                    && module
+                        .functions
+                        .iter()
+                        .any(|other| other.defined && other.name == function.name)
+                {
+                    return Err(Error::DuplicateDefinition {
+                        name: function.name,
+                        line: *start,
+                    });
+                }
+                module.functions.push(function);
+                declaration = None;
+            }
+        }
+
+        let unquoted = strip_strings(code);
+        depth += unquoted.matches('{').count();
+        depth = depth
+            .checked_sub(unquoted.matches('}').count())
+            .ok_or(Error::UnbalancedBraces(number))?;
+    }
+
+    if depth != 0 || declaration.is_some() {
+        return Err(Error::Truncated);
+    }
+    if let Some((_, line)) = marked {
+        return Err(Error::MismatchedMarker(line));
+    }
+    if let Some(missing) = HEADER.get(header) {
+        return Err(Error::MissingDirective(missing));
+    }
+
+    Ok(module)
+}
+
+/// Parses the declaration of a function, e.g.
+/// `.visible .entry kernel( .param .u64 kernel_param_0 )`
+fn parse_declaration(text: &str, line: usize, defined: bool) -> Result<Function, Error> {
+    let malformed = |name: &str| Error::MalformedDeclaration {
+        name: name.to_owned(),
+        line,
+    };
+
+    let name = function_name(text).ok_or_else(|| malformed("<unnamed>"))?;
+    let kind = if text.contains(".entry ") {
+        FunctionKind::Entry
+    } else {
+        FunctionKind::Func
+    };
+
+    // The parameters follow the name, after the return value of functions
+    let offset = name.as_ptr() as usize - text.as_ptr() as usize;
+    let rest = text[offset + name.len()..].trim_start();
+    let (params, directives) = match rest.strip_prefix('(') {
+        Some(rest) => {
+            let end = rest.find(')').ok_or_else(|| Error::MalformedParams {
+                name: name.to_owned(),
+                line,
+            })?;
+            (parse_params(name, &rest[..end], line)?, &rest[end + 1..])
+        }
+        None if rest.is_empty() || rest.starts_with(['.', ';']) => (Vec::new(), rest),
+        None => return Err(malformed(name)),
+    };
+
+    Ok(Function {
+        kind,
+        name: name.to_owned(),
+        params,
+        defined,
+        line,
+        launch_bounds: LaunchBounds::parse(directives),
+    })
+}
+
+/// Parses a comma separated list of `.param .u64 name` or `.reg .b32 name`
+fn parse_params(name: &str, list: &str, line: usize) -> Result<Vec<Param>, Error> {
+    let mut params: Vec<Param> = Vec::new();
+    if list.trim().is_empty() {
+        return Ok(params);
+    }
+
+    for param in list.split(',') {
+        let words = param.split_whitespace().collect::<Vec<_>>();
+        let param = match words.as_slice() {
+            [space, qualifiers @ .., param_name]
+                if [".param", ".reg"].contains(space) && words.len() >= 3 =>
+            {
+                parse_param(qualifiers, param_name)
+            }
+            _ => None,
+        };
+        let Some(param) = param else {
+            return Err(Error::MalformedParams {
+                name: name.to_owned(),
+                line,
+            });
+        };
+
+        if params.iter().any(|other| other.name == param.name) {
+            return Err(Error::DuplicateParam {
+                name: name.to_owned(),
+                param: param.name,
+                line,
+            });
+        }
+        params.push(param);
+    }
+
+    Ok(params)
+}
+
+/// Parses the qualifiers and the name of a parameter, e.g.
+/// `[".align", "8", ".b8"]` and `name[16]`
+///
+/// The state space and the qualifiers following the type, like `.ptr` of
+/// kernel pointers, are ignored.
+fn parse_param(qualifiers: &[&str], name: &str) -> Option<Param> {
+    let (ty_index, element_size) = qualifiers
+        .iter()
+        .enumerate()
+        .find_map(|(index, word)| Some((index, type_size(word)?)))?;
+    let align = match qualifiers[..ty_index] {
+        [.., ".align", align] => align.parse().ok()?,
+        _ => element_size,
+    };
+
+    // An array, e.g. `name[16]`
+    let (name, count) = match name.split_once('[') {
+        Some((name, count)) => (name, count.strip_suffix(']')?.parse::<u32>().ok()?),
+        None => (name, 1),
+    };
+
+    Some(Param {
+        name: name.to_owned(),
+        ty: qualifiers[ty_index].to_owned(),
+        size: element_size.checked_mul(count)?,
+        align,
+    })
+}
+
+/// The size in bytes of a fundamental type, e.g. 8 for `.u64`
+fn type_size(ty: &str) -> Option<u32> {
+    let ty = ty.strip_prefix('.')?;
+    if ty == "pred" {
+        return Some(1);
+    }
+    // Packed types, e.g. `.f16x2`
+    let (ty, lanes) = match ty.split_once('x') {
+        Some((ty, lanes)) => (ty, lanes.parse::<u32>().ok()?),
+        None => (ty, 1),
+    };
+    let bits = ty
+        .strip_prefix("bf")
+        .or_else(|| ty.strip_prefix(['b', 'u', 's', 'f']))?
+        .parse::<u32>()
+        .ok()?;
+    (bits >= 8 && bits % 8 == 0).then_some(bits / 8 * lanes)
+}
+
+/// Removes a trailing `// comment`
+fn strip_comment(line: &str) -> &str {
+    line.find("//").map_or(line, |index| &line[..index])
+}
+
+/// Removes string literals, e.g. the path of a `.file` directive
+fn strip_strings(line: &str) -> String {
+    line.split('"').step_by(2).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = ".version 7.0\n.target sm_70\n.address_size 64\n";
+
+    /// A module as `llc` emits it, with a kernel between markers
+    fn module() -> String {
+        format!(
+            "//\n// Generated by LLVM NVPTX Back-End\n//\n\n{HEADER}\n\
+             .extern .func  (.param .b32 func_retval0) vprintf\n\
+             (\n\t.param .b64 vprintf_param_0,\n\t.param .b64 vprintf_param_1\n)\n;\n\
+             .global .align 1 .b8 $str[4] = {{104, 105, 10, 0}};\n\n\
+             {KERNEL_BEGIN} kernel\n\
+             \t// .globl\tkernel\n\
+             .visible .entry kernel(\n\
+             \t.param .u64 .ptr .global .align 4 kernel_param_0,\n\
+             \t.param .align 8 .b8 kernel_param_1[24],\n\
+             \t.param .pred kernel_param_2\n\
+             )\n\
+             .maxntid 256, 1, 1\n\
+             .minnctapersm 2\n\
+             {{\n\
+             \t.reg .b64 \t%rd<3>;\n\
+             \t// a comment with a brace {{\n\
+             \tret;\n\
+             }}\n\
+             {KERNEL_END} kernel\n\n\
+             .func  (.param .b32 func_retval0) helper(\n\
+             \t.param .f16x2 helper_param_0\n\
+             )\n\
+             {{\n\
+             \t.file 1 \"/src/{{lib}}.rs\"\n\
+             \tret;\n\
+             }}\n"
+        )
+    }
+
+    #[test]
+    fn parses_directives() {
+        let module = parse(&module()).unwrap();
+        assert_eq!(module.version, "7.0");
+        assert_eq!(module.target, "sm_70");
+        assert_eq!(module.address_size, "64");
+
+        let functions = module
+            .functions
+            .iter()
+            .map(|function| (function.kind, function.name.as_str(), function.defined))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            functions,
+            [
+                (FunctionKind::Func, "vprintf", false),
+                (FunctionKind::Entry, "kernel", true),
+                (FunctionKind::Func, "helper", true),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_kernel_entries() {
+        let module = parse(&module()).unwrap();
+        let kernel = &module.functions[1];
+        assert_eq!(kernel.line, 19);
+
+        let param = |name: &str, ty: &str, size, align| Param {
+            name: name.to_owned(),
+            ty: ty.to_owned(),
+            size,
+            align,
+        };
+        // The alignment of `.ptr` is that of the memory pointed to
+        assert_eq!(
+            kernel.params,
+            [
+                param("kernel_param_0", ".u64", 8, 8),
+                param("kernel_param_1", ".b8", 24, 8),
+                param("kernel_param_2", ".pred", 1, 1),
+            ]
+        );
+        assert_eq!(
+            module.functions[2].params,
+            [param("helper_param_0", ".f16x2", 4, 4)]
+        );
+    }
+
+    #[test]
+    fn parses_limits() {
+        let module = parse(&module()).unwrap();
+        assert_eq!(
+            module.functions[1].launch_bounds,
+            LaunchBounds {
+                max_threads: Some([256, 1, 1]),
+                required_threads: None,
+                min_blocks: Some(2),
+                max_registers: None,
+            }
+        );
+        assert_eq!(module.functions[2].launch_bounds, LaunchBounds::default());
+
+        // Omitted dimensions are 1, and the required threads take precedence
+        let bounds = LaunchBounds::parse(".maxntid 1024 .reqntid 32,4 .maxnreg 64 .noreturn");
+        assert_eq!(bounds.max_threads, Some([1024, 1, 1]));
+        assert_eq!(bounds.required_threads, Some([32, 4, 1]));
+        assert_eq!(bounds.max_registers, Some(64));
+        assert_eq!(bounds.threads(), Some([32, 4, 1]));
+
+        // Directives without values are ignored
+        assert_eq!(
+            LaunchBounds::parse(".maxntid .maxnreg"),
+            LaunchBounds::default()
+        );
+    }
+
+    #[test]
+    fn sizes_types() {
+        assert_eq!(type_size(".pred"), Some(1));
+        assert_eq!(type_size(".b8"), Some(1));
+        assert_eq!(type_size(".bf16"), Some(2));
+        assert_eq!(type_size(".f16x2"), Some(4));
+        assert_eq!(type_size(".s32"), Some(4));
+        assert_eq!(type_size(".u64"), Some(8));
+        assert_eq!(type_size(".b128"), Some(16));
+        for ty in [".b4", ".u", ".x8", "u64", ".global", ".f16x", ".align"] {
+            assert_eq!(type_size(ty), None, "{ty}");
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_modules() {
+        let entry = |params: &str| format!("{HEADER}.visible .entry k({params})\n{{\nret;\n}}\n");
+        let cases = [
+            (String::new(), "missing `.version` directive"),
+            (
+                ".version 7.0\n.address_size 64\n".to_owned(),
+                "line 2: `.address_size` directive is duplicated or out of order",
+            ),
+            (
+                format!("{HEADER}.version 7.0\n"),
+                "line 4: `.version` directive is duplicated or out of order",
+            ),
+            (format!("{HEADER}}}\n"), "line 4: unbalanced braces"),
+            (
+                format!("{HEADER}.visible .entry k(\n{{\n}}\n"),
+                "line 4: malformed parameter list of `k`",
+            ),
+            (
+                entry(".param .u64 p, .param .u32"),
+                "line 4: malformed parameter list of `k`",
+            ),
+            (
+                entry(".param .u64 p, .param .u32 p"),
+                "line 4: parameter `p` of `k` is duplicated",
+            ),
+            (
+                entry("") + ".visible .entry k()\n{\nret;\n}\n",
+                "line 8: `k` is defined more than once",
+            ),
+            (
+                format!("{HEADER}.visible .entry k()\n{{\nret;\n"),
+                "unterminated declaration or body at the end of the module",
+            ),
+            (
+                format!("{HEADER}.visible .entry k(\n"),
+                "unterminated declaration or body at the end of the module",
+            ),
+            (
+                format!(
+                    "{HEADER}{KERNEL_BEGIN} k\n.visible .entry k()\n{{\n}}\n{KERNEL_END} other\n"
+                ),
+                "line 8: kernel marker does not match the kernel it surrounds",
+            ),
+            (
+                format!("{HEADER}{KERNEL_BEGIN} k\n.func k()\n{{\n}}\n"),
+                "line 4: kernel marker does not match the kernel it surrounds",
+            ),
+        ];
+
+        for (ptx, expected) in cases {
+            match parse(&ptx) {
+                Ok(_) => panic!("parsed malformed PTX:\n{ptx}"),
+                Err(err) => assert_eq!(err.to_string(), expected, "{ptx}"),
+            }
+        }
+    }
+
+    #[test]
+    fn does_not_panic_on_malformed_modules() {
+        let ptx = module();
+        // Every truncation, and every line dropped or duplicated
+        for (index, _) in ptx.char_indices() {
+            let _ = parse(&ptx[..index]);
+        }
+        let lines = ptx.lines().collect::<Vec<_>>();
+        for index in 0..lines.len() {
+            let mut dropped = lines.clone();
+            dropped.remove(index);
+            let _ = parse(&dropped.join("\n"));
+            let mut duplicated = lines.clone();
+            duplicated.insert(index, lines[index]);
+            let _ = parse(&duplicated.join("\n"));
+        }
+
+        for ptx in [
+            ".entry",
+            ".entry (",
+            ".func ()",
+            ".func (.param .b32 r",
+            ".visible .entry k(.param .b8 p[",
+            ".visible .entry k(.param .b8 p[4294967295]) {}",
+            ".visible .entry k(.param .align x .b8 p) {}",
+            ".visible .entry k(,,) {}",
+            ".version\n.target\n.address_size\n",
+            "\"{\" } {",
+            "\u{feff}.version 7.0 // é\n",
+        ] {
+            let _ = parse(ptx);
+            let _ = parse(&format!("{HEADER}{ptx}"));
+        }
+    }
+}
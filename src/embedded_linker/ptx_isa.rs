@@ -0,0 +1,134 @@
+//! The PTX ISA versions LLVM emits, and those the target cpus require
+
+use std::fmt::{Display, Formatter};
+
+/// A PTX ISA version, e.g. `7.8`
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct PtxVersion(u32);
+
+/// The PTX ISA versions LLVM emits with the major LLVM version that added
+/// them, from the oldest to the newest
+const VERSIONS: [(u32, u32); 28] = [
+    (32, 0),
+    (40, 0),
+    (41, 0),
+    (42, 0),
+    (43, 0),
+    (50, 0),
+    (60, 0),
+    (61, 0),
+    (63, 8),
+    (64, 9),
+    (65, 10),
+    (70, 11),
+    (71, 13),
+    (72, 13),
+    (73, 13),
+    (74, 14),
+    (75, 14),
+    (76, 15),
+    (77, 15),
+    (78, 16),
+    (80, 17),
+    (81, 17),
+    (82, 18),
+    (83, 18),
+    (84, 19),
+    (85, 19),
+    (86, 20),
+    (87, 20),
+];
+
+#[derive(Debug, Clone, thiserror::Error)]
+/// The PTX ISA version is malformed
+#[error("invalid PTX ISA version `{0}`, expected MAJOR.MINOR, e.g. 7.8")]
+pub struct InvalidPtxVersion(String);
+
+impl std::str::FromStr for PtxVersion {
+    type Err = InvalidPtxVersion;
+
+    fn from_str(s: &str) -> Result<Self, InvalidPtxVersion> {
+        let invalid = || InvalidPtxVersion(s.to_owned());
+        let (major, minor) = s.trim().split_once('.').ok_or_else(invalid)?;
+        let major = major.parse::<u32>().map_err(|_| invalid())?;
+        let minor = minor.parse::<u32>().map_err(|_| invalid())?;
+        if major == 0 || minor > 9 {
+            return Err(invalid());
+        }
+        Ok(PtxVersion(major * 10 + minor))
+    }
+}
+
+impl Display for PtxVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.0 / 10, self.0 % 10)
+    }
+}
+
+impl PtxVersion {
+    /// The version of a `ptx` target feature, e.g. 80 for `ptx80`
+    pub fn from_feature(feature: &str) -> Option<Self> {
+        let digits = feature.strip_prefix("ptx")?;
+        if digits.len() < 2 || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+            return None;
+        }
+        Some(PtxVersion(digits.parse().ok()?))
+    }
+
+    /// The enabled target feature selecting the version, e.g. `+ptx78`
+    pub fn feature(self) -> String {
+        format!("+ptx{}", self.0)
+    }
+
+    /// The oldest version LLVM emits for the target `cpu`, if it is known
+    pub fn min_for_cpu(cpu: &str) -> Option<Self> {
+        Some(PtxVersion(match cpu {
+            "sm_20" | "sm_21" | "sm_30" | "sm_35" => 32,
+            "sm_32" | "sm_50" => 40,
+            "sm_37" | "sm_52" => 41,
+            "sm_53" => 42,
+            "sm_60" | "sm_61" | "sm_62" => 50,
+            "sm_70" => 60,
+            "sm_72" => 61,
+            "sm_75" => 63,
+            "sm_80" => 70,
+            "sm_86" => 71,
+            "sm_87" => 74,
+            "sm_89" | "sm_90" => 78,
+            "sm_90a" => 80,
+            _ => return None,
+        }))
+    }
+
+    /// Checks that LLVM of the major version `llvm_major` emits this version,
+    /// listing the ones it does otherwise
+    ///
+    /// LLVM newer than the newest known version is assumed to emit any.
+    pub fn check_llvm(self, llvm_major: u32) -> Result<(), String> {
+        let newest_llvm = VERSIONS.iter().map(|&(_, llvm)| llvm).max();
+        if newest_llvm.is_some_and(|newest| llvm_major > newest) {
+            return Ok(());
+        }
+        let supported = Self::supported(llvm_major);
+        if supported.contains(&self) {
+            return Ok(());
+        }
+        Err(format!(
+            "LLVM {llvm_major} cannot emit PTX ISA {self}, it supports {}",
+            supported
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    }
+
+    /// The versions LLVM of the major version `llvm_major` emits
+    fn supported(llvm_major: u32) -> Vec<Self> {
+        VERSIONS
+            .iter()
+            .filter(|&&(_, llvm)| llvm <= llvm_major)
+            .map(|&(version, _)| PtxVersion(version))
+            .collect()
+    }
+}
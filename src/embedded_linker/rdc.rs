@@ -0,0 +1,61 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+/// The magic number at the start of a fatbin, `0xba55ed50` in little endian
+pub const FATBIN_MAGIC: [u8; 4] = [0x50, 0xed, 0x55, 0xba];
+pub const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+
+/// The container format of a prebuilt device library
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DeviceLibKind {
+    /// A single relocatable cubin ELF object
+    Cubin,
+    /// A fatbin bundling cubins (and possibly PTX) for several architectures
+    Fatbin,
+}
+
+/// A prebuilt device library with relocatable device code, linked with
+/// `nvlink` in relocatable device code mode
+#[derive(Debug, Clone)]
+pub struct DeviceLib {
+    pub path: PathBuf,
+    pub kind: DeviceLibKind,
+}
+
+impl DeviceLib {
+    /// Detects the kind of the device library at `path` from its magic number
+    ///
+    /// The path is made absolute, as `cuobjdump` extracts into its working
+    /// directory.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let mut magic = [0; 4];
+        std::fs::File::open(path)
+            .and_then(|mut file| file.read_exact(&mut magic))
+            .context(format!("Failed to read device library: {}", path.display()))?;
+
+        let kind = match magic {
+            ELF_MAGIC => DeviceLibKind::Cubin,
+            FATBIN_MAGIC => DeviceLibKind::Fatbin,
+            _ => anyhow::bail!(
+                "device library {} is neither a cubin nor a fatbin",
+                path.display()
+            ),
+        };
+
+        Ok(DeviceLib {
+            path: std::fs::canonicalize(path)?,
+            kind,
+        })
+    }
+
+    /// Whether `file_name` is a cubin for `cpu` extracted by `cuobjdump -xelf`,
+    /// which are named like `lib.1.sm_80.cubin`
+    pub fn is_extracted_cubin_for(file_name: &str, cpu: &str) -> bool {
+        file_name
+            .strip_suffix(".cubin")
+            .and_then(|stem| stem.rsplit('.').next())
+            .is_some_and(|arch| arch == cpu)
+    }
+}
@@ -0,0 +1,198 @@
+//! The optimization remarks `opt` writes with `--pass-remarks-output`,
+//! digested into a summary per kernel
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use super::symbol::demangle;
+
+/// Whether an optimization was applied
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+enum Kind {
+    Passed,
+    Missed,
+    Failure,
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Kind::Passed => "passed",
+            Kind::Missed => "missed",
+            Kind::Failure => "failed",
+        })
+    }
+}
+
+/// A single remark of a pass about a function
+#[derive(Debug)]
+struct Remark {
+    kind: Kind,
+    pass: String,
+    name: String,
+    function: String,
+    /// The message pieced together from the arguments
+    message: String,
+}
+
+/// The remarks of the same kind, pass and name in one function
+#[derive(Debug)]
+struct Group {
+    kind: Kind,
+    pass: String,
+    count: usize,
+    /// The message of the first remark
+    example: String,
+}
+
+/// The optimization remarks of the kernels of a module, grouped by what they
+/// are about
+#[derive(Debug, Default)]
+pub struct Summary {
+    kernels: BTreeMap<String, Vec<Group>>,
+    /// The number of remarks about functions that are not kernels
+    others: usize,
+}
+
+impl Summary {
+    /// Digests the YAML remarks of `opt` about the functions of a module with
+    /// the `kernels`
+    ///
+    /// Analysis remarks, e.g. the instruction counts of the backend, are
+    /// left out.
+    pub fn from_yaml(yaml: &str, kernels: &[String]) -> Self {
+        let mut summary = Summary::default();
+        let mut groups = BTreeMap::<(String, Kind, String, String), Group>::new();
+        for remark in parse(yaml) {
+            if !kernels.contains(&remark.function) {
+                summary.others += 1;
+                continue;
+            }
+            groups
+                .entry((
+                    remark.function,
+                    remark.kind,
+                    remark.pass.clone(),
+                    remark.name,
+                ))
+                .or_insert_with(|| Group {
+                    kind: remark.kind,
+                    pass: remark.pass,
+                    count: 0,
+                    example: remark.message,
+                })
+                .count += 1;
+        }
+
+        for ((function, ..), group) in groups {
+            summary.kernels.entry(function).or_default().push(group);
+        }
+        for groups in summary.kernels.values_mut() {
+            groups.sort_by(|a, b| a.kind.cmp(&b.kind).then(b.count.cmp(&a.count)));
+        }
+        summary
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kernels.is_empty() && self.others == 0
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (kernel, groups) in &self.kernels {
+            writeln!(f, "{}:", demangle(kernel))?;
+            for group in groups {
+                let count = if group.count > 1 {
+                    format!(" {} times", group.count)
+                } else {
+                    String::new()
+                };
+                writeln!(
+                    f,
+                    "  {} {}{count}: {}",
+                    group.kind, group.pass, group.example
+                )?;
+            }
+        }
+        if self.others > 0 {
+            writeln!(f, "{} remark(s) about other functions", self.others)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the YAML documents of the remarks, e.g.
+///
+/// ```yaml
+/// --- !Missed
+/// Pass:            inline
+/// Name:            NoDefinition
+/// Function:        kernel
+/// Args:
+///   - Callee:          foo
+///   - String:          ' will not be inlined into '
+///   - Caller:          kernel
+///   - String:          ' because its definition is unavailable'
+/// ...
+/// ```
+fn parse(yaml: &str) -> Vec<Remark> {
+    let mut remarks = Vec::new();
+    let mut current: Option<Remark> = None;
+
+    for line in yaml.lines() {
+        if let Some(kind) = line.strip_prefix("--- !") {
+            remarks.extend(current.take());
+            let kind = match kind.trim() {
+                "Passed" => Kind::Passed,
+                "Missed" => Kind::Missed,
+                "Failure" => Kind::Failure,
+                _ => continue,
+            };
+            current = Some(Remark {
+                kind,
+                pass: String::new(),
+                name: String::new(),
+                function: String::new(),
+                message: String::new(),
+            });
+            continue;
+        }
+        let Some(remark) = &mut current else {
+            continue;
+        };
+
+        if let Some(argument) = line.strip_prefix("  - ") {
+            if let Some((_, value)) = argument.split_once(':') {
+                remark.message.push_str(&demangle(&unquote(value)));
+            }
+        } else if let Some((key, value)) = line.split_once(':') {
+            match key {
+                "Pass" => remark.pass = unquote(value),
+                "Name" => remark.name = unquote(value),
+                "Function" => remark.function = unquote(value),
+                _ => {}
+            }
+        }
+    }
+    remarks.extend(current);
+    remarks
+}
+
+/// The plain, single or double quoted YAML scalar `value`
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    if let Some(quoted) = value
+        .strip_prefix('\'')
+        .and_then(|value| value.strip_suffix('\''))
+    {
+        return quoted.replace("''", "'");
+    }
+    if let Some(quoted) = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        return quoted.replace("\\\"", "\"").replace("\\\\", "\\");
+    }
+    value.to_owned()
+}
@@ -0,0 +1,225 @@
+//! The Rust bindings of the kernels emitted with `--emit rust-bindings`
+//!
+//! Every kernel gets a module with constants for its name and launch bounds
+//! and a `#[repr(C)]` struct of its parameters, which is laid out like the
+//! parameter buffer of the driver. Host crates `include!` the bindings to
+//! launch kernels without spelling out their mangled names.
+
+use std::collections::BTreeSet;
+
+use crate::kernel_metadata::{Kernel, KernelMetadata, Param};
+
+/// The keywords that cannot name a module or a field
+const KEYWORDS: [&str; 38] = [
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "static", "struct", "super", "trait", "true", "try", "type", "unsafe",
+    "use", "where", "while",
+];
+
+/// The Rust source of the bindings of the kernels in `metadata`
+pub fn generate(metadata: &KernelMetadata) -> String {
+    let mut source = format!(
+        "// Bindings of the kernels for {}, generated by {} {}.\n\
+         // Do not edit, they are regenerated by every link.\n\n",
+        metadata.target,
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    );
+
+    source.push_str(&format!(
+        "/// The mangled names of all kernels\npub const KERNELS: [&str; {}] = [{}];\n",
+        metadata.kernels.len(),
+        metadata
+            .kernels
+            .iter()
+            .map(|kernel| format!("{:?}", kernel.name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    ));
+
+    let mut modules = BTreeSet::new();
+    for kernel in &metadata.kernels {
+        // Distinct kernels may have the same sanitized path, e.g. generic ones
+        let mut module = identifier(&kernel.demangled);
+        let base = module.clone();
+        let mut index = 1;
+        while !modules.insert(module.clone()) {
+            module = format!("{base}_{index}");
+            index += 1;
+        }
+        source.push('\n');
+        source.push_str(&kernel_module(kernel, &module));
+    }
+    source
+}
+
+/// The module of the bindings of `kernel`
+fn kernel_module(kernel: &Kernel, module: &str) -> String {
+    let mut source = format!(
+        "/// The kernel `{}`\npub mod {module} {{\n    \
+         /// The mangled name to launch the kernel by\n    \
+         pub const NAME: &str = {:?};\n",
+        kernel.demangled, kernel.name
+    );
+
+    let bounds = &kernel.launch_bounds;
+    let dimensions = |[x, y, z]: [u32; 3]| format!("[u32; 3] = [{x}, {y}, {z}]");
+    let constants = [
+        (
+            "The most threads per block in x, y and z",
+            "MAX_THREADS",
+            bounds.max_threads.map(dimensions),
+        ),
+        (
+            "The exact number of threads per block in x, y and z",
+            "REQUIRED_THREADS",
+            bounds.required_threads.map(dimensions),
+        ),
+        (
+            "The fewest resident blocks per multiprocessor the kernel was compiled for",
+            "MIN_BLOCKS",
+            bounds.min_blocks.map(|blocks| format!("u32 = {blocks}")),
+        ),
+        (
+            "The most registers per thread",
+            "MAX_REGISTERS",
+            bounds
+                .max_registers
+                .map(|registers| format!("u32 = {registers}")),
+        ),
+    ];
+    for (doc, name, value) in constants {
+        if let Some(value) = value {
+            source.push_str(&format!("    /// {doc}\n    pub const {name}: {value};\n"));
+        }
+    }
+    source.push_str(&format!(
+        "    /// The PTX ISA version of the kernel by target cpu\n    \
+         pub const PTX_VERSIONS: [(&str, &str); {}] = [{}];\n",
+        kernel.ptx_versions.len(),
+        kernel
+            .ptx_versions
+            .iter()
+            .map(|(cpu, version)| format!("({cpu:?}, {version:?})"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    ));
+
+    source.push_str(&params(kernel));
+    source.push_str("}\n");
+    source
+}
+
+/// The struct of the parameters of `kernel`, with newtypes for parameters
+/// aligned more strictly than their Rust type
+fn params(kernel: &Kernel) -> String {
+    let mut source = String::new();
+    let mut fields = Vec::new();
+
+    for (index, param) in kernel.params.iter().enumerate() {
+        // `llc` names the parameters `<kernel>_param_<index>`
+        let name = param
+            .name
+            .strip_prefix(&kernel.name)
+            .and_then(|name| name.strip_prefix('_'))
+            .unwrap_or(&param.name);
+        let name = identifier(name);
+        let (ty, align) = rust_type(param);
+
+        let ty = if param.align > align {
+            let newtype = format!("Param{index}");
+            source.push_str(&format!(
+                "    /// The parameter `{name}`, aligned to {} bytes\n    \
+                 #[repr(C, align({}))]\n    \
+                 #[derive(Debug, Clone, Copy, PartialEq)]\n    \
+                 pub struct {newtype}(pub {ty});\n",
+                param.align, param.align
+            ));
+            newtype
+        } else {
+            ty
+        };
+        fields.push((name, ty));
+    }
+
+    source.push_str(
+        "    /// The parameters, laid out like the parameter buffer of the driver\n    \
+         ///\n    \
+         /// Pointers are passed as device addresses in `u64`.\n    \
+         #[repr(C)]\n    \
+         #[derive(Debug, Clone, Copy, PartialEq)]\n    \
+         pub struct Params {\n",
+    );
+    for (name, ty) in &fields {
+        source.push_str(&format!("        pub {name}: {ty},\n"));
+    }
+    source.push_str("    }\n");
+
+    source.push_str(&format!(
+        "\n    impl Params {{\n        \
+         /// The pointers to the parameters passed as `kernelParams` to\n        \
+         /// `cuLaunchKernel`, valid as long as the parameters are borrowed\n        \
+         pub fn pointers(&mut self) -> [*mut core::ffi::c_void; {}] {{\n            \
+         [\n{}            ]\n        \
+         }}\n    \
+         }}\n",
+        fields.len(),
+        fields
+            .iter()
+            .map(|(name, _)| {
+                format!("                core::ptr::addr_of_mut!(self.{name}).cast(),\n")
+            })
+            .collect::<String>()
+    ));
+    source
+}
+
+/// The Rust type of a parameter with its alignment, an array of the type of
+/// the elements for arrays and packed types, and bytes for unknown types
+fn rust_type(param: &Param) -> (String, u32) {
+    let element = match param.ty.as_str() {
+        ".u16" | ".b16" | ".f16" | ".bf16" => ("u16", 2),
+        ".u32" | ".b32" | ".f16x2" | ".bf16x2" => ("u32", 4),
+        ".u64" | ".b64" => ("u64", 8),
+        ".s8" => ("i8", 1),
+        ".s16" => ("i16", 2),
+        ".s32" => ("i32", 4),
+        ".s64" => ("i64", 8),
+        ".f32" => ("f32", 4),
+        ".f64" => ("f64", 8),
+        // `.u8`, `.b8`, `.pred` and unknown types
+        _ => ("u8", 1),
+    };
+
+    let (ty, size) = element;
+    if param.size == size {
+        (ty.to_owned(), size)
+    } else {
+        (format!("[{ty}; {}]", param.size / size), size)
+    }
+}
+
+/// A valid Rust identifier in snake case for `name`, e.g. `test_kernel` for
+/// `test::kernel`
+fn identifier(name: &str) -> String {
+    let mut identifier = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            identifier.push(c.to_ascii_lowercase());
+        } else if !identifier.is_empty() && !identifier.ends_with('_') {
+            identifier.push('_');
+        }
+    }
+    let identifier = identifier.trim_end_matches('_');
+
+    if identifier.is_empty() {
+        "unnamed".to_owned()
+    } else if identifier.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{identifier}")
+    } else if KEYWORDS.contains(&identifier) {
+        format!("{identifier}_")
+    } else {
+        identifier.to_owned()
+    }
+}
@@ -0,0 +1,58 @@
+use std::ffi::OsString;
+use std::fmt::{Debug, Formatter};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use ed25519_dalek::pkcs8::DecodePrivateKey;
+use ed25519_dalek::{Signer as _, SigningKey};
+
+/// The path of the detached signature of the artifact at `path`, e.g.
+/// `kernel.ptx.sig` for `kernel.ptx`
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut signature_path = OsString::from(path);
+    signature_path.push(".sig");
+    PathBuf::from(signature_path)
+}
+
+/// Signs artifacts with an Ed25519 key, writing the raw 64 byte signature
+/// next to them
+///
+/// The signatures can be checked with `openssl pkeyutl -verify -rawin` or
+/// with `ptx_linker::loader::verify_signature`.
+pub struct Signer(SigningKey);
+
+impl Signer {
+    /// Loads the PKCS#8 PEM private key at `path`, e.g. generated with
+    /// `openssl genpkey -algorithm ed25519`
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let pem = std::fs::read_to_string(path)
+            .context(format!("Failed to read signing key: {}", path.display()))?;
+        let key = SigningKey::from_pkcs8_pem(&pem).map_err(|err| {
+            anyhow::anyhow!("invalid Ed25519 signing key {}: {err}", path.display())
+        })?;
+        Ok(Signer(key))
+    }
+
+    /// The public key the signatures are verified with
+    pub fn public_key(&self) -> [u8; 32] {
+        self.0.verifying_key().to_bytes()
+    }
+
+    /// Signs the artifact at `path`, returning the path of the signature
+    pub fn sign(&self, path: &Path) -> anyhow::Result<PathBuf> {
+        let data =
+            std::fs::read(path).context(format!("Failed to read artifact: {}", path.display()))?;
+        let signature_path = signature_path(path);
+        std::fs::write(&signature_path, self.0.sign(&data).to_bytes()).context(format!(
+            "Failed to write signature: {}",
+            signature_path.display()
+        ))?;
+        Ok(signature_path)
+    }
+}
+
+impl Debug for Signer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Signer").field(&self.public_key()).finish()
+    }
+}
@@ -0,0 +1,163 @@
+use std::fmt::{Display, Formatter};
+
+use super::symbol::demangle;
+
+/// The crate reported for symbols without a Rust mangled name
+const UNMANGLED: &str = "<unmangled>";
+
+/// The number of instructions of each function in a PTX module
+#[derive(Debug, Default)]
+pub struct Report {
+    functions: Vec<(String, usize)>,
+}
+
+impl Report {
+    /// Counts the instructions in the body of every `.entry` and `.func`
+    pub fn from_ptx(ptx: &str) -> Self {
+        let mut functions = Vec::new();
+        let mut current: Option<(String, usize)> = None;
+        let mut depth = 0usize;
+
+        for line in ptx.lines().map(str::trim) {
+            if depth == 0 {
+                if let Some(name) = function_name(line) {
+                    current = Some((name.to_owned(), 0));
+                }
+            }
+
+            if let Some((_, instructions)) = &mut current {
+                if depth > 0 && line.ends_with(';') && !line.starts_with('.') {
+                    *instructions += 1;
+                }
+            }
+
+            depth += line.matches('{').count();
+            depth = depth.saturating_sub(line.matches('}').count());
+
+            if depth == 0 && line.contains('}') {
+                functions.extend(current.take());
+            } else if depth == 0 && line.ends_with(';') {
+                // A declaration without a body
+                current = None;
+            }
+        }
+
+        functions.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Report { functions }
+    }
+
+    pub fn total(&self) -> usize {
+        self.functions
+            .iter()
+            .map(|(_, instructions)| instructions)
+            .sum()
+    }
+
+    /// The number of instructions per crate, parsed from the mangled function
+    /// names, largest first
+    pub fn crates(&self) -> Vec<(String, usize)> {
+        let mut crates: Vec<(String, usize)> = Vec::new();
+
+        for (name, instructions) in &self.functions {
+            let krate = crate_name(name).unwrap_or(UNMANGLED);
+            match crates.iter_mut().find(|(name, _)| name == krate) {
+                Some((_, total)) => *total += instructions,
+                None => crates.push((krate.to_owned(), *instructions)),
+            }
+        }
+
+        crates.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        crates
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let total = self.total();
+        #[allow(clippy::cast_precision_loss)]
+        let percent = |instructions: usize| instructions as f64 * 100.0 / total.max(1) as f64;
+
+        writeln!(
+            f,
+            "{total} instructions in {} functions",
+            self.functions.len()
+        )?;
+        for (krate, instructions) in self.crates() {
+            writeln!(
+                f,
+                "  {krate}: {:.1}% of instructions ({instructions})",
+                percent(instructions)
+            )?;
+        }
+        for (name, instructions) in &self.functions {
+            writeln!(f, "  {instructions:>8} {}", demangle(name))?;
+        }
+        Ok(())
+    }
+}
+
+/// The name of the function declared on `line`, e.g.
+/// `.visible .func  (.param .b32 func_retval0) foo(`
+pub fn function_name(line: &str) -> Option<&str> {
+    if !line.starts_with('.') {
+        return None;
+    }
+
+    let start = [".entry ", ".func "]
+        .iter()
+        .find_map(|keyword| Some(line.find(keyword)? + keyword.len()))?;
+    let mut rest = line[start..].trim_start();
+
+    // Skip the return value of functions
+    if let Some(retval) = rest.strip_prefix('(') {
+        rest = retval[retval.find(')')? + 1..].trim_start();
+    }
+
+    let name = &rest[..rest.find(['(', ' ', ';']).unwrap_or(rest.len())];
+    (!name.is_empty()).then_some(name)
+}
+
+/// The crate of a legacy (`_ZN`) or v0 (`_R`) mangled Rust symbol
+fn crate_name(symbol: &str) -> Option<&str> {
+    if let Some(rest) = symbol.strip_prefix("_ZN") {
+        return identifier(rest, false);
+    }
+
+    let mut rest = symbol.strip_prefix("_R")?;
+    rest = rest.trim_start_matches(|c: char| c.is_ascii_digit());
+
+    // Skip the nested paths and impls down to the innermost crate root
+    loop {
+        let mut chars = rest.chars();
+        match chars.next()? {
+            'N' => {
+                chars.next()?;
+                rest = chars.as_str();
+            }
+            'M' | 'X' | 'Y' => rest = skip_disambiguator(chars.as_str()),
+            'C' => return identifier(skip_disambiguator(chars.as_str()), true),
+            _ => return None,
+        }
+    }
+}
+
+/// Skips an optional `s<base-62>_` disambiguator
+fn skip_disambiguator(rest: &str) -> &str {
+    rest.strip_prefix('s')
+        .and_then(|rest| Some(&rest[rest.find('_')? + 1..]))
+        .unwrap_or(rest)
+}
+
+/// Parses a length prefixed identifier
+///
+/// In v0 mangling the identifier is `separated` from its length by a `_` if
+/// it starts with a digit or `_`.
+fn identifier(rest: &str, separated: bool) -> Option<&str> {
+    let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+    let len = rest[..digits].parse::<usize>().ok()?;
+    let mut rest = &rest[digits..];
+    if separated {
+        rest = rest.strip_prefix('_').unwrap_or(rest);
+    }
+    rest.get(..len).filter(|name| !name.is_empty())
+}
@@ -0,0 +1,253 @@
+use std::borrow::Cow;
+use std::ffi::OsString;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether Rust symbols are demangled in log output and errors
+static DEMANGLE: AtomicBool = AtomicBool::new(true);
+
+/// A symbol name as reported by the LLVM tools
+///
+/// Symbol names are not guaranteed to be valid UTF-8, so the raw bytes are
+/// preserved and only converted lossily for display.
+#[derive(Clone, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub struct Symbol(Vec<u8>);
+
+impl Symbol {
+    pub fn new(name: impl Into<Vec<u8>>) -> Self {
+        Symbol(name.into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.0)
+    }
+
+    /// Show Rust symbols demangled in log output and errors, which is the
+    /// default
+    pub fn set_demangle(demangle: bool) {
+        DEMANGLE.store(demangle, Ordering::Relaxed);
+    }
+
+    /// The name for log output and errors, see [`demangle`]
+    pub fn demangled(&self) -> Cow<'_, str> {
+        match self.to_string_lossy() {
+            Cow::Borrowed(name) => demangle(name),
+            Cow::Owned(name) => Cow::Owned(demangle(&name).into_owned()),
+        }
+    }
+
+    /// Whether the glob `pattern` matches the whole name or, for Rust
+    /// symbols, the whole demangled path without its hash, e.g.
+    /// `my_crate::cold_*`
+    pub fn matches_glob_or_path(&self, pattern: &str) -> bool {
+        self.matches_glob(pattern)
+            || std::str::from_utf8(&self.0)
+                .ok()
+                .and_then(|name| rustc_demangle::try_demangle(name).ok())
+                .is_some_and(|path| glob_match(pattern.as_bytes(), format!("{path:#}").as_bytes()))
+    }
+
+    pub fn starts_with(&self, prefix: &str) -> bool {
+        self.0.starts_with(prefix.as_bytes())
+    }
+
+    /// Whether the glob `pattern`, where `*` matches any sequence and `?` any
+    /// single byte, matches the whole name
+    pub fn matches_glob(&self, pattern: &str) -> bool {
+        glob_match(pattern.as_bytes(), &self.0)
+    }
+
+    /// The symbol surrounded by `prefix` and `suffix` as a command line
+    /// argument, keeping non-UTF-8 names intact where the platform allows it
+    pub fn to_arg(&self, prefix: &str, suffix: &str) -> OsString {
+        let mut arg = Vec::with_capacity(prefix.len() + self.0.len() + suffix.len());
+        arg.extend_from_slice(prefix.as_bytes());
+        arg.extend_from_slice(&self.0);
+        arg.extend_from_slice(suffix.as_bytes());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::ffi::OsStringExt::from_vec(arg)
+        }
+        #[cfg(not(unix))]
+        {
+            OsString::from(String::from_utf8_lossy(&arg).into_owned())
+        }
+    }
+}
+
+/// The demangled `name` of a Rust symbol without its hash, or `name` itself
+/// for other symbols and if demangling is turned off
+pub fn demangle(name: &str) -> Cow<'_, str> {
+    if DEMANGLE.load(Ordering::Relaxed) {
+        if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+            return Cow::Owned(format!("{demangled:#}"));
+        }
+    }
+    Cow::Borrowed(name)
+}
+
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // The position after the last `*` and the name position it matched up to
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                p += 1;
+                backtrack = Some((p, n));
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_n)) => {
+                    p = star_p;
+                    n = star_n + 1;
+                    backtrack = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_string_lossy())
+    }
+}
+
+impl Debug for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.to_string_lossy())
+    }
+}
+
+/// The format of a file listing symbols, e.g. the emitted keep-set
+#[derive(Debug, Clone, Copy, Default, Hash, Eq, PartialEq, clap::ValueEnum)]
+pub enum KeepSymbolsFormat {
+    /// One raw symbol name per line
+    #[default]
+    List,
+    /// A YAML document with the list of quoted names under `keep`
+    Yaml,
+    /// One name per line with the glob characters escaped, as read by
+    /// `opt --internalize-public-api-file`
+    LlvmApi,
+}
+
+impl KeepSymbolsFormat {
+    /// Encodes `symbols` in this format
+    pub fn encode(self, symbols: &[Symbol]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        if self == KeepSymbolsFormat::Yaml {
+            let empty = if symbols.is_empty() { " []" } else { "" };
+            encoded.extend_from_slice(format!("keep:{empty}\n").as_bytes());
+        }
+
+        for symbol in symbols {
+            match self {
+                KeepSymbolsFormat::List => encoded.extend_from_slice(symbol.as_bytes()),
+                KeepSymbolsFormat::Yaml => {
+                    // JSON strings are valid YAML flow scalars
+                    let name = serde_json::Value::from(symbol.to_string_lossy());
+                    encoded.extend_from_slice(format!("  - {name}").as_bytes());
+                }
+                KeepSymbolsFormat::LlvmApi => {
+                    for &byte in symbol.as_bytes() {
+                        if b"*?[]{}\\".contains(&byte) {
+                            encoded.push(b'\\');
+                        }
+                        encoded.push(byte);
+                    }
+                }
+            }
+            encoded.push(b'\n');
+        }
+
+        encoded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::size::function_name;
+    use super::*;
+
+    /// `na::café` and `na::ñu::größe` as mangled by rustc
+    const CAFE: &str = "_ZN2na8caf$ue9$17h63127013d8fe6655E";
+    const GROESSE: &str = "_ZN2na7_$uf1$u13gr$uf6$$udf$e17h3c9cfb795a95ef50E";
+
+    #[test]
+    fn demangles_non_ascii_paths() {
+        assert_eq!(demangle(CAFE), "na::café");
+        assert_eq!(demangle(GROESSE), "na::ñu::größe");
+        assert_eq!(Symbol::new(CAFE).demangled(), "na::café");
+    }
+
+    #[test]
+    fn demangles_non_ascii_ptx_names() {
+        let line = format!(".visible .entry {CAFE}(");
+        let name = function_name(&line).unwrap();
+        assert_eq!(name, CAFE);
+        assert_eq!(demangle(name), "na::café");
+    }
+
+    #[test]
+    fn keeps_non_utf8_names() {
+        let symbol = Symbol::new(b"caf\xe9".to_vec());
+        assert_eq!(symbol.as_bytes(), b"caf\xe9");
+        assert_eq!(symbol.to_string_lossy(), "caf\u{fffd}");
+        assert_eq!(symbol.demangled(), "caf\u{fffd}");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let arg = symbol.to_arg("-keep=", ";");
+            assert_eq!(arg.as_bytes(), b"-keep=caf\xe9;");
+        }
+    }
+
+    #[test]
+    fn matches_non_ascii_globs() {
+        let symbol = Symbol::new(CAFE);
+        assert!(symbol.matches_glob_or_path("na::caf*"));
+        assert!(symbol.matches_glob_or_path("na::café"));
+        // `?` matches a single byte, not a character
+        assert!(!symbol.matches_glob_or_path("na::caf?"));
+        assert!(symbol.matches_glob_or_path("na::caf??"));
+        assert!(Symbol::new(b"caf\xe9".to_vec()).matches_glob("caf?"));
+    }
+
+    #[test]
+    fn encodes_non_ascii_names() {
+        let symbols = [
+            Symbol::new("café"),
+            Symbol::new(b"caf\xe9".to_vec()),
+            Symbol::new("a\"b\\c*"),
+        ];
+
+        assert_eq!(
+            KeepSymbolsFormat::List.encode(&symbols),
+            b"caf\xc3\xa9\ncaf\xe9\na\"b\\c*\n"
+        );
+        assert_eq!(
+            String::from_utf8(KeepSymbolsFormat::Yaml.encode(&symbols)).unwrap(),
+            "keep:\n  - \"café\"\n  - \"caf\u{fffd}\"\n  - \"a\\\"b\\\\c*\"\n"
+        );
+        assert_eq!(
+            KeepSymbolsFormat::LlvmApi.encode(&symbols),
+            b"caf\xc3\xa9\ncaf\xe9\na\"b\\\\c\\*\n"
+        );
+        assert_eq!(KeepSymbolsFormat::Yaml.encode(&[]), b"keep: []\n");
+    }
+}
@@ -3,6 +3,23 @@ pub enum Target {
     Nvptx64NvidiaCuda,
 }
 
+impl Target {
+    /// The target triple, e.g. `nvptx64-nvidia-cuda`
+    pub fn triple(self) -> &'static str {
+        match self {
+            Target::Nvptx64NvidiaCuda => "nvptx64-nvidia-cuda",
+        }
+    }
+}
+
+/// The target triple of a module from its textual IR, if it has one
+pub fn module_triple(ir: &str) -> Option<&str> {
+    ir.lines()
+        .find_map(|line| line.strip_prefix("target triple = \""))
+        .and_then(|rest| rest.strip_suffix('"'))
+        .filter(|triple| !triple.is_empty())
+}
+
 #[allow(clippy::module_name_repetitions)]
 #[derive(Debug, Clone, Copy, thiserror::Error)]
 /// The target is not supported by this linker
@@ -0,0 +1,323 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
+
+use super::globals::global_name;
+use super::ptx_isa::PtxVersion;
+use crate::bundle::arch_version;
+
+const TARGET_FEATURES: &str = "\"target-features\"=\"";
+const TARGET_CPU: &str = "\"target-cpu\"=\"";
+
+/// Target features of a single function overriding those of the module,
+/// given as `FUNCTION=FEATURES`, e.g. `kernel=+ptx80,+sm_90a`
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct FunctionFeatures {
+    pub function: String,
+    /// The features, each enabled with `+` or disabled with `-`
+    pub features: Vec<String>,
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+/// The function target features are malformed
+#[error("malformed function target features `{0}`, expected `FUNCTION=+FEATURE,-FEATURE`")]
+pub struct InvalidFunctionFeatures(String);
+
+impl std::str::FromStr for FunctionFeatures {
+    type Err = InvalidFunctionFeatures;
+
+    fn from_str(s: &str) -> Result<Self, InvalidFunctionFeatures> {
+        let invalid = || InvalidFunctionFeatures(s.to_owned());
+        let (function, features) = s.split_once('=').ok_or_else(invalid)?;
+        let features = features
+            .split(',')
+            .map(str::trim)
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+
+        if function.is_empty()
+            || features
+                .iter()
+                .any(|feature| feature.len() < 2 || !feature.starts_with(['+', '-']))
+        {
+            return Err(invalid());
+        }
+
+        Ok(FunctionFeatures {
+            function: function.to_owned(),
+            features,
+        })
+    }
+}
+
+impl Display for FunctionFeatures {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.function, self.features.join(","))
+    }
+}
+
+/// A target feature of the whole module passed to `llc`, e.g. `+ptx80` to
+/// select the PTX ISA version independently of the target cpu
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct TargetFeature(String);
+
+#[derive(Debug, Clone, thiserror::Error)]
+/// The target feature is not one of nvptx64
+#[error("invalid target feature `{0}`, expected `+ptxNN` or `+sm_NN`, or `-` to disable it")]
+pub struct InvalidTargetFeature(String);
+
+impl std::str::FromStr for TargetFeature {
+    type Err = InvalidTargetFeature;
+
+    fn from_str(s: &str) -> Result<Self, InvalidTargetFeature> {
+        let feature = TargetFeature(s.trim().to_owned());
+        if !feature.0.starts_with(['+', '-'])
+            || (feature.ptx_version().is_none() && feature.cpu().is_none())
+        {
+            return Err(InvalidTargetFeature(s.to_owned()));
+        }
+        Ok(feature)
+    }
+}
+
+impl Display for TargetFeature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl TargetFeature {
+    /// The enabled feature selecting the PTX ISA `version`
+    pub fn ptx(version: PtxVersion) -> Self {
+        TargetFeature(version.feature())
+    }
+
+    /// Whether the feature is enabled rather than disabled
+    pub fn is_enabled(&self) -> bool {
+        self.0.starts_with('+')
+    }
+
+    /// The PTX ISA version of a `ptx` feature, e.g. 8.0 for `+ptx80`
+    pub fn ptx_version(&self) -> Option<PtxVersion> {
+        PtxVersion::from_feature(&self.0[1..])
+    }
+
+    /// The target cpu of an `sm_` feature, e.g. `sm_80` for `+sm_80`
+    pub fn cpu(&self) -> Option<&str> {
+        let cpu = &self.0[1..];
+        arch_version(cpu)
+            .filter(|_| cpu.starts_with("sm_"))
+            .map(|_| cpu)
+    }
+
+    /// Checks that the enabled feature can be used when compiling for `cpu`
+    pub fn check_cpu(&self, cpu: &str) -> Result<(), String> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        if let Some(feature_cpu) = self.cpu().filter(|feature_cpu| *feature_cpu != cpu) {
+            return Err(format!(
+                "the target feature {self} conflicts with the target cpu {cpu}, compile for {feature_cpu} with --target-cpu instead"
+            ));
+        }
+        match (self.ptx_version(), PtxVersion::min_for_cpu(cpu)) {
+            (Some(version), Some(min)) if version < min => Err(format!(
+                "the target feature {self} selects PTX ISA {version}, but {cpu} requires at least {min}"
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Overrides the `"target-features"` of functions defined in the textual IR of
+/// a module
+///
+/// Every overridden function gets its own copy of its attribute group, with
+/// the features added to those it already had, replacing the ones of the same
+/// name. Returns the new IR and the functions that are not defined in it.
+pub fn apply<'a>(ir: &str, overrides: &'a [FunctionFeatures]) -> (String, Vec<&'a str>) {
+    let mut groups = HashMap::new();
+    let mut next_group = 0;
+    for line in ir.lines() {
+        if let Some((id, body)) = attribute_group(line) {
+            groups.insert(id, body);
+            next_group = next_group.max(id + 1);
+        }
+    }
+
+    let mut applied = String::with_capacity(ir.len());
+    let mut added_groups = String::new();
+    let mut defined = Vec::new();
+
+    for line in ir.split_inclusive('\n') {
+        let Some((name, header)) = definition(line) else {
+            applied.push_str(line);
+            continue;
+        };
+        let features = overrides
+            .iter()
+            .filter(|features| features.function == name)
+            .flat_map(|features| &features.features)
+            .collect::<Vec<_>>();
+        if features.is_empty() {
+            applied.push_str(line);
+            continue;
+        }
+        defined.push(name);
+
+        // Replace the reference to the attribute group of the function, or add
+        // one after the attributes that must come before it
+        let (prefix, attributes) = line.split_at(header);
+        let mut words = attributes.split(' ').collect::<Vec<_>>();
+        let group = format!("#{next_group}");
+        let body = if let Some(index) = words
+            .iter()
+            .position(|word| word.strip_prefix('#').is_some_and(is_number))
+        {
+            let id = words[index][1..].parse::<usize>().unwrap_or_default();
+            words[index] = &group;
+            groups.get(&id).copied().unwrap_or_default()
+        } else {
+            let index = words
+                .iter()
+                .position(|word| {
+                    !word.is_empty()
+                        && !["unnamed_addr", "local_unnamed_addr"].contains(word)
+                        && !word.starts_with("addrspace(")
+                })
+                .unwrap_or(words.len());
+            words.insert(index, &group);
+            ""
+        };
+
+        applied.push_str(prefix);
+        applied.push_str(&words.join(" "));
+        added_groups.push_str(&format!(
+            "attributes {group} = {{ {} }}\n",
+            override_features(body, &features)
+        ));
+        next_group += 1;
+    }
+
+    if !added_groups.is_empty() {
+        if !applied.ends_with('\n') {
+            applied.push('\n');
+        }
+        applied.push('\n');
+        applied.push_str(&added_groups);
+    }
+
+    let missing = overrides
+        .iter()
+        .map(|features| features.function.as_str())
+        .filter(|function| !defined.contains(function))
+        .collect();
+    (applied, missing)
+}
+
+/// The target cpus the functions in the textual IR of a module were compiled
+/// for, from their `"target-cpu"` and their `+sm_` target features, ordered
+/// from the oldest to the newest
+pub fn required_cpus(ir: &str) -> Vec<String> {
+    let mut cpus = BTreeSet::new();
+    for (_, body) in ir.lines().filter_map(attribute_group) {
+        if let Some(cpu) = string_attribute(body, TARGET_CPU) {
+            cpus.insert(cpu.to_owned());
+        }
+        if let Some(features) = string_attribute(body, TARGET_FEATURES) {
+            cpus.extend(
+                features
+                    .split(',')
+                    .filter_map(|feature| feature.strip_prefix('+'))
+                    .filter(|feature| feature.starts_with("sm_"))
+                    .map(str::to_owned),
+            );
+        }
+    }
+
+    let mut cpus = cpus
+        .into_iter()
+        .filter(|cpu| arch_version(cpu).is_some())
+        .collect::<Vec<_>>();
+    cpus.sort_by_key(|cpu| arch_version(cpu));
+    cpus
+}
+
+/// The value of the string attribute starting with `prefix` in the body of an
+/// attribute group
+fn string_attribute<'a>(body: &'a str, prefix: &str) -> Option<&'a str> {
+    let value = &body[body.find(prefix)? + prefix.len()..];
+    Some(&value[..value.find('"')?])
+}
+
+/// The id and body of an `attributes #0 = { ... }` line
+fn attribute_group(line: &str) -> Option<(usize, &str)> {
+    let (id, body) = line.strip_prefix("attributes #")?.split_once(" = {")?;
+    Some((id.parse().ok()?, body.trim_end().strip_suffix('}')?.trim()))
+}
+
+/// The name of the function defined on a `define` line and the offset of the
+/// attributes after its parameters
+fn definition(line: &str) -> Option<(&str, usize)> {
+    if !line.starts_with("define ") {
+        return None;
+    }
+    let index = line.find('@')?;
+    let (name, rest) = global_name(&line[index + 1..])?;
+
+    let mut depth = 0usize;
+    for (offset, c) in rest.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth = depth.checked_sub(1)?;
+                if depth == 0 {
+                    return Some((name, line.len() - rest.len() + offset + 1));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The attribute group `body` with `features` overriding its target features
+fn override_features(body: &str, features: &[&String]) -> String {
+    let (mut body, existing) = match body.find(TARGET_FEATURES) {
+        Some(start) => {
+            let value = &body[start + TARGET_FEATURES.len()..];
+            let end = value.find('"').unwrap_or(value.len());
+            let rest = value.get(end + 1..).unwrap_or_default();
+            (
+                format!("{}{}", body[..start].trim_end(), rest),
+                value[..end].split(',').collect::<Vec<_>>(),
+            )
+        }
+        None => (body.to_owned(), Vec::new()),
+    };
+
+    let mut merged = existing
+        .into_iter()
+        .filter(|feature| {
+            !feature.is_empty()
+                && !features
+                    .iter()
+                    .any(|other| feature_name(other) == feature_name(feature))
+        })
+        .collect::<Vec<_>>();
+    merged.extend(features.iter().map(|feature| feature.as_str()));
+
+    if !body.is_empty() {
+        body.push(' ');
+    }
+    body.push_str(&format!("{TARGET_FEATURES}{}\"", merged.join(",")));
+    body
+}
+
+/// The name of a feature without the `+` or `-`
+fn feature_name(feature: &str) -> &str {
+    &feature[1..]
+}
+
+fn is_number(text: &str) -> bool {
+    !text.is_empty() && text.bytes().all(|byte| byte.is_ascii_digit())
+}
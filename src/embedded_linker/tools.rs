@@ -0,0 +1,530 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+/// The tool probed for the version suffix, `opt` if `llvm-link` is not needed
+const PROBED_TOOL: &str = if cfg!(feature = "llvm") {
+    "opt"
+} else {
+    "llvm-link"
+};
+
+/// The environment variable with the directory of exactly the LLVM tools to
+/// run, like `--llvm-tools-path`
+pub const LLVM_TOOLS_ENV: &str = "PTX_LINKER_LLVM_TOOLS";
+
+/// The pinned LLVM version and the tools directory of a [`ToolLocator`]
+type LocatorKey = (Option<u32>, Option<PathBuf>);
+
+/// Finds the LLVM tools of a single major version
+///
+/// The tools are only taken from the tools directory if there is one, e.g. a
+/// custom LLVM build. Otherwise every tool is looked up, in this order:
+/// - in `$LLVM_PATH/bin` and `$LLVM_PATH`, with or without the version suffix
+/// - in the `llvm-tools` rustup component of the `rustc` in `PATH`, which has
+///   exactly its LLVM version, unless the version is pinned
+/// - in `PATH` with the version suffix, e.g. `llvm-link-17`
+/// - in the install prefixes of the version, `/usr/lib/llvm-17/bin` of Debian
+///   and Ubuntu and `llvm@17` of Homebrew
+/// - in `PATH` without the version suffix
+/// - in the install prefixes of the default version, `llvm` of Homebrew and
+///   `%ProgramFiles%\LLVM\bin` on Windows
+///
+/// Locators are `Send` and `Sync`, and the sessions of a process share them,
+/// see [`ToolLocator::get`].
+#[derive(Debug)]
+pub struct ToolLocator {
+    llvm_major: String,
+    pinned: bool,
+    override_dirs: Vec<PathBuf>,
+    path_dirs: Vec<PathBuf>,
+    version_dirs: Vec<PathBuf>,
+    default_dirs: Vec<PathBuf>,
+    rustup_dirs: OnceLock<Vec<PathBuf>>,
+    /// The major LLVM version reported by `opt`
+    opt_major: OnceLock<Option<u32>>,
+}
+
+impl ToolLocator {
+    /// The locator of the tools of `llvm_major`, or of the LLVM version of
+    /// `rustc`, taking them only from `tools_dir` or [`LLVM_TOOLS_ENV`] if
+    /// either is set
+    ///
+    /// The locator is created once per process for every version and tools
+    /// directory and shared by all sessions using them, also concurrently.
+    pub fn get(
+        llvm_major: Option<u32>,
+        tools_dir: Option<&Path>,
+    ) -> anyhow::Result<&'static ToolLocator> {
+        static LOCATORS: Mutex<BTreeMap<LocatorKey, &'static ToolLocator>> =
+            Mutex::new(BTreeMap::new());
+
+        let tools_dir = tools_dir
+            .map(Path::to_owned)
+            .or_else(|| std::env::var_os(LLVM_TOOLS_ENV).map(PathBuf::from));
+        let key = (llvm_major, tools_dir.clone());
+        // Held while locating, so that concurrent sessions probe only once
+        let mut locators = LOCATORS.lock().unwrap();
+        if let Some(locator) = locators.get(&key) {
+            return Ok(locator);
+        }
+
+        let locator = match &tools_dir {
+            Some(tools_dir) => ToolLocator::in_dir(llvm_major, tools_dir.clone())?,
+            None => ToolLocator::new(llvm_major)?,
+        };
+        let Some(probed) = locator.locate(PROBED_TOOL) else {
+            if let Some(tools_dir) = tools_dir {
+                anyhow::bail!("unable to find {PROBED_TOOL} in {}", tools_dir.display());
+            }
+            anyhow::bail!(
+                "unable to find {PROBED_TOOL}-{0} or {PROBED_TOOL} in LLVM_PATH, PATH or the \
+                 install prefixes of LLVM {0}, e.g. install the llvm-tools rustup component \
+                 with `rustup component add llvm-tools`",
+                locator.llvm_major
+            );
+        };
+        tracing::info!(
+            "using LLVM {} tools like {}",
+            locator.llvm_major,
+            probed.display()
+        );
+
+        let locator = Box::leak(Box::new(locator));
+        locators.insert(key, locator);
+        Ok(locator)
+    }
+
+    fn new(llvm_major: Option<u32>) -> anyhow::Result<Self> {
+        let llvm_version = match llvm_major {
+            Some(llvm_major) => {
+                tracing::info!("using pinned LLVM version {llvm_major}");
+                llvm_major.to_string()
+            }
+            None => rustc_llvm_major()?,
+        };
+
+        let env_dir = |name: &str| std::env::var_os(name).map(PathBuf::from);
+        let homebrew_prefixes = env_dir("HOMEBREW_PREFIX")
+            .into_iter()
+            .chain(["/opt/homebrew", "/usr/local"].map(PathBuf::from))
+            .collect::<Vec<_>>();
+        let homebrew_dirs = |formula: &str| {
+            homebrew_prefixes
+                .iter()
+                .map(|prefix| prefix.join("opt").join(formula).join("bin"))
+                .collect::<Vec<_>>()
+        };
+
+        let mut version_dirs = vec![PathBuf::from(format!("/usr/lib/llvm-{llvm_version}/bin"))];
+        version_dirs.extend(homebrew_dirs(&format!("llvm@{llvm_version}")));
+        let mut default_dirs = homebrew_dirs("llvm");
+        default_dirs.extend(env_dir("ProgramFiles").map(|dir| dir.join("LLVM").join("bin")));
+
+        Ok(ToolLocator {
+            pinned: llvm_major.is_some(),
+            override_dirs: env_dir("LLVM_PATH")
+                .map(|dir| vec![dir.join("bin"), dir])
+                .unwrap_or_default(),
+            path_dirs: std::env::var_os("PATH")
+                .map(|paths| std::env::split_paths(&paths).collect())
+                .unwrap_or_default(),
+            version_dirs,
+            default_dirs,
+            rustup_dirs: OnceLock::new(),
+            opt_major: OnceLock::new(),
+            llvm_major: llvm_version,
+        })
+    }
+
+    /// The locator of exactly the tools in `tools_dir`, whose version is
+    /// `llvm_major` or the one reported by the tools
+    fn in_dir(llvm_major: Option<u32>, tools_dir: PathBuf) -> anyhow::Result<Self> {
+        let probed = tools_dir.join(format!("{PROBED_TOOL}{}", std::env::consts::EXE_SUFFIX));
+        let llvm_version = match llvm_major {
+            Some(llvm_major) => llvm_major.to_string(),
+            None => match tool_llvm_major(&probed) {
+                Some(llvm_version) => llvm_version,
+                None => rustc_llvm_major()?,
+            },
+        };
+        tracing::info!(
+            "using the LLVM {llvm_version} tools in {}",
+            tools_dir.display()
+        );
+
+        Ok(ToolLocator {
+            llvm_major: llvm_version,
+            pinned: true,
+            override_dirs: vec![tools_dir],
+            path_dirs: Vec::new(),
+            version_dirs: Vec::new(),
+            default_dirs: Vec::new(),
+            rustup_dirs: OnceLock::new(),
+            opt_major: OnceLock::new(),
+        })
+    }
+
+    /// The major LLVM version of the tools
+    pub fn llvm_major(&self) -> &str {
+        &self.llvm_major
+    }
+
+    /// The major LLVM version of the `opt` that is run, which differs from
+    /// [`Self::llvm_major`] if only an `opt` of another version was found
+    pub fn opt_major(&self) -> Option<u32> {
+        *self
+            .opt_major
+            .get_or_init(|| tool_llvm_major(&self.path("opt"))?.parse().ok())
+    }
+
+    /// The path of the LLVM tool `name`, e.g. `llvm-link`, if it is found
+    pub fn locate(&self, name: &str) -> Option<PathBuf> {
+        let exe = std::env::consts::EXE_SUFFIX;
+        let versioned = format!("{name}-{}{exe}", self.llvm_major);
+        let plain = format!("{name}{exe}");
+        let find = |dirs: &[PathBuf], file_name: &str| {
+            dirs.iter()
+                .map(|dir| dir.join(file_name))
+                .find(|path| path.is_file())
+        };
+
+        find(&self.override_dirs, &versioned)
+            .or_else(|| find(&self.override_dirs, &plain))
+            .or_else(|| find(self.rustup_dirs(), &plain))
+            .or_else(|| find(&self.path_dirs, &versioned))
+            .or_else(|| find(&self.version_dirs, &plain))
+            .or_else(|| find(&self.path_dirs, &plain))
+            .or_else(|| find(&self.default_dirs, &plain))
+    }
+
+    /// The command running the LLVM tool `name`, e.g. `llvm-link`
+    ///
+    /// A tool that is not found is run by its plain name, which fails with
+    /// the error of the operating system.
+    pub fn command(&self, name: &str) -> std::process::Command {
+        std::process::Command::new(self.path(name))
+    }
+
+    /// The path of the LLVM tool `name`, or its plain name if it is not found
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.locate(name).unwrap_or_else(|| PathBuf::from(name))
+    }
+
+    /// The directories of the `llvm-tools` rustup component of the `rustc` in
+    /// `PATH`, e.g. `lib/rustlib/x86_64-unknown-linux-gnu/bin` in its sysroot
+    ///
+    /// Only the host has a `bin` directory, the other targets only have
+    /// libraries.
+    fn rustup_dirs(&self) -> &[PathBuf] {
+        self.rustup_dirs.get_or_init(|| {
+            if self.pinned {
+                return Vec::new();
+            }
+            let Some(sysroot) = rustc_sysroot().or_else(print_sysroot) else {
+                return Vec::new();
+            };
+
+            let Ok(targets) = std::fs::read_dir(sysroot.join("lib").join("rustlib")) else {
+                return Vec::new();
+            };
+            let mut dirs = targets
+                .filter_map(|target| Some(target.ok()?.path().join("bin")))
+                .filter(|dir| dir.is_dir())
+                .collect::<Vec<_>>();
+            dirs.sort();
+            tracing::debug!("llvm-tools rustup component directories: {dirs:?}");
+            dirs
+        })
+    }
+}
+
+/// The major LLVM version reported by `tool --version`, e.g. `17` for
+/// `Debian LLVM version 17.0.6`
+fn tool_llvm_major(tool: &Path) -> Option<String> {
+    let output = std::process::Command::new(tool)
+        .arg("--version")
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    let (_, version) = stdout
+        .lines()
+        .find_map(|line| line.split_once("LLVM version "))?;
+    let (major, _) = version.split_once('.')?;
+    Some(major.to_owned())
+}
+
+/// The major LLVM version `rustc` was built with
+///
+/// Probing requires running `rustc`, so the result is cached on disk for the
+/// toolchain `rustc` resolves to, keyed by its sysroot and the modification
+/// time of its compiler. The toolchain is resolved without running `rustc`,
+/// and toolchains that cannot be resolved are probed every time.
+fn rustc_llvm_major() -> anyhow::Result<String> {
+    let cache = ProbeCache::new();
+
+    if let Some(llvm_version) = cache.as_ref().and_then(ProbeCache::load) {
+        tracing::info!("using cached rustc LLVM version {llvm_version}");
+        return Ok(llvm_version);
+    }
+
+    let version_output = std::process::Command::new("rustc")
+        .arg("--version")
+        .arg("--verbose")
+        .output()?;
+    let version_output = String::from_utf8_lossy(&version_output.stdout);
+
+    let mut llvm_version = None;
+
+    for line in version_output.lines() {
+        if let Some(version) = str::strip_prefix(line, "LLVM version: ") {
+            if let Some((version, _)) = version.split_once('.') {
+                llvm_version = Some(String::from(version));
+                break;
+            }
+        }
+    }
+
+    let Some(llvm_version) = llvm_version else {
+        anyhow::bail!("unable to determine LLVM version from:\n{version_output}");
+    };
+
+    if let Some(cache) = cache {
+        cache.store(&llvm_version);
+    }
+
+    Ok(llvm_version)
+}
+
+/// On-disk cache of the `rustc` LLVM version probe
+///
+/// The file has a line of the sysroot, the modification time of the compiler
+/// and the LLVM major version for every toolchain probed.
+struct ProbeCache {
+    path: PathBuf,
+    sysroot: PathBuf,
+    modified: String,
+}
+
+impl ProbeCache {
+    /// Returns `None` if there is no cache directory or the toolchain of
+    /// `rustc` cannot be resolved without running it
+    fn new() -> Option<Self> {
+        let cache_dir = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+
+        let sysroot = rustc_sysroot()?;
+        let compiler = sysroot
+            .join("bin")
+            .join(format!("rustc{}", std::env::consts::EXE_SUFFIX));
+        let modified = compiler.metadata().and_then(|meta| meta.modified()).ok()?;
+
+        Some(ProbeCache {
+            path: cache_dir.join("rust-ptx-linker").join("rustc-llvm-version"),
+            sysroot,
+            modified: format!("{modified:?}"),
+        })
+    }
+
+    /// The cached entries as the sysroot, the modification time and the LLVM
+    /// major version
+    fn entries(&self) -> Vec<(PathBuf, String, String)> {
+        let content = std::fs::read_to_string(&self.path).unwrap_or_default();
+        content
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split('\t');
+                let entry = (
+                    PathBuf::from(fields.next()?),
+                    fields.next()?.to_owned(),
+                    fields.next()?.to_owned(),
+                );
+                fields.next().is_none().then_some(entry)
+            })
+            .collect()
+    }
+
+    fn load(&self) -> Option<String> {
+        self.entries()
+            .into_iter()
+            .find(|(sysroot, modified, _)| *sysroot == self.sysroot && *modified == self.modified)
+            .map(|(_, _, llvm_version)| llvm_version)
+    }
+
+    /// Stores the version of this toolchain, dropping its outdated entry and
+    /// those of removed toolchains
+    fn store(&self, llvm_version: &str) {
+        let mut content = String::new();
+        for (sysroot, modified, other_version) in self.entries() {
+            if sysroot != self.sysroot && sysroot.is_dir() {
+                content.push_str(&format!(
+                    "{}\t{modified}\t{other_version}\n",
+                    sysroot.display()
+                ));
+            }
+        }
+        content.push_str(&format!(
+            "{}\t{}\t{llvm_version}\n",
+            self.sysroot.display(),
+            self.modified
+        ));
+
+        let result = self
+            .path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| std::fs::write(&self.path, content));
+        if let Err(err) = result {
+            tracing::warn!(
+                "failed to cache rustc LLVM version in {}: {err}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// The sysroot of the toolchain the `rustc` in `PATH` resolves to, found
+/// without running it
+///
+/// A rustup proxy runs the toolchain of `RUSTUP_TOOLCHAIN`, a directory
+/// override, the closest `rust-toolchain` file or the default toolchain, which
+/// is looked up in the rustup settings and toolchains. Any other `rustc` is the
+/// compiler of its sysroot.
+fn rustc_sysroot() -> Option<PathBuf> {
+    let rustc = std::env::var_os("PATH").and_then(|paths| {
+        std::env::split_paths(&paths)
+            .map(|dir| dir.join(format!("rustc{}", std::env::consts::EXE_SUFFIX)))
+            .find(|rustc| rustc.is_file())
+    })?;
+
+    let rustup = rustc.with_file_name(format!("rustup{}", std::env::consts::EXE_SUFFIX));
+    if !rustup.is_file() {
+        return Some(rustc.canonicalize().ok()?.parent()?.parent()?.to_owned());
+    }
+
+    let rustup_home = std::env::var_os("RUSTUP_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".rustup")))?;
+    let toolchain = rustup_toolchain(&rustup_home)?;
+    tracing::debug!("rustup toolchain: {toolchain}");
+    rustup_toolchain_dir(&rustup_home, &toolchain)
+}
+
+/// The sysroot printed by the `rustc` in `PATH`, for toolchains
+/// [`rustc_sysroot`] cannot resolve
+fn print_sysroot() -> Option<PathBuf> {
+    let output = std::process::Command::new("rustc")
+        .args(["--print", "sysroot"])
+        .output()
+        .ok()?;
+    let sysroot = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    (output.status.success() && !sysroot.is_empty()).then(|| PathBuf::from(sysroot))
+}
+
+/// The toolchain a rustup proxy runs in the current directory, as a name like
+/// `stable` or the path of a custom toolchain
+fn rustup_toolchain(rustup_home: &Path) -> Option<String> {
+    if let Some(toolchain) = std::env::var_os("RUSTUP_TOOLCHAIN") {
+        return toolchain.into_string().ok();
+    }
+
+    let settings = std::fs::read_to_string(rustup_home.join("settings.toml"))
+        .ok()
+        .and_then(|content| content.parse::<toml::Table>().ok())
+        .unwrap_or_default();
+    let overrides = settings.get("overrides").and_then(toml::Value::as_table);
+
+    let cwd = std::env::current_dir().ok()?.canonicalize().ok()?;
+    cwd.ancestors()
+        .find_map(|dir| {
+            overrides
+                .and_then(|overrides| overrides.get(dir.to_str()?)?.as_str())
+                .map(str::to_owned)
+                .or_else(|| toolchain_file(dir))
+        })
+        .or_else(|| Some(settings.get("default_toolchain")?.as_str()?.to_owned()))
+}
+
+/// The toolchain of the `rust-toolchain` or `rust-toolchain.toml` file in
+/// `dir`, either a TOML file or the legacy single line with the name
+fn toolchain_file(dir: &Path) -> Option<String> {
+    let content = ["rust-toolchain", "rust-toolchain.toml"]
+        .into_iter()
+        .find_map(|name| std::fs::read_to_string(dir.join(name)).ok())?;
+
+    let Ok(file) = content.parse::<toml::Table>() else {
+        let name = content.trim();
+        return (!name.is_empty() && !name.contains(char::is_whitespace))
+            .then(|| name.to_owned());
+    };
+    let toolchain = file.get("toolchain")?;
+    if let Some(path) = toolchain.get("path").and_then(toml::Value::as_str) {
+        return Some(dir.join(path).to_string_lossy().into_owned());
+    }
+    Some(toolchain.get("channel")?.as_str()?.to_owned())
+}
+
+/// The directory rustup installed `toolchain` in
+///
+/// Names without the host, e.g. `stable`, are installed with it, as in
+/// `stable-x86_64-unknown-linux-gnu`. If several hosts match, the toolchain
+/// is not resolved.
+fn rustup_toolchain_dir(rustup_home: &Path, toolchain: &str) -> Option<PathBuf> {
+    let path = Path::new(toolchain);
+    if path.is_absolute() {
+        return path.is_dir().then(|| path.to_owned());
+    }
+
+    let toolchains = rustup_home.join("toolchains");
+    let exact = toolchains.join(toolchain);
+    if exact.is_dir() {
+        return Some(exact);
+    }
+
+    // Host triples start with the architecture, which tells `nightly-x86_64-…`
+    // from dated toolchains like `nightly-2024-01-01-x86_64-…`
+    let prefix = format!("{toolchain}-");
+    let mut matches = std::fs::read_dir(toolchains).ok()?.filter_map(|entry| {
+        let entry = entry.ok()?;
+        let name = entry.file_name().into_string().ok()?;
+        let host = name.strip_prefix(&prefix)?;
+        host.starts_with(|c: char| c.is_ascii_alphabetic())
+            .then(|| entry.path())
+    });
+    let dir = matches.next()?;
+    matches.next().is_none().then_some(dir)
+}
+
+/// The libdevice of the CUDA toolkit in `$CUDA_HOME` or `$CUDA_PATH`, if
+/// either is set and has one
+pub fn cuda_libdevice() -> Option<PathBuf> {
+    ["CUDA_HOME", "CUDA_PATH"]
+        .into_iter()
+        .filter_map(std::env::var_os)
+        .map(|cuda_home| {
+            PathBuf::from(cuda_home)
+                .join("nvvm")
+                .join("libdevice")
+                .join("libdevice.10.bc")
+        })
+        .find(|path| path.is_file())
+}
+
+/// The path of a CUDA toolkit tool like `ptxas` or `nvlink`
+///
+/// Tools are taken from `$CUDA_HOME/bin` or `$CUDA_PATH/bin` if either is set
+/// and from `PATH` otherwise.
+pub fn cuda_tool(name: &str) -> PathBuf {
+    std::env::var_os("CUDA_HOME")
+        .or_else(|| std::env::var_os("CUDA_PATH"))
+        .map_or_else(
+            || PathBuf::from(name),
+            |cuda_home| {
+                PathBuf::from(cuda_home)
+                    .join("bin")
+                    .join(format!("{name}{}", std::env::consts::EXE_SUFFIX))
+            },
+        )
+}
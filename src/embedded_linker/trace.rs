@@ -0,0 +1,33 @@
+use std::fmt::Display;
+
+use super::symbol::Symbol;
+
+/// Symbols for which every linking decision is logged, like `ld --trace-symbol`
+#[derive(Debug, Default)]
+pub struct Symbols {
+    /// Glob patterns where `*` matches any sequence and `?` any single byte
+    patterns: Vec<String>,
+}
+
+impl Symbols {
+    pub fn new(patterns: Vec<String>) -> Self {
+        Symbols { patterns }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.patterns.is_empty()
+    }
+
+    pub fn matches(&self, symbol: &Symbol) -> bool {
+        self.patterns
+            .iter()
+            .any(|pattern| symbol.matches_glob(pattern))
+    }
+
+    /// Logs `event` if `symbol` is traced
+    pub fn log(&self, symbol: &Symbol, event: impl Display) {
+        if self.matches(symbol) {
+            tracing::info!("trace-symbol {}: {event}", symbol.demangled());
+        }
+    }
+}
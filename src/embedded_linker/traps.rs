@@ -0,0 +1,88 @@
+//! The panic machinery of `core` replaced with a trap
+//!
+//! Formatting the message of a panic pulls in most of `core::fmt`, which is
+//! large and partly unsupported on nvptx64. With the panic functions trapping
+//! right away, nothing references the formatting code and the optimizer
+//! removes it.
+
+use super::globals::global_name;
+
+/// The paths of the functions that panic, which are replaced as a whole
+const PANIC_PATHS: &[&str] = &[
+    "core::panicking::",
+    "core::result::unwrap_failed",
+    "core::option::expect_failed",
+    "core::option::unwrap_failed",
+    "core::slice::index::slice_start_index_len_fail",
+    "core::slice::index::slice_end_index_len_fail",
+    "core::slice::index::slice_index_order_fail",
+    "core::str::slice_error_fail",
+    "std::panicking::begin_panic",
+    "std::panicking::rust_panic",
+    "rust_begin_unwind",
+];
+
+/// The panic functions of a module replaced with a trap
+#[derive(Debug, Default)]
+pub struct Trapped {
+    pub ir: String,
+    /// The names of the replaced functions
+    pub functions: Vec<String>,
+}
+
+/// Replaces the bodies of the panic functions defined in the textual IR of a
+/// module with a call to `llvm.trap`
+///
+/// Their callers are left alone, so panics still end the kernel where they
+/// happen, just without formatting a message.
+pub fn replace(ir: &str) -> Trapped {
+    let mut trapped = Trapped {
+        ir: String::with_capacity(ir.len()),
+        ..Trapped::default()
+    };
+    let mut in_replaced = false;
+
+    for line in ir.split_inclusive('\n') {
+        if in_replaced {
+            if line.trim_end() == "}" {
+                trapped.ir.push_str(line);
+                in_replaced = false;
+            }
+            continue;
+        }
+
+        trapped.ir.push_str(line);
+        let Some(name) = panic_definition(line) else {
+            continue;
+        };
+        trapped
+            .ir
+            .push_str("start:\n  call void @llvm.trap()\n  unreachable\n");
+        trapped.functions.push(name.to_owned());
+        in_replaced = true;
+    }
+
+    let declared = ir
+        .lines()
+        .any(|line| line.starts_with("declare ") && line.contains("@llvm.trap("));
+    if !trapped.functions.is_empty() && !declared {
+        trapped.ir.push_str("\ndeclare void @llvm.trap()\n");
+    }
+
+    trapped
+}
+
+/// The name of the panic function whose body starts on `line`, if it defines
+/// one
+fn panic_definition(line: &str) -> Option<&str> {
+    if !line.starts_with("define ") || !line.trim_end().ends_with('{') {
+        return None;
+    }
+    let (name, _) = global_name(&line[line.find('@')? + 1..])?;
+    let path = rustc_demangle::try_demangle(name)
+        .map_or_else(|_| name.to_owned(), |demangled| format!("{demangled:#}"));
+    PANIC_PATHS
+        .iter()
+        .any(|prefix| path.starts_with(prefix))
+        .then_some(name)
+}
@@ -0,0 +1,69 @@
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+
+use super::asserts::ASSERT_FAIL;
+use super::math::BUILTIN_FUNCTIONS;
+use super::symbol::Symbol;
+
+/// The functions that the CUDA driver provides to every module
+const RUNTIME: [&str; 4] = ["vprintf", "malloc", "free", ASSERT_FAIL];
+
+/// Whether the undefined `symbol` is resolved without any input, by the
+/// driver or by `llc` itself
+pub fn is_provided(symbol: &Symbol) -> bool {
+    RUNTIME.iter().any(|name| symbol.as_bytes() == name.as_bytes())
+        || symbol.starts_with("llvm.")
+        // Folded by the `NVVMReflect` pass of `llc`
+        || symbol.as_bytes() == b"__nvvm_reflect"
+}
+
+/// A symbol used by the optimized module that no input defines
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub symbol: Symbol,
+    /// The inputs declaring the symbol
+    pub inputs: Vec<PathBuf>,
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, thiserror::Error)]
+/// The optimized module uses symbols that no input defines, which `ptxas`
+/// would fail on
+#[error("undefined references in the linked module:{}", List(.0))]
+pub struct UndefinedReferences(pub Vec<Reference>);
+
+/// The references listed one per line
+struct List<'a>(&'a [Reference]);
+
+impl Display for List<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for reference in self.0 {
+            let demangled = reference.symbol.demangled();
+            write!(f, "\n  {demangled}")?;
+            if demangled != reference.symbol.to_string_lossy() {
+                write!(f, " ({})", reference.symbol)?;
+            }
+            if !reference.inputs.is_empty() {
+                let inputs = reference
+                    .inputs
+                    .iter()
+                    .map(|input| input.display().to_string())
+                    .collect::<Vec<_>>();
+                write!(f, ", referenced by {}", inputs.join(", "))?;
+            }
+        }
+        let builtin = self.0.iter().any(|reference| {
+            BUILTIN_FUNCTIONS
+                .iter()
+                .any(|name| reference.symbol.as_bytes() == name.as_bytes())
+        });
+        if builtin {
+            write!(
+                f,
+                "\nhelp: link the math functions built into the linker ({}) with --math builtin",
+                BUILTIN_FUNCTIONS.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
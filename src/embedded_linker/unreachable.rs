@@ -0,0 +1,73 @@
+use std::fmt::{Display, Formatter};
+
+/// The prefix of the blocks spinning in place of `unreachable`
+const LOOP_LABEL: &str = "ptx_linker.unreachable.";
+
+/// How `unreachable` terminators are lowered, which `llc` otherwise drops so
+/// that execution runs off the end of the block
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, clap::ValueEnum)]
+pub enum Unreachable {
+    /// Abort the kernel with `trap`
+    Trap,
+    /// Stop at a `brkpt` a debugger can attach to
+    Brkpt,
+    /// Spin in place, the cheapest encoding
+    Loop,
+}
+
+impl Display for Unreachable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Unreachable::Trap => "trap",
+            Unreachable::Brkpt => "brkpt",
+            Unreachable::Loop => "loop",
+        })
+    }
+}
+
+/// Lowers every `unreachable` terminator in the textual IR of a module
+///
+/// Returns the new IR and the number of lowered terminators.
+pub fn lower(ir: &str, mode: Unreachable) -> (String, usize) {
+    let mut lowered = String::with_capacity(ir.len());
+    let mut count = 0;
+
+    for line in ir.split_inclusive('\n') {
+        // Keep attached metadata, e.g. `, !dbg !12`
+        let Some(metadata) = line
+            .trim()
+            .strip_prefix("unreachable")
+            .filter(|rest| rest.is_empty() || rest.starts_with(','))
+        else {
+            lowered.push_str(line);
+            continue;
+        };
+
+        match mode {
+            Unreachable::Trap => {
+                lowered.push_str(&format!(
+                    "  call void @llvm.trap(){metadata}\n  unreachable\n"
+                ));
+            }
+            Unreachable::Brkpt => lowered.push_str(&format!(
+                "  call void asm sideeffect \"brkpt;\", \"\"(){metadata}\n  unreachable\n"
+            )),
+            Unreachable::Loop => {
+                let label = format!("{LOOP_LABEL}{count}");
+                lowered.push_str(&format!(
+                    "  br label %\"{label}\"{metadata}\n\"{label}\":\n  br label %\"{label}\"\n"
+                ));
+            }
+        }
+        count += 1;
+    }
+
+    let declared = ir
+        .lines()
+        .any(|line| line.starts_with("declare ") && line.contains("@llvm.trap("));
+    if mode == Unreachable::Trap && count > 0 && !declared {
+        lowered.push_str("\ndeclare void @llvm.trap()\n");
+    }
+
+    (lowered, count)
+}
@@ -0,0 +1,177 @@
+//! Exception handling leaking into device code
+//!
+//! Functions of `panic=unwind` builds call others with `invoke`, clean up in
+//! landing pads and name a personality function, none of which the nvptx
+//! backend can lower. The device cannot unwind, so the invokes become calls
+//! and the landing pads dead code.
+
+use std::collections::BTreeSet;
+
+use super::globals::{global_name, references_in};
+
+/// The exception handling removed from a module
+#[derive(Debug, Default)]
+pub struct Pruned {
+    pub ir: String,
+    pub invokes: usize,
+    pub landing_pads: usize,
+    /// The functions that had a personality function
+    pub functions: usize,
+}
+
+impl Pruned {
+    pub fn is_empty(&self) -> bool {
+        self.invokes == 0 && self.landing_pads == 0 && self.functions == 0
+    }
+}
+
+/// Removes the exception handling from the textual IR of a module
+///
+/// Every `invoke` is replaced with a `call` and a branch to its normal
+/// destination, every `landingpad` with a placeholder value of its type and
+/// every `resume` with `unreachable`. The declarations of the personality
+/// functions are dropped once they are no longer referenced.
+pub fn prune(ir: &str) -> Pruned {
+    let mut pruned = Pruned {
+        ir: String::with_capacity(ir.len()),
+        ..Pruned::default()
+    };
+    let mut personalities = BTreeSet::new();
+    let mut lines = ir.split_inclusive('\n').peekable();
+
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim();
+        let indent = &line[..line.len() - line.trim_start().len()];
+
+        if line.starts_with("define ") {
+            if let Some((define, personality)) = strip_personality(line) {
+                personalities.insert(personality);
+                pruned.ir.push_str(&define);
+                pruned.functions += 1;
+                continue;
+            }
+        } else if let Some(offset) = opcode_offset(trimmed, "invoke ") {
+            // The destinations are usually printed on the next line
+            let split = trimmed.split_once(" to label ").or_else(|| {
+                let next = lines.peek().copied()?;
+                Some((trimmed, next.trim().strip_prefix("to label ")?))
+            });
+            let Some((invoke, (normal, unwind))) = split
+                .and_then(|(invoke, edges)| Some((invoke, edges.split_once(" unwind label ")?)))
+            else {
+                pruned.ir.push_str(line);
+                continue;
+            };
+            if invoke.len() == trimmed.len() {
+                lines.next();
+            }
+            // Keep attached metadata, e.g. `, !dbg !12`
+            let metadata = unwind.find(',').map_or("", |index| &unwind[index..]);
+
+            pruned.ir.push_str(&format!(
+                "{indent}{}call {}{metadata}\n{indent}br label {normal}\n",
+                &invoke[..offset],
+                &invoke[offset + "invoke ".len()..],
+            ));
+            pruned.invokes += 1;
+            continue;
+        } else if let Some(offset) = opcode_offset(trimmed, "landingpad ") {
+            while lines.peek().is_some_and(|next| is_clause(next.trim())) {
+                lines.next();
+            }
+            // The value of the landing pad only flows into dead code, but it
+            // still needs a definition
+            if offset > 0 {
+                let ty = &trimmed[offset + "landingpad ".len()..];
+                let ty = match ty.find('}') {
+                    Some(end) if ty.starts_with('{') => &ty[..=end],
+                    _ => ty.strip_suffix(" cleanup").unwrap_or(ty),
+                };
+                pruned.ir.push_str(&format!(
+                    "{indent}{}freeze {ty} undef\n",
+                    &trimmed[..offset]
+                ));
+            }
+            pruned.landing_pads += 1;
+            continue;
+        } else if opcode_offset(trimmed, "resume ") == Some(0) {
+            pruned.ir.push_str(&format!("{indent}unreachable\n"));
+            continue;
+        }
+
+        pruned.ir.push_str(line);
+    }
+
+    if !personalities.is_empty() {
+        pruned.ir = drop_unused_declarations(&pruned.ir, &personalities);
+    }
+
+    pruned
+}
+
+/// The offset of `opcode` in `instruction`, which is either unnamed or
+/// assigned to a local value, e.g. `%5 = invoke ...`
+fn opcode_offset(instruction: &str, opcode: &str) -> Option<usize> {
+    if instruction.starts_with(opcode) {
+        return Some(0);
+    }
+    if !instruction.starts_with('%') {
+        return None;
+    }
+    let assignment = format!(" = {opcode}");
+    instruction
+        .find(&assignment)
+        .map(|index| index + assignment.len() - opcode.len())
+}
+
+/// Whether `line` continues a `landingpad` with one of its clauses
+fn is_clause(line: &str) -> bool {
+    line == "cleanup" || line.starts_with("catch ") || line.starts_with("filter ")
+}
+
+/// The `define` line without its `personality` and the name of the
+/// personality function, e.g. `rust_eh_personality`
+///
+/// The personality may be a constant expression like
+/// `i8* bitcast (i32 (...)* @__gxx_personality_v0 to i8*)`.
+fn strip_personality(line: &str) -> Option<(String, String)> {
+    const PERSONALITY: &str = " personality ";
+    let start = line.find(PERSONALITY)?;
+    let operand = &line[start + PERSONALITY.len()..];
+    let at = operand.find('@')?;
+    let (name, rest) = global_name(&operand[at + 1..])?;
+
+    let mut end = operand.len() - rest.len();
+    let open = operand[..end].matches('(').count();
+    let closed = operand[..end].matches(')').count();
+    for _ in closed..open {
+        end += operand[end..].find(')')? + 1;
+    }
+
+    Some((
+        format!("{}{}", &line[..start], &operand[end..]),
+        name.to_owned(),
+    ))
+}
+
+/// Drops the declarations of the `functions` that are not referenced anymore
+fn drop_unused_declarations(ir: &str, functions: &BTreeSet<String>) -> String {
+    let referenced = ir
+        .lines()
+        .filter(|line| declared(line, functions).is_none())
+        .flat_map(references_in)
+        .filter(|name| functions.contains(*name))
+        .collect::<BTreeSet<_>>();
+
+    ir.split_inclusive('\n')
+        .filter(|line| declared(line, functions).map_or(true, |name| referenced.contains(name)))
+        .collect()
+}
+
+/// The one of the `functions` declared on `line`, if it is a declaration
+fn declared<'a>(line: &'a str, functions: &BTreeSet<String>) -> Option<&'a str> {
+    if !line.starts_with("declare ") {
+        return None;
+    }
+    references_in(line).find(|name| functions.contains(*name))
+}
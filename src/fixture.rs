@@ -0,0 +1,85 @@
+//! Test fixtures for the integration tests in `tests/` and for tools wrapping
+//! the linker
+//!
+//! `rust-ptx-linker gen-fixture` compiles a tiny vendored kernel crate into an
+//! rlib and a bitcode module with the `rustc` at hand, so that tests neither
+//! need network access nor a nightly toolchain. The kernel uses the unstable
+//! `ptx-kernel` ABI, which is enabled with `RUSTC_BOOTSTRAP`, but still
+//! requires the standard library of the target, e.g. from
+//! `rustup target add nvptx64-nvidia-cuda`.
+
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::Parser;
+use ptx_linker::Target;
+
+/// The hidden subcommand generating the fixtures
+pub const SUBCOMMAND: &str = "gen-fixture";
+
+/// The name of the vendored kernel crate
+const CRATE_NAME: &str = "ptx_linker_fixture";
+
+const KERNEL_SOURCE: &str = include_str!("fixture/kernel.rs");
+
+#[derive(Debug, Parser)]
+#[command(bin_name = "rust-ptx-linker gen-fixture")]
+/// Generate rlib and bitcode fixtures from a vendored kernel crate
+pub struct Options {
+    /// The directory the fixtures are written to
+    #[arg(long, default_value = ".")]
+    out_dir: PathBuf,
+
+    /// The rustc used to compile the kernel crate
+    #[arg(long, default_value = "rustc")]
+    rustc: PathBuf,
+
+    /// Target triple for which the kernel crate is compiled
+    #[arg(long, default_value = "nvptx64-nvidia-cuda")]
+    target: Target,
+}
+
+/// Writes the kernel crate source, its rlib and its bitcode to the output
+/// directory
+pub fn run(options: &Options) -> anyhow::Result<()> {
+    std::fs::create_dir_all(&options.out_dir).context(format!(
+        "Failed to create the fixture directory: {}",
+        options.out_dir.display()
+    ))?;
+
+    let source_path = options.out_dir.join(format!("{CRATE_NAME}.rs"));
+    let rlib_path = options.out_dir.join(format!("lib{CRATE_NAME}.rlib"));
+    let bitcode_path = options.out_dir.join(format!("{CRATE_NAME}.bc"));
+    std::fs::write(&source_path, KERNEL_SOURCE).context(format!(
+        "Failed to write the kernel crate: {}",
+        source_path.display()
+    ))?;
+
+    let mut emit = std::ffi::OsString::from("--emit=link=");
+    emit.push(&rlib_path);
+    emit.push(",llvm-bc=");
+    emit.push(&bitcode_path);
+
+    let status = std::process::Command::new(&options.rustc)
+        .env("RUSTC_BOOTSTRAP", "1")
+        .args(["--edition", "2021", "--crate-type", "rlib"])
+        .args(["--crate-name", CRATE_NAME])
+        .args(["--target", options.target.triple()])
+        .args(["-C", "opt-level=3", "-C", "linker-plugin-lto"])
+        .arg(emit)
+        .arg(&source_path)
+        .status()
+        .context(format!("Failed to run {}", options.rustc.display()))?;
+
+    if !status.success() {
+        anyhow::bail!(
+            "{} failed to compile the kernel crate for {}, is its standard library installed?",
+            options.rustc.display(),
+            options.target.triple()
+        );
+    }
+
+    println!("{}", rlib_path.display());
+    println!("{}", bitcode_path.display());
+    Ok(())
+}
@@ -0,0 +1,35 @@
+//! A tiny kernel crate compiled into test fixtures by
+//! `rust-ptx-linker gen-fixture`
+
+#![no_std]
+#![feature(abi_ptx)]
+
+/// Incremented by every launch of `add`, to keep a global in the output
+#[no_mangle]
+pub static mut LAUNCHES: u32 = 0;
+
+/// Adds `a` and `b` elementwise into `c`
+///
+/// # Safety
+///
+/// All pointers must be valid for `n` elements.
+#[no_mangle]
+pub unsafe extern "ptx-kernel" fn add(a: *const f32, b: *const f32, c: *mut f32, n: usize) {
+    LAUNCHES += 1;
+    for i in 0..n {
+        *c.add(i) = sum(*a.add(i), *b.add(i));
+    }
+}
+
+/// A device function that is inlined into the kernel
+#[inline(never)]
+pub fn sum(a: f32, b: f32) -> f32 {
+    a + b
+}
+
+/// A public function that is never called, removed unless the rlib is linked
+/// whole
+#[no_mangle]
+pub extern "C" fn unused(x: u32) -> u32 {
+    x.wrapping_mul(3)
+}
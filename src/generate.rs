@@ -0,0 +1,56 @@
+//! `rust-ptx-linker --generate` writes shell completions and a man page
+//!
+//! Both are generated from the parsers of the flat arguments and of the
+//! subcommands, so they always list the options of this build.
+
+use clap::{CommandFactory, Parser};
+use clap_complete::Shell;
+
+use crate::{cache, doctor, inspect, Args, LINK_SUBCOMMAND};
+
+/// The flag generating the completions or the man page
+pub const FLAG: &str = "--generate";
+
+#[derive(Debug, Parser)]
+#[command(bin_name = "rust-ptx-linker --generate")]
+/// Write shell completions or a man page to stdout
+pub struct Options {
+    #[command(subcommand)]
+    what: What,
+}
+
+#[derive(Debug, Clone, Copy, clap::Subcommand)]
+enum What {
+    /// The completions for SHELL
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// The man page in roff
+    Man,
+}
+
+/// Writes the completions or the man page to stdout
+pub fn run(options: &Options) -> anyhow::Result<()> {
+    let mut command = command();
+    let mut stdout = std::io::stdout().lock();
+    match options.what {
+        What::Completions { shell } => {
+            clap_complete::generate(shell, &mut command, env!("CARGO_PKG_NAME"), &mut stdout);
+        }
+        What::Man => clap_mangen::Man::new(command).render(&mut stdout)?,
+    }
+    Ok(())
+}
+
+/// The command line of the linker, the flat arguments of `link` with the
+/// subcommands
+fn command() -> clap::Command {
+    Args::command()
+        .name(env!("CARGO_PKG_NAME"))
+        .bin_name(env!("CARGO_PKG_NAME"))
+        .subcommand(Args::command().name(LINK_SUBCOMMAND))
+        .subcommand(inspect::Options::command().name(inspect::SUBCOMMAND))
+        .subcommand(doctor::Options::command().name(doctor::SUBCOMMAND))
+        .subcommand(cache::Options::command().name(cache::SUBCOMMAND))
+}
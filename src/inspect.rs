@@ -0,0 +1,48 @@
+//! `rust-ptx-linker inspect` lists the contents of link inputs and outputs
+//!
+//! It identifies rlibs, archives, bitcode, PTX, cubins, fatbins and bundles
+//! from their content and prints their kernels, symbols, architectures and
+//! sizes, without going through `llvm-nm` or `cuobjdump`.
+
+use std::path::PathBuf;
+
+use clap::Parser;
+use ptx_linker::{Inspection, Symbol};
+
+/// The subcommand inspecting artifacts
+pub const SUBCOMMAND: &str = "inspect";
+
+#[derive(Debug, Parser)]
+#[command(bin_name = "rust-ptx-linker inspect")]
+/// Print the kind, kernels, symbols, architectures and sizes of artifacts
+pub struct Options {
+    /// The artifacts to inspect
+    #[arg(value_name = "FILE", required = true)]
+    files: Vec<PathBuf>,
+
+    /// The major LLVM version of the tools disassembling bitcode instead of
+    /// the one of rustc
+    #[arg(long)]
+    llvm_major: Option<u32>,
+
+    /// The directory of the LLVM tools disassembling bitcode instead of
+    /// looking them up
+    #[arg(long, value_name = "DIR")]
+    llvm_tools_path: Option<PathBuf>,
+
+    /// Show Rust symbols mangled
+    #[arg(long)]
+    no_demangle: bool,
+}
+
+/// Prints the inspection of every file
+pub fn run(options: &Options) -> anyhow::Result<()> {
+    Symbol::set_demangle(!options.no_demangle);
+    for file in &options.files {
+        print!(
+            "{}",
+            Inspection::new(file, options.llvm_major, options.llvm_tools_path.as_deref(),)?
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,119 @@
+//! The metadata of the kernels of the final module, for host-side launch code
+//!
+//! The metadata is written as JSON with `--kernel-metadata` after the code
+//! generation, and can be read with [`KernelMetadata::from_slice`], e.g. by
+//! generators of launch code validating the layout of the arguments.
+//!
+//! The schema is versioned with [`SCHEMA_VERSION`] and evolves like the one
+//! of the [output manifest](crate::manifest).
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// The kernel metadata schema version written by this linker
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The kernels of a link
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct KernelMetadata {
+    pub schema_version: u32,
+    /// The version of the linker that wrote the metadata
+    pub linker_version: String,
+    /// The target triple, e.g. `nvptx64-nvidia-cuda`
+    pub target: String,
+    #[serde(default)]
+    pub kernels: Vec<Kernel>,
+}
+
+/// A kernel defined in the PTX
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Kernel {
+    /// The mangled name the kernel is launched by
+    pub name: String,
+    pub demangled: String,
+    #[serde(default)]
+    pub params: Vec<Param>,
+    #[serde(default)]
+    pub launch_bounds: LaunchBounds,
+    /// The PTX ISA version of the PTX of the kernel by target cpu, which the
+    /// driver has to support to load it, e.g. `{"sm_80": "7.0"}`
+    #[serde(default)]
+    pub ptx_versions: BTreeMap<String, String>,
+}
+
+/// A parameter of a kernel
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Param {
+    pub name: String,
+    /// The PTX type of the parameter or of its elements, e.g. `.u64`, or
+    /// `.b8` for structs passed by value
+    #[serde(rename = "type")]
+    pub ty: String,
+    /// The size in bytes
+    pub size: u32,
+    /// The alignment in bytes
+    pub align: u32,
+}
+
+/// The launch bounds of a kernel, all optional
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LaunchBounds {
+    /// The maximum number of threads per block in x, y and z
+    #[serde(default)]
+    pub max_threads: Option<[u32; 3]>,
+    /// The exact number of threads per block in x, y and z
+    #[serde(default)]
+    pub required_threads: Option<[u32; 3]>,
+    /// The minimum number of resident blocks per multiprocessor
+    #[serde(default)]
+    pub min_blocks: Option<u32>,
+    /// The maximum number of registers per thread
+    #[serde(default)]
+    pub max_registers: Option<u32>,
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, thiserror::Error)]
+/// Kernel metadata could not be read
+pub enum KernelMetadataError {
+    #[error("malformed kernel metadata: {0}")]
+    Malformed(#[from] serde_json::Error),
+    #[error(
+        "unsupported kernel metadata schema version {0}, at most {SCHEMA_VERSION} is supported"
+    )]
+    UnsupportedVersion(u32),
+}
+
+impl KernelMetadata {
+    /// Metadata without kernels of the current schema version
+    pub fn new(target: &str) -> Self {
+        KernelMetadata {
+            schema_version: SCHEMA_VERSION,
+            linker_version: env!("CARGO_PKG_VERSION").to_owned(),
+            target: target.to_owned(),
+            kernels: Vec::new(),
+        }
+    }
+
+    /// Reads metadata written by this or an older version of the linker
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, KernelMetadataError> {
+        #[derive(Deserialize)]
+        struct Version {
+            schema_version: u32,
+        }
+
+        // Check the version first, a newer schema may not deserialize at all
+        let Version { schema_version } = serde_json::from_slice(bytes)?;
+        if schema_version > SCHEMA_VERSION {
+            return Err(KernelMetadataError::UnsupportedVersion(schema_version));
+        }
+
+        Ok(serde_json::from_slice(bytes)?)
+    }
+
+    /// Serializes the metadata as pretty printed JSON
+    pub fn to_vec(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).expect("kernel metadata is serializable")
+    }
+}
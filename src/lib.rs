@@ -0,0 +1,19 @@
+#![deny(clippy::pedantic)]
+#![allow(
+    clippy::missing_errors_doc,
+    clippy::missing_panics_doc,
+    clippy::must_use_candidate
+)]
+//! Linker for embedded code without any system dependencies
+
+pub mod bundle;
+pub mod embed;
+mod embedded_linker;
+pub mod kernel_metadata;
+#[cfg(feature = "loader")]
+pub mod loader;
+pub mod manifest;
+mod passes;
+
+pub use embedded_linker::*;
+pub use passes::UnsupportedPipeline;
@@ -0,0 +1,116 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use ptx_linker::Emit;
+
+use crate::Args;
+
+/// A declarative description of a link given with `--link-manifest`, e.g.
+/// generated by build tooling
+///
+/// Inputs, kept symbols and emitted outputs are added to those given on the
+/// command line, the output and target cpus are only used if none are given
+/// there. Relative paths are relative to the manifest.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Manifest {
+    output: Option<PathBuf>,
+    #[serde(default)]
+    target_cpus: Vec<String>,
+    /// The outputs to emit as `KIND[=PATH]`
+    #[serde(default)]
+    emit: Vec<String>,
+    /// Symbols kept in addition to the ones of the whole inputs
+    #[serde(default)]
+    keep: Vec<String>,
+    #[serde(default)]
+    inputs: Inputs,
+    /// The directory of the manifest, which relative paths are relative to
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+/// The `[inputs]` of a manifest, as the command line options of the same name
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Inputs {
+    #[serde(default)]
+    bitcode: Vec<PathBuf>,
+    #[serde(default)]
+    rlib: Vec<PathBuf>,
+    #[serde(default)]
+    whole_rlib: Vec<PathBuf>,
+    #[serde(default)]
+    archive: Vec<PathBuf>,
+    #[serde(default)]
+    whole_archive: Vec<PathBuf>,
+    /// Static archives `lib<NAME>.a`, searched for in the `library-dirs`
+    #[serde(default)]
+    library: Vec<String>,
+    #[serde(default)]
+    library_dirs: Vec<PathBuf>,
+    #[serde(default)]
+    device_lib: Vec<PathBuf>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .context(format!("Failed to read link manifest: {}", path.display()))?;
+        let mut manifest: Manifest = toml::from_str(&content)
+            .context(format!("Failed to parse link manifest: {}", path.display()))?;
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        manifest.dir = dir.to_owned();
+        let inputs = &mut manifest.inputs;
+        for path in manifest.output.iter_mut().chain(
+            [
+                &mut inputs.bitcode,
+                &mut inputs.rlib,
+                &mut inputs.whole_rlib,
+                &mut inputs.archive,
+                &mut inputs.whole_archive,
+                &mut inputs.library_dirs,
+                &mut inputs.device_lib,
+            ]
+            .into_iter()
+            .flatten(),
+        ) {
+            *path = dir.join(&*path);
+        }
+
+        tracing::info!("using link manifest: {}", path.display());
+        Ok(manifest)
+    }
+
+    /// Merges the manifest into the command line `args`
+    pub fn apply(self, mut args: Args) -> anyhow::Result<Args> {
+        args.output = args.output.or(self.output);
+        if args.target_cpu.is_empty() {
+            args.target_cpu = self.target_cpus;
+        }
+
+        for emit in self.emit {
+            let mut emit: Emit = emit
+                .parse()
+                .context(format!("Invalid emit kind in link manifest: {emit}"))?;
+            if let Some(path) = &mut emit.path {
+                *path = self.dir.join(&*path);
+            }
+            args.emit.push(emit);
+        }
+        args.keep.extend(self.keep);
+
+        let inputs = self.inputs;
+        args.bitcode.extend(inputs.bitcode);
+        args.rlib.extend(inputs.rlib);
+        args.whole_rlib.extend(inputs.whole_rlib);
+        args.archive.extend(inputs.archive);
+        args.whole_archive.extend(inputs.whole_archive);
+        args.library.extend(inputs.library);
+        args.input_dir.extend(inputs.library_dirs);
+        args.device_lib.extend(inputs.device_lib);
+
+        Ok(args)
+    }
+}
@@ -0,0 +1,235 @@
+//! Helpers for host crates loading the output of this linker
+//!
+//! [`load`] accepts anything the linker emits, i.e. PTX assembly, a cubin or a
+//! [bundle](crate::bundle), and returns its images together with the kernels
+//! they define, so that host crates do not need to parse these formats
+//! themselves. Images embedded into the executable with `--emit object` are
+//! found with [`embedded`]. Outputs signed with `--sign-key` are checked with
+//! [`verify_signature`].
+
+use crate::bundle::{self, Bundle, BundleError, EntryKind};
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+
+/// A single piece of loadable code
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Image {
+    pub kind: EntryKind,
+    /// The architecture the code was compiled for, e.g. `sm_80`
+    pub arch: Option<String>,
+    pub data: Vec<u8>,
+    pub metadata: Metadata,
+}
+
+/// Information about an image that is needed to launch its kernels
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Metadata {
+    /// The names of the kernel entry points
+    pub kernels: Vec<String>,
+    /// The PTX ISA version, e.g. `7.0`
+    pub ptx_version: Option<String>,
+    /// The address size in bits
+    pub address_size: Option<u32>,
+}
+
+#[derive(Debug, thiserror::Error)]
+/// The linker output could not be loaded
+pub enum Error {
+    #[error(transparent)]
+    Bundle(#[from] BundleError),
+    #[error("PTX is not valid UTF-8")]
+    InvalidPtx,
+    #[error("malformed cubin")]
+    InvalidCubin,
+    #[error("invalid Ed25519 public key")]
+    InvalidPublicKey,
+    #[error("signature does not match")]
+    InvalidSignature,
+}
+
+/// Loads all images from PTX assembly, a cubin or a bundle
+pub fn load(bytes: &[u8]) -> Result<Vec<Image>, Error> {
+    if bytes.starts_with(&bundle::MAGIC) {
+        return Bundle::parse(bytes)?
+            .entries
+            .into_iter()
+            .map(|entry| Image::new(entry.kind, Some(entry.arch), entry.data))
+            .collect();
+    }
+
+    let kind = if bytes.starts_with(&ELF_MAGIC) {
+        EntryKind::Cubin
+    } else {
+        EntryKind::Ptx
+    };
+
+    Ok(vec![Image::new(kind, None, bytes.to_vec())?])
+}
+
+/// Verifies the detached signature written with `--sign-key` for the linker
+/// output `bytes`, given the PEM public key of the signing key, e.g. from
+/// `openssl pkey -pubout`
+pub fn verify_signature(bytes: &[u8], signature: &[u8], public_key_pem: &str) -> Result<(), Error> {
+    use ed25519_dalek::pkcs8::DecodePublicKey;
+
+    let key = ed25519_dalek::VerifyingKey::from_public_key_pem(public_key_pem)
+        .map_err(|_| Error::InvalidPublicKey)?;
+    let signature =
+        ed25519_dalek::Signature::from_slice(signature).map_err(|_| Error::InvalidSignature)?;
+    key.verify_strict(bytes, &signature)
+        .map_err(|_| Error::InvalidSignature)
+}
+
+/// Loads the image to use on a GPU of architecture `arch`
+///
+/// See [`Bundle::select`] for how the image is chosen from a bundle. PTX
+/// assembly and cubins are always returned as is.
+pub fn load_for(bytes: &[u8], arch: &str) -> Result<Option<Image>, Error> {
+    if !bytes.starts_with(&bundle::MAGIC) {
+        return Ok(load(bytes)?.pop());
+    }
+
+    Bundle::parse(bytes)?
+        .select(arch)
+        .map(|entry| Image::new(entry.kind, Some(entry.arch.clone()), entry.data.clone()))
+        .transpose()
+}
+
+/// The image named `name` embedded into the running executable by linking in
+/// an object emitted with `--emit object`, see [`crate::embed`]
+///
+/// The image is the PTX or bundle that can be passed to [`load`] or
+/// [`load_for`].
+#[cfg(target_os = "linux")]
+pub fn embedded(name: &str) -> Option<&'static [u8]> {
+    // An empty record, so that the section and its bounds exist even if no
+    // image is linked in
+    #[used]
+    #[link_section = "ptx_linker_images"]
+    static PADDING: [u8; 8] = [0; 8];
+
+    extern "C" {
+        static __start_ptx_linker_images: u8;
+        static __stop_ptx_linker_images: u8;
+    }
+
+    // SAFETY: the linker defines both symbols at the bounds of the section,
+    // which is never written to
+    let section = unsafe {
+        let start = std::ptr::addr_of!(__start_ptx_linker_images);
+        let stop = std::ptr::addr_of!(__stop_ptx_linker_images);
+        std::slice::from_raw_parts(start, stop as usize - start as usize)
+    };
+
+    crate::embed::records(section)
+        .find(|(image, _)| *image == name)
+        .map(|(_, data)| data)
+}
+
+impl Image {
+    fn new(kind: EntryKind, arch: Option<String>, data: Vec<u8>) -> Result<Self, Error> {
+        let (arch, metadata) = match kind {
+            EntryKind::Ptx => {
+                let ptx = std::str::from_utf8(&data).map_err(|_| Error::InvalidPtx)?;
+                let (target, metadata) = ptx_metadata(ptx);
+                (arch.or(target), metadata)
+            }
+            EntryKind::Cubin => (
+                arch,
+                Metadata {
+                    kernels: cubin_kernels(&data).ok_or(Error::InvalidCubin)?,
+                    ptx_version: None,
+                    address_size: Some(64),
+                },
+            ),
+        };
+
+        Ok(Image {
+            kind,
+            arch,
+            data,
+            metadata,
+        })
+    }
+
+    /// The PTX text of the image, if it is PTX
+    pub fn ptx(&self) -> Option<&str> {
+        match self.kind {
+            EntryKind::Ptx => std::str::from_utf8(&self.data).ok(),
+            EntryKind::Cubin => None,
+        }
+    }
+}
+
+/// Parses the `.target` and the metadata from the header and entry points of
+/// PTX assembly
+fn ptx_metadata(ptx: &str) -> (Option<String>, Metadata) {
+    let mut target = None;
+    let mut metadata = Metadata::default();
+
+    for line in ptx.lines() {
+        let mut words = line
+            .split(|c: char| c.is_whitespace() || c == ',' || c == '(')
+            .filter(|word| !word.is_empty());
+
+        match words.next() {
+            Some(".version") => metadata.ptx_version = words.next().map(str::to_owned),
+            Some(".target") => target = words.next().map(str::to_owned),
+            Some(".address_size") => {
+                metadata.address_size = words.next().and_then(|size| size.parse().ok());
+            }
+            Some(".visible" | ".weak") if words.next() == Some(".entry") => {
+                metadata.kernels.extend(words.next().map(str::to_owned));
+            }
+            Some(".entry") => metadata.kernels.extend(words.next().map(str::to_owned)),
+            _ => {}
+        }
+    }
+
+    (target, metadata)
+}
+
+/// Collects the kernels of a 64-bit cubin from the names of its
+/// `.nv.constant0.<kernel>` parameter sections, which only kernels have
+fn cubin_kernels(elf: &[u8]) -> Option<Vec<String>> {
+    let u16_at = |offset: usize| {
+        Some(u16::from_le_bytes(
+            elf.get(offset..offset + 2)?.try_into().ok()?,
+        ))
+    };
+    let u32_at = |offset: usize| {
+        Some(u32::from_le_bytes(
+            elf.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    };
+    let u64_at = |offset: usize| {
+        let value = u64::from_le_bytes(elf.get(offset..offset + 8)?.try_into().ok()?);
+        usize::try_from(value).ok()
+    };
+
+    let section_offset = u64_at(0x28)?;
+    let section_size = usize::from(u16_at(0x3a)?);
+    let section_count = usize::from(u16_at(0x3c)?);
+    let names_index = usize::from(u16_at(0x3e)?);
+
+    let section = |index: usize| {
+        let header = section_offset.checked_add(index.checked_mul(section_size)?)?;
+        let name = usize::try_from(u32_at(header)?).ok()?;
+        let offset = u64_at(header + 0x18)?;
+        let size = u64_at(header + 0x20)?;
+        Some((name, elf.get(offset..offset.checked_add(size)?)?))
+    };
+
+    let (_, names) = section(names_index)?;
+    let mut kernels = Vec::new();
+    for index in 0..section_count {
+        let (name, _) = section(index)?;
+        let name = names.get(name..)?;
+        let name = &name[..name.iter().position(|&b| b == 0)?];
+        if let Some(kernel) = name.strip_prefix(b".nv.constant0.") {
+            kernels.push(String::from_utf8_lossy(kernel).into_owned());
+        }
+    }
+
+    Some(kernels)
+}
@@ -1,16 +1,49 @@
 #![deny(clippy::pedantic)]
 
+use std::ffi::OsString;
+use std::io::Write;
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use clap::Parser;
+use anyhow::Context;
+use clap::{CommandFactory, FromArgMatches, Parser};
 
-mod embedded_linker;
-use embedded_linker::{Optimization, Session, Target};
+mod cache;
+mod config;
+mod doctor;
+mod fixture;
+mod generate;
+mod inspect;
+mod link_manifest;
+mod response_file;
+mod rustc_args;
+mod worker;
+use ptx_linker::{
+    DebugInfo, Deny, DtorPolicy, Emit, FunctionFeatures, Interrupted, KeepSymbolsFormat, Math,
+    MessageFormat, Optimization, Pipeline, PtxVersion, Session, Step, Symbol, Target,
+    TargetFeature, Unreachable,
+};
 
-#[derive(Debug, Parser)]
-#[command(version)]
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, Parser)]
+#[command(
+    version,
+    after_help = "The subcommands `link`, `inspect`, `doctor` and `cache` are run as \
+                  `rust-ptx-linker SUBCOMMAND`, see their --help. Without a subcommand the \
+                  arguments are those of `link`, which also accepts the `cc` and `ld` style \
+                  arguments of rustc's `-Clinker=rust-ptx-linker`. Shell completions and a man \
+                  page are written by `rust-ptx-linker --generate completions SHELL` and \
+                  `rust-ptx-linker --generate man`."
+)]
 /// Linker for embedded code without any system dependencies
 pub struct Args {
+    /// Input files of any kind, detected from their content: bitcode is kept
+    /// like with --bitcode, archives are linked like with --archive and cubins
+    /// and fatbins like with --device-lib
+    #[arg(value_name = "INPUT")]
+    inputs: Vec<PathBuf>,
+
     /// Input LLVM bitcode file
     #[arg(long)]
     bitcode: Vec<PathBuf>,
@@ -23,6 +56,22 @@ pub struct Args {
     #[arg(long)]
     whole_rlib: Vec<PathBuf>,
 
+    /// Input static archives of bitcode, e.g. from `clang --cuda-device-only -emit-llvm`
+    #[arg(long)]
+    archive: Vec<PathBuf>,
+
+    /// Input static archives of bitcode where all global symbols should be kept
+    #[arg(long)]
+    whole_archive: Vec<PathBuf>,
+
+    /// Input static archive lib<NAME>.a, searched for in the input directories
+    #[arg(short = 'l', value_name = "NAME")]
+    library: Vec<String>,
+
+    /// Input cubin or fatbin with relocatable device code, requires --rdc
+    #[arg(long, requires = "rdc")]
+    device_lib: Vec<PathBuf>,
+
     /// Input files directory
     #[arg(short = 'L')]
     input_dir: Vec<PathBuf>,
@@ -31,25 +80,152 @@ pub struct Args {
     #[arg(long, default_value = "nvptx64-nvidia-cuda")]
     target: Target,
 
-    /// The target cpu
-    #[arg(long, alias = "arch")]
-    target_cpu: Option<String>,
+    /// The target cpu, may be given multiple times or as a comma separated
+    /// list to produce one output per cpu [default: the newest target cpu the
+    /// inputs were compiled for]
+    #[arg(long, alias = "arch", value_delimiter = ',')]
+    target_cpu: Vec<String>,
+
+    /// A target feature of the whole module passed to llc, e.g. +ptx80 to
+    /// emit a newer PTX ISA version than the target cpu needs, may be given
+    /// multiple times or as a comma separated list
+    #[arg(long, value_name = "FEATURE", value_delimiter = ',')]
+    target_feature: Vec<TargetFeature>,
+
+    /// The PTX ISA VERSION to emit, e.g. 7.8, the same as --target-feature
+    /// +ptx78 [default: the oldest the target cpu supports]
+    #[arg(long, value_name = "VERSION")]
+    ptx_version: Option<PtxVersion>,
+
+    /// Add target features to those of a single function, e.g.
+    /// `kernel=+ptx80,+sm_90a`, may be given multiple times
+    #[arg(long, value_name = "FUNCTION=FEATURES")]
+    function_target_features: Vec<FunctionFeatures>,
 
-    /// The fallback arch
+    /// The target cpu to compile for instead of those that llc, ptxas or
+    /// nvlink reject, e.g. when the toolchain is older than them, even if
+    /// the warning of llc about them is denied
     #[arg(long)]
     fallback_arch: Option<String>,
 
     /// Write output to the filename
-    #[arg(short, long)]
-    output: PathBuf,
+    #[arg(short, long, required_unless_present = "link_manifest")]
+    output: Option<PathBuf>,
+
+    /// Read inputs, kept symbols and outputs from a TOML link manifest in
+    /// addition to the command line
+    #[arg(long, value_name = "PATH")]
+    link_manifest: Option<PathBuf>,
+
+    /// Keep the global SYMBOL exported even if no whole input defines it, e.g.
+    /// a single device function of an rlib, may be given multiple times
+    #[arg(long, alias = "export-symbol", value_name = "SYMBOL")]
+    keep: Vec<String>,
+
+    /// Keep the symbols listed in FILE, one per line, which may be glob
+    /// patterns matching the symbols defined by any input, e.g. of kernels
+    /// registered dynamically
+    #[arg(long, value_name = "FILE")]
+    export_symbols_file: Vec<PathBuf>,
+
+    /// The outputs to emit as KIND[=PATH], where KIND is asm, bundle, cubin,
+    /// fatbin, object, keep-symbols, metadata, device-rlib, llvm-ir, llvm-bc or
+    /// rust-bindings [default: asm]
+    ///
+    /// The first output without a path is written to the output filename, all
+    /// others next to it with the extension of their kind. A device-rlib is
+    /// the optimized bitcode for later links, llvm-ir and llvm-bc are the
+    /// optimized module for debugging. Emitting only these skips code
+    /// generation. The rust-bindings are a Rust module to `include!` into host
+    /// crates, with the name, launch bounds and parameter struct of every
+    /// kernel.
+    #[arg(long, value_delimiter = ',')]
+    emit: Vec<Emit>,
 
-    // Enable link time optimization
+    /// Keep the named metadata NAME of the inputs, e.g. `my.kernels`, together
+    /// with the globals it references, and emit it with --emit metadata
+    #[arg(long, value_name = "NAME")]
+    gc_keep_exported_metadata: Vec<String>,
+
+    /// The format of the symbols emitted with --emit keep-symbols
+    #[arg(long, value_enum, default_value = "list")]
+    keep_symbols_format: KeepSymbolsFormat,
+
+    /// Write a JSON manifest of all outputs, their hashes and the versions of
+    /// the tools next to the output, e.g. `kernel.ptx.outputs.json`
     #[arg(long)]
+    output_manifest: bool,
+
+    /// Run CMD on every emitted output after a successful link, with the path
+    /// of the output and of a JSON manifest of all outputs as its last
+    /// arguments, failing the link if it fails. The manifest is written for it
+    #[arg(long, value_name = "CMD")]
+    post_process: Vec<String>,
+
+    /// Fail if the versions of the tools differ from those recorded in the
+    /// output manifest MANIFEST of an earlier link with --output-manifest,
+    /// e.g. one checked in
+    #[arg(long, value_name = "MANIFEST")]
+    locked: Option<PathBuf>,
+
+    /// Sign every emitted output with the Ed25519 private key in the PKCS#8 PEM
+    /// file KEY, writing detached signatures next to them with a .sig extension
+    #[arg(long, value_name = "KEY")]
+    sign_key: Option<PathBuf>,
+
+    /// The target triple of emitted host objects [default: the host]
+    #[arg(long, value_name = "TRIPLE")]
+    host_target: Option<String>,
+
+    /// Optimize across the inputs: merge them, optimize the merged module and
+    /// inline all functions into the kernels, which is the default. rustc's
+    /// -Olto implies it
+    #[arg(long, overrides_with = "no_lto")]
     lto: bool,
 
-    /// Emit debug information
+    /// Optimize every input on its own and keep the calls across them, the
+    /// same as --pipeline per-module
+    ///
+    /// This links faster than --lto but the code may run slower. Unused
+    /// symbols are removed either way.
+    #[arg(long, overrides_with = "lto")]
+    no_lto: bool,
+
+    /// Do not remove unreachable functions from non-whole inputs before linking
+    #[arg(long)]
+    no_prune: bool,
+
+    /// Rename the symbols that several inputs define differently, e.g. the
+    /// intrinsics of duplicated compiler_builtins, in all inputs but the first
+    /// instead of failing, and record them in the output manifest
     #[arg(long)]
-    debug: bool,
+    rename_on_conflict: bool,
+
+    /// Emit relocatable device code and link it into a cubin using nvlink
+    #[arg(long)]
+    rdc: bool,
+
+    /// Emit debug information, which is stripped on nvptx64 unless all of it
+    /// is kept with --debug=full or the line tables with --debug=line-tables
+    #[arg(
+        long,
+        value_enum,
+        value_name = "INFO",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "default"
+    )]
+    debug: Option<DebugInfo>,
+
+    /// Keep only the line tables for profilers like Nsight Compute, like
+    /// nvcc's -lineinfo, the same as --debug=line-tables
+    #[arg(long, conflicts_with = "debug")]
+    generate_line_info: bool,
+
+    /// Write the debug information to a separate .debug file next to the PTX,
+    /// which the manifest references
+    #[arg(long, requires = "debug")]
+    split_debug: bool,
 
     /// The optimization level
     #[arg(
@@ -59,28 +235,563 @@ pub struct Args {
         overrides_with = "optimization"
     )]
     optimization: Optimization,
+
+    /// The optimization LEVEL of the code generation with llc, independent of
+    /// the one of the optimizer, where s and z generate code like 2
+    /// [default: the default of llc]
+    #[arg(long, value_enum, value_name = "LEVEL")]
+    codegen_opt_level: Option<Optimization>,
+
+    /// How the inputs are optimized [default: merge, per-module with
+    /// --no-lto]
+    #[arg(long, value_enum)]
+    pipeline: Option<Pipeline>,
+
+    /// Optimize with summary-based ThinLTO, the same as `--pipeline thin`
+    #[arg(long, conflicts_with = "pipeline")]
+    thinlto: bool,
+
+    /// Optimize with the pass PIPELINE of opt, e.g.
+    /// "default<O3>,loop-unroll,slp-vectorizer", instead of the one of the
+    /// optimization level, still internalizing after it
+    #[arg(long, value_name = "PIPELINE")]
+    passes: Option<String>,
+
+    /// Skip an optional STAGE of the pipeline, e.g. to bisect a miscompilation
+    #[arg(long, value_enum, value_name = "STAGE")]
+    skip_stage: Vec<Step>,
+
+    /// Run only STAGE and the merge it needs, writing its output, which is
+    /// bitcode for all stages before codegen. The pruned inputs are written to
+    /// numbered files next to the output if there are several, e.g. `out.0.bc`
+    #[arg(long, value_enum, value_name = "STAGE")]
+    only_stage: Option<Step>,
+
+    /// Fail instead of dropping destructors registered by the device code,
+    /// and instead of warning about host-only code it uses, like
+    /// thread-locals or the threads and files of `std`
+    #[arg(long)]
+    strict: bool,
+
+    /// Call the destructors registered by the device code from a
+    /// __ptx_linker_fini kernel, to be launched before unloading the module
+    #[arg(long, conflicts_with = "strict")]
+    emit_fini_kernel: bool,
+
+    /// Where the math functions called by the device code are defined: builtin
+    /// links the minimal sinf, cosf, expf, logf and powf of the linker,
+    /// libdevice CUDA's libdevice, by default the one in $CUDA_HOME or
+    /// $CUDA_PATH, and none neither [default: libdevice if given or
+    /// configured, otherwise none]
+    #[arg(long, value_enum)]
+    math: Option<Math>,
+
+    /// Link CUDA's libdevice at PATH, so that the device code can call
+    /// __nv_sinf, __nv_exp and the other functions of libdevice [default: the
+    /// libdevice of the configuration]
+    #[arg(long, value_name = "PATH")]
+    libdevice: Option<PathBuf>,
+
+    /// Lower `unreachable` to a `trap`, to a `brkpt` a debugger can attach to
+    /// or to a `loop` [default: dropped by llc]
+    #[arg(long, value_enum, value_name = "MODE")]
+    unreachable: Option<Unreachable>,
+
+    /// Report panics like failed `assert!`s with their message, file and line
+    /// through CUDA's __assertfail instead of trapping
+    #[arg(long)]
+    device_asserts: bool,
+
+    /// Replace the panic functions of core with a trap, removing the panic
+    /// formatting code they pull in
+    #[arg(long)]
+    panic_trap: bool,
+
+    /// Keep the functions called by kernels with at least INSTRUCTIONS IR
+    /// instructions out of line, so that sampling profilers like Nsight
+    /// Compute show them in call stacks, and log them
+    #[arg(
+        long,
+        value_name = "INSTRUCTIONS",
+        num_args = 0..=1,
+        default_missing_value = "100"
+    )]
+    profile_boundaries: Option<usize>,
+
+    /// Keep the functions matching the glob SYMBOL, by mangled name or
+    /// demangled path, out of line, e.g. large cold functions, taking
+    /// precedence over --force-inline
+    #[arg(long, value_name = "SYMBOL")]
+    no_inline: Vec<String>,
+
+    /// Inline the functions matching the glob SYMBOL, by mangled name or
+    /// demangled path, into all their callers, also for targets whose
+    /// functions are not all inlined
+    #[arg(long, value_name = "SYMBOL")]
+    force_inline: Vec<String>,
+
+    /// Coalesce the local constant globals of at most BYTES bytes, e.g. the
+    /// lookup tables of the device code, into a single array per address space
+    #[arg(
+        long,
+        value_name = "BYTES",
+        num_args = 0..=1,
+        default_missing_value = "64"
+    )]
+    coalesce_constants: Option<u64>,
+
+    /// The major LLVM version of the tools to use instead of the one of rustc
+    ///
+    /// The tools are looked up in $LLVM_PATH, in PATH, in the LLVM install
+    /// prefixes and in the llvm-tools rustup component.
+    #[arg(long)]
+    llvm_major: Option<u32>,
+
+    /// The directory of exactly the LLVM tools to run, e.g. of a custom LLVM
+    /// build, instead of looking them up
+    ///
+    /// Defaults to $PTX_LINKER_LLVM_TOOLS. Their LLVM version is the one
+    /// they report unless --llvm-major is given.
+    #[arg(long, value_name = "DIR")]
+    llvm_tools_path: Option<PathBuf>,
+
+    /// The configuration file [default: ptx-linker.toml if it exists]
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Log the number of PTX instructions per function and per crate
+    #[arg(long)]
+    size_report: bool,
+
+    /// Log the mutable globals that the device code writes but never reads,
+    /// reads but never writes, or never accesses
+    #[arg(long)]
+    globals_report: bool,
+
+    /// Log what the optimizer did and failed to do in each kernel, like
+    /// unrolled loops or calls that were not inlined, from the remarks of opt
+    #[arg(long)]
+    remarks_summary: bool,
+
+    /// Log every linking decision about the symbols matching the glob SYMBOL
+    #[arg(long, value_name = "SYMBOL")]
+    trace_symbols: Vec<String>,
+
+    /// Check the structure of the emitted PTX, e.g. duplicate kernels or
+    /// malformed parameter lists, without running ptxas
+    #[arg(long)]
+    verify_ptx_syntax: bool,
+
+    /// Fail the link on warnings of the external tools, either all `warnings`
+    /// or those of a single tool, e.g. `llc-warnings`
+    #[arg(long, value_name = "WARNINGS")]
+    deny: Vec<Deny>,
+
+    /// How diagnostics of the external tools are reported
+    #[arg(long, value_enum, default_value = "human")]
+    message_format: MessageFormat,
+
+    /// Abort when the linker and its tools use more than MB megabytes of memory
+    #[arg(long, value_name = "MB")]
+    max_memory: Option<u64>,
+
+    /// Kill and fail on any external tool that runs longer than SECS seconds
+    #[arg(long, value_name = "SECS")]
+    tool_timeout: Option<u64>,
+
+    /// Log the duration of each stage and the peak memory usage
+    #[arg(long)]
+    timings: bool,
+
+    /// Reuse the linked and optimized modules of an earlier run with the same
+    /// inputs and options, e.g. after fixing a failed code generation
+    #[arg(long)]
+    resume: bool,
+
+    /// Keep the intermediates, like the linked and optimized modules, after a
+    /// successful link instead of removing them, and log their directory
+    ///
+    /// They are written to a directory in the temporary directory named after
+    /// the output, which is kept after a failed link for --resume.
+    #[arg(long)]
+    keep_intermediates: bool,
+
+    /// Show Rust symbols mangled in log output and errors, e.g. for scripts
+    /// matching them
+    #[arg(long)]
+    no_demangle: bool,
+
+    /// Log the output of the external tools as it is written
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// The maximum number of concurrent codegen jobs [default: number of CPUs]
+    #[arg(short, long)]
+    jobs: Option<NonZeroUsize>,
+
+    /// Log the functions of the optimized module that differ from those of the
+    /// bitcode module PREVIOUS, e.g. the optimized bitcode of an earlier build
+    #[arg(long, value_name = "PREVIOUS")]
+    ir_diff: Option<PathBuf>,
+
+    /// Fail if the optimized module exports any symbol not listed in FILE, one
+    /// per line, or misses a listed one, e.g. to keep device functions out of
+    /// the stable kernel API of a release
+    #[arg(long, value_name = "FILE")]
+    public_api: Option<PathBuf>,
+
+    /// Write the mangled and demangled names, the parameter types and sizes,
+    /// the launch bounds and the PTX ISA versions of the kernels as JSON to
+    /// FILE, e.g. for generators of host launch code
+    #[arg(long, value_name = "FILE")]
+    kernel_metadata: Option<PathBuf>,
+
+    /// Print information about the link to stdout, or into the output of the
+    /// work response in persistent worker mode
+    #[arg(long, value_enum)]
+    print: Option<Print>,
 }
 
+/// The information printed with `--print`
+#[derive(Debug, Clone, Copy, Eq, PartialEq, clap::ValueEnum)]
+enum Print {
+    /// A fingerprint of all options that affect the outputs, for cache keys,
+    /// instead of linking
+    Fingerprint,
+    /// The hash of the module after every step of the pipeline, after
+    /// linking, to find the first step that is not deterministic
+    StageHashes,
+}
+
+/// The subcommand linking, which is also run without a subcommand
+const LINK_SUBCOMMAND: &str = "link";
+
 fn main() -> anyhow::Result<()> {
-    tracing_subscriber::FmtSubscriber::builder()
-        .with_max_level(tracing::Level::DEBUG)
-        .init();
+    let args_os = response_file::expand(std::env::args_os())?;
+    if args_os
+        .iter()
+        .any(|arg| arg == worker::PERSISTENT_WORKER_FLAG)
+    {
+        return worker::run(&worker::Options::parse_startup(&args_os));
+    }
+    let subcommand_args = || args_os.iter().skip(1);
+    let mut ignored = Vec::new();
+    let args = match args_os.get(1).and_then(|arg| arg.to_str()) {
+        Some(fixture::SUBCOMMAND) => {
+            return fixture::run(&fixture::Options::parse_from(subcommand_args()))
+        }
+        Some(inspect::SUBCOMMAND) => {
+            return inspect::run(&inspect::Options::parse_from(subcommand_args()))
+        }
+        Some(doctor::SUBCOMMAND) => {
+            return doctor::run(&doctor::Options::parse_from(subcommand_args()))
+        }
+        Some(cache::SUBCOMMAND) => {
+            return cache::run(&cache::Options::parse_from(subcommand_args()))
+        }
+        Some(generate::FLAG) => {
+            return generate::run(&generate::Options::parse_from(subcommand_args()))
+        }
+        Some(LINK_SUBCOMMAND) => {
+            let matches = Args::command()
+                .bin_name("rust-ptx-linker link")
+                .get_matches_from(subcommand_args());
+            Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit())
+        }
+        // The flat arguments of `link`, as passed by rustc
+        _ => {
+            let (args, flat_ignored) = parse_flat(args_os.clone()).unwrap_or_else(|err| err.exit());
+            ignored = flat_ignored;
+            args
+        }
+    };
+
+    let subscriber = tracing_subscriber::FmtSubscriber::builder().with_max_level(log_level(&args));
+    // Keep stdout clean for the printed information
+    if args.print.is_some() {
+        subscriber.with_writer(std::io::stderr).init();
+    } else {
+        subscriber.init();
+    }
+
+    if !ignored.is_empty() {
+        tracing::debug!("ignoring the linker arguments {ignored:?}");
+    }
 
-    let args = Args::parse();
+    Interrupted::install_handler().context("Failed to install the signal handler")?;
 
-    let mut linker = Session::new(args.target, args.target_cpu, args.output)?;
+    let result = link(&args, &mut std::io::stdout());
+    if let Err(err) = &result {
+        if err.is::<Interrupted>() {
+            eprintln!("Error: {err:?}");
+            std::process::exit(Interrupted::EXIT_CODE);
+        }
+    }
+    result
+}
+
+/// The most detailed level logged by the link of `args`
+fn log_level(args: &Args) -> tracing::Level {
+    if args.verbose {
+        tracing::Level::TRACE
+    } else {
+        tracing::Level::DEBUG
+    }
+}
+
+/// Parses the flat arguments of `link`, starting with the program name, after
+/// translating the linker arguments of rustc, returning them with the
+/// arguments that were ignored
+fn parse_flat(args: Vec<OsString>) -> Result<(Args, Vec<OsString>), clap::Error> {
+    let translated = rustc_args::translate(args);
+    Ok((Args::try_parse_from(translated.args)?, translated.ignored))
+}
+
+/// Runs a single link as described by `args`, writing the information of
+/// `--print` to `out`
+fn link(args: &Args, out: &mut dyn Write) -> anyhow::Result<()> {
+    let args = &match &args.link_manifest {
+        Some(path) => link_manifest::Manifest::load(path)?.apply(args.clone())?,
+        None => args.clone(),
+    };
+    let output = args
+        .output
+        .clone()
+        .context("no output file given on the command line or in the link manifest")?;
+
+    Symbol::set_demangle(!args.no_demangle);
+    let config = config::Config::load(args.config.as_deref())?.for_target(args.target);
+
+    let target_cpus = if args.target_cpu.is_empty() {
+        config.target_cpus.clone()
+    } else {
+        args.target_cpu.clone()
+    };
+    let mut linker = Session::new(
+        args.target,
+        target_cpus,
+        output,
+        args.llvm_major.or(config.llvm_major),
+        args.llvm_tools_path.as_deref(),
+    )?;
 
-    for rlib in args.whole_rlib {
-        linker.link_rlib(rlib, true)?;
+    let jobs = args
+        .jobs
+        .or_else(|| std::thread::available_parallelism().ok())
+        .unwrap_or(NonZeroUsize::MIN);
+    let result = run_session(&mut linker, args, &config, jobs, out);
+
+    match &result {
+        Ok(()) => {}
+        Err(err) if err.is::<Interrupted>() => linker.remove_outputs(),
+        // For --resume
+        Err(_) => linker.set_keep_intermediates(true),
     }
 
-    for rlib in args.rlib {
-        linker.link_rlib(rlib, false)?;
+    if args.message_format == MessageFormat::Json {
+        for diagnostic in linker.diagnostics() {
+            eprintln!("{}", serde_json::to_string(&diagnostic)?);
+        }
     }
 
-    for bitcode in args.bitcode {
+    result
+}
+
+/// Adds all inputs from `args` to the session and performs the link
+fn run_session(
+    linker: &mut Session,
+    args: &Args,
+    config: &config::Config,
+    jobs: NonZeroUsize,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    linker.set_trace_symbols(args.trace_symbols.clone());
+    add_inputs(linker, args, config)?;
+    let debug = debug_info(args);
+
+    linker.set_emit(args.emit.clone());
+    linker.set_keep_symbols_format(args.keep_symbols_format);
+    if let Some(manifest) = &args.locked {
+        linker.set_locked(manifest)?;
+    }
+    if let Some(sign_key) = &args.sign_key {
+        linker.set_sign_key(sign_key)?;
+    }
+    linker.set_write_manifest(args.output_manifest);
+    for command in &args.post_process {
+        linker.add_post_processor(
+            ptx_linker::Command::parse(command).context("empty post-processor command")?,
+        );
+    }
+    linker.set_host_target(args.host_target.clone());
+    linker.set_codegen_optimization(args.codegen_opt_level);
+    linker.set_size_report(args.size_report);
+    linker.set_globals_report(args.globals_report);
+    linker.set_remarks_summary(args.remarks_summary);
+    linker.set_debug_info(debug.unwrap_or_default());
+    linker.set_split_debug(args.split_debug);
+    linker.set_verify_ptx(args.verify_ptx_syntax);
+    for deny in &args.deny {
+        linker.deny(deny.clone());
+    }
+    for feature in &args.target_feature {
+        linker.add_target_feature(feature.clone());
+    }
+    if let Some(version) = args.ptx_version {
+        linker.add_target_feature(TargetFeature::ptx(version));
+    }
+    for features in &args.function_target_features {
+        linker.override_target_features(features.clone());
+    }
+    linker.set_ir_diff(args.ir_diff.clone());
+    linker.set_kernel_metadata(args.kernel_metadata.clone());
+    if let Some(public_api) = &args.public_api {
+        linker.set_public_api(public_api)?;
+    }
+    linker.set_prune(!args.no_prune);
+    linker.set_max_memory(args.max_memory);
+    linker.set_tool_timeout(args.tool_timeout.map(Duration::from_secs));
+    linker.set_timings(args.timings);
+    linker.set_resume(args.resume);
+    linker.set_keep_intermediates(args.keep_intermediates);
+    linker.set_pipeline(match args.pipeline {
+        _ if args.thinlto => Pipeline::Thin,
+        Some(pipeline) => pipeline,
+        None if args.no_lto => Pipeline::PerModule,
+        None => Pipeline::Merge,
+    });
+    linker.set_passes(args.passes.clone());
+    linker.set_extra_passes(config.passes.clone());
+    for step in &args.skip_stage {
+        linker.skip_step(*step);
+    }
+    if let Some(step) = args.only_stage {
+        linker.set_only_step(step);
+    }
+    set_rewrites(linker, args);
+    linker.set_fallback_cpu(args.fallback_arch.clone());
+    linker.set_rdc(args.rdc);
+    for device_lib in &args.device_lib {
+        linker.add_device_lib(device_lib)?;
+    }
+
+    if args.print == Some(Print::Fingerprint) {
+        writeln!(
+            out,
+            "{}",
+            linker.fingerprint(args.optimization, true, debug.is_some(), true)?
+        )?;
+        return Ok(());
+    }
+
+    linker.lto(args.optimization, true, debug.is_some(), true, jobs)?;
+
+    if args.print == Some(Print::StageHashes) {
+        for stage_hash in linker.stage_hashes() {
+            writeln!(out, "{stage_hash}")?;
+        }
+    }
+    Ok(())
+}
+
+/// Sets how the linked module is rewritten from `args`
+fn set_rewrites(linker: &mut Session, args: &Args) {
+    linker.set_dtor_policy(if args.emit_fini_kernel {
+        DtorPolicy::FiniKernel
+    } else if args.strict {
+        DtorPolicy::Error
+    } else {
+        DtorPolicy::Drop
+    });
+    linker.set_strict_host_code(args.strict);
+    linker.set_unreachable(args.unreachable);
+    linker.set_device_asserts(args.device_asserts);
+    linker.set_panic_trap(args.panic_trap);
+    linker.set_profile_boundaries(args.profile_boundaries);
+    for pattern in &args.no_inline {
+        linker.no_inline(pattern);
+    }
+    for pattern in &args.force_inline {
+        linker.force_inline(pattern);
+    }
+    linker.set_coalesce_constants(args.coalesce_constants);
+    linker.set_rename_on_conflict(args.rename_on_conflict);
+}
+
+/// The debug information requested by `args`, if any
+fn debug_info(args: &Args) -> Option<DebugInfo> {
+    if args.generate_line_info {
+        Some(DebugInfo::LineTables)
+    } else {
+        args.debug
+    }
+}
+
+/// Adds the inputs and the symbols to keep from `args` to the session
+fn add_inputs(linker: &mut Session, args: &Args, config: &config::Config) -> anyhow::Result<()> {
+    let libdevice = args.libdevice.as_deref().or(config.libdevice.as_deref());
+    let math = args.math.unwrap_or(if libdevice.is_some() {
+        Math::Libdevice
+    } else {
+        Math::None
+    });
+    if math == Math::Builtin {
+        linker.add_builtin_math()?;
+    }
+
+    for rlib in &args.whole_rlib {
+        linker.link_archive(rlib, true)?;
+    }
+
+    for rlib in &args.rlib {
+        linker.link_archive(rlib, false)?;
+    }
+
+    for archive in &args.whole_archive {
+        linker.link_archive(archive, true)?;
+    }
+
+    for archive in &args.archive {
+        linker.link_archive(archive, false)?;
+    }
+
+    for library in &args.library {
+        let file_name = format!("lib{library}.a");
+        let archive = args
+            .input_dir
+            .iter()
+            .map(|dir| dir.join(&file_name))
+            .find(|path| path.is_file())
+            .context(format!(
+                "unable to find library -l{library} in the input directories {:?}",
+                args.input_dir
+            ))?;
+        linker.link_archive(archive, false)?;
+    }
+
+    for bitcode in &args.bitcode {
         linker.add_bitcode(bitcode, true)?;
     }
 
-    linker.lto(args.optimization, true, args.debug, true)
+    for input in &args.inputs {
+        linker.add_file(input)?;
+    }
+
+    for symbol in &args.keep {
+        linker.keep_symbol(symbol.as_bytes());
+    }
+    for path in &args.export_symbols_file {
+        linker.export_symbols_file(path)?;
+    }
+    for name in &args.gc_keep_exported_metadata {
+        linker.keep_exported_metadata(name.trim_start_matches('!'));
+    }
+
+    if math == Math::Libdevice {
+        linker.add_libdevice(libdevice)?;
+    } else if args.libdevice.is_some() {
+        tracing::warn!("not linking the libdevice given with --libdevice without --math libdevice");
+    }
+
+    Ok(())
 }
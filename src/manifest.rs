@@ -0,0 +1,163 @@
+//! The manifest of the outputs of a link
+//!
+//! The manifest is written as JSON next to the output as
+//! `<output>.outputs.json` with `--output-manifest`, and can be read with
+//! [`Manifest::from_slice`].
+//!
+//! # Compatibility
+//!
+//! The schema is versioned with [`SCHEMA_VERSION`]. Within a version fields
+//! are only ever added, and only as optional fields: readers ignore the fields
+//! they do not know and default the ones that are missing. Removing, renaming
+//! or changing the meaning of a field bumps the version, and manifests of a
+//! newer version than the reader supports are rejected.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// The manifest schema version written by this linker
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// All outputs of a link
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub schema_version: u32,
+    /// The version of the linker that wrote the manifest
+    pub linker_version: String,
+    /// The target triple, e.g. `nvptx64-nvidia-cuda`
+    pub target: String,
+    #[serde(default)]
+    pub artifacts: Vec<Artifact>,
+    /// The kernels with identical PTX for several target cpus, which packaging
+    /// can store once
+    #[serde(default)]
+    pub shared_kernels: Vec<SharedKernel>,
+    /// The versions of the external tools the link ran by tool, e.g. `llc`,
+    /// for `--locked` builds
+    #[serde(default)]
+    pub tools: BTreeMap<String, String>,
+    /// The hashes of the module after each step of the pipeline that ran, in
+    /// order, to find the first step whose output differs between builds
+    #[serde(default)]
+    pub stage_hashes: Vec<StageHash>,
+    /// The symbols defined by several inputs that were renamed in all but the
+    /// first of them with `--rename-on-conflict`
+    #[serde(default)]
+    pub renamed_symbols: Vec<RenamedSymbol>,
+    /// The fingerprint of the options of the link, as printed by
+    /// `--print fingerprint`
+    #[serde(default)]
+    pub fingerprint: Option<String>,
+}
+
+/// An emitted output of a link
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Artifact {
+    /// The kind of the output as given to `--emit`, e.g. `asm`
+    ///
+    /// Later versions of the linker may add kinds.
+    pub kind: String,
+    /// The target cpu of per-cpu outputs like the PTX for several cpus
+    #[serde(default)]
+    pub cpu: Option<String>,
+    pub path: PathBuf,
+    /// The detached signature of the artifact, if it is signed
+    #[serde(default)]
+    pub signature: Option<PathBuf>,
+    /// The debug information split off the PTX with `--split-debug`
+    #[serde(default)]
+    pub debug: Option<PathBuf>,
+    /// The kernels defined in the PTX, empty for other kinds
+    #[serde(default)]
+    pub kernels: Vec<String>,
+}
+
+/// A kernel whose PTX is identical in the assembly of several target cpus
+///
+/// The optimized module is shared by all target cpus, so kernels only differ
+/// where the code generation for them does.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SharedKernel {
+    pub name: String,
+    pub cpus: Vec<String>,
+}
+
+/// The hash of the output of a step of the link pipeline
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct StageHash {
+    /// The step, e.g. `merge` or `inline`
+    pub stage: String,
+    /// The target cpu of the code generated by the `codegen` step
+    #[serde(default)]
+    pub cpu: Option<String>,
+    pub hash: String,
+}
+
+/// A symbol renamed in one input as another input defines it as well
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RenamedSymbol {
+    pub symbol: String,
+    pub renamed: String,
+    /// The input whose definition was renamed
+    pub input: PathBuf,
+}
+
+impl Display for StageHash {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.cpu {
+            Some(cpu) => write!(f, "{} {cpu} {}", self.stage, self.hash),
+            None => write!(f, "{} {}", self.stage, self.hash),
+        }
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, thiserror::Error)]
+/// A manifest could not be read
+pub enum ManifestError {
+    #[error("malformed manifest: {0}")]
+    Malformed(#[from] serde_json::Error),
+    #[error("unsupported manifest schema version {0}, at most {SCHEMA_VERSION} is supported")]
+    UnsupportedVersion(u32),
+}
+
+impl Manifest {
+    /// An empty manifest of the current schema version
+    pub fn new(target: &str) -> Self {
+        Manifest {
+            schema_version: SCHEMA_VERSION,
+            linker_version: env!("CARGO_PKG_VERSION").to_owned(),
+            target: target.to_owned(),
+            artifacts: Vec::new(),
+            shared_kernels: Vec::new(),
+            tools: BTreeMap::new(),
+            stage_hashes: Vec::new(),
+            renamed_symbols: Vec::new(),
+            fingerprint: None,
+        }
+    }
+
+    /// Reads a manifest written by this or an older version of the linker
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, ManifestError> {
+        #[derive(Deserialize)]
+        struct Version {
+            schema_version: u32,
+        }
+
+        // Check the version first, a newer schema may not deserialize at all
+        let Version { schema_version } = serde_json::from_slice(bytes)?;
+        if schema_version > SCHEMA_VERSION {
+            return Err(ManifestError::UnsupportedVersion(schema_version));
+        }
+
+        Ok(serde_json::from_slice(bytes)?)
+    }
+
+    /// Serializes the manifest as pretty printed JSON
+    pub fn to_vec(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).expect("manifest is serializable")
+    }
+}
@@ -0,0 +1,117 @@
+//! The math intrinsics like `llvm.sin.f32` that the NVPTX backend cannot
+//! select, mapped to their libdevice equivalents like `__nv_sinf`
+
+use std::collections::BTreeSet;
+
+use crate::embedded_linker::globals::{global_name, reference_spans};
+
+/// The math intrinsics with a libdevice equivalent of the same name, which is
+/// suffixed with `f` for single precision
+const MAPPED: &[&str] = &[
+    "sin", "cos", "tan", "asin", "acos", "atan", "atan2", "sinh", "cosh", "tanh", "exp", "exp2",
+    "exp10", "log", "log2", "log10", "pow", "powi", "ldexp",
+];
+
+/// The calls to math intrinsics of a module rewritten into calls to libdevice
+#[derive(Debug, Default)]
+pub struct Mapped {
+    pub ir: String,
+    /// The number of rewritten calls
+    pub calls: usize,
+    /// The mapped intrinsics
+    pub intrinsics: BTreeSet<String>,
+}
+
+/// The libdevice function equivalent to the intrinsic `name`, if it is a
+/// scalar `f32` or `f64` math intrinsic the backend cannot select
+pub fn libdevice_function(name: &str) -> Option<String> {
+    let (base, types) = name.strip_prefix("llvm.")?.split_once('.')?;
+    if !MAPPED.contains(&base) {
+        return None;
+    }
+    // `powi` and `ldexp` are also overloaded on their exponent, which
+    // libdevice takes as an `int`
+    let types = match base {
+        "powi" | "ldexp" => types.strip_suffix(".i32").unwrap_or(types),
+        _ => types,
+    };
+    match types {
+        "f32" => Some(format!("__nv_{base}f")),
+        "f64" => Some(format!("__nv_{base}")),
+        _ => None,
+    }
+}
+
+/// The mappable intrinsics called in the textual IR of a module
+pub fn called(ir: &str) -> BTreeSet<String> {
+    ir.lines()
+        .filter(|line| !line.starts_with("declare ") && !line.starts_with('!'))
+        .flat_map(reference_spans)
+        .map(|(_, name)| name)
+        .filter(|name| libdevice_function(name).is_some())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Rewrites the calls to math intrinsics in the textual IR of a module into
+/// calls to their libdevice equivalents
+///
+/// The declarations of the intrinsics are renamed along with them, unless the
+/// module already declares or defines the libdevice function.
+pub fn map(ir: &str) -> Mapped {
+    let mut mapped = Mapped {
+        ir: String::with_capacity(ir.len()),
+        ..Mapped::default()
+    };
+    if !ir.contains("@llvm.") {
+        mapped.ir.push_str(ir);
+        return mapped;
+    }
+
+    let present = ir
+        .lines()
+        .filter_map(|line| {
+            let rest = line
+                .strip_prefix("declare ")
+                .or_else(|| line.strip_prefix("define "))?;
+            global_name(&rest[rest.find('@')? + 1..]).map(|(name, _)| name)
+        })
+        .collect::<BTreeSet<_>>();
+
+    for line in ir.split_inclusive('\n') {
+        if line.starts_with('!') {
+            mapped.ir.push_str(line);
+            continue;
+        }
+
+        let spans = reference_spans(line)
+            .into_iter()
+            .filter_map(|(span, name)| Some((span, name, libdevice_function(name)?)))
+            .collect::<Vec<_>>();
+        let declaration = line.starts_with("declare ");
+        if declaration
+            && spans
+                .iter()
+                .any(|(_, _, function)| present.contains(function.as_str()))
+        {
+            continue;
+        }
+
+        let mut rewritten = String::with_capacity(line.len());
+        let mut end = 0;
+        for (span, name, function) in spans {
+            if !declaration {
+                mapped.calls += 1;
+                mapped.intrinsics.insert(name.to_owned());
+            }
+            rewritten.push_str(&line[end..span.start]);
+            rewritten.push('@');
+            rewritten.push_str(&function);
+            end = span.end;
+        }
+        rewritten.push_str(&line[end..]);
+        mapped.ir.push_str(&rewritten);
+    }
+
+    mapped
+}
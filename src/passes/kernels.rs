@@ -0,0 +1,54 @@
+//! Finding the kernels of a module, which are kept even if their inputs are
+//! not whole
+
+use crate::embedded_linker::globals::global_name;
+use crate::Symbol;
+
+/// The names of the kernels defined in the textual IR of a module, either
+/// with the `ptx_kernel` calling convention or annotated as `kernel` in
+/// `!nvvm.annotations`
+pub fn defined(ir: &str) -> Vec<String> {
+    let mut kernels = Vec::new();
+
+    for line in ir.lines() {
+        let name = if line.starts_with("define ") {
+            let Some(index) = line.find('@') else {
+                continue;
+            };
+            if !line[..index].contains(" ptx_kernel ") {
+                continue;
+            }
+            global_name(&line[index + 1..])
+        } else if line.starts_with('!') && line.contains("!\"kernel\"") {
+            line.find('@')
+                .and_then(|index| global_name(&line[index + 1..]))
+        } else {
+            continue;
+        };
+
+        if let Some((name, _)) = name {
+            kernels.push(name.to_owned());
+        }
+    }
+
+    kernels.sort();
+    kernels.dedup();
+    kernels
+}
+
+/// The kernels defined in the textual IR of a module that are not in the
+/// keep-set `kept` yet, which internalizing would otherwise remove
+pub fn unkept(ir: &str, kept: &[Symbol]) -> Vec<Symbol> {
+    defined(ir)
+        .into_iter()
+        .map(Symbol::new)
+        .filter(|kernel| !kept.contains(kernel))
+        .collect()
+}
+
+/// The name of the crate of an rlib, e.g. `kernels` for
+/// `libkernels-0123456789abcdef.rlib`
+pub fn crate_name(file_name: &str) -> Option<&str> {
+    let stem = file_name.strip_prefix("lib")?.strip_suffix(".rlib")?;
+    stem.split('-').next()
+}
@@ -0,0 +1,87 @@
+//! The passes of the linker over the IR of the modules, and pass pipelines
+//! for the `opt` of old LLVM versions
+//!
+//! The textual pipelines of `--passes`, e.g. `default<O3>,function(sroa)`, are
+//! run by the new pass manager. The `opt` of older versions runs passes given
+//! as flags with the legacy pass manager instead, e.g. `-O3 -sroa`, which
+//! mostly have the same names.
+
+pub mod intrinsics;
+pub mod kernels;
+
+/// The first LLVM version whose `opt` runs `--passes` pipelines with the new
+/// pass manager
+pub const NEW_PASS_MANAGER_MAJOR: u32 = 13;
+
+#[derive(Debug, Clone, thiserror::Error)]
+/// A pipeline that cannot be run by the legacy pass manager
+#[error(
+    "opt of LLVM {llvm_major} cannot run `{pass}` of the pipeline `{pipeline}`, which requires \
+     opt of LLVM {NEW_PASS_MANAGER_MAJOR} or newer"
+)]
+pub struct UnsupportedPipeline {
+    pub pipeline: String,
+    pub pass: String,
+    pub llvm_major: u32,
+}
+
+/// The arguments of `opt` running `pipeline`, translated into the flags of the
+/// legacy pass manager for the `opt` of `llvm_major` if it is older than
+/// [`NEW_PASS_MANAGER_MAJOR`]
+pub fn args(pipeline: &str, llvm_major: Option<u32>) -> Result<Vec<String>, UnsupportedPipeline> {
+    let Some(llvm_major) = llvm_major.filter(|major| *major < NEW_PASS_MANAGER_MAJOR) else {
+        return Ok(vec![format!("--passes={pipeline}")]);
+    };
+
+    let mut flags = Vec::new();
+    let mut pending = split(pipeline);
+    while let Some(pass) = pending.pop() {
+        if let Some(level) = pass
+            .strip_prefix("default<")
+            .and_then(|pass| pass.strip_suffix('>'))
+        {
+            flags.push(format!("-{level}"));
+        } else if let Some(nested) = ["module(", "cgscc(", "function(", "loop("]
+            .iter()
+            .find_map(|adaptor| pass.strip_prefix(adaptor)?.strip_suffix(')'))
+        {
+            pending.extend(split(nested));
+        } else if pass.contains(['<', '(']) {
+            return Err(UnsupportedPipeline {
+                pipeline: pipeline.to_owned(),
+                pass: pass.to_owned(),
+                llvm_major,
+            });
+        } else {
+            flags.push(format!("-{pass}"));
+        }
+    }
+    tracing::debug!(
+        "running the pipeline {pipeline} as {} with the legacy pass manager of LLVM {llvm_major}",
+        flags.join(" ")
+    );
+    Ok(flags)
+}
+
+/// The passes of a comma separated `pipeline` in reverse order, keeping the
+/// commas of nested pipelines and parameters
+fn split(pipeline: &str) -> Vec<&str> {
+    let mut passes = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (index, c) in pipeline.char_indices() {
+        match c {
+            '(' | '<' => depth += 1,
+            ')' | '>' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                passes.push(&pipeline[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    passes.push(&pipeline[start..]);
+    passes.retain(|pass| !pass.is_empty());
+    passes.reverse();
+    passes
+}
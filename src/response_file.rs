@@ -0,0 +1,206 @@
+//! Response files `@FILE` containing further arguments
+//!
+//! rustc and build systems pass long command lines in response files, which
+//! are expanded in place before parsing. Their arguments are separated by
+//! whitespace and quoted like those of GCC: within single quotes everything is
+//! literal, within double quotes and outside of quotes a backslash escapes the
+//! next character. Response files may reference other response files.
+
+use std::ffi::OsString;
+use std::path::Path;
+
+use anyhow::Context;
+
+/// How deeply response files may reference each other, to stop on cycles
+const MAX_DEPTH: usize = 16;
+
+/// Expands the response files in `args`
+pub fn expand<T: Into<OsString>>(
+    args: impl IntoIterator<Item = T>,
+) -> anyhow::Result<Vec<OsString>> {
+    let mut expanded = Vec::new();
+    for arg in args {
+        expand_arg(arg.into(), 0, &mut expanded)?;
+    }
+    Ok(expanded)
+}
+
+fn expand_arg(arg: OsString, depth: usize, expanded: &mut Vec<OsString>) -> anyhow::Result<()> {
+    let Some(path) = arg
+        .to_str()
+        .and_then(|arg| arg.strip_prefix('@'))
+        .filter(|path| !path.is_empty())
+    else {
+        expanded.push(arg);
+        return Ok(());
+    };
+
+    if depth == MAX_DEPTH {
+        anyhow::bail!("response files nested more than {MAX_DEPTH} levels deep at @{path}");
+    }
+    let content = std::fs::read_to_string(Path::new(path))
+        .context(format!("Failed to read response file: {path}"))?;
+    for arg in split(&content).context(format!("Failed to parse response file: {path}"))? {
+        expand_arg(arg.into(), depth + 1, expanded)?;
+    }
+    Ok(())
+}
+
+/// The whitespace separated, quoted and escaped arguments of `content`
+fn split(content: &str) -> anyhow::Result<Vec<String>> {
+    let mut args = Vec::new();
+    // The argument being read, if any, which may be empty after quotes
+    let mut arg: Option<String> = None;
+    let mut quote = None;
+    let mut chars = content.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('\''), c) => arg.get_or_insert_with(String::new).push(c),
+            (_, '\\') => {
+                let escaped = chars.next().context("backslash at the end of the file")?;
+                arg.get_or_insert_with(String::new).push(escaped);
+            }
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                arg.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => args.extend(arg.take()),
+            (_, c) => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    if let Some(quote) = quote {
+        anyhow::bail!("unterminated {quote} quote");
+    }
+    args.extend(arg);
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// A directory of its own for the response files of the test `name`
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "{}-test-{name}-{}",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn at(path: &Path) -> String {
+        format!("@{}", path.display())
+    }
+
+    #[test]
+    fn splits_on_whitespace() {
+        assert_eq!(
+            split(" a.bc\tb.bc\n\n-o  out.ptx \r\n").unwrap(),
+            ["a.bc", "b.bc", "-o", "out.ptx"]
+        );
+        assert!(split("").unwrap().is_empty());
+        assert!(split(" \n\t").unwrap().is_empty());
+    }
+
+    #[test]
+    fn splits_quoted_arguments() {
+        assert_eq!(
+            split(r#"'a b.bc' "c d.bc" e'f g'h "" ''"#).unwrap(),
+            ["a b.bc", "c d.bc", "ef gh", "", ""]
+        );
+        // Quotes of the other kind are literal within quotes
+        assert_eq!(
+            split(r#"'say "hi"' "it's""#).unwrap(),
+            [r#"say "hi""#, "it's"]
+        );
+    }
+
+    #[test]
+    fn splits_escaped_characters() {
+        assert_eq!(
+            split(r#"a\ b.bc \"c\" "d\"e\\f" \\"#).unwrap(),
+            ["a b.bc", "\"c\"", "d\"e\\f", "\\"]
+        );
+        // Backslashes are literal within single quotes
+        assert_eq!(split(r"'C:\dir\a.bc'").unwrap(), [r"C:\dir\a.bc"]);
+        // An escaped newline continues the argument
+        assert_eq!(split("a\\\nb").unwrap(), ["a\nb"]);
+    }
+
+    #[test]
+    fn rejects_unterminated_quotes_and_escapes() {
+        assert!(split("'a.bc").is_err());
+        assert!(split("\"a.bc").is_err());
+        assert!(split(r#""a.bc\""#).is_err());
+        assert!(split("a.bc\\").is_err());
+    }
+
+    #[test]
+    fn expands_nested_response_files() {
+        let dir = test_dir("nested");
+        let inner = dir.join("inner.rsp");
+        let outer = dir.join("outer.rsp");
+        std::fs::write(&inner, "'b c.bc' -o out.ptx").unwrap();
+        std::fs::write(&outer, format!("a.bc {}\n--verbose", at(&inner))).unwrap();
+
+        let expanded = expand(["rust-ptx-linker".to_owned(), at(&outer), "d.bc".to_owned()]);
+        assert_eq!(
+            expanded.unwrap(),
+            [
+                "rust-ptx-linker",
+                "a.bc",
+                "b c.bc",
+                "-o",
+                "out.ptx",
+                "--verbose",
+                "d.bc"
+            ]
+        );
+
+        // A lone `@`, quoted or not, is an argument and not a response file
+        std::fs::write(&outer, "@ '@'").unwrap();
+        assert_eq!(expand([at(&outer)]).unwrap(), ["@", "@"]);
+    }
+
+    #[test]
+    fn rejects_cyclic_response_files() {
+        let dir = test_dir("cycle");
+        let file = dir.join("cycle.rsp");
+        std::fs::write(&file, at(&file)).unwrap();
+
+        let err = expand([at(&file)]).unwrap_err();
+        assert!(err.to_string().contains("nested more than"), "{err}");
+    }
+
+    #[test]
+    fn reports_missing_response_files() {
+        let missing = test_dir("missing").join("missing.rsp");
+        let err = expand([at(&missing)]).unwrap_err();
+        assert!(
+            err.to_string().starts_with("Failed to read response file"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn reports_malformed_response_files() {
+        let file = test_dir("malformed").join("malformed.rsp");
+        std::fs::write(&file, "'a.bc").unwrap();
+        let err = expand([at(&file)]).unwrap_err();
+        assert!(
+            err.to_string().starts_with("Failed to parse response file"),
+            "{err}"
+        );
+        assert!(
+            format!("{err:#}").contains("unterminated ' quote"),
+            "{err:#}"
+        );
+    }
+}
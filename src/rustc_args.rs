@@ -0,0 +1,285 @@
+//! The linker arguments of rustc with `-Clinker=rust-ptx-linker`
+//!
+//! Unless `-Clinker-flavor=ptx` is given, rustc passes the arguments of a `cc`
+//! or `ld` flavored linker: rlibs and objects as positional arguments, linker
+//! options wrapped in `-Wl,` and options without meaning for PTX like
+//! `-Bstatic`. They are translated into the flat arguments of `link` before
+//! parsing.
+
+use std::collections::VecDeque;
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+
+/// Options without meaning for PTX, which are ignored
+const IGNORED: [&str; 22] = [
+    "-Bstatic",
+    "-Bdynamic",
+    "-Bsymbolic",
+    "--as-needed",
+    "--no-as-needed",
+    "--gc-sections",
+    "--no-gc-sections",
+    "--eh-frame-hdr",
+    "--start-group",
+    "--end-group",
+    "--export-dynamic",
+    "--strip-debug",
+    "--strip-all",
+    "--build-id",
+    "-nodefaultlibs",
+    "-nostartfiles",
+    "-nostdlib",
+    "-static",
+    "-static-pie",
+    "-pie",
+    "-no-pie",
+    "-m64",
+];
+
+/// The prefixes of options without meaning for PTX, which are ignored
+const IGNORED_PREFIXES: [&str; 5] = [
+    "-fuse-ld=",
+    "-plugin-opt=",
+    "--version-script=",
+    "--hash-style=",
+    "--build-id=",
+];
+
+/// Options without meaning for PTX whose value is the next argument
+const IGNORED_WITH_VALUE: [&str; 2] = ["-z", "-flavor"];
+
+/// The keywords of `ld -z` that rustc passes joined to it, e.g.
+/// `-znoexecstack`, which are ignored
+const IGNORED_KEYWORDS: [&str; 7] = [
+    "noexecstack",
+    "execstack",
+    "relro",
+    "norelro",
+    "now",
+    "lazy",
+    "text",
+];
+
+/// The flat `link` arguments for the arguments rustc passed, and those that
+/// were ignored
+#[derive(Debug, Default)]
+pub struct Translated {
+    pub args: Vec<OsString>,
+    pub ignored: Vec<OsString>,
+}
+
+/// Translates `args`, starting with the program name, unwrapping linker
+/// options
+///
+/// Archives between `--whole-archive` and `--no-whole-archive` become whole
+/// inputs. The optimization levels of `ld`, which only affect its hash tables,
+/// are ignored, while `-Olto` of rustc also enables `--lto`.
+pub fn translate(args: impl IntoIterator<Item = OsString>) -> Translated {
+    // The arguments with whether they are passed to the linker by the compiler
+    let mut pending = args
+        .into_iter()
+        .map(|arg| (arg, false))
+        .collect::<VecDeque<_>>();
+    let mut translated = Translated::default();
+    let mut whole = false;
+
+    while let Some((arg, linker)) = pending.pop_front() {
+        let Some(text) = arg.to_str() else {
+            translated.args.push(arg);
+            continue;
+        };
+
+        if let Some(options) = text.strip_prefix("-Wl,") {
+            for option in options.split(',').rev() {
+                pending.push_front((option.into(), true));
+            }
+        } else if text == "-Xlinker" {
+            if let Some((option, _)) = pending.pop_front() {
+                pending.push_front((option, true));
+            }
+        } else if text == "--whole-archive" || text == "--no-whole-archive" {
+            whole = text == "--whole-archive";
+        } else if IGNORED_WITH_VALUE.contains(&text) {
+            translated.ignored.push(arg);
+            translated
+                .ignored
+                .extend(pending.pop_front().map(|(value, _)| value));
+        } else if IGNORED.contains(&text)
+            || IGNORED_PREFIXES
+                .iter()
+                .any(|prefix| text.starts_with(prefix))
+            || text
+                .strip_prefix("-z")
+                .is_some_and(|keyword| IGNORED_KEYWORDS.contains(&keyword))
+            || (linker && text.starts_with("-O"))
+        {
+            translated.ignored.push(arg);
+        } else if text == "-Olto" {
+            // How rustc asks for link time optimization
+            translated.args.push(arg);
+            translated.args.push("--lto".into());
+        } else if whole
+            && !text.starts_with('-')
+            && translated.args.last().map_or(true, |last| last != "-o")
+        {
+            let path = Path::new(text);
+            match path.extension().and_then(OsStr::to_str) {
+                Some("rlib") => translated.args.push("--whole-rlib".into()),
+                Some("a") => translated.args.push("--whole-archive".into()),
+                _ => {}
+            }
+            translated.args.push(arg);
+        } else {
+            translated.args.push(arg);
+        }
+    }
+    translated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translate(args: &[&str]) -> (Vec<String>, Vec<String>) {
+        let translated = super::translate(
+            std::iter::once("rust-ptx-linker")
+                .chain(args.iter().copied())
+                .map(OsString::from),
+        );
+        let strings = |args: Vec<OsString>| {
+            args.into_iter()
+                .map(|arg| arg.into_string().unwrap())
+                .collect::<Vec<_>>()
+        };
+        let mut args = strings(translated.args);
+        assert_eq!(args.remove(0), "rust-ptx-linker");
+        (args, strings(translated.ignored))
+    }
+
+    #[test]
+    fn enables_lto_for_rustc_only() {
+        // The `-Olto` of rustc, not the optimization levels of `ld`
+        assert_eq!(translate(&["-Olto"]).0, ["-Olto", "--lto"]);
+        assert_eq!(
+            translate(&["-Wl,-Olto", "-Wl,-O1", "-Xlinker", "-O2"]),
+            (Vec::new(), vec!["-Olto".into(), "-O1".into(), "-O2".into()])
+        );
+        assert_eq!(translate(&["-O3"]).0, ["-O3"]);
+    }
+
+    #[test]
+    fn unwraps_linker_options() {
+        // As passed for `-C link-arg` and `-C link-args`
+        let (args, ignored) = translate(&[
+            "-Wl,--gc-sections,-z,relro,-z,now",
+            "-Xlinker",
+            "--as-needed",
+            "-Wl,--target-cpu,sm_80",
+            "-Xlinker",
+            "-znoexecstack",
+        ]);
+        assert_eq!(args, ["--target-cpu", "sm_80"]);
+        assert_eq!(
+            ignored,
+            [
+                "--gc-sections",
+                "-z",
+                "relro",
+                "-z",
+                "now",
+                "--as-needed",
+                "-znoexecstack"
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_options_without_meaning_for_ptx() {
+        let (args, ignored) = translate(&[
+            "-m64",
+            "-fuse-ld=lld",
+            "-Wl,--version-script=/tmp/list",
+            "-Wl,--hash-style=gnu,--build-id=sha1",
+            "-flavor",
+            "gnu",
+            "-nodefaultlibs",
+            "-Bstatic",
+            "k.rlib",
+            "-Bdynamic",
+            "-o",
+            "k.ptx",
+        ]);
+        assert_eq!(args, ["k.rlib", "-o", "k.ptx"]);
+        assert_eq!(
+            ignored,
+            [
+                "-m64",
+                "-fuse-ld=lld",
+                "--version-script=/tmp/list",
+                "--hash-style=gnu",
+                "--build-id=sha1",
+                "-flavor",
+                "gnu",
+                "-nodefaultlibs",
+                "-Bstatic",
+                "-Bdynamic",
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_joined_keywords() {
+        // Only known `-z` keywords are dropped, not everything starting with it
+        assert_eq!(
+            translate(&["-zunknown", "-zrelro"]),
+            (vec!["-zunknown".into()], vec!["-zrelro".into()])
+        );
+    }
+
+    #[test]
+    fn marks_whole_archives() {
+        let (args, _) = translate(&[
+            "a.rlib",
+            "-Wl,--whole-archive",
+            "b.rlib",
+            "libc.a",
+            "d.bc",
+            "-o",
+            "out.a",
+            "-Wl,--no-whole-archive",
+            "e.rlib",
+        ]);
+        assert_eq!(
+            args,
+            [
+                "a.rlib",
+                "--whole-rlib",
+                "b.rlib",
+                "--whole-archive",
+                "libc.a",
+                "d.bc",
+                "-o",
+                "out.a",
+                "e.rlib"
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_the_flat_arguments_of_link() {
+        let args = [
+            "--target-cpu",
+            "sm_70",
+            "-L",
+            "/lib dir",
+            "--lto",
+            "-O2",
+            "-o",
+            "out.ptx",
+        ];
+        assert_eq!(
+            translate(&args),
+            (args.map(String::from).to_vec(), Vec::new())
+        );
+    }
+}
@@ -0,0 +1,502 @@
+//! Persistent worker mode for build systems
+//!
+//! Implements the singleplex [Bazel persistent worker protocol], reading
+//! length-delimited protobuf `WorkRequest`s from stdin and writing
+//! `WorkResponse`s to stdout. The same messages can alternatively be exchanged
+//! as newline-delimited JSON objects with `--worker-protocol json`.
+//!
+//! Each request runs one link with the request's arguments, so the process and
+//! the probed LLVM tool versions stay warm between actions.
+//!
+//! [Bazel persistent worker protocol]: https://bazel.build/remote/persistent
+
+use std::ffi::OsString;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use clap::Parser;
+use ptx_linker::Interrupted;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::reload;
+
+/// The flag build systems pass to start a tool in persistent worker mode
+pub const PERSISTENT_WORKER_FLAG: &str = "--persistent_worker";
+
+#[derive(Debug, Parser)]
+#[command(version)]
+/// Persistent worker for build systems
+pub struct Options {
+    /// Run as a persistent worker, reading work requests from stdin
+    #[arg(long = "persistent_worker")]
+    persistent_worker: bool,
+
+    /// The encoding of work requests and responses
+    #[arg(long, value_enum, default_value = "proto")]
+    worker_protocol: Protocol,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, clap::ValueEnum)]
+pub enum Protocol {
+    /// Length-delimited protobuf messages
+    Proto,
+    /// Newline-delimited JSON messages
+    Json,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct WorkRequest {
+    arguments: Vec<String>,
+    request_id: i32,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WorkResponse {
+    exit_code: i32,
+    output: String,
+    request_id: i32,
+}
+
+/// Log output collected while handling a single work request
+#[derive(Debug, Clone, Default)]
+struct CapturedOutput(Arc<Mutex<Vec<u8>>>);
+
+impl CapturedOutput {
+    fn take(&self) -> String {
+        let output = std::mem::take(&mut *self.0.lock().unwrap());
+        String::from_utf8_lossy(&output).into_owned()
+    }
+}
+
+impl Write for CapturedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Options {
+    /// Parses the startup arguments of the worker, ignoring those it does not
+    /// know, which build systems may pass to all workers
+    pub fn parse_startup(args: &[OsString]) -> Self {
+        let mut known = Vec::new();
+        let mut args = args.iter();
+        known.extend(args.next().cloned());
+        while let Some(arg) = args.next() {
+            let text = arg.to_string_lossy();
+            if text == "--worker-protocol" {
+                known.push(arg.clone());
+                known.extend(args.next().cloned());
+            } else if text == PERSISTENT_WORKER_FLAG
+                || text.starts_with("--worker-protocol=")
+                || ["-h", "--help", "-V", "--version"].contains(&&*text)
+            {
+                known.push(arg.clone());
+            }
+        }
+        Self::parse_from(known)
+    }
+}
+
+/// Serves work requests from stdin until it is closed
+pub fn run(options: &Options) -> anyhow::Result<()> {
+    debug_assert!(options.persistent_worker);
+
+    let output = CapturedOutput::default();
+    let writer = output.clone();
+    // The level is set for every request like for a link run on its own
+    let (filter, level_handle) = reload::Layer::new(LevelFilter::DEBUG);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(move || writer.clone()),
+        )
+        .init();
+    let set_level = |level| {
+        // Only fails once the subscriber is dropped, which it never is
+        let _ = level_handle.reload(LevelFilter::from_level(level));
+    };
+
+    Interrupted::install_handler().context("Failed to install the signal handler")?;
+
+    let stdin = std::io::stdin().lock();
+    let mut stdout = std::io::stdout().lock();
+
+    match options.worker_protocol {
+        Protocol::Proto => {
+            let mut stdin = stdin;
+            while let Some(request) = read_proto_request(&mut stdin)? {
+                let response = handle(request, &output, &set_level);
+                stdout.write_all(&encode_proto_response(&response))?;
+                stdout.flush()?;
+                exit_if_interrupted(&response);
+            }
+        }
+        Protocol::Json => {
+            for request in serde_json::Deserializer::from_reader(stdin).into_iter() {
+                let request = request.context("Failed to parse work request")?;
+                let response = handle(request, &output, &set_level);
+                serde_json::to_writer(&mut stdout, &response)?;
+                writeln!(stdout)?;
+                stdout.flush()?;
+                exit_if_interrupted(&response);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Exits once the response to the request that was interrupted is sent
+fn exit_if_interrupted(response: &WorkResponse) {
+    if response.exit_code == Interrupted::EXIT_CODE {
+        std::process::exit(Interrupted::EXIT_CODE);
+    }
+}
+
+fn handle(
+    request: WorkRequest,
+    output: &CapturedOutput,
+    set_level: &dyn Fn(tracing::Level),
+) -> WorkResponse {
+    let arguments = std::iter::once(String::from("rust-ptx-linker")).chain(request.arguments);
+    // The same arguments as of the linker invoked by rustc
+    let result = crate::response_file::expand(arguments)
+        .and_then(|arguments| Ok(crate::parse_flat(arguments)?))
+        .and_then(|(args, ignored)| {
+            set_level(crate::log_level(&args));
+            if !ignored.is_empty() {
+                tracing::debug!("ignoring the linker arguments {ignored:?}");
+            }
+            // Stdout carries the responses, so `--print` goes into the output
+            crate::link(&args, &mut output.clone())
+        });
+
+    let exit_code = match result {
+        Ok(()) => 0,
+        Err(err) => {
+            let mut output = output.clone();
+            // Writing into the in-memory buffer cannot fail
+            let _ = writeln!(output, "Error: {err:?}");
+            if err.is::<Interrupted>() {
+                Interrupted::EXIT_CODE
+            } else {
+                1
+            }
+        }
+    };
+
+    WorkResponse {
+        exit_code,
+        output: output.take(),
+        request_id: request.request_id,
+    }
+}
+
+/// Reads one length-delimited `WorkRequest`, returning `None` once stdin is
+/// closed
+fn read_proto_request(reader: &mut impl Read) -> anyhow::Result<Option<WorkRequest>> {
+    let mut first = [0];
+    if reader.read(&mut first)? == 0 {
+        return Ok(None);
+    }
+
+    let mut length = u64::from(first[0] & 0x7f);
+    let mut shift = 7;
+    let mut byte = first[0];
+    while byte & 0x80 != 0 {
+        let mut next = [0];
+        reader.read_exact(&mut next)?;
+        byte = next[0];
+        length |= u64::from(byte & 0x7f) << shift;
+        shift += 7;
+        anyhow::ensure!(shift < 64, "malformed work request length");
+    }
+
+    let mut message = vec![0; usize::try_from(length)?];
+    reader
+        .read_exact(&mut message)
+        .context("Failed to read work request")?;
+
+    let mut message = message.as_slice();
+    let mut request = WorkRequest::default();
+    while !message.is_empty() {
+        let key = decode_varint(&mut message)?;
+        match (key >> 3, key & 0x7) {
+            (1, 2) => {
+                let argument = decode_bytes(&mut message)?;
+                request
+                    .arguments
+                    .push(String::from_utf8(argument.to_vec())?);
+            }
+            (3, 0) => {
+                // int32 fields are sign extended to 64 bits on the wire
+                let request_id = i64::from_ne_bytes(decode_varint(&mut message)?.to_ne_bytes());
+                request.request_id = i32::try_from(request_id).context("invalid request id")?;
+            }
+            (_, 0) => {
+                decode_varint(&mut message)?;
+            }
+            (_, 1) => skip_bytes(&mut message, 8)?,
+            (_, 2) => {
+                decode_bytes(&mut message)?;
+            }
+            (_, 5) => skip_bytes(&mut message, 4)?,
+            (field, wire_type) => {
+                anyhow::bail!("unsupported wire type {wire_type} for work request field {field}")
+            }
+        }
+    }
+
+    Ok(Some(request))
+}
+
+/// Encodes a length-delimited `WorkResponse`
+fn encode_proto_response(response: &WorkResponse) -> Vec<u8> {
+    let mut message = Vec::new();
+
+    if response.exit_code != 0 {
+        encode_varint(&mut message, 1 << 3);
+        encode_int32(&mut message, response.exit_code);
+    }
+    if !response.output.is_empty() {
+        encode_varint(&mut message, 2 << 3 | 2);
+        encode_varint(&mut message, response.output.len() as u64);
+        message.extend_from_slice(response.output.as_bytes());
+    }
+    if response.request_id != 0 {
+        encode_varint(&mut message, 3 << 3);
+        encode_int32(&mut message, response.request_id);
+    }
+
+    let mut delimited = Vec::with_capacity(message.len() + 10);
+    encode_varint(&mut delimited, message.len() as u64);
+    delimited.extend_from_slice(&message);
+    delimited
+}
+
+fn decode_varint(buf: &mut &[u8]) -> anyhow::Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let Some((&byte, rest)) = buf.split_first() else {
+            anyhow::bail!("truncated varint in work request");
+        };
+        *buf = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    anyhow::bail!("malformed varint in work request")
+}
+
+fn decode_bytes<'a>(buf: &mut &'a [u8]) -> anyhow::Result<&'a [u8]> {
+    let length = usize::try_from(decode_varint(buf)?)?;
+    anyhow::ensure!(length <= buf.len(), "truncated field in work request");
+    let (bytes, rest) = buf.split_at(length);
+    *buf = rest;
+    Ok(bytes)
+}
+
+fn skip_bytes(buf: &mut &[u8], length: usize) -> anyhow::Result<()> {
+    anyhow::ensure!(length <= buf.len(), "truncated field in work request");
+    *buf = &buf[length..];
+    Ok(())
+}
+
+fn encode_int32(buf: &mut Vec<u8>, value: i32) {
+    // int32 fields are sign extended to 64 bits on the wire
+    encode_varint(buf, u64::from_ne_bytes(i64::from(value).to_ne_bytes()));
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn encode_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A length-delimited `WorkRequest` with the fields of `request`, followed
+    /// by the encoded `unknown` fields
+    fn encode_request(request: &WorkRequest, unknown: &[u8]) -> Vec<u8> {
+        let mut message = Vec::new();
+        for argument in &request.arguments {
+            encode_varint(&mut message, 1 << 3 | 2);
+            encode_varint(&mut message, argument.len() as u64);
+            message.extend_from_slice(argument.as_bytes());
+        }
+        message.extend_from_slice(unknown);
+        encode_varint(&mut message, 3 << 3);
+        encode_int32(&mut message, request.request_id);
+
+        let mut delimited = Vec::new();
+        encode_varint(&mut delimited, message.len() as u64);
+        delimited.extend_from_slice(&message);
+        delimited
+    }
+
+    #[test]
+    fn encodes_varints() {
+        for (value, encoded) in [
+            (0, vec![0]),
+            (127, vec![0x7f]),
+            (128, vec![0x80, 0x01]),
+            (300, vec![0xac, 0x02]),
+            (u64::MAX, [vec![0xff; 9], vec![0x01]].concat()),
+        ] {
+            let mut buf = Vec::new();
+            encode_varint(&mut buf, value);
+            assert_eq!(buf, encoded);
+            assert_eq!(decode_varint(&mut buf.as_slice()).unwrap(), value);
+        }
+        assert!(decode_varint(&mut [0x80].as_slice()).is_err());
+    }
+
+    #[test]
+    fn decodes_requests() {
+        // A long argument needs a two byte length, as does the message
+        let long = "-Lnative=".to_owned() + &"dir/".repeat(50);
+        let requests = [
+            WorkRequest {
+                arguments: vec![
+                    "k.bc".to_owned(),
+                    long,
+                    "-o".to_owned(),
+                    "kérnel.ptx".to_owned(),
+                ],
+                request_id: 7,
+            },
+            WorkRequest {
+                arguments: Vec::new(),
+                request_id: -1,
+            },
+        ];
+        let stream = requests
+            .iter()
+            .flat_map(|request| encode_request(request, &[]))
+            .collect::<Vec<_>>();
+        assert_eq!(stream[0] & 0x80, 0x80);
+
+        let mut reader = stream.as_slice();
+        for expected in &requests {
+            let request = read_proto_request(&mut reader).unwrap().unwrap();
+            assert_eq!(request.arguments, expected.arguments);
+            assert_eq!(request.request_id, expected.request_id);
+        }
+        assert!(read_proto_request(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn skips_unknown_fields() {
+        let mut unknown = Vec::new();
+        // A varint, `inputs` with their digests, a fixed64 and a fixed32
+        encode_varint(&mut unknown, 4 << 3);
+        encode_varint(&mut unknown, 1 << 40);
+        encode_varint(&mut unknown, 2 << 3 | 2);
+        encode_varint(&mut unknown, 3);
+        unknown.extend_from_slice(b"abc");
+        encode_varint(&mut unknown, 5 << 3 | 1);
+        unknown.extend_from_slice(&[1; 8]);
+        encode_varint(&mut unknown, 6 << 3 | 5);
+        unknown.extend_from_slice(&[1; 4]);
+
+        let request = WorkRequest {
+            arguments: vec!["k.bc".to_owned()],
+            request_id: 3,
+        };
+        let encoded = encode_request(&request, &unknown);
+        let decoded = read_proto_request(&mut encoded.as_slice())
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded.arguments, ["k.bc"]);
+        assert_eq!(decoded.request_id, 3);
+    }
+
+    #[test]
+    fn rejects_truncated_requests() {
+        let request = WorkRequest {
+            arguments: vec!["k.bc".to_owned()],
+            request_id: 1,
+        };
+        let encoded = encode_request(&request, &[]);
+        assert!(read_proto_request(&mut &encoded[..encoded.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn encodes_responses() {
+        let response = WorkResponse {
+            exit_code: -2,
+            output: "warning: ".repeat(20),
+            request_id: 300,
+        };
+        let encoded = encode_proto_response(&response);
+
+        let mut delimited = encoded.as_slice();
+        let length = decode_varint(&mut delimited).unwrap();
+        assert_eq!(length, delimited.len() as u64);
+        assert!(length > 127);
+
+        let mut message = delimited;
+        let mut fields = Vec::new();
+        while !message.is_empty() {
+            fields.push(decode_varint(&mut message).unwrap());
+            let wire_type = fields.last().unwrap() & 0x7;
+            if wire_type == 0 {
+                fields.push(decode_varint(&mut message).unwrap());
+            } else {
+                let output = decode_bytes(&mut message).unwrap();
+                assert_eq!(output, response.output.as_bytes());
+            }
+        }
+        assert_eq!(
+            fields,
+            [
+                1 << 3,
+                u64::from_ne_bytes((-2i64).to_ne_bytes()),
+                2 << 3 | 2,
+                3 << 3,
+                300
+            ]
+        );
+
+        // Fields with their default value are left out
+        let empty = WorkResponse {
+            exit_code: 0,
+            output: String::new(),
+            request_id: 0,
+        };
+        assert_eq!(encode_proto_response(&empty), [0]);
+    }
+
+    #[test]
+    fn ignores_unknown_startup_arguments() {
+        let args = [
+            "rust-ptx-linker",
+            "--persistent_worker",
+            "--bazel_flag=1",
+            "--other",
+            "value",
+            "--worker-protocol",
+            "json",
+        ]
+        .map(OsString::from);
+        let options = Options::parse_startup(&args);
+        assert!(options.persistent_worker);
+        assert_eq!(options.worker_protocol, Protocol::Json);
+    }
+}
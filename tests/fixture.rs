@@ -0,0 +1,173 @@
+//! Links the fixtures of `rust-ptx-linker gen-fixture` end to end
+//!
+//! The fixtures need the standard library of the target, e.g. from
+//! `rustup target add nvptx64-nvidia-cuda`, and the tests are skipped without
+//! it.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+
+use ptx_linker::manifest::Manifest;
+
+const LINKER: &str = env!("CARGO_BIN_EXE_rust-ptx-linker");
+
+const TARGET: &str = "nvptx64-nvidia-cuda";
+
+/// The rlib and the bitcode of the fixture kernel crate
+struct Fixtures {
+    rlib: PathBuf,
+    bitcode: PathBuf,
+}
+
+/// The fixtures, generated once for all tests, if the target is installed
+fn fixtures() -> Option<&'static Fixtures> {
+    static FIXTURES: OnceLock<Option<Fixtures>> = OnceLock::new();
+    FIXTURES
+        .get_or_init(|| {
+            if !target_installed() {
+                eprintln!("skipping: the standard library of {TARGET} is not installed");
+                return None;
+            }
+
+            let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("fixtures");
+            let output = Command::new(LINKER)
+                .args(["gen-fixture", "--target", TARGET, "--out-dir"])
+                .arg(&out_dir)
+                .output()
+                .unwrap();
+            assert!(
+                output.status.success(),
+                "gen-fixture failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+
+            Some(Fixtures {
+                rlib: out_dir.join("libptx_linker_fixture.rlib"),
+                bitcode: out_dir.join("ptx_linker_fixture.bc"),
+            })
+        })
+        .as_ref()
+}
+
+/// Whether `rustc` has the standard library of the target
+fn target_installed() -> bool {
+    let Ok(output) = Command::new("rustc")
+        .args(["--print", "target-libdir", "--target", TARGET])
+        .output()
+    else {
+        return false;
+    };
+    let libdir = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    output.status.success()
+        && std::fs::read_dir(libdir).map_or(false, |entries| {
+            entries
+                .flatten()
+                .any(|entry| entry.file_name().to_string_lossy().starts_with("libcore-"))
+        })
+}
+
+/// Links `inputs` into `name`.ptx, returning the PTX and the manifest
+fn link(name: &str, inputs: &[&str]) -> (String, Manifest) {
+    let out_path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{name}.ptx"));
+    let output = Command::new(LINKER)
+        .args(inputs)
+        .args(["--target-cpu", "sm_70", "--output-manifest", "-o"])
+        .arg(&out_path)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "the link failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let ptx = std::fs::read_to_string(&out_path).unwrap();
+    let manifest = std::fs::read(out_path.with_extension("ptx.outputs.json")).unwrap();
+    (ptx, Manifest::from_slice(&manifest).unwrap())
+}
+
+#[test]
+fn links_bitcode() {
+    let Some(fixtures) = fixtures() else {
+        return;
+    };
+    let (ptx, manifest) = link("bitcode", &[fixtures.bitcode.to_str().unwrap()]);
+
+    assert!(ptx.contains(".entry add("));
+    assert!(ptx.contains("LAUNCHES"));
+    // The symbols of bitcode inputs are all kept
+    assert!(ptx.contains("unused"));
+
+    let [artifact] = manifest.artifacts.as_slice() else {
+        panic!("expected the PTX only: {:?}", manifest.artifacts);
+    };
+    assert_eq!(artifact.kind, "asm");
+    assert_eq!(artifact.kernels, ["add"]);
+}
+
+#[test]
+fn removes_unused_functions_of_rlibs() {
+    let Some(fixtures) = fixtures() else {
+        return;
+    };
+    let (ptx, _) = link("rlib", &["--rlib", fixtures.rlib.to_str().unwrap()]);
+
+    assert!(ptx.contains(".entry add("));
+    assert!(!ptx.contains("unused"));
+}
+
+#[test]
+fn keeps_whole_rlibs() {
+    let Some(fixtures) = fixtures() else {
+        return;
+    };
+    let (ptx, _) = link(
+        "whole-rlib",
+        &["--whole-rlib", fixtures.rlib.to_str().unwrap()],
+    );
+
+    assert!(ptx.contains(".entry add("));
+    assert!(ptx.contains("unused"));
+}
+
+#[test]
+fn writes_pruned_inputs() {
+    let Some(fixtures) = fixtures() else {
+        return;
+    };
+    let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("only-prune");
+    let _ = std::fs::remove_dir_all(&out_dir);
+    std::fs::create_dir_all(&out_dir).unwrap();
+
+    let output = Command::new(LINKER)
+        .arg("--rlib")
+        .arg(&fixtures.rlib)
+        .args(["--target-cpu", "sm_70", "--only-stage", "prune", "-o"])
+        .arg(out_dir.join("pruned.bc"))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "the link failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // The output, or numbered files next to it for several inputs
+    let mut written = std::fs::read_dir(&out_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    written.sort();
+    assert!(
+        written == ["pruned.bc"] || (written.len() > 1 && written[0] == "pruned.0.bc"),
+        "unexpected outputs: {written:?}"
+    );
+    for file_name in written {
+        let bitcode = std::fs::read(out_dir.join(&file_name)).unwrap();
+        assert!(
+            bitcode.starts_with(b"BC\xc0\xde"),
+            "{file_name} is not bitcode"
+        );
+    }
+}